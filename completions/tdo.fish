@@ -7,7 +7,7 @@ complete -c tdo -f
 # Helper function to check if we've seen a subcommand
 function __tdo_needs_command
     set -l cmd (commandline -opc)
-    set -l subcmds add modify do start stop del list waiting pull push sync show undo attach prioritize move config complete
+    set -l subcmds add modify do start stop del list waiting pull push sync show undo attach prioritize move config complete archive stats merge agenda subtask tree stale workload completed validate repair search info bump lower reopen export recompute-dates forget next events swap tag-tree import
     for i in $cmd[2..-1]
         if contains -- $i $subcmds
             return 1
@@ -18,10 +18,10 @@ end
 
 function __tdo_using_command
     set -l cmd (commandline -opc)
-    set -l subcmds add modify do start stop del list waiting pull push sync show undo attach prioritize move config complete
+    set -l subcmds add modify do start stop del list waiting pull push sync show undo attach prioritize move config complete archive stats merge agenda subtask tree stale workload completed validate repair search info bump lower reopen export recompute-dates forget next events swap tag-tree import
     for i in $cmd[2..-1]
         if contains -- $i $subcmds
-            if test "$i" = "$argv[1]"
+            if contains -- $i $argv
                 return 0
             end
             return 1
@@ -45,6 +45,8 @@ end
 # Global options
 complete -c tdo -l version -d "Show version"
 complete -c tdo -l env -d "Environment name" -rf -a "(tdo complete envs 2>/dev/null)"
+complete -c tdo -n "__tdo_needs_command" -l pretty -d "Indent JSON output"
+complete -c tdo -n "__tdo_needs_command" -l timestamps -x -a "epoch iso" -d "Format of JSON times"
 
 # Commands
 complete -c tdo -n __tdo_needs_command -a add -d "Create new task"
@@ -64,10 +66,34 @@ complete -c tdo -n __tdo_needs_command -a attach -d "Manage attachments"
 complete -c tdo -n __tdo_needs_command -a prioritize -d "Interactive priority"
 complete -c tdo -n __tdo_needs_command -a move -d "Move task to another env"
 complete -c tdo -n __tdo_needs_command -a config -d "Configuration"
+complete -c tdo -n __tdo_needs_command -a archive -d "Archive completed tasks"
+complete -c tdo -n __tdo_needs_command -a stats -d "Project and tag counts"
+complete -c tdo -n __tdo_needs_command -a merge -d "Merge duplicate tasks"
+complete -c tdo -n __tdo_needs_command -a agenda -d "Tasks grouped by due horizon"
+complete -c tdo -n __tdo_needs_command -a subtask -d "Create task under a parent"
+complete -c tdo -n __tdo_needs_command -a tree -d "Tasks as nested JSON"
+complete -c tdo -n __tdo_needs_command -a stale -d "Tasks not synced recently"
+complete -c tdo -n __tdo_needs_command -a workload -d "Sum estimated minutes"
+complete -c tdo -n __tdo_needs_command -a completed -d "List completed tasks with notes"
+complete -c tdo -n __tdo_needs_command -a validate -d "Check the cache for consistency problems"
+complete -c tdo -n __tdo_needs_command -a repair -d "Fix problems found by validate"
+complete -c tdo -n __tdo_needs_command -a search -d "Find tasks by summary"
+complete -c tdo -n __tdo_needs_command -a info -d "Show the env and its cache file"
+complete -c tdo -n __tdo_needs_command -a bump -d "Raise priority one level"
+complete -c tdo -n __tdo_needs_command -a lower -d "Lower priority one level"
+complete -c tdo -n __tdo_needs_command -a reopen -d "Move completed tasks back"
+complete -c tdo -n __tdo_needs_command -a export -d "Write tasks as JSON Lines"
+complete -c tdo -n __tdo_needs_command -a recompute-dates -d "Rederive UTC due/wait columns"
+complete -c tdo -n __tdo_needs_command -a forget -d "Erase tasks by uid"
+complete -c tdo -n __tdo_needs_command -a next -d "Show the task to work on next"
+complete -c tdo -n __tdo_needs_command -a events -d "Tasks that became overdue or stopped waiting"
+complete -c tdo -n __tdo_needs_command -a swap -d "Exchange two tasks' indices or priorities"
+complete -c tdo -n __tdo_needs_command -a tag-tree -d "Tags nested on :: with counts"
+complete -c tdo -n __tdo_needs_command -a import -d "Create tasks from an export file"
 complete -c tdo -n __tdo_needs_command -a complete -d "Shell completion data"
 
 # Task index filter (before command) - only show when token starts with digit
-complete -c tdo -n "__tdo_needs_command; and __tdo_completing_filter" -a "(tdo complete tasks 2>/dev/null)"
+complete -c tdo -n "__tdo_needs_command; and __tdo_completing_filter" -a "(tdo complete tasks --detailed 2>/dev/null)"
 
 # Multi-index completion (e.g., "2,<TAB>" shows "2,3", "2,4", etc.)
 complete -c tdo -n "__tdo_needs_command; and __tdo_completing_comma_list" -a "(
@@ -79,13 +105,118 @@ complete -c tdo -n "__tdo_needs_command; and __tdo_completing_comma_list" -a "(
 )"
 
 # Project filter (before command) - filter by project
-complete -c tdo -n __tdo_needs_command -a "(tdo complete projects 2>/dev/null | sed 's/^/project:/')" -d "Filter by project"
+complete -c tdo -n __tdo_needs_command -a "(tdo complete projects --prefix (commandline -ct | string replace -r '^project:' '') 2>/dev/null | sed 's/^/project:/')" -d "Filter by project"
 
 # Tag filter (before command) - filter by tag
 complete -c tdo -n __tdo_needs_command -a "(tdo complete tags 2>/dev/null | sed 's/^/+/')" -d "Filter by tag"
 
 # list command options
 complete -c tdo -n "__tdo_using_command list" -l no-reverse -d "Don't reverse sort order"
+complete -c tdo -n "__tdo_using_command list" -l all-envs -d "Merge all environments"
+complete -c tdo -n "__tdo_using_command list" -l activate-waited -d "Clear passed wait dates"
+complete -c tdo -n "__tdo_using_command list" -l include-completed -d "Mix in completed tasks by time"
+complete -c tdo -n "__tdo_using_command list" -l relative -d "Show due dates relative to now"
+complete -c tdo -n "__tdo_using_command list" -l group-by -d "One table per group" -xa "project tag status due"
+complete -c tdo -n "__tdo_using_command list" -l last-changed -d "Tasks the last change touched"
+complete -c tdo -n "__tdo_using_command list" -l count-by -d "Counts per group instead of tasks" -xa "project tag status"
+complete -c tdo -n "__tdo_using_command list" -l json -d "Print --count-by counts as JSON"
+
+# archive command options
+complete -c tdo -n "__tdo_using_command archive" -l days -d "Archive tasks completed more than N days ago" -r
+complete -c tdo -n "__tdo_using_command archive" -l uid -d "Archive a specific completed task" -r
+complete -c tdo -n "__tdo_using_command archive" -l list -d "List archived tasks"
+
+# agenda command options
+complete -c tdo -n "__tdo_using_command agenda" -l soon-days -d "Horizon for the Soon bucket" -r
+complete -c tdo -n "__tdo_using_command agenda" -l week-days -d "Horizon for the This week bucket" -r
+complete -c tdo -n "__tdo_using_command agenda" -l all-envs -d "Merge all environments"
+
+# add command options
+complete -c tdo -n "__tdo_using_command add" -l start -d "Create the task already started"
+complete -c tdo -n "__tdo_using_command add" -l index -d "Create the task at this index" -r
+complete -c tdo -n "__tdo_using_command add" -l index-taken -d "When --index is taken" -xa "error next"
+complete -c tdo -n "__tdo_using_command modify" -l append -d "Add text to the end of the summary" -x
+complete -c tdo -n "__tdo_using_command modify" -l append-separator -d "Text between summary and --append" -x
+
+# date checks for creating and modifying tasks
+complete -c tdo -n "__tdo_using_command add modify subtask" -l force -d "Skip due/wait date checks"
+
+# idempotency key for mutating commands
+complete -c tdo -n "__tdo_using_command add modify do start stop del subtask merge archive undo move attach bump lower swap reopen" -l idempotency-key -d "Apply at most once per key" -r
+complete -c tdo -n "__tdo_using_command start" -l force -d "Start several tasks despite single_active"
+
+# stats command options
+complete -c tdo -n "__tdo_using_command stats" -l include-completed -d "Count completed tasks too"
+complete -c tdo -n "__tdo_using_command stats" -l by-day -d "Completed tasks per day"
+complete -c tdo -n "__tdo_using_command stats" -l time -d "Time spent per project and tag"
+complete -c tdo -n "__tdo_using_command stats" -l days -d "Days to count with --by-day" -x
+complete -c tdo -n "__tdo_using_command stats" -l json -d "Print counts as JSON"
+complete -c tdo -n "__tdo_using_command stats" -l all-envs -d "Merge all environments"
+
+# do command options
+complete -c tdo -n "__tdo_using_command do" -l note -d "Record a completion note" -r
+complete -c tdo -n "__tdo_using_command do" -l at -d "Record the completion at this time" -r
+complete -c tdo -n "__tdo_using_command do" -l force -d "Allow an --at time in the future"
+complete -c tdo -n "__tdo_using_command do" -l current -d "Complete the in-progress task"
+complete -c tdo -n "__tdo_using_command do" -l cascade -d "Also complete all subtasks"
+complete -c tdo -n "__tdo_using_command do" -l allow-completed -d "Treat already completed tasks as done"
+complete -c tdo -n "__tdo_using_command export" -l output -d "Stream to this file" -r
+complete -c tdo -n "__tdo_using_command import" -l force -d "Accept completion or deletion times in the future"
+complete -c tdo -n "__tdo_using_command import" -F
+complete -c tdo -n "__tdo_using_command recompute-dates" -l dry-run -d "Count changes without writing"
+complete -c tdo -n "__tdo_using_command forget" -l confirm -d "Required: cannot be undone"
+complete -c tdo -n "__tdo_using_command next" -l order -d "Sort keys, most significant first" -r
+complete -c tdo -n "__tdo_using_command next" -l count -d "Show the top N tasks" -r
+
+# workload command options
+complete -c tdo -n "__tdo_using_command workload" -l json -d "Print the workload as JSON"
+
+# validate command options
+complete -c tdo -n "__tdo_using_command validate" -l json -d "Print problems as JSON"
+
+# repair command options
+complete -c tdo -n "__tdo_using_command repair" -l dry-run -d "Show fixes without applying them"
+complete -c tdo -n "__tdo_using_command repair" -l json -d "Print fixes as JSON"
+
+# info command options
+complete -c tdo -n "__tdo_using_command info" -l json -d "Print the details as JSON"
+
+# search command options
+complete -c tdo -n "__tdo_using_command show tree search" -l priority-letters -d "Show priorities as H/M/L too"
+complete -c tdo -n "__tdo_using_command show tree" -l raw -d "Include X-properties verbatim"
+complete -c tdo -n "__tdo_using_command do del" -l confirm -d "Allow changing more tasks than confirm_batch_size"
+complete -c tdo -n "__tdo_using_command search" -l limit -d "Show at most N matches" -r
+complete -c tdo -n "__tdo_using_command search" -l json -d "Print matches as JSON"
+complete -c tdo -n "__tdo_using_command search" -l envelope -d "Wrap JSON with total and filters"
+complete -c tdo -n "__tdo_using_command search" -l include-deleted -d "Also search unpushed deletions"
+complete -c tdo -n "__tdo_using_command search" -l include-archived -d "Also search archived tasks"
+complete -c tdo -n "__tdo_using_command search" -l all-envs -d "Merge all environments"
+complete -c tdo -n "__tdo_using_command search" -l weights -d "Field weights, e.g. summary=3,project=2,tags=1" -r
+
+# stale command options
+complete -c tdo -n "__tdo_using_command events" -l since -d "Start of the interval" -r
+complete -c tdo -n "__tdo_using_command events" -l json -d "Print events as JSON"
+complete -c tdo -n "__tdo_using_command stale" -l days -d "Report tasks last synced more than N days ago" -r
+complete -c tdo -n "__tdo_using_command stale" -l json -d "Print stale tasks as JSON"
+
+# show command options
+complete -c tdo -n "__tdo_using_command show" -l relative -d "Show due date relative to now"
+complete -c tdo -n "__tdo_using_command show" -l sort -d "Order of shown tasks" -xa "input index due priority"
+complete -c tdo -n "__tdo_using_command show" -l resolve-parent -d "Name parent tasks by index and summary"
+complete -c tdo -n "__tdo_using_command show" -l history -d "Include logged change history"
+complete -c tdo -n "__tdo_using_command show" -l envelope -d "Wrap JSON with total and filters"
+complete -c tdo -n "__tdo_using_command show" -l format -d "Detail blocks, one row per task, or JSON" -xa "detail table json"
+complete -c tdo -n "__tdo_using_command show" -l fields -d "JSON fields to include (comma-separated)" -x
+complete -c tdo -n "__tdo_using_command show" -l as-map -d "JSON object keyed by index"
+
+# del command options
+complete -c tdo -n "__tdo_using_command del" -l cascade -d "Also delete subtasks"
+complete -c tdo -n "__tdo_using_command del" -l orphan -d "Keep subtasks as top-level tasks"
+complete -c tdo -n "__tdo_using_command del" -l at -d "Record the deletion at this time" -r
+complete -c tdo -n "__tdo_using_command del" -l force -d "Allow an --at time in the future"
+
+# merge command options
+complete -c tdo -n "__tdo_using_command merge" -l prefer-secondary -d "Prefer secondary values on conflict"
 
 # attach command options
 complete -c tdo -n "__tdo_using_command attach" -l fmttype -d "MIME type" -r
@@ -94,15 +225,24 @@ complete -c tdo -n "__tdo_using_command attach" -l list -d "List attachments"
 
 # move command - destination environment
 complete -c tdo -n "__tdo_using_command move" -a "(tdo complete envs 2>/dev/null)" -d "Destination environment"
+complete -c tdo -n "__tdo_using_command move" -l json -d "Print the index mapping as JSON"
+complete -c tdo -n "__tdo_using_command move" -l dry-run -d "Preview without moving anything"
+complete -c tdo -n "__tdo_using_command move" -l with-history -d "Copy the moved tasks' undo history"
 
 # complete command subcommands
 complete -c tdo -n "__tdo_using_command complete" -a "envs" -d "List environments"
 complete -c tdo -n "__tdo_using_command complete" -a "tasks" -d "List task indices"
 complete -c tdo -n "__tdo_using_command complete" -a "projects" -d "List projects"
 complete -c tdo -n "__tdo_using_command complete" -a "tags" -d "List tags"
+complete -c tdo -n "__tdo_using_command complete" -l detailed -d "Include priority and due in task entries"
+complete -c tdo -n "__tdo_using_command complete" -l prefix -d "Complete next project path segment" -r
+complete -c tdo -n "__tdo_using_command complete" -l sort -d "Order projects and tags" -xa "alpha recent"
+complete -c tdo -n "__tdo_using_command complete" -l query -d "Fuzzy-match projects and tags" -r
+complete -c tdo -n "__tdo_using_command complete" -l exact -d "Only prefix matches for --query"
 
 # config subcommand
 complete -c tdo -n "__tdo_using_command config" -a "init" -d "Initialize config"
+complete -c tdo -n "__tdo_using_command config" -a "set-default-env" -d "Set default environment"
 
 # config init options
 function __tdo_config_init
@@ -126,7 +266,7 @@ complete -c tdo -n __tdo_config_init -l force -d "Overwrite existing config"
 function __tdo_add_or_modify
     set -l cmd (commandline -opc)
     for i in $cmd[2..-1]
-        if contains -- $i add modify
+        if contains -- $i add modify subtask
             return 0
         end
     end
@@ -162,16 +302,21 @@ complete -c tdo -n __tdo_add_or_modify -a "wait:tomorrow" -d "Wait until tomorro
 complete -c tdo -n __tdo_add_or_modify -a "wait:1d" -d "Wait 1 day"
 complete -c tdo -n __tdo_add_or_modify -a "wait:1w" -d "Wait 1 week"
 
+complete -c tdo -n __tdo_add_or_modify -a "estimate:30" -d "Estimate 30 minutes"
+complete -c tdo -n __tdo_add_or_modify -a "estimate:60" -d "Estimate 1 hour"
+
 # Status values
 complete -c tdo -n __tdo_add_or_modify -a "status:NEEDS-ACTION" -d "Needs action"
 complete -c tdo -n __tdo_add_or_modify -a "status:IN-PROCESS" -d "In process"
 complete -c tdo -n __tdo_add_or_modify -a "status:COMPLETED" -d "Completed"
+complete -c tdo -n __tdo_add_or_modify -a "custom_status:" -d "Custom workflow state"
 
 # Dynamic project completions
-complete -c tdo -n __tdo_add_or_modify -a "(tdo complete projects 2>/dev/null | sed 's/^/project:/')" -d "Project"
+complete -c tdo -n __tdo_add_or_modify -a "(tdo complete projects --prefix (commandline -ct | string replace -r '^project:' '') 2>/dev/null | sed 's/^/project:/')" -d "Project"
 
 # Dynamic tag completions (with + prefix for adding)
 complete -c tdo -n __tdo_add_or_modify -a "(tdo complete tags 2>/dev/null | sed 's/^/+/')" -d "Add tag"
 
 # Dynamic tag completions (with - prefix for removing)
 complete -c tdo -n "__tdo_using_command modify" -a "(tdo complete tags 2>/dev/null | sed 's/^/-/')" -d "Remove tag"
+complete -c tdo -n "__tdo_using_command swap" -l field -d "Field to exchange" -xa "index priority"
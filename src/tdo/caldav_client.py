@@ -1,11 +1,12 @@
 from __future__ import annotations
 
 import time
-from dataclasses import dataclass, field
+from contextlib import asynccontextmanager
+from dataclasses import dataclass, field, replace
 from datetime import datetime
 from pathlib import Path
 from time import perf_counter
-from typing import Dict, TYPE_CHECKING
+from typing import AsyncIterator, Dict, Mapping, Sequence, TYPE_CHECKING
 from uuid import uuid4
 
 import arrow
@@ -13,7 +14,22 @@ import arrow
 from .config import CaldavConfig
 from .diff import TaskDiff, TaskSetDiff
 from .models import Attachment, Task, TaskData, TaskPatch, TaskPayload
-from .sqlite_cache import SqliteTaskCache
+from .sqlite_cache import (
+    ArchivedMatch,
+    BoundaryEvent,
+    CacheInfo,
+    DeletedMatch,
+    ForgetReport,
+    IdempotentResult,
+    IntegrityProblem,
+    SqliteTaskCache,
+    StaleTask,
+    TaskRef,
+    TimelineEntry,
+    TimeSpent,
+    TransactionLogEntry,
+    TransactionLogRetention,
+)
 
 if TYPE_CHECKING:
     from caldav import DAVClient, Calendar
@@ -29,6 +45,14 @@ def _debug_log(stage: str, duration: float, info: str | None = None) -> None:
     print(f"[timing] {stage}: {duration:.3f}s{suffix}")
 
 
+class EtagConflictError(RuntimeError):
+    """The server copy changed since the last pull, so an If-Match update was refused."""
+
+    def __init__(self, uid: str) -> None:
+        super().__init__(f"task {uid} changed on the server since the last pull")
+        self.uid = uid
+
+
 @dataclass
 class SyncError:
     """Represents an error during sync for a specific task."""
@@ -90,16 +114,31 @@ class CalDAVClient:
     client: "DAVClient" | None = field(default=None, init=False)
     calendar: "Calendar" | None = field(default=None, init=False)
     cache: SqliteTaskCache | None = field(default=None, init=False)
+    _log_batch: dict[str, TaskDiff] | None = field(default=None, init=False, repr=False)
 
     @classmethod
-    async def create(cls, config: CaldavConfig, cache_path: Path | None = None) -> CalDAVClient:
+    async def create(
+        cls,
+        config: CaldavConfig,
+        cache_path: Path | None = None,
+        *,
+        read_only: bool = False,
+        upgrade: bool = True,
+    ) -> CalDAVClient:
         instance = cls(config=config, cache_path=cache_path)
-        await instance._init_cache()
+        await instance._init_cache(read_only=read_only, upgrade=upgrade)
         return instance
 
-    async def _init_cache(self) -> None:
+    async def _init_cache(self, *, read_only: bool = False, upgrade: bool = True) -> None:
         env = self.config.env or "default"
-        self.cache = await SqliteTaskCache.create(self.cache_path, env=env)
+        retention = TransactionLogRetention(
+            max_entries=self.config.cache.transaction_log_size,
+            max_age_days=self.config.cache.transaction_log_max_age_days,
+            idempotency_window_hours=self.config.cache.idempotency_window_hours,
+        )
+        self.cache = await SqliteTaskCache.create(
+            self.cache_path, env=env, retention=retention, read_only=read_only, upgrade=upgrade
+        )
 
     def __enter__(self) -> CalDAVClient:
         from caldav import DAVClient
@@ -141,6 +180,44 @@ class CalDAVClient:
             raise RuntimeError("cache is not initialized; use CalDAVClient.create()")
         return self.cache
 
+    def _ensure_writable_cache(self, action: str) -> SqliteTaskCache:
+        cache = self._ensure_cache()
+        cache.require_writable(action)
+        return cache
+
+    @asynccontextmanager
+    async def logged(self, operation: str) -> AsyncIterator[None]:
+        """Log every mutation made inside the block as one transaction.
+
+        A task changed more than once keeps its earliest pre and latest
+        post, so undo reverts the whole block. The changes and their log
+        entry share one SQLite transaction: if the block raises, neither is
+        written. A nested block joins the outer one and its operation is
+        ignored.
+        """
+        if self._log_batch is not None:
+            yield
+            return
+        self._log_batch = {}
+        try:
+            async with self._ensure_cache().transaction():
+                yield
+                diff: TaskSetDiff[str] = TaskSetDiff(diffs=self._log_batch)
+                if not diff.is_empty:
+                    cache = self._ensure_writable_cache("log a transaction")
+                    await cache.log_transaction(diff, operation=operation)
+        finally:
+            self._log_batch = None
+
+    async def _record(self, uid: str, pre: TaskData | None, post: TaskData | None, operation: str) -> None:
+        """Add a change to the open batch, or log it on its own."""
+        if self._log_batch is None:
+            diff: TaskSetDiff[str] = TaskSetDiff(diffs={uid: TaskDiff(pre=pre, post=post)})
+            await self._ensure_writable_cache("log a transaction").log_transaction(diff, operation=operation)
+            return
+        earlier = self._log_batch.get(uid)
+        self._log_batch[uid] = TaskDiff(pre=earlier.pre if earlier else pre, post=post)
+
     async def list_tasks(self, force_refresh: bool = False) -> list[Task]:
         return await self._ensure_cache().list_tasks()
 
@@ -167,7 +244,123 @@ class CalDAVClient:
         """List waiting tasks using SQL filtering."""
         return await self._ensure_cache().list_waiting_tasks(task_filter=task_filter)
 
-    async def create_task(self, payload: TaskPayload) -> Task:
+    async def search_tasks(
+        self,
+        text: str,
+        *,
+        task_filter: "TaskFilter | None" = None,
+        limit: int | None = None,
+        weights: Mapping[str, int] | None = None,
+    ) -> list[Task]:
+        """Active tasks matching text, ranked by relevance."""
+        return await self._ensure_cache().search_tasks(text, task_filter=task_filter, limit=limit, weights=weights)
+
+    async def search_deleted_tasks(
+        self,
+        text: str,
+        *,
+        task_filter: "TaskFilter | None" = None,
+        limit: int | None = None,
+        weights: Mapping[str, int] | None = None,
+    ) -> list[DeletedMatch]:
+        """Deleted tasks matching text, ranked by relevance."""
+        return await self._ensure_cache().search_deleted_tasks(
+            text, task_filter=task_filter, limit=limit, weights=weights
+        )
+
+    async def search_archived_tasks(
+        self,
+        text: str,
+        *,
+        task_filter: "TaskFilter | None" = None,
+        limit: int | None = None,
+        weights: Mapping[str, int] | None = None,
+    ) -> list[ArchivedMatch]:
+        """Archived tasks matching text, ranked by relevance."""
+        return await self._ensure_cache().search_archived_tasks(
+            text, task_filter=task_filter, limit=limit, weights=weights
+        )
+
+    async def index_available(self, index: int) -> bool:
+        return await self._ensure_cache().index_available(index)
+
+    async def swap_indices(self, first: Task, second: Task) -> tuple[Task, Task]:
+        """Exchange two tasks' indices, logged as "swap-index".
+
+        Indices live outside TaskData, so the logged diff is a no-op pair
+        that undo recognises by its operation and swaps back. The swap and
+        its log entry are written in one transaction.
+        """
+        cache = self._ensure_writable_cache("swap indices")
+        diff: TaskSetDiff[str] = TaskSetDiff(
+            diffs={task.uid: TaskDiff(pre=task.data, post=task.data) for task in (first, second)}
+        )
+        async with cache.transaction():
+            first_index, second_index = await cache.swap_indices(first.uid, second.uid)
+            await cache.log_transaction(diff, operation="swap-index")
+        return replace(first, task_index=first_index), replace(second, task_index=second_index)
+
+    async def activate_waited_tasks(self) -> list[Task]:
+        """Clear passed wait dates; returns the activated tasks before the change.
+
+        The cleared dates and their log entry are written in one transaction.
+        """
+        async with self.logged("activate"):
+            activated = await self._ensure_writable_cache("activate waited tasks").activate_waited_tasks()
+            for task in activated:
+                await self._record(task.uid, task.data, replace(task.data, wait=None), "activate")
+        return activated
+
+    async def get_idempotent_result(self, key: str) -> IdempotentResult | None:
+        return await self._ensure_cache().get_idempotent_result(key)
+
+    async def claim_idempotency_key(self, key: str, command: str) -> IdempotentResult | None:
+        cache = self._ensure_writable_cache("claim an idempotency key")
+        return await cache.claim_idempotency_key(key, command)
+
+    async def release_idempotency_key(self, key: str) -> None:
+        await self._ensure_writable_cache("release an idempotency key").release_idempotency_key(key)
+
+    async def record_idempotent_result(self, key: str, command: str, result: str) -> int:
+        cache = self._ensure_writable_cache("record an idempotent result")
+        return await cache.record_idempotent_result(key, command, result)
+
+    async def count_tags_and_projects(
+        self,
+        *,
+        include_completed: bool = False,
+    ) -> dict[str, dict[str, int]]:
+        return await self._ensure_cache().count_tags_and_projects(include_completed=include_completed)
+
+    async def sum_time_spent(self, *, include_completed: bool = False) -> TimeSpent:
+        return await self._ensure_cache().sum_time_spent(include_completed=include_completed)
+
+    async def count_tasks_by(
+        self,
+        key: str,
+        *,
+        task_filter: "TaskFilter | None" = None,
+        include_completed: bool = False,
+    ) -> dict[str, int]:
+        return await self._ensure_cache().count_tasks_by(
+            key, task_filter=task_filter, include_completed=include_completed
+        )
+
+    async def tag_and_project_recency(self) -> dict[str, dict[str, float]]:
+        return await self._ensure_cache().tag_and_project_recency()
+
+    async def count_completed_by_day(self, since: float) -> dict[str, int]:
+        return await self._ensure_cache().count_completed_by_day(since)
+
+    async def create_task(self, payload: TaskPayload, *, index: int | None = None) -> Task:
+        """Create a task at index, or at the next available index when None.
+
+        A requested index that is already taken raises ValueError before
+        anything is written.
+        """
+        cache = self._ensure_writable_cache("create a task")
+        if index is not None and not await cache.index_available(index):
+            raise ValueError(f"index {index} is already taken")
         uid = self._uid_from_summary(payload.summary)
         categories = list(payload.categories) if payload.categories else []
         attachments = list(payload.attachments) if payload.attachments else []
@@ -183,35 +376,185 @@ class CalDAVClient:
                 categories=categories,
                 url=payload.url,
                 attachments=attachments,
+                parent=payload.parent,
             ),
         )
-        cache = self._ensure_cache()
         await cache.upsert_task(task, pending_action="create")
         # Assign a stable index to the new task
-        task_index = await cache.assign_index(uid)
+        task_index = await cache.assign_index(uid, index)
         task.task_index = task_index
+        await self._record(uid, None, task.data, "add")
         return task
 
     async def modify_task(self, task: Task, patch: TaskPatch) -> Task:
+        updated, _ = await self.modify_task_if_changed(task, patch)
+        return updated
+
+    async def modify_task_if_changed(self, task: Task, patch: TaskPatch) -> tuple[Task, bool]:
+        """Apply patch, skipping the cache write when nothing would change.
+
+        An unchanged task keeps its pending_action and updated_at, so no-op
+        edits don't queue a push. Returns the task and whether it changed.
+        An empty patch returns before touching the cache at all.
+        """
+        if patch == TaskPatch():
+            return task, False
         updated = self._apply_patch(task, patch)
-        pending_action = await self._ensure_cache().get_pending_action(task.uid)
-        action = "create" if pending_action == "create" else "update"
-        await self._ensure_cache().upsert_task(updated, pending_action=action)
+        if not task.diff(updated).changes():
+            return task, False
+        await self._write_update(task, updated)
+        return updated, True
+
+    async def replace_task_data(self, task: Task, data: TaskData) -> Task:
+        """Store data as the task's new contents, for edits a patch can't express."""
+        updated = replace(task, data=data)
+        await self._write_update(task, updated)
         return updated
 
-    async def delete_task(self, uid: str) -> str:
-        cache = self._ensure_cache()
+    async def _write_update(self, task: Task, updated: Task) -> None:
+        cache = self._ensure_writable_cache("update a task")
+        pending_action = await cache.get_pending_action(task.uid)
+        action = "create" if pending_action == "create" else "update"
+        await cache.upsert_task(updated, pending_action=action)
+        await self._record(task.uid, task.data, updated.data, "modify")
+
+    async def delete_task(
+        self, uid: str, *, deleted_at: float | None = None, keep_unsynced: bool = False
+    ) -> bool:
+        """Delete a task; returns whether a tombstone awaits the next push."""
+        cache = self._ensure_writable_cache("delete a task")
+        existing = await cache.get_task(uid) or await cache.get_completed_task(uid)
         # mark_for_deletion handles both active and completed tasks,
         # and also handles the case where task was never synced (pending create)
-        await cache.mark_for_deletion(uid)
-        return uid
+        tombstone = await cache.mark_for_deletion(uid, deleted_at=deleted_at, keep_unsynced=keep_unsynced)
+        if existing:
+            await self._record(uid, existing.data, None, "delete")
+        return tombstone
 
-    async def complete_task(self, uid: str) -> None:
-        """Mark a task as completed.
+    async def complete_task(
+        self, uid: str, *, note: str | None = None, completed_at: float | None = None
+    ) -> str:
+        """Mark a task as completed; returns the complete_mode applied.
 
-        Moves the task from tasks to completed_tasks with status COMPLETED.
+        Moves the task from tasks to completed_tasks with status COMPLETED,
+        keeping the optional completion note on the completed row. With
+        complete_mode "delete", a task that was never synced is removed
+        outright instead, with completed_at as its deletion time; synced
+        tasks are still archived so the server learns about the completion.
         """
-        await self._ensure_cache().complete_task(uid)
+        cache = self._ensure_writable_cache("complete a task")
+        existing = await cache.get_task(uid)
+        if self.config.complete_mode == "delete" and await cache.get_pending_action(uid) == "create":
+            await cache.mark_for_deletion(uid, deleted_at=completed_at)
+            if existing:
+                await self._record(uid, existing.data, None, "do")
+            return "delete"
+        await cache.complete_task(uid, note=note, completed_at=completed_at)
+        if existing:
+            await self._record(uid, existing.data, replace(existing.data, status="COMPLETED"), "do")
+        return "archive"
+
+    async def reopen_task(self, uid: str) -> Task:
+        """Move a completed task back to the active list.
+
+        Each reopen bumps X-REOPENED, so a later completion can be told
+        apart from the first one. The task is queued for the next push.
+        """
+        cache = self._ensure_writable_cache("reopen a task")
+        completed = await cache.get_completed_task(uid)
+        if completed is None:
+            raise KeyError(f"task {uid} not found in completed_tasks")
+        restored = await cache.restore_from_completed(uid)
+        reopened = replace(restored, data=restored.data.with_x_property("X-REOPENED", str(completed.reopened + 1)))
+        action = "create" if await cache.get_pending_action(uid) == "create" else "update"
+        await cache.upsert_task(reopened, pending_action=action)
+        await self._record(uid, completed.data, reopened.data, "reopen")
+        return reopened
+
+    def iter_tasks_for_export(self, *, task_filter: "TaskFilter | None" = None) -> AsyncIterator[tuple[str, Task]]:
+        return self._ensure_cache().iter_tasks_for_export(task_filter=task_filter)
+
+    async def list_completed_tasks(self) -> list[Task]:
+        return await self._ensure_cache().list_completed_tasks()
+
+    async def peek_transaction(self) -> TransactionLogEntry | None:
+        return await self._ensure_cache().peek_transaction()
+
+    async def touched_tasks(self, entry: TransactionLogEntry) -> list[TimelineEntry]:
+        """Current state of each task a logged transaction changed, in log order.
+
+        A task found in no table (e.g. a never-synced task that was deleted)
+        is returned as "removed" with its data from before the change.
+        """
+        cache = self._ensure_cache()
+        touched: list[TimelineEntry] = []
+        for uid, diff in TaskSetDiff.from_json(entry.diff_json).diffs.items():
+            for state, lookup in (
+                ("active", cache.get_task),
+                ("completed", cache.get_completed_task),
+                ("deleted", cache.get_deleted_task),
+            ):
+                task = await lookup(uid)
+                if task is not None:
+                    touched.append(TimelineEntry(task, state, entry.created_at))
+                    break
+            else:
+                data = diff.pre or diff.post
+                if data is not None:
+                    touched.append(TimelineEntry(Task(uid=uid, data=data), "removed", entry.created_at))
+        return touched
+
+    async def list_timeline(self, *, task_filter: "TaskFilter | None" = None) -> list[TimelineEntry]:
+        return await self._ensure_cache().list_timeline(task_filter=task_filter)
+
+    async def archive_completed_tasks(
+        self,
+        *,
+        completed_before: float | None = None,
+        uids: Sequence[str] | None = None,
+    ) -> list[Task]:
+        """Move completed tasks out of the completed view into the archive."""
+        return await self._ensure_writable_cache("archive tasks").archive_completed_tasks(
+            completed_before=completed_before,
+            uids=uids,
+        )
+
+    async def list_archived_tasks(self) -> list[Task]:
+        return await self._ensure_cache().list_archived_tasks()
+
+    async def cache_info(self) -> CacheInfo:
+        return await self._ensure_cache().info()
+
+    async def resolve_task_refs(self, uids: Sequence[str]) -> dict[str, TaskRef]:
+        return await self._ensure_cache().resolve_refs(uids)
+
+    async def check_integrity(self) -> list[IntegrityProblem]:
+        return await self._ensure_cache().check_integrity()
+
+    async def repair(self, *, dry_run: bool = False) -> list[IntegrityProblem]:
+        return await self._ensure_writable_cache("repair the cache").repair(dry_run=dry_run)
+
+    async def forget_tasks(self, uids: Sequence[str]) -> list[ForgetReport]:
+        return await self._ensure_writable_cache("forget tasks").forget_tasks(uids)
+
+    async def transaction_history(self, uids: Sequence[str]) -> list[TransactionLogEntry]:
+        return await self._ensure_cache().transaction_history(uids)
+
+    async def import_transactions(
+        self, entries: Sequence[TransactionLogEntry], uid_map: Mapping[str, str]
+    ) -> int:
+        return await self._ensure_writable_cache("import transactions").import_transactions(entries, uid_map)
+
+    async def recompute_utc_dates(self, *, dry_run: bool = False) -> int:
+        return await self._ensure_writable_cache("recompute dates").recompute_utc_dates(dry_run=dry_run)
+
+    async def list_stale_tasks(self, *, synced_before: float) -> list[StaleTask]:
+        return await self._ensure_cache().list_stale_tasks(synced_before=synced_before)
+
+    async def list_boundary_events(
+        self, *, since: float, until: float, task_filter: "TaskFilter | None" = None
+    ) -> list[BoundaryEvent]:
+        return await self._ensure_cache().list_boundary_events(since=since, until=until, task_filter=task_filter)
 
     def _apply_patch(self, task: Task, patch: TaskPatch) -> Task:
         summary = patch.summary or task.data.summary or task.uid
@@ -252,6 +595,13 @@ class CalDAVClient:
         attachments = list(task.data.attachments)
         if patch.attachments:
             attachments.extend(patch.attachments)
+        # Handle parent: empty string = unset, None = no change
+        if patch.parent == "":
+            parent = None
+        elif patch.parent is not None:
+            parent = patch.parent
+        else:
+            parent = task.data.parent
         return Task(
             uid=task.uid,
             data=TaskData(
@@ -264,14 +614,16 @@ class CalDAVClient:
                 categories=categories,
                 url=url,
                 attachments=attachments,
+                parent=parent,
             ),
             href=task.href,
+            etag=task.etag,
             task_index=task.task_index,
         )
 
     async def pull(self, *, dry_run: bool = False) -> PullResult:
         start = perf_counter()
-        cache = self._ensure_cache()
+        cache = self._ensure_writable_cache("pull")
         errors: list[SyncError] = []
 
         # Get cached state before pull
@@ -378,7 +730,6 @@ class CalDAVClient:
             await cache.log_transaction(
                 uid_diff,
                 operation="pull",
-                max_entries=self.config.cache.transaction_log_size,
             )
 
         elapsed = perf_counter() - start
@@ -388,7 +739,7 @@ class CalDAVClient:
 
     async def push(self, *, dry_run: bool = False) -> PushResult:
         start = perf_counter()
-        cache = self._ensure_cache()
+        cache = self._ensure_writable_cache("push")
         pending = await cache.dirty_tasks()
         diffs: dict[int, TaskDiff] = {}
         errors: list[SyncError] = []
@@ -451,6 +802,17 @@ class CalDAVClient:
                             self._push_delete(task, calendar)
                             diffs[index] = TaskDiff(pre=task.data, post=None)
                             successfully_deleted.append(task.uid)
+                except EtagConflictError as e:
+                    # The row stays pending: a pull would replace the local edit
+                    errors.append(
+                        SyncError(
+                            uid=task.uid,
+                            action=entry.action,
+                            error=f"{e}; kept the local edit pending (tdo pull takes the server copy and drops it)",
+                            task_index=index,
+                        )
+                    )
+                    _debug_log("push_conflict", 0.0, f"uid={task.uid}")
                 except Exception as e:
                     error = SyncError(
                         uid=task.uid,
@@ -463,7 +825,7 @@ class CalDAVClient:
 
             # Flush only successfully deleted tasks after push
             if not dry_run and successfully_deleted:
-                await cache.flush_deleted_tasks()
+                await cache.flush_deleted_tasks(successfully_deleted)
 
         diff: TaskSetDiff[int] = TaskSetDiff(diffs=diffs)
 
@@ -475,7 +837,6 @@ class CalDAVClient:
             await cache.log_transaction(
                 uid_diff,
                 operation="push",
-                max_entries=self.config.cache.transaction_log_size,
             )
 
         elapsed = perf_counter() - start
@@ -507,9 +868,12 @@ class CalDAVClient:
             task.data.status,
             task.data.url,
             task.data.attachments,
+            task.data.parent,
         )
         todo = calendar.add_todo(body)
         synced = self._task_from_resource(todo)
+        if synced.etag is None and synced.href:
+            synced.etag = self._fetch_etag(synced.href)
         # Ensure we keep the original UID (server might return different/empty UID)
         synced.uid = task.uid
         synced.task_index = task.task_index
@@ -528,17 +892,36 @@ class CalDAVClient:
             task.data.status,
             task.data.url,
             task.data.attachments,
+            task.data.parent,
         )
         resource = self._resource_for_update(task, calendar)
-        resource.id = task.uid
-        resource.data = body
-        resource.save()
-        synced = self._task_from_resource(resource)
+        url = str(resource.url)
+        headers = {"Content-Type": 'text/calendar; charset="utf-8"'}
+        if task.etag:
+            # Only overwrite the copy we last pulled
+            headers["If-Match"] = task.etag
+        assert self.client is not None
+        response = self.client.put(url, body, headers)
+        if response.status == 412:
+            raise EtagConflictError(task.uid)
+        if response.status >= 300:
+            raise RuntimeError(f"server refused the update of {task.uid}: {response.status} {response.reason}")
+        synced = self._task_from_data(body)
+        synced.href = url
+        synced.etag = (response.headers or {}).get("ETag") or self._fetch_etag(url)
         # Ensure we keep the original UID and index (server might return different/empty UID)
         synced.uid = task.uid
         synced.task_index = task.task_index
         return synced
 
+    def _fetch_etag(self, url: str) -> str | None:
+        """The server's current etag for url, for servers that leave it out of PUT replies."""
+        assert self.client is not None
+        response = self.client.request(url, "HEAD")
+        if response.status >= 300:
+            return None
+        return (response.headers or {}).get("ETag")
+
     def _push_delete(self, task: Task, calendar: "Calendar") -> None:
         from caldav import error as caldav_error
 
@@ -564,6 +947,9 @@ class CalDAVClient:
         task = self._task_from_data(resource.data or "")
         if resource.url:
             task.href = str(resource.url)
+        # Present when the server reported getetag with the object
+        etag = (getattr(resource, "props", None) or {}).get("{DAV:}getetag")
+        task.etag = str(etag) if etag else None
         return task
 
     def _build_ics(
@@ -578,6 +964,7 @@ class CalDAVClient:
         status: str | None,
         url: str | None = None,
         attachments: list[Attachment] | None = None,
+        parent: str | None = None,
     ) -> str:
         lines = [
             "BEGIN:VCALENDAR",
@@ -599,6 +986,8 @@ class CalDAVClient:
             lines.append(f"CATEGORIES:{','.join(categories)}")
         if url:
             lines.append(f"URL:{url}")
+        if parent:
+            lines.append(f"RELATED-TO;RELTYPE=PARENT:{parent}")
         for attach in attachments or []:
             if attach.fmttype:
                 lines.append(f"ATTACH;FMTTYPE={attach.fmttype}:{attach.uri}")
@@ -623,6 +1012,7 @@ class CalDAVClient:
         categories: list[str] = []
         url: str | None = None
         attachments: list[Attachment] = []
+        parent: str | None = None
         for raw in data.splitlines():
             line = raw.strip()
             if not line or ":" not in line:
@@ -651,6 +1041,10 @@ class CalDAVClient:
                 categories.extend(self._split_categories(value))
             elif key == "URL":
                 url = value
+            elif key == "RELATED-TO" or key.startswith("RELATED-TO;"):
+                # RELTYPE defaults to PARENT when omitted (RFC 5545)
+                if self._extract_param(key, "RELTYPE") in (None, "PARENT"):
+                    parent = value
             elif key == "ATTACH" or key.startswith("ATTACH;"):
                 fmttype = self._extract_fmttype(key)
                 attachments.append(Attachment(uri=value, fmttype=fmttype))
@@ -668,9 +1062,18 @@ class CalDAVClient:
                 categories=categories,
                 url=url,
                 attachments=attachments,
+                parent=parent,
             ),
         )
 
+    def _extract_param(self, key: str, name: str) -> str | None:
+        """Extract a parameter value from a property key like 'RELATED-TO;RELTYPE=PARENT'."""
+        prefix = f"{name}="
+        for param in key.split(";")[1:]:
+            if param.upper().startswith(prefix):
+                return param[len(prefix):].upper()
+        return None
+
     def _extract_tzid(self, key: str) -> str | None:
         """Extract TZID from a property key like 'DUE;TZID=America/New_York'."""
         if ";" not in key:
@@ -726,4 +1129,4 @@ class CalDAVClient:
         return [candidate.strip() for candidate in raw.split(",") if candidate.strip()]
 
     def _uid_from_summary(self, summary: str) -> str:
-        return f"{summary.replace(' ', '_')}-{uuid4()}"
+        return self.config.uid_template.format(summary=summary.replace(" ", "_"), uuid=uuid4())
@@ -2,35 +2,50 @@ from __future__ import annotations
 
 import argparse
 import asyncio
+import io
+import json
 import os
 import random
+import re
+import shutil
 import sys
-from datetime import datetime
+import time
+from collections import Counter
+from contextlib import AsyncExitStack, asynccontextmanager, redirect_stdout, suppress
+from contextvars import ContextVar
+from datetime import date, datetime, timedelta, timezone
 from importlib.metadata import PackageNotFoundError, version
 from pathlib import Path
-from typing import Awaitable, Callable, NoReturn, Sequence, TypeVar
+from typing import Any, AsyncIterator, Awaitable, Callable, Mapping, NoReturn, Sequence, TextIO, TypeVar
 
 from rich import box
 from rich.console import Console
 from rich.table import Table
 
 from .config import (
+    NEXT_SORT_KEYS,
     CaldavConfig,
+    ValidationConfig,
     config_file_path,
+    list_environments,
     load_config,
     load_config_from_path,
     resolve_env,
     write_config_file,
+    write_default_env,
 )
 from .diff import TaskDiff, TaskSetDiff
-from .models import Attachment, Task, TaskData, TaskFilter, TaskPatch, TaskPayload
-from .time_parser import parse_due_value
+from .models import TAG_SEPARATOR, Attachment, Task, TaskData, TaskFilter, TaskPatch, TaskPayload
+from .time_parser import parse_age_value, parse_due_value
 from .update_descriptor import UpdateDescriptor
-from .update_linear_parser import parse_update
+from .update_linear_parser import METADATA_KEYS, parse_update
 
 
 T = TypeVar("T")
 
+# Set by _async_main for read commands; _cache_client then opens the cache read-only
+_read_only_cache: ContextVar[bool] = ContextVar("_read_only_cache", default=False)
+
 # Sentinel value to indicate a datetime field should be explicitly unset
 _UNSET_DATETIME = datetime(1, 1, 1, 0, 0, 0)
 
@@ -42,11 +57,20 @@ def _get_version() -> str:
         return "dev"
 
 
-async def _run_with_client(env: str | None, callback: Callable[["CalDAVClient"], Awaitable[T]]) -> T:
+async def _open_client(config: CaldavConfig, *, read_only: bool = False, upgrade: bool = True) -> "CalDAVClient":
+    """Create a client, exiting with a readable message if its cache is unusable."""
     from .caldav_client import CalDAVClient
+    from .sqlite_cache import CacheUnavailableError
+
+    try:
+        return await CalDAVClient.create(config, read_only=read_only, upgrade=upgrade)
+    except CacheUnavailableError as exc:
+        _exit_with_message(str(exc))
 
+
+async def _run_with_client(env: str | None, callback: Callable[["CalDAVClient"], Awaitable[T]]) -> T:
     config = _resolve_config(env)
-    client = await CalDAVClient.create(config)
+    client = await _open_client(config)
     try:
         with client:
             return await callback(client)
@@ -55,10 +79,8 @@ async def _run_with_client(env: str | None, callback: Callable[["CalDAVClient"],
 
 
 async def _cache_client(env: str | None) -> "CalDAVClient":
-    from .caldav_client import CalDAVClient
-
     config = _resolve_config(env)
-    return await CalDAVClient.create(config)
+    return await _open_client(config, read_only=_read_only_cache.get())
 
 
 def _resolve_config(env: str | None) -> CaldavConfig:
@@ -126,8 +148,20 @@ def _truncate_summary(summary: str, max_len: int = 30) -> str:
     return summary[: max_len - 3] + "..."
 
 
+def _quote_argv_token(token: str) -> str:
+    """Quote the value of a key:value argv token that holds whitespace.
+
+    The shell passes "project:Home Stuff" as one token; without quotes,
+    joining argv with spaces would cut the value at its first space.
+    """
+    key, sep, value = token.partition(":")
+    if not sep or key.lower() not in METADATA_KEYS or '"' in value or not any(char.isspace() for char in value):
+        return token
+    return f'{key}:"{value}"'
+
+
 def _parse_update_descriptor(tokens: Sequence[str]) -> UpdateDescriptor:
-    raw = " ".join(token.strip() for token in tokens if token and token.strip())
+    raw = " ".join(_quote_argv_token(token.strip()) for token in tokens if token and token.strip())
     return parse_update(raw)
 
 
@@ -140,9 +174,18 @@ def _resolve_due_value(raw: str | None) -> datetime | None:
     return resolved.to("UTC").naive
 
 
+def _resolve_age_value(raw: str | None) -> datetime | None:
+    if not raw:
+        return None
+    resolved = parse_age_value(raw)
+    if resolved is None:
+        return None
+    return resolved.to("UTC").datetime
+
+
 def _apply_tag_changes(existing: Sequence[str] | None, descriptor: UpdateDescriptor) -> list[str] | None:
-    add_tags = descriptor.add_data.categories or []
-    remove_tags = descriptor.remove_data.categories or []
+    add_tags = descriptor.add_data.tags
+    remove_tags = descriptor.remove_data.tags
     if not add_tags and not remove_tags:
         return None
     normalized = {tag.strip() for tag in existing or [] if tag.strip()}
@@ -169,8 +212,15 @@ def _has_update_candidates(descriptor: UpdateDescriptor) -> bool:
     )
 
 
+def _check_estimate(x_properties: Mapping[str, str]) -> None:
+    raw = x_properties.get("X-ESTIMATE")
+    if raw and not raw.isdigit():
+        _exit_with_message(f"invalid estimate {raw!r}: expected whole minutes, e.g. estimate:30")
+
+
 def _build_payload(descriptor: UpdateDescriptor) -> TaskPayload:
     add = descriptor.add_data
+    _check_estimate(add.x_properties)
     summary = add.summary
     due = _resolve_due_value(add.due)
     wait = _resolve_due_value(add.wait)
@@ -199,6 +249,7 @@ def _build_patch_from_descriptor(
     descriptor: UpdateDescriptor, existing: Task | None
 ) -> TaskPatch:
     add = descriptor.add_data
+    _check_estimate(add.x_properties)
     # Handle empty string as "unset" using sentinel datetime
     if add.due == "":
         due = _UNSET_DATETIME
@@ -243,9 +294,24 @@ async def _sorted_tasks(client: "CalDAVClient") -> list[Task]:
     return sorted(await client.list_tasks(), key=_task_sort_key)
 
 
-def _task_sort_key(task: Task) -> tuple[datetime, int, str]:
+def _priority_rank(priority: int | None) -> int:
+    """Sort rank of a CalDAV priority: 1 is most urgent, 9 least.
+
+    0 means undefined in CalDAV, so it ranks with an unset priority after 9
+    rather than ahead of 1.
+    """
+    return priority if priority else 10
+
+
+def _task_sort_key(task: Task, tasks_by_uid: Mapping[str, Task] | None = None) -> tuple[datetime, int, str]:
+    """Sort by due, priority, then summary.
+
+    When tasks_by_uid is given, subtasks without a priority inherit their
+    parent's (see Task.effective_priority).
+    """
     due_key = task.data.due or datetime.max
-    priority_key = task.data.priority if task.data.priority is not None else 10
+    priority = task.effective_priority(tasks_by_uid) if tasks_by_uid is not None else task.data.priority
+    priority_key = _priority_rank(priority)
     summary_key = task.data.summary.strip().lower() if task.data.summary else ""
     return due_key, priority_key, summary_key
 
@@ -267,10 +333,30 @@ def _format_due_label(due: datetime | None, now: datetime) -> str:
     return f"{sign}0m"
 
 
+def _format_relative_due(due: datetime | None, now: datetime) -> str | None:
+    """Describe due relative to now, e.g. "in 2 days" or "3 hours ago".
+
+    Granularity is the largest whole unit of minutes, hours, or days.
+    Returns None when there is no due date.
+    """
+    if due is None:
+        return None
+    seconds = (due - now).total_seconds()
+    magnitude = int(abs(seconds))
+    if magnitude < 60:
+        return "now"
+    for unit, size in (("day", 86400), ("hour", 3600), ("minute", 60)):
+        if magnitude >= size:
+            count = magnitude // size
+            break
+    label = f"{count} {unit}{'' if count == 1 else 's'}"
+    return f"in {label}" if seconds > 0 else f"{label} ago"
+
+
 SUMMARY_WIDTH = 45
 
 
-from dataclasses import dataclass
+from dataclasses import dataclass, replace
 
 
 @dataclass(frozen=True)
@@ -292,6 +378,9 @@ _BASE_COLUMN_SPECS = [
     ColumnSpec("Urg", "bright_red", "right", 4),
 ]
 _UID_COLUMN_SPEC = ColumnSpec("UID", "dim", "left", 36)
+_WHEN_COLUMN_SPEC = ColumnSpec("When", "bright_green", "left", 14)
+_ENV_COLUMN_SPEC = ColumnSpec("Env", "green", "left", 12)
+_STATE_COLUMN_SPEC = ColumnSpec("State", "bold", "left", 9)
 
 
 def _truncate_value(value: str, max_width: int, ellipsize: bool = False) -> str:
@@ -303,14 +392,14 @@ def _truncate_value(value: str, max_width: int, ellipsize: bool = False) -> str:
 
 
 def _format_project(task: Task) -> str:
-    project = task.data.x_properties.get("X-PROJECT") or task.data.x_properties.get("X-TASKS-ORG-ORDER")
+    project = task.data.project or task.data.x_property("X-TASKS-ORG-ORDER")
     return project or "-"
 
 
 def _format_tag(task: Task) -> str:
     if task.data.categories:
         return ",".join(task.data.categories)
-    tag = task.data.x_properties.get("X-TAG") or task.data.x_properties.get("X-COLOR")
+    tag = task.data.x_property("X-TAG") or task.data.x_property("X-COLOR")
     return tag or "-"
 
 
@@ -321,8 +410,21 @@ def _format_due_date(due: datetime | None) -> str:
 
 
 def _pretty_print_tasks(
-    tasks: list[Task], show_uids: bool, *, title: str | None = None, reverse: bool = False
+    tasks: list[Task],
+    show_uids: bool,
+    *,
+    title: str | None = None,
+    reverse: bool = False,
+    show_envs: bool = False,
+    tasks_by_uid: Mapping[str, Task] | None = None,
+    with_relative: bool = False,
+    states: Mapping[str, str] | None = None,
 ) -> None:
+    """Print tasks as a table, sorted by due date and priority.
+
+    With states (uid -> state), tasks keep the order they were given in and
+    a State column follows ID.
+    """
     console = Console(file=sys.stdout, color_system="auto")
     table = Table(
         title=title,
@@ -334,12 +436,22 @@ def _pretty_print_tasks(
         padding=(0, 1),
     )
     column_specs = list(_BASE_COLUMN_SPECS)
+    if states is not None:
+        column_specs.insert(1, _STATE_COLUMN_SPEC)
+    if show_envs:
+        column_specs.insert(1, _ENV_COLUMN_SPEC)
+    if with_relative:
+        due_position = [spec.name for spec in column_specs].index("Due")
+        column_specs.insert(due_position + 1, _WHEN_COLUMN_SPEC)
     if show_uids:
         column_specs.append(_UID_COLUMN_SPEC)
     column_lengths: dict[str, int] = {spec.name: len(spec.name) for spec in column_specs}
     rows: list[list[str]] = []
     now = datetime.now()
-    sorted_tasks = sorted(tasks, key=_task_sort_key, reverse=reverse)
+    if states is not None:
+        sorted_tasks = list(tasks)
+    else:
+        sorted_tasks = sorted(tasks, key=lambda task: _task_sort_key(task, tasks_by_uid), reverse=reverse)
     for task in sorted_tasks:
         due_label = _format_due_label(task.data.due, now)
         project = _format_project(task)
@@ -347,6 +459,10 @@ def _pretty_print_tasks(
         due_date = _format_due_date(task.data.due)
         summary = task.data.summary or ""
         priority_label = str(task.data.priority) if task.data.priority is not None else "-"
+        if task.data.priority is None and tasks_by_uid is not None:
+            inherited = task.effective_priority(tasks_by_uid)
+            if inherited is not None:
+                priority_label = f"({inherited})"
         # Use stable task_index for ID column
         id_label = str(task.task_index) if task.task_index is not None else "?"
         values: dict[str, str] = {
@@ -358,6 +474,12 @@ def _pretty_print_tasks(
             "Description": summary,
             "Urg": priority_label,
         }
+        if states is not None:
+            values["State"] = states.get(task.uid, "-")
+        if show_envs:
+            values["Env"] = task.env or "-"
+        if with_relative:
+            values["When"] = _format_relative_due(task.data.due, now) or "-"
         if show_uids:
             values["UID"] = task.uid
         row: list[str] = []
@@ -381,22 +503,117 @@ def _pretty_print_tasks(
     console.print(table)
 
 
-_COMMAND_NAMES = {"add", "complete", "config", "del", "do", "list", "modify", "move", "prioritize", "pull", "push", "show", "start", "stop", "sync", "undo"}
+_COMMAND_NAMES = {"add", "agenda", "archive", "bump", "complete", "completed", "config", "del", "do", "events", "export", "forget", "import", "info", "list", "lower", "merge", "modify", "move", "next", "prioritize", "pull", "push", "recompute-dates", "reopen", "repair", "search", "show", "stale", "start", "stats", "stop", "subtask", "swap", "sync", "tag-tree", "tree", "undo", "validate", "workload"}
+
+
+# Index selector grammar (comma-separated in one filter token):
+#   N   the task with stable index N (N >= 1)
+#   -N  the Nth-highest active index; -1 is the highest
+#   $   alias for -1
+#   ^   the lowest active index
+#   N-M every index from N to M, inclusive
+_INDEX_SELECTOR_RE = re.compile(r"[1-9]\d*|-[1-9]\d*|\$|\^")
+_INDEX_RANGE_RE = re.compile(r"([1-9]\d*)-([1-9]\d*)")
+_INDEX_SELECTOR_CHARS = re.compile(r"[\d$^-]+")
+# Most indices one command line may select; each becomes an SQL parameter,
+# and SQLite caps those at 999 in older builds
+_MAX_SELECTED_INDICES = 500
+
+
+def _index_segments(value: str) -> list[str]:
+    return [segment for segment in (part.strip() for part in value.split(",")) if segment]
 
 
 def _looks_like_index_filter(value: str) -> bool:
-    """Check if value looks like numeric indices (e.g., '1,2,3')."""
+    """Check if value looks like index selectors (e.g., '1,2,3', '2-6', '-1', '$', '^').
+
+    Malformed selectors such as '-0' still count, so they can be rejected
+    with a clear message instead of being taken for a command.
+    """
     if not value:
         return False
-    segments = [segment.strip() for segment in value.split(",")]
-    normalized = [segment for segment in segments if segment]
+    normalized = _index_segments(value)
     if not normalized:
         return False
-    return all(segment.isdigit() for segment in normalized)
+    return all(_INDEX_SELECTOR_CHARS.fullmatch(segment) for segment in normalized)
+
+
+def _is_relative_selector(segment: str) -> bool:
+    return segment in ("$", "^") or segment.startswith("-")
+
+
+def _expand_index_range(segment: str) -> list[str] | None:
+    """Indices covered by an N-M range segment, or None if it is not a range."""
+    match = _INDEX_RANGE_RE.fullmatch(segment)
+    if match is None:
+        return None
+    start, end = int(match.group(1)), int(match.group(2))
+    if start > end:
+        _exit_with_message(f"invalid index range: {segment} (start is after end)")
+    if end - start + 1 > _MAX_SELECTED_INDICES:
+        _exit_with_message(
+            f"index range {segment} covers {end - start + 1} indices; at most {_MAX_SELECTED_INDICES} can be selected"
+        )
+    return [str(index) for index in range(start, end + 1)]
+
+
+async def _resolve_index_selectors(tokens: list[str], env: str | None) -> list[str]:
+    """Rewrite ranges (N-M) and relative selectors ($, ^, -N) in filter tokens to concrete indices.
+
+    Runs before the filter is parsed, so every command sees plain indices.
+    Malformed selectors exit with an error.
+    """
+    relative = False
+    for token in tokens:
+        if not _looks_like_index_filter(token):
+            continue
+        for segment in _index_segments(token):
+            if _INDEX_RANGE_RE.fullmatch(segment):
+                continue
+            if not _INDEX_SELECTOR_RE.fullmatch(segment):
+                _exit_with_message(f"invalid index selector: {segment} (expected N, -N, N-M, $ or ^)")
+            relative = relative or _is_relative_selector(segment)
+
+    active: list[int] = []
+    if relative:
+        client = await _cache_client(env)
+        try:
+            active = sorted(task.task_index for task in await client.list_tasks() if task.task_index is not None)
+        finally:
+            await client.close()
+
+    def resolve(segment: str) -> list[str]:
+        expanded = _expand_index_range(segment)
+        if expanded is not None:
+            return expanded
+        if not _is_relative_selector(segment):
+            return [segment]
+        if not active:
+            _exit_with_message(f"index selector {segment} did not match any task")
+        if segment == "^":
+            return [str(active[0])]
+        offset = 1 if segment == "$" else int(segment[1:])
+        if offset > len(active):
+            _exit_with_message(f"index selector {segment} is out of range ({len(active)} active tasks)")
+        return [str(active[-offset])]
+
+    resolved = [
+        ",".join(index for segment in _index_segments(token) for index in resolve(segment))
+        if _looks_like_index_filter(token)
+        else token
+        for token in tokens
+    ]
+    selected = sum(len(_index_segments(token)) for token in resolved if _looks_like_index_filter(token))
+    if selected > _MAX_SELECTED_INDICES:
+        _exit_with_message(f"{selected} indices selected; at most {_MAX_SELECTED_INDICES} can be selected")
+    return resolved
+
+
+_AGE_FILTER_PREFIXES = ("created.before:", "created.after:", "updated.before:", "updated.after:")
 
 
 def _looks_like_metadata_filter(value: str) -> bool:
-    """Check if value looks like a metadata filter (project:X, +tag)."""
+    """Check if value looks like a metadata filter (project:X, +tag, status:X, due.before:X, tag.not:X, ...)."""
     if not value:
         return False
     # +tag or -tag (for filtering)
@@ -405,6 +622,30 @@ def _looks_like_metadata_filter(value: str) -> bool:
     # project:value
     if value.startswith("project:"):
         return True
+    # status:value
+    if value.startswith("status:") and len(value) > len("status:"):
+        return True
+    # custom_status:value
+    if value.startswith("custom_status:") and len(value) > len("custom_status:"):
+        return True
+    # due.before:value
+    if value.startswith("due.before:") and len(value) > len("due.before:"):
+        return True
+    # due.within:days and its due.align:day modifier
+    if value.startswith(("due.within:", "due.align:")) and not value.endswith(":"):
+        return True
+    # created.before:value, updated.after:value, ...
+    if value.startswith(_AGE_FILTER_PREFIXES) and not value.endswith(":"):
+        return True
+    # tags.mode:any or tags.mode:all, tags.match:exact or tags.match:nested
+    if value.startswith(("tags.mode:", "tags.match:")):
+        return True
+    # tag.not:value or project.not:value
+    if value.startswith(("tag.not:", "project.not:")) and not value.endswith(":"):
+        return True
+    # has:url, has.not:attachments, ...
+    if value.startswith(("has:", "has.not:")):
+        return True
     return False
 
 
@@ -417,9 +658,17 @@ def _split_filter_and_command(argv: Sequence[str]) -> tuple[list[str], list[str]
     """Split argv into filter tokens and command tokens.
 
     Returns (filter_tokens, command_tokens) where filter_tokens can include:
-    - Numeric indices: "1,2,3"
+    - Index selectors: "1,2,3", "-1", "$", "^"
     - Project filter: "project:tdo"
     - Tag filter: "+easy"
+    - Status filter: "status:IN-PROCESS"
+    - Custom status filter: "custom_status:waiting-on-reply"
+    - Due filter: "due.before:now"
+    - Due window: "due.within:3", "due.align:day"
+    - Age filters: "updated.before:90d", "created.after:2025-01-01"
+    - Tag matching: "tags.mode:any", "tags.match:exact"
+    - Exclusions: "tag.not:waiting", "project.not:home"
+    - Presence: "has:url", "has.not:attachments"
     """
     candidates = list(argv)
     if not candidates:
@@ -431,6 +680,12 @@ def _split_filter_and_command(argv: Sequence[str]) -> tuple[list[str], list[str]
         if candidates[idx] == "--env" and idx + 1 < len(candidates):
             idx += 2  # Skip --env and its value
             continue
+        if candidates[idx] == "--pretty":
+            idx += 1
+            continue
+        if candidates[idx] == "--timestamps" and idx + 1 < len(candidates):
+            idx += 2  # Skip --timestamps and its value
+            continue
         break
 
     remaining = candidates[idx:]
@@ -464,11 +719,67 @@ def _parse_task_filter(tokens: list[str]) -> TaskFilter | None:
 
     project: str | None = None
     tags: list[str] = []
+    tags_mode = "all"
+    tags_match = "nested"
     indices: list[int] = []
+    status: str | None = None
+    custom_status: str | None = None
+    due_before: datetime | None = None
+    due_within_days: int | None = None
+    align_to_day = False
+    exclude_tags: list[str] = []
+    exclude_projects: list[str] = []
+    has: dict[str, bool] = {}
+    ages: dict[str, datetime] = {}
 
     for token in tokens:
         if token.startswith("project:"):
             project = token[8:]  # len("project:") = 8
+        elif token.startswith("status:"):
+            status = token[7:].strip().upper() or None
+        elif token.startswith("custom_status:"):
+            custom_status = token[len("custom_status:"):].strip() or None
+        elif token.startswith("due.before:"):
+            raw = token[len("due.before:"):]
+            due_before = _resolve_due_value(raw)
+            if due_before is None:
+                _exit_with_message(f"invalid due.before value: {raw}")
+        elif token.startswith("due.within:"):
+            raw = token[len("due.within:"):]
+            days = raw.strip().lower().removesuffix("d")
+            if not days.isdigit():
+                _exit_with_message(f"invalid due.within value: {raw} (expected whole days, e.g. due.within:3)")
+            due_within_days = int(days)
+        elif token.startswith("due.align:"):
+            raw = token[len("due.align:"):]
+            if raw.strip().lower() != "day":
+                _exit_with_message(f"invalid due.align value: {raw} (expected day)")
+            align_to_day = True
+        elif token.startswith(_AGE_FILTER_PREFIXES):
+            name, raw = token.split(":", 1)
+            bound = _resolve_age_value(raw)
+            if bound is None:
+                _exit_with_message(f"invalid {name} value: {raw}")
+            ages[name] = bound
+        elif token.startswith("tag.not:"):
+            exclude_tags.append(token[len("tag.not:"):])
+        elif token.startswith("project.not:"):
+            exclude_projects.append(token[len("project.not:"):])
+        elif token.startswith(("has:", "has.not:")):
+            kind, field_name = token.split(":", 1)
+            if field_name not in ("url", "attachments"):
+                _exit_with_message(f"invalid {kind} value: {field_name} (expected url or attachments)")
+            has[field_name] = kind == "has"
+        elif token.startswith("tags.mode:"):
+            tags_mode = token[len("tags.mode:"):].strip().lower()
+            if tags_mode not in ("any", "all"):
+                _exit_with_message(f"invalid tags.mode value: {token[len('tags.mode:'):]} (expected any or all)")
+        elif token.startswith("tags.match:"):
+            tags_match = token[len("tags.match:"):].strip().lower()
+            if tags_match not in ("exact", "nested"):
+                _exit_with_message(
+                    f"invalid tags.match value: {token[len('tags.match:'):]} (expected exact or nested)"
+                )
         elif token.startswith("+") and len(token) > 1:
             tags.append(token[1:])
         elif _looks_like_index_filter(token):
@@ -477,10 +788,30 @@ def _parse_task_filter(tokens: list[str]) -> TaskFilter | None:
                 if segment.isdigit():
                     indices.append(int(segment))
 
-    if not project and not tags and not indices:
+    task_filter = TaskFilter(
+        project=project,
+        tags=tags,
+        tags_mode=tags_mode,
+        tags_match=tags_match,
+        indices=indices,
+        status=status,
+        custom_status=custom_status,
+        due_before=due_before,
+        due_within_days=due_within_days,
+        align_to_day=align_to_day,
+        exclude_tags=exclude_tags,
+        exclude_projects=exclude_projects,
+        has_url=has.get("url"),
+        has_attachments=has.get("attachments"),
+        created_before=ages.get("created.before"),
+        created_after=ages.get("created.after"),
+        updated_before=ages.get("updated.before"),
+        updated_after=ages.get("updated.after"),
+    )
+    if not task_filter.has_metadata and not indices:
         return None
+    return task_filter
 
-    return TaskFilter(project=project, tags=tags, indices=indices)
 
 
 def _parse_filter_indices(raw: str | None) -> list[str] | None:
@@ -517,6 +848,73 @@ def _select_tasks_for_filter(tasks: list[Task], indices: list[str]) -> list[Task
     return selected
 
 
+async def _select_tasks(client: "CalDAVClient", args: argparse.Namespace) -> list[Task]:
+    """Resolve filter tokens (indices and/or project/tag/status/due) to tasks.
+
+    The result is a snapshot taken before any mutation, so callers can
+    complete or modify tasks without indices shifting underneath them.
+    """
+    task_filter: TaskFilter | None = getattr(args, "task_filter", None)
+    if task_filter is None or not task_filter.has_metadata:
+        return _select_tasks_for_filter(
+            await _sorted_tasks(client),
+            _effective_filter_indices(args.filter_indices),
+        )
+    matches = sorted(await client.list_tasks_filtered(task_filter), key=_task_sort_key)
+    if task_filter.indices:
+        # Keep the order the indices were given in
+        position = {index: pos for pos, index in reversed(list(enumerate(task_filter.indices)))}
+        matches.sort(key=lambda task: position.get(task.task_index, len(position)))
+    seen: set[str] = set()
+    selected: list[Task] = []
+    for task in matches:
+        if task.uid in seen:
+            continue
+        seen.add(task.uid)
+        selected.append(task)
+    return selected
+
+
+async def _query_envs(
+    args: argparse.Namespace,
+    fetch: Callable[["CalDAVClient"], Awaitable[T]],
+) -> list[tuple[str | None, T]]:
+    """Run a query in the selected env, or in every env with --all-envs."""
+    if not getattr(args, "all_envs", False):
+        envs: list[str | None] = [args.env]
+    else:
+        task_filter: TaskFilter | None = getattr(args, "task_filter", None)
+        if task_filter and task_filter.indices:
+            _exit_with_message("index filters cannot be combined with --all-envs")
+        envs = list(list_environments())
+    results: list[tuple[str | None, T]] = []
+    for env in envs:
+        client = await _cache_client(env)
+        try:
+            results.append((env, await fetch(client)))
+        finally:
+            await client.close()
+    return results
+
+
+async def _collect_tasks(
+    args: argparse.Namespace,
+    fetch: Callable[["CalDAVClient"], Awaitable[list[Task]]],
+) -> list[Task]:
+    """Like _query_envs, but merges task lists.
+
+    With --all-envs each task is tagged with its env, since indices are only
+    unique within one environment.
+    """
+    all_envs = getattr(args, "all_envs", False)
+    merged: list[Task] = []
+    for env, tasks in await _query_envs(args, fetch):
+        if all_envs:
+            tasks = [replace(task, env=env) for task in tasks]
+        merged.extend(tasks)
+    return merged
+
+
 def _is_task_completed(task: Task) -> bool:
     status = (task.data.status or "").strip().upper()
     return status in {"COMPLETED", "DONE"}
@@ -539,138 +937,444 @@ def _normalize_tokens(tokens: Sequence[str] | None) -> list[str]:
 
 
 
+def _format_check_date(value: datetime) -> str:
+    return value.strftime("%Y-%m-%d %H:%M")
+
+
+def _check_due_and_wait(due: datetime | None, wait: datetime | None, *, label: str = "") -> None:
+    """Reject a wait date that falls after the due date."""
+    if due is None or wait is None:
+        return
+    # timestamp() compares naive (local) and aware datetimes alike
+    if wait.timestamp() > due.timestamp():
+        _exit_with_message(
+            f"{label}wait {_format_check_date(wait)} is after due {_format_check_date(due)}; "
+            "pass --force to keep it"
+        )
+
+
+def _check_new_task_dates(payload: TaskPayload, max_past_due_days: int) -> None:
+    """Guard rails for a task about to be created; skipped by --force."""
+    _check_due_and_wait(payload.due, payload.wait)
+    if payload.due is None or max_past_due_days <= 0:
+        return
+    cutoff = datetime.now() - timedelta(days=max_past_due_days)
+    if payload.due.timestamp() < cutoff.timestamp():
+        _exit_with_message(
+            f"due {_format_check_date(payload.due)} is more than {max_past_due_days} days in the past; "
+            "pass --force to keep it"
+        )
+
+
+def _check_taxonomy(
+    validation: ValidationConfig, tags: Sequence[str], project: str | None, *, label: str = ""
+) -> None:
+    """Reject tags or a project missing from the env's allow-lists; an empty list allows any."""
+    if validation.allowed_tags:
+        for tag in tags:
+            if tag not in validation.allowed_tags:
+                _exit_with_message(
+                    f"{label}tag {tag!r} is not allowed; allowed tags: {', '.join(validation.allowed_tags)}"
+                )
+    if project and validation.allowed_projects and project not in validation.allowed_projects:
+        _exit_with_message(
+            f"{label}project {project!r} is not allowed; "
+            f"allowed projects: {', '.join(validation.allowed_projects)}"
+        )
+
+
+def _parse_backdate(raw: str | float | None, *, force: bool, label: str = "--at") -> float | None:
+    """Timestamp for an --at value; a time in the future needs --force.
+
+    Imported rows may give epoch seconds instead of a date string.
+    """
+    if raw is None:
+        return None
+    if isinstance(raw, (int, float)) and not isinstance(raw, bool):
+        at = float(raw)
+    else:
+        resolved = parse_due_value(str(raw))
+        if resolved is None:
+            _exit_with_message(f"invalid {label} value: {raw!r}")
+        at = resolved.timestamp()
+    if at > datetime.now().timestamp() and not force:
+        _exit_with_message(
+            f"{label} {_format_check_date(datetime.fromtimestamp(at))} is in the future; pass --force to keep it"
+        )
+    return at
+
+
+def _patched_date(value: datetime | None, existing: datetime | None) -> datetime | None:
+    if value == _UNSET_DATETIME:
+        return None
+    return value if value is not None else existing
+
+
+async def _create_and_show(
+    client: "CalDAVClient", payload: TaskPayload, *, force: bool = False, index: int | None = None
+) -> Task:
+    """Create a task and print the diff; the client logs it as an "add"."""
+    _check_taxonomy(client.config.validation, payload.tags, payload.project)
+    if not force:
+        _check_new_task_dates(payload, client.config.validation.max_past_due_days)
+    created = await client.create_task(payload, index=index)
+    diff: TaskSetDiff[int] = TaskSetDiff(
+        diffs={created.task_index: TaskDiff(pre=None, post=created.data)}
+    )
+    print(diff.pretty())
+    return created
+
+
+def _route_target(config: CaldavConfig, payload: TaskPayload) -> str | None:
+    """Env that config's routing rules send payload to, if any.
+
+    A project rule wins over tag rules. Tags that route to different envs
+    are ambiguous and rejected.
+    """
+    project = payload.project
+    if project and project in config.routing.projects:
+        return config.routing.projects[project]
+    targets = {config.routing.tags[tag] for tag in payload.tags if tag in config.routing.tags}
+    if len(targets) > 1:
+        _exit_with_message(f"tags route to different environments: {', '.join(sorted(targets))}")
+    return targets.pop() if targets else None
+
+
+def _route_env(env: str | None, payload: TaskPayload) -> str | None:
+    """Follow routing rules from env to the env the task should be created in.
+
+    Each hop uses the rules of the env it arrived at. Returns env unchanged
+    when no rule applies; a chain that revisits an env is an error.
+    """
+    config = _resolve_config(env)
+    visited = [config.env]
+    while True:
+        target = _route_target(config, payload)
+        if target is None or target == config.env:
+            return env if len(visited) == 1 else config.env
+        if target in visited:
+            _exit_with_message(f"routing rules loop: {' -> '.join([*visited, target])}")
+        visited.append(target)
+        config = _resolve_config(target)
+
+
+def _add_payload(args: argparse.Namespace) -> TaskPayload:
+    """The task `add` creates, checked before any env is opened."""
+    tokens = _normalize_tokens(args.tokens)
+    descriptor = _parse_update_descriptor(tokens)
+    payload = _build_payload(descriptor)
+    if args.start:
+        if descriptor.add_data.status not in (None, "IN-PROCESS"):
+            _exit_with_message(f"add --start conflicts with status:{descriptor.add_data.status}")
+        # Insert already started, so the log holds one "add" and never a NEEDS-ACTION state
+        x_properties = {**payload.x_properties, "X-STARTED-AT": str(int(datetime.now().timestamp()))}
+        payload = replace(payload, status="IN-PROCESS", x_properties=x_properties)
+    if args.index is not None and args.index < 1:
+        _exit_with_message("--index must be positive")
+    return payload
+
+
 async def _handle_add(args: argparse.Namespace) -> None:
+    payload = _add_payload(args)
+    env = _route_env(args.env, payload)
+    client = await _cache_client(env)
+    try:
+        index = args.index
+        if index is not None and not await client.index_available(index):
+            if args.index_taken == "error":
+                _exit_with_message(f"index {index} is already taken; pass --index-taken next to use the next free one")
+            index = None
+        await _create_and_show(client, payload, force=args.force, index=index)
+        if env != args.env:
+            print(f"Routed to env: {env}")
+    finally:
+        await client.close()
+
+
+async def _handle_subtask(args: argparse.Namespace) -> None:
+    """Create a task nested under the single task selected by the filter."""
     tokens = _normalize_tokens(args.tokens)
     descriptor = _parse_update_descriptor(tokens)
     payload = _build_payload(descriptor)
+    if not args.filter_indices:
+        _exit_with_message("subtask requires a parent task index")
     client = await _cache_client(args.env)
     try:
-        created = await client.create_task(payload)
-        diff: TaskSetDiff[int] = TaskSetDiff(
-            diffs={created.task_index: TaskDiff(pre=None, post=created.data)}
+        parents = _select_tasks_for_filter(
+            await _sorted_tasks(client),
+            _effective_filter_indices(args.filter_indices),
         )
-        print(diff.pretty())
-
-        # Log transaction
-        if not diff.is_empty and client.cache:
-            uid_diff = diff.to_uid_keyed(lambda idx: created.uid if idx == created.task_index else str(idx))
-            await client.cache.log_transaction(
-                uid_diff,
-                operation="add",
-                max_entries=client.config.cache.transaction_log_size,
-            )
+        if len(parents) != 1:
+            _exit_with_message("subtask requires exactly one parent task")
+        payload.parent = parents[0].uid
+        await _create_and_show(client, payload, force=args.force)
     finally:
         await client.close()
 
 
+def _append_to_summary(summary: str | None, text: str, separator: str) -> str:
+    """summary followed by separator and text; just text when summary is empty."""
+    return f"{summary}{separator}{text}" if summary else text
+
+
 async def _handle_modify(args: argparse.Namespace) -> None:
     tokens = _normalize_tokens(args.tokens)
     descriptor = _parse_update_descriptor(tokens)
-    if not _has_update_candidates(descriptor):
+    if args.append is not None and not args.append.strip():
+        _exit_with_message("--append requires text")
+    if not _has_update_candidates(descriptor) and args.append is None:
         _exit_with_message("no changes provided")
     client = await _cache_client(args.env)
     try:
-        tasks = _select_tasks_for_filter(
-            await _sorted_tasks(client),
-            _effective_filter_indices(args.filter_indices),
-        )
+        # Metadata filters (+tag, project:, status:) select tasks just as indices do
+        tasks = await _select_tasks(client, args)
         if not tasks:
             _exit_with_message("no tasks match filter")
+        patches = [(task, _build_patch_from_descriptor(descriptor, task)) for task in tasks]
+        if descriptor.description and client.config.modify_words == "append":
+            for task, patch in patches:
+                patch.summary = _append_to_summary(task.data.summary, descriptor.description, args.append_separator)
+        if args.append is not None:
+            # Append after any summary: replacement, so both compose
+            for task, patch in patches:
+                patch.summary = _append_to_summary(
+                    patch.summary or task.data.summary, args.append, args.append_separator
+                )
+        # Check every task before changing any of them; only values the
+        # patch introduces count, so legacy tags don't block other edits
+        for task, patch in patches:
+            _check_taxonomy(
+                client.config.validation,
+                [tag for tag in patch.tags if tag not in task.data.tags],
+                patch.project if patch.project != task.data.project else None,
+                label=f"task {task.task_index}: ",
+            )
+        if not args.force:
+            for task, patch in patches:
+                _check_due_and_wait(
+                    _patched_date(patch.due, task.data.due),
+                    _patched_date(patch.wait, task.data.wait),
+                    label=f"task {task.task_index}: ",
+                )
         diffs: dict[int, TaskDiff] = {}
-        index_to_uid: dict[int, str] = {}
-        for task in tasks:
-            patch = _build_patch_from_descriptor(descriptor, task)
-            if not _has_changes(patch):
-                continue
-            updated = await client.modify_task(task, patch)
-            diffs[task.task_index] = TaskDiff(pre=task.data, post=updated.data)
-            index_to_uid[task.task_index] = task.uid
+        unchanged = 0
+        async with client.logged("modify"):
+            for task, patch in patches:
+                if not _has_changes(patch):
+                    continue
+                updated, changed = await client.modify_task_if_changed(task, patch)
+                if not changed:
+                    unchanged += 1
+                    continue
+                diffs[task.task_index] = TaskDiff(pre=task.data, post=updated.data)
         if not diffs:
+            if unchanged:
+                print(f"{unchanged} task(s) already up to date; nothing to modify")
+                return
             _exit_with_message("no changes provided")
         result: TaskSetDiff[int] = TaskSetDiff(diffs=diffs)
         print(result.pretty())
-
-        # Log transaction
-        if not result.is_empty and client.cache:
-            uid_diff = result.to_uid_keyed(lambda idx: index_to_uid.get(idx, str(idx)))
-            await client.cache.log_transaction(
-                uid_diff,
-                operation="modify",
-                max_entries=client.config.cache.transaction_log_size,
-            )
     finally:
         await client.close()
 
 
+def _time_tracking_properties(task: Task, status: str, now: datetime) -> dict[str, str]:
+    """X-property updates for moving task to status.
+
+    Entering IN-PROCESS stamps X-STARTED-AT unless an interval is already
+    open, so a repeated start does not restart the clock. Leaving it adds
+    the elapsed seconds to X-TIMESPENT and clears the stamp.
+    """
+    if status == "IN-PROCESS":
+        if task.data.started_at is not None:
+            return {}
+        return {"X-STARTED-AT": str(int(now.timestamp()))}
+    if task.data.started_at is None:
+        return {}
+    return {"X-TIMESPENT": str(task.time_spent(now)), "X-STARTED-AT": ""}
+
+
+def _freed_indices_line(tasks: Sequence[Task]) -> str | None:
+    """Name the indices tasks leaving the active list give up, so callers can predict the next add."""
+    freed = sorted({task.task_index for task in tasks if task.task_index is not None})
+    if not freed:
+        return None
+    return f"Freed indices: {', '.join(str(index) for index in freed)}"
+
+
+def _check_batch_size(count: int, limit: int, confirm: bool) -> None:
+    """Refuse to change more than limit tasks at once unless confirmed.
+
+    A single task is never gated, and a limit of 0 disables the check.
+    """
+    if confirm or count <= 1 or limit <= 0 or count <= limit:
+        return
+    _exit_with_message(f"too many tasks ({count}, limit {limit}); pass --confirm to change them all")
+
+
+def _current_task(tasks: Sequence[Task]) -> Task:
+    """The one IN-PROCESS task among tasks, for `do --current`."""
+    started = [task for task in tasks if task.data.status == "IN-PROCESS"]
+    if not started:
+        _exit_with_message("no task is in progress")
+    if len(started) > 1:
+        listed = ", ".join(f"[{task.task_index}]" for task in started)
+        _exit_with_message(f"{len(started)} tasks are in progress ({listed}); pass an index")
+    return started[0]
+
+
+def _open_children_notes(parents: Sequence[Task], children: Sequence[Task]) -> list[str]:
+    """One line per parent that still has open children, pointing at --cascade."""
+    counts = Counter(child.data.parent for child in children)
+    return [
+        f"[{parent.task_index}] {_truncate_summary(parent.data.summary or parent.uid, SUMMARY_WIDTH)} "
+        f"has {counts[parent.uid]} open subtask(s); pass --cascade to complete them too"
+        for parent in parents
+        if counts[parent.uid]
+    ]
+
+
+async def _split_completed_indices(
+    client: "CalDAVClient", indices: list[str]
+) -> tuple[list[str], list[Task], list[str]]:
+    """Split off indices no active task holds but a completed task still does.
+
+    Returns the remaining indices, the most recently completed task for each
+    index split off, and notes for remaining indices that a completed task
+    also held. Indices are reused, so an active task always takes the index;
+    the notes name the completed task so a retry that meant it shows up.
+    Unknown indices stay in the remainder.
+    """
+    active = {str(task.task_index) for task in await client.list_tasks()}
+    latest: dict[str, Task] = {}
+    # Newest first, so a reused index resolves to its latest completion
+    for task in await client.list_completed_tasks():
+        latest.setdefault(str(task.task_index), task)
+    remaining = [token for token in indices if token in active or token not in latest]
+    done = [latest[token] for token in indices if token not in active and token in latest]
+    reused = [
+        f"index {token} belongs to an active task, so that task was completed; "
+        f"completed task {latest[token].uid} ({_truncate_summary(latest[token].data.summary or '', SUMMARY_WIDTH)}) "
+        "held it before"
+        for token in indices
+        if token in active and token in latest
+    ]
+    return remaining, done, reused
+
+
 async def _handle_do(args: argparse.Namespace) -> None:
+    completed_at = _parse_backdate(args.at, force=args.force)
     client = await _cache_client(args.env)
     try:
-        tasks = _select_tasks_for_filter(
-            await _sorted_tasks(client),
-            _effective_filter_indices(args.filter_indices),
-        )
-        if not tasks:
+        already_completed: list[Task] = []
+        reused: list[str] = []
+        if args.allow_completed and args.filter_indices:
+            args.filter_indices, already_completed, reused = await _split_completed_indices(
+                client, args.filter_indices
+            )
+            if args.task_filter is not None:
+                args.task_filter = replace(args.task_filter, indices=[int(i) for i in args.filter_indices])
+        # With every index already completed there is nothing left to select
+        tasks = [] if already_completed and not args.filter_indices else await _select_tasks(client, args)
+        if args.current and not args.filter_indices and not already_completed:
+            # Explicit indices win; a metadata filter only narrows the candidates
+            tasks = [_current_task(tasks)]
+        if not tasks and not already_completed:
             _exit_with_message("no tasks match filter")
+        selected = {task.uid for task in tasks}
+        all_tasks = await client.list_tasks()
+        open_children: list[str] = []
+        if args.cascade:
+            tasks = tasks + _subtasks_of(selected, all_tasks, recursive=True)
+        else:
+            open_children = _open_children_notes(tasks, _subtasks_of(selected, all_tasks, recursive=False))
+            if open_children and client.config.open_children == "error":
+                _exit_with_message("\n".join(open_children))
+        _check_batch_size(len(tasks), client.config.validation.confirm_batch_size, args.confirm)
         diffs: dict[int, TaskDiff] = {}
-        index_to_uid: dict[int, str] = {}
-        for task in tasks:
-            # Use complete_task to move task to completed_tasks table
-            await client.complete_task(task.uid)
-            # Build diff with original data -> completed status
-            completed_data = TaskData(
-                summary=task.data.summary,
-                status="COMPLETED",
-                due=task.data.due,
-                wait=task.data.wait,
-                priority=task.data.priority,
-                x_properties=task.data.x_properties,
-                categories=task.data.categories,
-            )
-            diffs[task.task_index] = TaskDiff(pre=task.data, post=completed_data)
-            index_to_uid[task.task_index] = task.uid
-        result: TaskSetDiff[int] = TaskSetDiff(diffs=diffs)
-        print(result.pretty())
-
-        # Log transaction
-        if not result.is_empty and client.cache:
-            uid_diff = result.to_uid_keyed(lambda idx: index_to_uid.get(idx, str(idx)))
-            await client.cache.log_transaction(
-                uid_diff,
-                operation="do",
-                max_entries=client.config.cache.transaction_log_size,
-            )
+        now = datetime.now()
+        async with client.logged("do"):
+            for task in tasks:
+                # Close out a running interval before completing
+                tracked = task
+                timing = _time_tracking_properties(task, "COMPLETED", now)
+                if timing:
+                    tracked = await client.modify_task(task, TaskPatch(x_properties=timing))
+                # Use complete_task to move task to completed_tasks table
+                mode = await client.complete_task(task.uid, note=args.note, completed_at=completed_at)
+                if mode == "delete":
+                    diffs[task.task_index] = TaskDiff(pre=task.data, post=None)
+                    continue
+                # Build diff with original data -> completed status
+                completed_data = TaskData(
+                    summary=tracked.data.summary,
+                    status="COMPLETED",
+                    due=tracked.data.due,
+                    wait=tracked.data.wait,
+                    priority=tracked.data.priority,
+                    x_properties=tracked.data.x_properties,
+                    categories=tracked.data.categories,
+                )
+                diffs[task.task_index] = TaskDiff(pre=task.data, post=completed_data)
+        if diffs:
+            result: TaskSetDiff[int] = TaskSetDiff(diffs=diffs)
+            print(result.pretty())
+            freed = _freed_indices_line(tasks)
+            if freed:
+                print(freed)
+        for note in open_children:
+            print(note)
+        for note in reused:
+            print(f"Note: {note}")
+        if already_completed:
+            # Matched by index alone, so name each task by uid as well
+            print(f"Already completed ({len(already_completed)}; no active task holds these indices):")
+            for task in already_completed:
+                summary = _truncate_summary(task.data.summary or task.uid, SUMMARY_WIDTH)
+                print(f"  [{task.task_index}] {summary}  ({task.uid})")
     finally:
         await client.close()
 
 
 async def _change_status(args: argparse.Namespace, status: str, operation: str) -> None:
-    """Change task status and log the transaction."""
-    patch = TaskPatch(status=status)
+    """Change task status and track time spent in progress, logged as operation.
+
+    With single_active, starting a task first stops every other started
+    task in the same logged step, so one undo restores them all.
+    """
     client = await _cache_client(args.env)
     try:
-        tasks = _select_tasks_for_filter(
-            await _sorted_tasks(client),
-            _effective_filter_indices(args.filter_indices),
-        )
+        all_tasks = await _sorted_tasks(client)
+        tasks = _select_tasks_for_filter(all_tasks, _effective_filter_indices(args.filter_indices))
         if not tasks:
             _exit_with_message("no tasks match filter")
+        others: list[Task] = []
+        if status == "IN-PROCESS" and client.config.single_active:
+            if len(tasks) > 1 and not args.force:
+                _exit_with_message(
+                    f"single_active allows one started task; pass --force to start {len(tasks)} at once"
+                )
+            selected = {task.uid for task in tasks}
+            others = [task for task in all_tasks if task.data.status == "IN-PROCESS" and task.uid not in selected]
         diffs: dict[int, TaskDiff] = {}
-        index_to_uid: dict[int, str] = {}
-        for task in tasks:
-            updated = await client.modify_task(task, patch)
-            diffs[task.task_index] = TaskDiff(pre=task.data, post=updated.data)
-            index_to_uid[task.task_index] = task.uid
+        stopped: list[Task] = []
+        now = datetime.now()
+        async with client.logged(operation):
+            for task in others:
+                patch = TaskPatch(
+                    status="NEEDS-ACTION", x_properties=_time_tracking_properties(task, "NEEDS-ACTION", now)
+                )
+                stopped.append(await client.modify_task(task, patch))
+            for task in tasks:
+                patch = TaskPatch(status=status, x_properties=_time_tracking_properties(task, status, now))
+                updated = await client.modify_task(task, patch)
+                diffs[task.task_index] = TaskDiff(pre=task.data, post=updated.data)
         result: TaskSetDiff[int] = TaskSetDiff(diffs=diffs)
         print(result.pretty())
-
-        # Log transaction
-        if not result.is_empty and client.cache:
-            uid_diff = result.to_uid_keyed(lambda idx: index_to_uid.get(idx, str(idx)))
-            await client.cache.log_transaction(
-                uid_diff,
-                operation=operation,
-                max_entries=client.config.cache.transaction_log_size,
-            )
+        if stopped:
+            print(f"Stopped ({len(stopped)}):")
+            for task in stopped:
+                print(f"  ~ [{task.task_index}] {task.data.summary}")
     finally:
         await client.close()
 
@@ -685,128 +1389,1360 @@ async def _handle_stop(args: argparse.Namespace) -> None:
     await _change_status(args, "NEEDS-ACTION", "stop")
 
 
-async def _handle_delete(args: argparse.Namespace) -> None:
+def _shifted_priority(priority: int | None, step: int) -> int | None:
+    """Priority one CalDAV level more (step -1) or less (step +1) urgent, kept within 1-9.
+
+    0 and unset rank below 9 (see _priority_rank), so bumping them gives 9
+    and lowering leaves them as they are.
+    """
+    if not priority:
+        return 9 if step < 0 else priority
+    return min(9, max(1, priority + step))
+
+
+async def _shift_priority(args: argparse.Namespace, step: int, operation: str) -> None:
+    """Move each selected task's priority one level, logged as operation."""
     client = await _cache_client(args.env)
     try:
-        tasks = _select_tasks_for_filter(
-            await _sorted_tasks(client),
-            _effective_filter_indices(args.filter_indices),
-        )
+        tasks = await _select_tasks(client, args)
         if not tasks:
             _exit_with_message("no tasks match filter")
-        diffs: dict[int, TaskDiff] = {}
-        index_to_uid: dict[int, str] = {}
-        for task in tasks:
-            await client.delete_task(task.uid)
-            diffs[task.task_index] = TaskDiff(pre=task.data, post=None)
-            index_to_uid[task.task_index] = task.uid
-        result: TaskSetDiff[int] = TaskSetDiff(diffs=diffs)
-        print(result.pretty())
-
-        # Log transaction
-        if not result.is_empty and client.cache:
-            uid_diff = result.to_uid_keyed(lambda idx: index_to_uid.get(idx, str(idx)))
-            await client.cache.log_transaction(
-                uid_diff,
-                operation="delete",
-                max_entries=client.config.cache.transaction_log_size,
-            )
+        changed: list[tuple[Task, Task]] = []
+        unchanged = 0
+        async with client.logged(operation):
+            for task in tasks:
+                priority = _shifted_priority(task.data.priority, step)
+                if priority == task.data.priority:
+                    unchanged += 1
+                    continue
+                changed.append((task, await client.modify_task(task, TaskPatch(priority=priority))))
     finally:
         await client.close()
+    limit = "highest" if step < 0 else "lowest"
+    if not changed:
+        print(f"{unchanged} task(s) already at the {limit} priority; nothing to change")
+        return
+    print(f"{'Bumped' if step < 0 else 'Lowered'} ({len(changed)}):")
+    for before, after in changed:
+        old = before.data.priority if before.data.priority else "-"
+        summary = _truncate_summary(after.data.summary or after.uid, SUMMARY_WIDTH)
+        print(f"  [{after.task_index or '?'}] {summary}: {old} -> {after.data.priority}")
+    if unchanged:
+        print(f"{unchanged} task(s) already at the {limit} priority")
 
 
-async def _handle_list(args: argparse.Namespace) -> None:
-    config = _resolve_config(args.env)
-    client = await _cache_client(args.env)
-    try:
-        task_filter = getattr(args, "task_filter", None)
-        # Use SQL-based filtering that excludes waiting tasks
-        tasks = await client.list_active_tasks(
-            exclude_waiting=True,
-            task_filter=task_filter,
-        )
-        if not tasks:
-            if task_filter:
-                print("no tasks match filter")
-            else:
-                print("no cached tasks found; run 'tdo pull' to synchronize")
-            return
+async def _handle_bump(args: argparse.Namespace) -> None:
+    """Make tasks one priority level more urgent (priority 5 becomes 4)."""
+    await _shift_priority(args, -1, "bump")
 
-        # Filter out completed tasks (they're in a separate table, but just in case)
-        active_tasks = [t for t in tasks if t.data.status != "COMPLETED"]
-        if not active_tasks:
-            print("no tasks match filter")
-            return
 
-        # Split tasks by status: IN-PROCESS (started) and NEEDS-ACTION (backlog)
-        started = [t for t in active_tasks if t.data.status == "IN-PROCESS"]
-        backlog = [t for t in active_tasks if t.data.status == "NEEDS-ACTION"]
-        other = [t for t in active_tasks if t.data.status not in ("IN-PROCESS", "NEEDS-ACTION", "COMPLETED")]
+async def _handle_lower(args: argparse.Namespace) -> None:
+    """Make tasks one priority level less urgent (priority 4 becomes 5)."""
+    await _shift_priority(args, 1, "lower")
 
-        reverse = not getattr(args, "no_reverse", False)
 
-        # Display order: Backlog first, then Started (so Started appears at bottom)
-        if backlog:
-            _pretty_print_tasks(backlog, config.show_uids, title="Backlog", reverse=reverse)
-        if started:
-            if backlog:
-                print()  # Blank line between tables
-            _pretty_print_tasks(started, config.show_uids, title="Started", reverse=reverse)
-        # Handle tasks with other statuses (if any)
-        if other:
-            if started or backlog:
-                print()
-            _pretty_print_tasks(other, config.show_uids, title="Other", reverse=reverse)
+async def _handle_swap(args: argparse.Namespace) -> None:
+    """Exchange two tasks' indices, or their priorities with --field priority."""
+    client = await _cache_client(args.env)
+    try:
+        tasks = await _select_tasks(client, args)
+        if len(tasks) != 2:
+            _exit_with_message(f"swap needs exactly two tasks, got {len(tasks)}")
+        first, second = tasks
+        if first.uid == second.uid:
+            _exit_with_message(f"swap needs two different tasks, got [{first.task_index or '?'}] twice")
+        if args.field == "index":
+            try:
+                swapped = await client.swap_indices(first, second)
+            except KeyError as exc:
+                _exit_with_message(f"task {exc.args[0]} has no index to swap")
+        else:
+            # 0 clears the priority, so an unset one swaps across too
+            async with client.logged("swap-priority"):
+                swapped = (
+                    await client.modify_task(first, TaskPatch(priority=second.data.priority or 0)),
+                    await client.modify_task(second, TaskPatch(priority=first.data.priority or 0)),
+                )
     finally:
         await client.close()
+    print(f"Swapped {args.field}:")
+    for task in swapped:
+        summary = _truncate_summary(task.data.summary or task.uid, SUMMARY_WIDTH)
+        value = task.task_index if args.field == "index" else task.data.priority or "-"
+        print(f"  [{task.task_index or '?'}] {summary}: {args.field} {value}")
+
+
+def _subtasks_of(parent_uids: set[str], tasks: list[Task], *, recursive: bool) -> list[Task]:
+    """Return tasks whose parent is in parent_uids, optionally at any depth."""
+    found: list[Task] = []
+    seen = set(parent_uids)
+    frontier = set(parent_uids)
+    while frontier:
+        level = [task for task in tasks if task.data.parent in frontier and task.uid not in seen]
+        found.extend(level)
+        if not recursive:
+            break
+        frontier = {task.uid for task in level}
+        seen |= frontier
+    return found
 
 
-async def _handle_wait(args: argparse.Namespace) -> None:
-    """Show tasks with future wait dates."""
-    config = _resolve_config(args.env)
+async def _handle_delete(args: argparse.Namespace) -> None:
+    deleted_at = _parse_backdate(args.at, force=args.force)
     client = await _cache_client(args.env)
     try:
-        task_filter = getattr(args, "task_filter", None)
-        # Use SQL-based filtering for waiting tasks
-        waiting_tasks = await client.list_waiting_tasks(task_filter=task_filter)
-        if not waiting_tasks:
-            print("no waiting tasks")
-            return
-        _pretty_print_tasks(waiting_tasks, config.show_uids, title="Waiting")
+        all_tasks = await _sorted_tasks(client)
+        tasks = _select_tasks_for_filter(all_tasks, _effective_filter_indices(args.filter_indices))
+        if not tasks:
+            _exit_with_message("no tasks match filter")
+        mode = args.children or client.config.delete_children
+        subtasks = _subtasks_of({task.uid for task in tasks}, all_tasks, recursive=mode == "cascade")
+        diffs: dict[int, TaskDiff] = {}
+        if mode == "cascade":
+            tasks = tasks + subtasks
+        _check_batch_size(len(tasks), client.config.validation.confirm_batch_size, args.confirm)
+        async with client.logged("delete"):
+            if mode != "cascade":
+                # Detach direct children so they become top-level tasks
+                for child in subtasks:
+                    updated = await client.modify_task(child, TaskPatch(parent=""))
+                    diffs[child.task_index] = TaskDiff(pre=child.data, post=updated.data)
+            for task in tasks:
+                await client.delete_task(task.uid, deleted_at=deleted_at)
+                diffs[task.task_index] = TaskDiff(pre=task.data, post=None)
+        result: TaskSetDiff[int] = TaskSetDiff(diffs=diffs)
+        print(result.pretty())
+        freed = _freed_indices_line(tasks)
+        if freed:
+            print(freed)
     finally:
         await client.close()
 
 
-def _format_task_detail(task: Task) -> str:
-    lines = []
-    lines.append(f"ID:          {task.task_index or '?'}")
-    lines.append(f"Summary:     {task.data.summary}")
-    lines.append(f"Status:      {task.data.status}")
-    lines.append(f"Priority:    {task.data.priority if task.data.priority is not None else '-'}")
-    lines.append(f"Due:         {task.data.due.isoformat() if task.data.due else '-'}")
-    lines.append(f"Wait:        {task.data.wait.isoformat() if task.data.wait else '-'}")
+def _build_merge_patch(primary: Task, secondaries: list[Task], *, prefer_secondary: bool) -> TaskPatch:
+    """Combine duplicates into a patch for the primary task.
 
-    if task.data.categories:
-        lines.append(f"Tags:        {', '.join(task.data.categories)}")
-    else:
-        lines.append("Tags:        -")
+    Tags, attachments, and summaries are unioned and the earliest due date
+    wins. Scalar fields (priority, project, other X-properties, url) come
+    from the primary unless prefer_secondary is set; an unset value is
+    always filled from the other side.
+    """
+    everyone = [primary, *secondaries]
+    ordered = [*secondaries, primary] if prefer_secondary else everyone
+
+    summaries: list[str] = []
+    categories: list[str] = []
+    for task in everyone:
+        summary = (task.data.summary or "").strip()
+        if summary and summary not in summaries:
+            summaries.append(summary)
+        for tag in task.data.tags:
+            if tag not in categories:
+                categories.append(tag)
+
+    dues = [task.data.due for task in everyone if task.data.due is not None]
+    x_properties: dict[str, str] = {}
+    for task in reversed(ordered):
+        x_properties.update(task.data.x_properties)
+
+    known_uris = {attachment.uri for attachment in primary.data.attachments}
+    attachments: list[Attachment] = []
+    for task in secondaries:
+        for attachment in task.data.attachments:
+            if attachment.uri not in known_uris:
+                known_uris.add(attachment.uri)
+                attachments.append(attachment)
+
+    return TaskPatch(
+        summary="; ".join(summaries) or None,
+        due=min(dues) if dues else None,
+        priority=next((task.data.priority for task in ordered if task.data.priority), None),
+        x_properties=x_properties,
+        categories=categories,
+        url=next((task.data.url for task in ordered if task.data.url), None),
+        attachments=attachments,
+    )
 
-    project = task.data.x_properties.get("X-PROJECT")
-    if project:
-        lines.append(f"Project:     {project}")
 
-    if task.data.url:
-        lines.append(f"URL:         {task.data.url}")
+async def _handle_merge(args: argparse.Namespace) -> None:
+    """Merge secondary tasks into the first (primary) index and delete them."""
+    indices = _effective_filter_indices(args.filter_indices)
+    if len(set(indices)) < 2:
+        _exit_with_message("merge requires a primary index and at least one secondary index")
+    client = await _cache_client(args.env)
+    try:
+        tasks = _select_tasks_for_filter(await _sorted_tasks(client), list(dict.fromkeys(indices)))
+        primary, secondaries = tasks[0], tasks[1:]
+        patch = _build_merge_patch(primary, secondaries, prefer_secondary=args.prefer_secondary)
+        async with client.logged("merge"):
+            merged = await client.modify_task(primary, patch)
+            diffs: dict[int, TaskDiff] = {primary.task_index: TaskDiff(pre=primary.data, post=merged.data)}
+            for task in secondaries:
+                # delete_task leaves a tombstone so the next push removes it remotely
+                await client.delete_task(task.uid)
+                diffs[task.task_index] = TaskDiff(pre=task.data, post=None)
+        result: TaskSetDiff[int] = TaskSetDiff(diffs=diffs)
+        print(f"Merged {len(secondaries)} task(s) into [{primary.task_index}] {merged.data.summary}")
+        print(result.pretty())
+    finally:
+        await client.close()
 
-    if task.data.attachments:
-        lines.append(f"Attachments: {len(task.data.attachments)}")
-        for i, attach in enumerate(task.data.attachments, 1):
-            fmttype_display = f" ({attach.fmttype})" if attach.fmttype else ""
-            lines.append(f"  [{i}] {attach.uri}{fmttype_display}")
 
-    for key, value in task.data.x_properties.items():
+async def _handle_archive(args: argparse.Namespace) -> None:
+    """Move completed tasks into the archive, or list archived tasks."""
+    client = await _cache_client(args.env)
+    try:
+        if args.list_only:
+            archived = await client.list_archived_tasks()
+            if not archived:
+                print("no archived tasks")
+                return
+            _pretty_print_tasks(archived, True, title="Archived")
+            return
+
+        if args.days is None and not args.uids:
+            _exit_with_message("archive requires --days or --uid")
+        if args.days is not None and args.days < 0:
+            _exit_with_message("--days must be non-negative")
+        completed_before = None
+        if args.days is not None:
+            completed_before = (datetime.now() - timedelta(days=args.days)).timestamp()
+        archived = await client.archive_completed_tasks(
+            completed_before=completed_before,
+            uids=args.uids or None,
+        )
+        archived_uids = {task.uid for task in archived}
+        for uid in args.uids or []:
+            if uid not in archived_uids:
+                print(f"skipped {uid}: not a synced completed task")
+        if not archived:
+            print("no completed tasks to archive")
+            return
+        print(f"Archived ({len(archived)}):")
+        for task in archived:
+            print(f"  {task.uid}  {_truncate_summary(task.data.summary or task.uid, SUMMARY_WIDTH)}")
+    finally:
+        await client.close()
+
+
+async def _handle_completed(args: argparse.Namespace) -> None:
+    """List completed tasks that are not archived yet, with their notes."""
+    client = await _cache_client(args.env)
+    try:
+        tasks = await client.list_completed_tasks()
+    finally:
+        await client.close()
+    if not tasks:
+        print("no completed tasks")
+        return
+    print(f"Completed ({len(tasks)}):")
+    for task in tasks:
+        print(f"  [{task.task_index or '?'}] {_truncate_summary(task.data.summary or task.uid, SUMMARY_WIDTH)}")
+        if task.completion_note:
+            print(f"      note: {task.completion_note}")
+
+
+async def _handle_reopen(args: argparse.Namespace) -> None:
+    """Bring completed tasks back to the active list, by their `tdo completed` index."""
+    indices = _effective_filter_indices(args.filter_indices)
+    if not indices:
+        _exit_with_message("reopen needs the index of a completed task (see tdo completed)")
+    client = await _cache_client(args.env)
+    try:
+        by_index = {str(task.task_index): task for task in await client.list_completed_tasks()}
+        selected: list[Task] = []
+        for token in indices:
+            task = by_index.get(token)
+            if task is None:
+                _exit_with_message(f"no completed task has index {token}")
+            selected.append(task)
+        diffs: dict[int, TaskDiff] = {}
+        async with client.logged("reopen"):
+            for task in selected:
+                reopened = await client.reopen_task(task.uid)
+                diffs[reopened.task_index] = TaskDiff(pre=task.data, post=reopened.data)
+        result: TaskSetDiff[int] = TaskSetDiff(diffs=diffs)
+        print(result.pretty())
+    finally:
+        await client.close()
+
+
+# Write buffer for `export --output`; rows are flushed in chunks of this size
+_EXPORT_BUFFER_SIZE = 1 << 16
+
+
+def _export_line(state: str, task: Task) -> bytes:
+    return (json.dumps({"state": state, **task.to_dict()}) + "\n").encode("utf-8")
+
+
+async def _handle_export(args: argparse.Namespace) -> None:
+    """Write cached tasks as JSON Lines to --output, or to stdout."""
+    client = await _cache_client(args.env)
+    try:
+        rows = client.iter_tasks_for_export(task_filter=args.task_filter)
+        if args.output is None:
+            async for state, task in rows:
+                sys.stdout.write(_export_line(state, task).decode("utf-8"))
+            return
+        path = Path(args.output).expanduser()
+        try:
+            sink = path.open("wb", buffering=_EXPORT_BUFFER_SIZE)
+        except OSError as exc:
+            _exit_with_message(f"cannot write {path}: {exc.strerror}")
+        count = written = 0
+        with sink:
+            async for state, task in rows:
+                written += sink.write(_export_line(state, task))
+                count += 1
+        print(f"Exported {count} task(s) to {path} ({written} bytes)")
+    finally:
+        await client.close()
+
+
+# Export states import accepts, and the field each reads its time from
+_IMPORT_TIME_FIELDS = {"active": None, "completed": "completed_at", "deleted": "deleted_at"}
+
+
+def _read_import_rows(path: Path, *, force: bool) -> list[tuple[str, TaskData, float | None]]:
+    """Parse and check every row of an import file before anything is written."""
+    try:
+        lines = path.read_text(encoding="utf-8").splitlines()
+    except OSError as exc:
+        _exit_with_message(f"cannot read {path}: {exc.strerror}")
+    rows: list[tuple[str, TaskData, float | None]] = []
+    for number, line in enumerate(lines, 1):
+        if not line.strip():
+            continue
+        try:
+            record = json.loads(line)
+            data = TaskData.from_dict(record["data"])
+        except (json.JSONDecodeError, KeyError, TypeError, ValueError, AttributeError):
+            _exit_with_message(f"{path}:{number}: not a task row as written by tdo export")
+        state = record.get("state", "active")
+        if state not in _IMPORT_TIME_FIELDS:
+            _exit_with_message(f"{path}:{number}: unknown state {state!r}; expected active, completed, or deleted")
+        field = _IMPORT_TIME_FIELDS[state]
+        at = _parse_backdate(record.get(field), force=force, label=f"{path}:{number}: {field}") if field else None
+        rows.append((state, data, at))
+    return rows
+
+
+async def _handle_import(args: argparse.Namespace) -> None:
+    """Create tasks from JSON Lines in the `tdo export` format.
+
+    Completed and deleted rows keep their completed_at and deleted_at, so
+    stats --by-day counts a migrated history on its original days. Completed
+    rows are always archived: complete_mode applies to tasks finished here,
+    not to a history being carried over.
+    """
+    path = Path(args.file).expanduser()
+    rows = _read_import_rows(path, force=args.force)
+    client = await _cache_client(args.env)
+    try:
+        counts: Counter[str] = Counter()
+        async with client.logged("import"):
+            for state, data, at in rows:
+                # Finished rows start open, then complete or delete at their recorded time
+                payload = data if state == "active" else replace(data, status=None)
+                created = await client.create_task(payload)
+                if state == "completed":
+                    await client._ensure_cache().complete_task(created.uid, completed_at=at)
+                elif state == "deleted":
+                    await client.delete_task(created.uid, deleted_at=at, keep_unsynced=True)
+                counts[state] += 1
+    finally:
+        await client.close()
+    summary = ", ".join(f"{counts[state]} {state}" for state in _IMPORT_TIME_FIELDS if counts[state])
+    print(f"Imported {len(rows)} task(s) from {path}" + (f" ({summary})" if summary else ""))
+
+
+def _search_match_json(task: Task, args: argparse.Namespace) -> dict[str, Any]:
+    return {
+        **({"env": task.env} if getattr(args, "all_envs", False) else {}),
+        "index": task.task_index,
+        "uid": task.uid,
+        "summary": task.data.summary,
+        "priority": task.data.priority,
+        **({"priority_letter": task.data.priority_letter} if args.priority_letters else {}),
+    }
+
+
+def _filter_json(task_filter: TaskFilter | None) -> dict[str, Any]:
+    """The filter fields that differ from their defaults, as JSON values."""
+    if task_filter is None:
+        return {}
+    defaults = TaskFilter()
+    echoed: dict[str, Any] = {}
+    for name in TaskFilter.__dataclass_fields__:
+        value = getattr(task_filter, name)
+        if value == getattr(defaults, name):
+            continue
+        echoed[name] = value.isoformat() if isinstance(value, datetime) else value
+    return echoed
+
+
+def _json_text(value: Any, *, pretty: bool, sort_keys: bool = False) -> str:
+    """Compact JSON for machine consumers; indented with the global --pretty flag."""
+    return json.dumps(value, indent=2 if pretty else None, sort_keys=sort_keys)
+
+
+def _json_timestamp(value: float | None, style: str) -> float | str | None:
+    """Epoch seconds as given, or with --timestamps iso an RFC 3339 UTC string.
+
+    Milliseconds are always written, so the string has one fixed shape.
+    """
+    if value is None or style != "iso":
+        return value
+    return datetime.fromtimestamp(value, tz=timezone.utc).isoformat(timespec="milliseconds")
+
+
+def _json_envelope(
+    rows: list[dict[str, Any]] | dict[str, dict[str, Any]], *, total: int, task_filter: TaskFilter | None, started: float
+) -> dict[str, Any]:
+    """Wrap JSON rows with how many matched, the filter used, and how long it took."""
+    return {
+        "tasks": rows,
+        "meta": {
+            "total": total,
+            "returned": len(rows),
+            "filters": _filter_json(task_filter),
+            "elapsed_ms": round((time.perf_counter() - started) * 1000, 1),
+        },
+    }
+
+
+def _check_envelope(args: argparse.Namespace, json_output: bool, flag: str) -> None:
+    if args.envelope and not json_output:
+        _exit_with_message(f"--envelope requires {flag}")
+
+
+def _parse_search_weights(raw: str) -> dict[str, int]:
+    """Parse --weights "summary=3,project=2,tags=1"; fields left out are not searched."""
+    from .sqlite_cache import SEARCH_FIELDS
+
+    weights: dict[str, int] = {}
+    for part in raw.split(","):
+        if not part.strip():
+            continue
+        name, sep, value = part.partition("=")
+        name = name.strip().lower()
+        if name not in SEARCH_FIELDS:
+            _exit_with_message(f"unknown search field: {name} (expected {', '.join(SEARCH_FIELDS)})")
+        if not sep or not value.strip().isdigit():
+            _exit_with_message(f"invalid weight for {name}: {value.strip() or '(none)'} (expected a whole number)")
+        weights[name] = int(value)
+    if not any(weights.values()):
+        _exit_with_message("--weights needs at least one field with a positive weight")
+    return weights
+
+
+async def _handle_search(args: argparse.Namespace) -> None:
+    """List active tasks whose summary contains the text, best match first.
+
+    With --include-deleted, deleted tasks that are not pushed yet follow the
+    active matches, and with --include-archived archived tasks come last.
+    Both are named by uid, since their index may be reused.
+    """
+    text = " ".join(args.text).strip()
+    if not text:
+        _exit_with_message("search requires text to look for")
+    if args.limit is not None and args.limit < 1:
+        _exit_with_message("--limit must be positive")
+    _check_envelope(args, args.json, "--json")
+    weights = _parse_search_weights(args.weights) if args.weights is not None else None
+    started = time.perf_counter()
+    # The envelope reports the total, so it needs every match before the limit
+    fetch_limit = None if args.envelope else args.limit
+    task_filter = getattr(args, "task_filter", None)
+
+    async def fetch(
+        client: "CalDAVClient",
+    ) -> tuple[list[Task], list["DeletedMatch"], list["ArchivedMatch"]]:
+        matches = await client.search_tasks(text, task_filter=task_filter, limit=fetch_limit, weights=weights)
+        deleted: list["DeletedMatch"] = []
+        remaining = None if fetch_limit is None else fetch_limit - len(matches)
+        if args.include_deleted and remaining != 0:
+            deleted = await client.search_deleted_tasks(
+                text, task_filter=task_filter, limit=remaining, weights=weights
+            )
+        archived: list["ArchivedMatch"] = []
+        remaining = None if fetch_limit is None else fetch_limit - len(matches) - len(deleted)
+        if args.include_archived and remaining != 0:
+            archived = await client.search_archived_tasks(
+                text, task_filter=task_filter, limit=remaining, weights=weights
+            )
+        return matches, deleted, archived
+
+    # With --all-envs each env's matches follow the previous env's, tagged
+    # with their env since indices and uids are only unique within one
+    all_envs = getattr(args, "all_envs", False)
+    matches: list[Task] = []
+    deleted: list["DeletedMatch"] = []
+    archived: list["ArchivedMatch"] = []
+    for env, (env_matches, env_deleted, env_archived) in await _query_envs(args, fetch):
+        if all_envs:
+            env_matches = [replace(task, env=env) for task in env_matches]
+            env_deleted = [replace(match, task=replace(match.task, env=env)) for match in env_deleted]
+            env_archived = [replace(match, task=replace(match.task, env=env)) for match in env_archived]
+        matches.extend(env_matches)
+        deleted.extend(env_deleted)
+        archived.extend(env_archived)
+    total = len(matches) + len(deleted) + len(archived)
+    if args.limit is not None:
+        matches = matches[: args.limit]
+        deleted = deleted[: args.limit - len(matches)]
+        archived = archived[: args.limit - len(matches) - len(deleted)]
+    if args.json:
+        rows = [_search_match_json(task, args) for task in matches]
+        if args.include_deleted or args.include_archived:
+            # Say which table each match came from so the others can be told apart
+            stamps = {"deleted_at": args.include_deleted, "archived_at": args.include_archived}
+            blank = {key: None for key, wanted in stamps.items() if wanted}
+            rows = [{**row, "table": "tasks", **blank} for row in rows]
+            rows += [
+                {
+                    **_search_match_json(match.task, args),
+                    "table": "deleted_tasks",
+                    **blank,
+                    "deleted_at": datetime.fromtimestamp(match.deleted_at).isoformat(timespec="seconds"),
+                }
+                for match in deleted
+            ]
+            rows += [
+                {
+                    **_search_match_json(match.task, args),
+                    "table": "archived_tasks",
+                    **blank,
+                    "archived_at": datetime.fromtimestamp(match.archived_at).isoformat(timespec="seconds"),
+                }
+                for match in archived
+            ]
+        if args.envelope:
+            envelope = _json_envelope(rows, total=total, task_filter=task_filter, started=started)
+            print(_json_text(envelope, pretty=args.pretty))
+            return
+        print(_json_text(rows, pretty=args.pretty))
+        return
+    if not matches and not deleted and not archived:
+        print(f"no tasks match {text!r}")
+        return
+    def env_label(task: Task) -> str:
+        return f"{task.env or '-'}  " if all_envs else ""
+
+    if matches:
+        print(f"Matches ({len(matches)}):")
+        for task in matches:
+            summary = _truncate_summary(task.data.summary or task.uid, SUMMARY_WIDTH)
+            uid = f"  ({task.uid})" if all_envs else ""
+            print(f"  {env_label(task)}[{task.task_index or '?'}] {summary}{uid}")
+    if deleted:
+        if matches:
+            print()
+        print(f"Deleted ({len(deleted)}):")
+        for match in deleted:
+            when = datetime.fromtimestamp(match.deleted_at).strftime("%Y-%m-%d %H:%M")
+            summary = _truncate_summary(match.task.data.summary or match.task.uid, SUMMARY_WIDTH)
+            print(f"  {env_label(match.task)}{match.task.uid}  {summary}  (deleted {when})")
+    if archived:
+        if matches or deleted:
+            print()
+        print(f"Archived ({len(archived)}):")
+        for match in archived:
+            when = datetime.fromtimestamp(match.archived_at).strftime("%Y-%m-%d %H:%M")
+            summary = _truncate_summary(match.task.data.summary or match.task.uid, SUMMARY_WIDTH)
+            print(f"  {env_label(match.task)}{match.task.uid}  {summary}  (archived {when})")
+
+
+DEFAULT_STALE_DAYS = 7
+
+
+async def _handle_stale(args: argparse.Namespace) -> None:
+    """Show synced tasks that have not been reconciled with the server recently."""
+    if args.days < 0:
+        _exit_with_message("--days must be non-negative")
+    now = datetime.now()
+    client = await _cache_client(args.env)
+    try:
+        stale = await client.list_stale_tasks(synced_before=(now - timedelta(days=args.days)).timestamp())
+    finally:
+        await client.close()
+    if args.json:
+        print(
+            _json_text(
+                [
+                    {
+                        "uid": entry.task.uid,
+                        "href": entry.task.href,
+                        "index": entry.task.task_index,
+                        "summary": entry.task.data.summary,
+                        "completed": entry.completed,
+                        "last_synced": _json_timestamp(entry.last_synced, args.timestamps),
+                        "age_seconds": now.timestamp() - entry.last_synced if entry.last_synced is not None else None,
+                    }
+                    for entry in stale
+                ],
+                pretty=args.pretty,
+            )
+        )
+        return
+    if not stale:
+        print(f"no tasks unsynced for more than {args.days} days")
+        return
+    print(f"Stale ({len(stale)}):")
+    for entry in stale:
+        age = "never" if entry.last_synced is None else _format_due_label(now, datetime.fromtimestamp(entry.last_synced))
+        state = "completed" if entry.completed else "active"
+        summary = _truncate_summary(entry.task.data.summary or entry.task.uid, SUMMARY_WIDTH)
+        print(f"  {entry.task.uid}  {age:>6}  {state:<9}  {summary}")
+
+
+async def _handle_events(args: argparse.Namespace) -> None:
+    """List tasks that became overdue or stopped waiting since a given time."""
+    since = _resolve_age_value(args.since)
+    if since is None:
+        _exit_with_message(f"invalid --since value: {args.since}")
+    until = time.time()
+    client = await _cache_client(args.env)
+    try:
+        events = await client.list_boundary_events(
+            since=since.timestamp(), until=until, task_filter=getattr(args, "task_filter", None)
+        )
+    finally:
+        await client.close()
+    if args.json:
+        print(
+            _json_text(
+                {
+                    "since": _json_timestamp(since.timestamp(), args.timestamps),
+                    "until": _json_timestamp(until, args.timestamps),
+                    "events": [
+                        {"event": event.kind, "at": _json_timestamp(event.at, args.timestamps), **_task_json(event.task)}
+                        for event in events
+                    ],
+                },
+                pretty=args.pretty,
+            )
+        )
+        return
+    since_label = since.astimezone().strftime("%Y-%m-%d %H:%M")
+    if not events:
+        print(f"no events since {since_label}")
+        return
+    print(f"Events since {since_label} ({len(events)}):")
+    for event in events:
+        at = datetime.fromtimestamp(event.at).strftime("%Y-%m-%d %H:%M")
+        boundary = "due" if event.kind == "overdue" else "wait"
+        summary = _truncate_summary(event.task.data.summary or event.task.uid, SUMMARY_WIDTH)
+        print(f"  {event.kind:<8}  [{event.task.task_index}] {summary}  {boundary} {at}")
+
+
+def _print_integrity_report(
+    title: str, problems: Sequence["IntegrityProblem"], *, as_json: bool, pretty: bool, empty_message: str
+) -> None:
+    if as_json:
+        print(
+            _json_text(
+                [
+                    {"check": problem.check, "table": problem.table, "uid": problem.uid, "detail": problem.detail}
+                    for problem in problems
+                ],
+                pretty=pretty,
+            )
+        )
+        return
+    if not problems:
+        print(empty_message)
+        return
+    print(f"{title} ({len(problems)}):")
+    for problem in problems:
+        location = f"{problem.table} {problem.uid}" if problem.uid else problem.table
+        print(f"  {problem.check:<19}  {location}: {problem.detail}")
+
+
+async def _handle_validate(args: argparse.Namespace) -> None:
+    """Report cache consistency problems; read-only, exits 1 if any are found."""
+    client = await _cache_client(args.env)
+    try:
+        problems = await client.check_integrity()
+    finally:
+        await client.close()
+    _print_integrity_report(
+        "Problems", problems, as_json=args.json, pretty=args.pretty, empty_message="no problems found"
+    )
+    if problems:
+        raise SystemExit(1)
+
+
+async def _handle_info(args: argparse.Namespace) -> None:
+    """Show the resolved env, its cache file, and row counts; read-only."""
+    client = await _cache_client(args.env)
+    try:
+        env = client.config.env
+        info = await client.cache_info()
+    finally:
+        await client.close()
+    if args.json:
+        print(
+            _json_text(
+                {
+                    "env": env,
+                    "db_path": str(info.path),
+                    "schema_version": info.schema_version,
+                    "size_bytes": info.size_bytes,
+                    "active": info.active,
+                    "completed": info.completed,
+                    "deleted": info.deleted,
+                    "archived": info.archived,
+                },
+                pretty=args.pretty,
+            )
+        )
+        return
+    print(f"Env:         {env}")
+    print(f"Database:    {info.path}")
+    print(f"Schema:      {info.schema_version}")
+    print(f"Size:        {info.size_bytes} bytes")
+    print(f"Active:      {info.active}")
+    print(f"Completed:   {info.completed}")
+    print(f"Deleted:     {info.deleted} (pending push)")
+    print(f"Archived:    {info.archived}")
+
+
+async def _handle_repair(args: argparse.Namespace) -> None:
+    """Fix what validate reports, or with --dry-run show what would be fixed."""
+    client = await _cache_client(args.env)
+    try:
+        fixes = await client.repair(dry_run=args.dry_run)
+    finally:
+        await client.close()
+    title = "Would repair" if args.dry_run else "Repaired"
+    _print_integrity_report(
+        title, fixes, as_json=args.json, pretty=args.pretty, empty_message="nothing to repair"
+    )
+
+
+async def _handle_forget(args: argparse.Namespace) -> None:
+    """Erase tasks by uid from every table and the undo log; needs --confirm."""
+    if not args.confirm:
+        _exit_with_message("forget erases tasks permanently and cannot be undone; pass --confirm")
+    client = await _cache_client(args.env)
+    try:
+        reports = await client.forget_tasks(list(dict.fromkeys(args.uids)))
+    finally:
+        await client.close()
+    forgotten = [report for report in reports if report.tables]
+    missing = [report.uid for report in reports if not report.tables]
+    if forgotten:
+        print(f"Forgot ({len(forgotten)}):")
+        for report in forgotten:
+            print(f"  {report.uid} {_truncate_summary(report.summary or '', SUMMARY_WIDTH)}")
+            removed = list(report.tables)
+            if report.log_entries:
+                removed.append(f"transaction_log ({report.log_entries})")
+            if report.idempotency_keys:
+                removed.append(f"idempotency_keys ({report.idempotency_keys})")
+            print(f"      removed from: {', '.join(removed)}")
+    if missing:
+        _exit_with_message(f"no task with uid {', '.join(missing)}")
+
+
+async def _handle_recompute_dates(args: argparse.Namespace) -> None:
+    """Rederive due_utc/wait_utc from the stored dates, e.g. after a timezone change."""
+    client = await _cache_client(args.env)
+    try:
+        changed = await client.recompute_utc_dates(dry_run=args.dry_run)
+    finally:
+        await client.close()
+    if not changed:
+        print("all UTC dates are up to date")
+        return
+    verb = "would change" if args.dry_run else "changed"
+    print(f"Recomputed UTC dates: {changed} row(s) {verb}")
+
+
+async def _print_timeline(args: argparse.Namespace, config: CaldavConfig) -> None:
+    """Active and completed tasks in one table, ordered by when each last changed.
+
+    The newest task is printed last, next to the prompt, unless --no-reverse
+    is passed.
+    """
+    task_filter = getattr(args, "task_filter", None)
+    all_envs = getattr(args, "all_envs", False)
+    entries: list["TimelineEntry"] = []
+    for env, env_entries in await _query_envs(args, lambda client: client.list_timeline(task_filter=task_filter)):
+        if all_envs:
+            env_entries = [replace(entry, task=replace(entry.task, env=env)) for entry in env_entries]
+        entries.extend(env_entries)
+    if not entries:
+        print("no tasks match filter" if task_filter else "no cached tasks found; run 'tdo pull' to synchronize")
+        return
+    entries.sort(key=lambda entry: entry.at, reverse=getattr(args, "no_reverse", False))
+    _pretty_print_tasks(
+        [entry.task for entry in entries],
+        config.show_uids or all_envs,
+        title="Timeline",
+        show_envs=all_envs,
+        with_relative=args.relative,
+        states={entry.task.uid: "done" if entry.state == "completed" else "active" for entry in entries},
+    )
+
+
+async def _print_list_counts(args: argparse.Namespace) -> None:
+    """Task counts per project, tag, or status instead of the tasks, scoped like a plain list."""
+    for flag, value in (
+        ("--group-by", args.group_by),
+        ("--activate-waited", args.activate_waited),
+        ("--last-changed", args.last_changed),
+    ):
+        if value:
+            _exit_with_message(f"{flag} cannot be combined with --count-by")
+    task_filter = getattr(args, "task_filter", None)
+    per_env = await _query_envs(
+        args,
+        lambda client: client.count_tasks_by(
+            args.count_by, task_filter=task_filter, include_completed=args.include_completed
+        ),
+    )
+    counts: dict[str, int] = {}
+    for _, env_counts in per_env:
+        for name, count in env_counts.items():
+            counts[name] = counts.get(name, 0) + count
+    if args.json:
+        print(_json_text(counts, pretty=args.pretty, sort_keys=True))
+        return
+    if not counts:
+        print("no tasks match filter" if task_filter else "no cached tasks found; run 'tdo pull' to synchronize")
+        return
+    missing = {"project": _NO_PROJECT_GROUP, "tag": _NO_TAG_GROUP}.get(args.count_by, "")
+    labeled = {name or missing: count for name, count in counts.items()}
+    _print_count_table(f"Tasks by {args.count_by}", args.count_by.capitalize(), labeled)
+
+
+async def _print_last_changed(args: argparse.Namespace, config: CaldavConfig) -> None:
+    """The tasks the newest transaction touched, as they are now, for reviewing a bulk edit."""
+    for flag, value in (
+        ("--include-completed", args.include_completed),
+        ("--group-by", args.group_by),
+        ("--activate-waited", args.activate_waited),
+        ("--all-envs", args.all_envs),
+    ):
+        if value:
+            _exit_with_message(f"{flag} cannot be combined with --last-changed")
+    client = await _cache_client(args.env)
+    try:
+        entry = await client.peek_transaction()
+        touched = await client.touched_tasks(entry) if entry else []
+    finally:
+        await client.close()
+    if entry is None:
+        print("no transactions recorded")
+        return
+    _pretty_print_tasks(
+        [item.task for item in touched],
+        config.show_uids,
+        title=f"Last change: {entry.operation or 'unknown'}",
+        with_relative=args.relative,
+        states={item.task.uid: "done" if item.state == "completed" else item.state for item in touched},
+    )
+
+
+async def _handle_list(args: argparse.Namespace) -> None:
+    config = _resolve_config(args.env)
+    if args.json and not args.count_by:
+        _exit_with_message("--json requires --count-by")
+    if args.count_by:
+        await _print_list_counts(args)
+        return
+    if args.last_changed:
+        await _print_last_changed(args, config)
+        return
+    if args.activate_waited and args.all_envs:
+        # It writes, and a list should only ever change the env it was asked about
+        _exit_with_message("--activate-waited cannot be combined with --all-envs")
+    if args.include_completed:
+        if args.activate_waited:
+            _exit_with_message("--activate-waited cannot be combined with --include-completed")
+        if args.group_by:
+            _exit_with_message("--group-by cannot be combined with --include-completed")
+        await _print_timeline(args, config)
+        return
+    task_filter = getattr(args, "task_filter", None)
+    activated: list[Task] = []
+
+    async def fetch(client: "CalDAVClient") -> list[Task]:
+        if args.activate_waited:
+            activated.extend(await client.activate_waited_tasks())
+        # Use SQL-based filtering that excludes waiting tasks
+        return await client.list_active_tasks(exclude_waiting=True, task_filter=task_filter)
+
+    tasks = await _collect_tasks(args, fetch)
+    if activated:
+        print(f"Activated ({len(activated)}):")
+        for task in activated:
+            label = task.task_index if task.task_index is not None else task.uid
+            print(f"  [{label}] {_truncate_summary(task.data.summary or task.uid, SUMMARY_WIDTH)}")
+    if not tasks:
+        if task_filter:
+            print("no tasks match filter")
+        else:
+            print("no cached tasks found; run 'tdo pull' to synchronize")
+        return
+
+    # Filter out completed tasks (they're in a separate table, but just in case)
+    active_tasks = [t for t in tasks if t.data.status != "COMPLETED"]
+    if not active_tasks:
+        print("no tasks match filter")
+        return
+
+    # Split tasks by status: IN-PROCESS (started) and NEEDS-ACTION (backlog)
+    started = [t for t in active_tasks if t.data.status == "IN-PROCESS"]
+    backlog = [t for t in active_tasks if t.data.status == "NEEDS-ACTION"]
+    other = [t for t in active_tasks if t.data.status not in ("IN-PROCESS", "NEEDS-ACTION", "COMPLETED")]
+
+    reverse = not getattr(args, "no_reverse", False)
+    # Merged views need env + uid to address a task unambiguously
+    all_envs = getattr(args, "all_envs", False)
+    show_uids = config.show_uids or all_envs
+    tasks_by_uid = {t.uid: t for t in active_tasks}
+
+    if args.group_by:
+        groups = _group_tasks(
+            active_tasks,
+            args.group_by,
+            datetime.now(),
+            soon_days=config.agenda.soon_days,
+            week_days=config.agenda.week_days,
+        )
+        for position, (name, group) in enumerate(groups.items()):
+            if position:
+                print()
+            _pretty_print_tasks(
+                group,
+                show_uids,
+                title=name,
+                reverse=reverse,
+                show_envs=all_envs,
+                tasks_by_uid=tasks_by_uid,
+                with_relative=args.relative,
+            )
+        return
+
+    # Display order: Backlog first, then Started (so Started appears at bottom)
+    if backlog:
+        _pretty_print_tasks(
+            backlog,
+            show_uids,
+            title="Backlog",
+            reverse=reverse,
+            show_envs=all_envs,
+            tasks_by_uid=tasks_by_uid,
+            with_relative=args.relative,
+        )
+    if started:
+        if backlog:
+            print()  # Blank line between tables
+        _pretty_print_tasks(
+            started,
+            show_uids,
+            title="Started",
+            reverse=reverse,
+            show_envs=all_envs,
+            tasks_by_uid=tasks_by_uid,
+            with_relative=args.relative,
+        )
+    # Handle tasks with other statuses (if any)
+    if other:
+        if started or backlog:
+            print()
+        _pretty_print_tasks(
+            other,
+            show_uids,
+            title="Other",
+            reverse=reverse,
+            show_envs=all_envs,
+            tasks_by_uid=tasks_by_uid,
+            with_relative=args.relative,
+        )
+
+
+def _print_count_table(
+    title: str,
+    label: str,
+    counts: dict[str, int],
+    *,
+    value_label: str = "Count",
+    render: Callable[[int], str] = str,
+) -> None:
+    console = Console(file=sys.stdout, color_system="auto")
+    table = Table(title=title, title_style="bold", box=box.SIMPLE_HEAVY, header_style="bold cyan")
+    table.add_column(label, style="magenta")
+    table.add_column(value_label, style="cyan", justify="right")
+    for name, count in sorted(counts.items(), key=lambda item: (-item[1], item[0])):
+        table.add_row(name, render(count))
+    console.print(table)
+
+
+async def _print_completed_by_day(args: argparse.Namespace) -> None:
+    """Completions per local day over the last args.days days, oldest first.
+
+    Days without completions are listed with a zero count so the range has
+    no gaps.
+    """
+    if args.days < 1:
+        _exit_with_message("--days must be positive")
+    today = date.today()
+    first = today - timedelta(days=args.days - 1)
+    since = datetime.combine(first, datetime.min.time()).timestamp()
+    per_env = await _query_envs(args, lambda client: client.count_completed_by_day(since))
+    counts = {(first + timedelta(days=offset)).isoformat(): 0 for offset in range(args.days)}
+    for _, env_counts in per_env:
+        for day, count in env_counts.items():
+            if day in counts:
+                counts[day] += count
+    if args.json:
+        print(_json_text([{"date": day, "count": count} for day, count in counts.items()], pretty=args.pretty))
+        return
+    console = Console(file=sys.stdout, color_system="auto")
+    table = Table(title="Completed per day", title_style="bold", box=box.SIMPLE_HEAVY, header_style="bold cyan")
+    table.add_column("Date", style="magenta")
+    table.add_column("Count", style="cyan", justify="right")
+    for day, count in counts.items():
+        table.add_row(day, str(count))
+    console.print(table)
+
+
+async def _print_time_spent(args: argparse.Namespace) -> None:
+    """Tracked time per project and per tag, with the total over all tasks."""
+    per_env = await _query_envs(
+        args,
+        lambda client: client.sum_time_spent(include_completed=args.include_completed),
+    )
+    spent: dict[str, dict[str, int]] = {"tags": {}, "projects": {}}
+    total = 0
+    for _, env_spent in per_env:
+        total += env_spent.total
+        for kind, values in (("tags", env_spent.tags), ("projects", env_spent.projects)):
+            for name, seconds in values.items():
+                spent[kind][name] = spent[kind].get(name, 0) + seconds
+    if args.json:
+        print(_json_text({**spent, "total_seconds": total}, pretty=args.pretty, sort_keys=True))
+        return
+    if not total:
+        print("no time tracked")
+        return
+    print(f"Time spent: {_format_minutes(total // 60)}")
+
+    def render(seconds: int) -> str:
+        return _format_minutes(seconds // 60)
+
+    if spent["projects"]:
+        print()
+        _print_count_table("Projects", "Project", spent["projects"], value_label="Spent", render=render)
+    if spent["tags"]:
+        print()
+        _print_count_table("Tags", "Tag", spent["tags"], value_label="Spent", render=render)
+
+
+async def _handle_stats(args: argparse.Namespace) -> None:
+    """Show per-project and per-tag task counts or time spent, or completions per day."""
+    if args.by_day and args.time:
+        _exit_with_message("--by-day cannot be combined with --time")
+    if args.by_day:
+        await _print_completed_by_day(args)
+        return
+    if args.time:
+        await _print_time_spent(args)
+        return
+    per_env = await _query_envs(
+        args,
+        lambda client: client.count_tags_and_projects(include_completed=args.include_completed),
+    )
+    counts: dict[str, dict[str, int]] = {"tags": {}, "projects": {}}
+    for _, env_counts in per_env:
+        for kind, values in env_counts.items():
+            for name, count in values.items():
+                counts[kind][name] = counts[kind].get(name, 0) + count
+    if args.json:
+        print(_json_text(counts, pretty=args.pretty, sort_keys=True))
+        return
+    if not counts["projects"] and not counts["tags"]:
+        print("no tagged or project tasks found")
+        return
+    if counts["projects"]:
+        _print_count_table("Projects", "Project", counts["projects"])
+    if counts["tags"]:
+        if counts["projects"]:
+            print()
+        _print_count_table("Tags", "Tag", counts["tags"])
+
+
+def _format_minutes(minutes: int) -> str:
+    hours, rest = divmod(minutes, 60)
+    if not hours:
+        return f"{rest}m"
+    return f"{hours}h" if not rest else f"{hours}h {rest}m"
+
+
+async def _handle_workload(args: argparse.Namespace) -> None:
+    """Sum estimated minutes and tracked time over the filtered tasks.
+
+    Tasks without an estimate count as zero and are listed separately.
+    Time spent includes intervals that are still running.
+    """
+    client = await _cache_client(args.env)
+    try:
+        tasks = await _select_tasks(client, args)
+    finally:
+        await client.close()
+    estimated = [task for task in tasks if task.data.estimate is not None]
+    missing = [task for task in tasks if task.data.estimate is None]
+    total = sum(task.data.estimate or 0 for task in estimated)
+    now = datetime.now()
+    spent = sum(task.time_spent(now) for task in tasks)
+    if args.json:
+        print(
+            _json_text(
+                {
+                    "total_minutes": total,
+                    "spent_seconds": spent,
+                    "estimated": [
+                        {"index": task.task_index, "minutes": task.data.estimate} for task in estimated
+                    ],
+                    "unestimated": [task.task_index for task in missing],
+                },
+                pretty=args.pretty,
+            )
+        )
+        return
+    print(f"Workload: {_format_minutes(total)} across {len(estimated)} estimated task(s)")
+    if spent:
+        print(f"Time spent: {_format_minutes(spent // 60)}")
+    for task in estimated:
+        print(f"  [{task.task_index}] {_format_minutes(task.data.estimate or 0):>7}  {task.data.summary or ''}")
+    if missing:
+        print(f"Without estimate ({len(missing)}):")
+        for task in missing:
+            print(f"  [{task.task_index}] {task.data.summary or ''}")
+
+
+AGENDA_BUCKETS = ("Overdue", "Today", "Soon", "This week", "Later", "No due date")
+
+
+def _agenda_bucket(due: datetime | None, now: datetime, *, soon_days: int, week_days: int) -> str:
+    """Place a due date into one of AGENDA_BUCKETS relative to now."""
+    if due is None:
+        return "No due date"
+    if due < now:
+        return "Overdue"
+    days_until = (due.date() - now.date()).days
+    if days_until == 0:
+        return "Today"
+    if days_until <= soon_days:
+        return "Soon"
+    if days_until <= week_days:
+        return "This week"
+    return "Later"
+
+
+GROUP_BY_CHOICES = ("project", "tag", "status", "due")
+COUNT_BY_CHOICES = ("project", "tag", "status")
+_NO_PROJECT_GROUP = "No project"
+_NO_TAG_GROUP = "No tag"
+# Started work first, then the backlog, then any other status alphabetically
+_STATUS_GROUP_ORDER = ("IN-PROCESS", "NEEDS-ACTION")
+
+
+def _group_tasks(
+    tasks: Sequence[Task], group_by: str, now: datetime, *, soon_days: int, week_days: int
+) -> dict[str, list[Task]]:
+    """Split tasks into named groups, in display order; empty groups are left out.
+
+    "due" uses the agenda buckets. With "tag" a task is listed under each of
+    its tags. Tasks without a project or tag are collected last.
+    """
+    groups: dict[str, list[Task]] = {}
+    for task in tasks:
+        if group_by == "due":
+            keys = [_agenda_bucket(task.data.due, now, soon_days=soon_days, week_days=week_days)]
+        elif group_by == "project":
+            keys = [task.data.project or _NO_PROJECT_GROUP]
+        elif group_by == "tag":
+            keys = list(dict.fromkeys(task.data.tags)) or [_NO_TAG_GROUP]
+        else:
+            keys = [task.data.status or "NEEDS-ACTION"]
+        for key in keys:
+            groups.setdefault(key, []).append(task)
+    if group_by == "due":
+        order = {name: position for position, name in enumerate(AGENDA_BUCKETS)}
+        ranked = sorted(groups, key=order.__getitem__)
+    elif group_by == "status":
+        order = {name: position for position, name in enumerate(_STATUS_GROUP_ORDER)}
+        ranked = sorted(groups, key=lambda key: (order.get(key, len(order)), key))
+    else:
+        ranked = sorted(groups, key=lambda key: (key in (_NO_PROJECT_GROUP, _NO_TAG_GROUP), key))
+    return {key: groups[key] for key in ranked}
+
+
+def _next_sort_key(task: Task, order: Sequence[str], now: datetime) -> tuple[Any, ...]:
+    """Sort key for `tdo next`: one component per key in order, then the summary.
+
+    Overdue tasks sort first, priorities by _priority_rank, and a missing
+    due date or index last.
+    """
+    due = task.data.due.timestamp() if task.data.due else float("inf")
+    components: dict[str, Any] = {
+        "overdue": due >= now.timestamp(),
+        "priority": _priority_rank(task.data.priority),
+        "due": due,
+        "index": task.task_index if task.task_index is not None else float("inf"),
+    }
+    summary_key = task.data.summary.strip().lower() if task.data.summary else ""
+    return (*(components[key] for key in order), summary_key)
+
+
+def _parse_next_order(raw: str) -> list[str]:
+    order = _split_categories_value(raw.lower())
+    for key in order:
+        if key not in NEXT_SORT_KEYS:
+            _exit_with_message(f"unknown --order key: {key} (expected {', '.join(NEXT_SORT_KEYS)})")
+    return order
+
+
+async def _handle_next(args: argparse.Namespace) -> None:
+    """Show the task to work on next, ranked by --order or the [next] order setting."""
+    config = _resolve_config(args.env)
+    order = _parse_next_order(args.order) if args.order is not None else config.next.order
+    if not order:
+        _exit_with_message("--order needs at least one key")
+    if args.count < 1:
+        _exit_with_message("--count must be positive")
+    task_filter = getattr(args, "task_filter", None)
+    client = await _cache_client(args.env)
+    try:
+        tasks = _filter_active_tasks(await client.list_active_tasks(exclude_waiting=True, task_filter=task_filter))
+    finally:
+        await client.close()
+    if not tasks:
+        print("no tasks match filter" if task_filter else "nothing to do")
+        return
+    now = datetime.now()
+    ranked = sorted(tasks, key=lambda task: _next_sort_key(task, order, now))
+    for position, task in enumerate(ranked[: args.count]):
+        if position:
+            print()
+        print(_format_task_detail(task, with_relative=True))
+
+
+async def _handle_agenda(args: argparse.Namespace) -> None:
+    """Show active tasks grouped into due-date buckets."""
+    config = _resolve_config(args.env)
+    soon_days = args.soon_days if args.soon_days is not None else config.agenda.soon_days
+    week_days = args.week_days if args.week_days is not None else config.agenda.week_days
+    if soon_days < 0 or week_days < soon_days:
+        _exit_with_message("agenda requires 0 <= soon_days <= week_days")
+    task_filter = getattr(args, "task_filter", None)
+    tasks = await _collect_tasks(
+        args,
+        lambda client: client.list_active_tasks(exclude_waiting=True, task_filter=task_filter),
+    )
+    all_envs = getattr(args, "all_envs", False)
+    buckets = _group_tasks(
+        _filter_active_tasks(tasks),
+        "due",
+        datetime.now(),
+        soon_days=soon_days,
+        week_days=week_days,
+    )
+    if not buckets:
+        print("no tasks match filter")
+        return
+    for position, (name, bucket) in enumerate(buckets.items()):
+        if position:
+            print()
+        _pretty_print_tasks(
+            bucket,
+            config.show_uids or all_envs,
+            title=name,
+            show_envs=all_envs,
+        )
+
+
+async def _handle_wait(args: argparse.Namespace) -> None:
+    """Show tasks with future wait dates."""
+    config = _resolve_config(args.env)
+    client = await _cache_client(args.env)
+    try:
+        task_filter = getattr(args, "task_filter", None)
+        # Use SQL-based filtering for waiting tasks
+        waiting_tasks = await client.list_waiting_tasks(task_filter=task_filter)
+        if not waiting_tasks:
+            print("no waiting tasks")
+            return
+        _pretty_print_tasks(waiting_tasks, config.show_uids, title="Waiting")
+    finally:
+        await client.close()
+
+
+def _format_task_ref(ref: "TaskRef") -> str:
+    done = " (done)" if ref.done else ""
+    return f"[{ref.index if ref.index is not None else '?'}] {ref.summary or ref.uid}{done}"
+
+
+def _task_ref_json(uid: str, refs: Mapping[str, "TaskRef"]) -> dict[str, Any]:
+    """A related task as {uid, index, summary, done}; unknown uids keep only the uid."""
+    ref = refs.get(uid)
+    if ref is None:
+        return {"uid": uid, "index": None, "summary": None, "done": None}
+    return {"uid": ref.uid, "index": ref.index, "summary": ref.summary, "done": ref.done}
+
+
+def _format_task_detail(
+    task: Task,
+    *,
+    with_relative: bool = False,
+    priority_letters: bool = False,
+    raw: bool = False,
+    refs: Mapping[str, "TaskRef"] | None = None,
+) -> str:
+    lines = []
+    lines.append(f"ID:          {task.task_index or '?'}")
+    lines.append(f"Summary:     {task.data.summary}")
+    lines.append(f"Status:      {task.data.status}")
+    priority = str(task.data.priority) if task.data.priority is not None else "-"
+    if priority_letters and task.data.priority_letter:
+        priority = f"{task.data.priority_letter} ({priority})"
+    lines.append(f"Priority:    {priority}")
+    lines.append(f"Due:         {task.data.due.isoformat() if task.data.due else '-'}")
+    if with_relative and task.data.due:
+        lines.append(f"When:        {_format_relative_due(task.data.due, datetime.now(task.data.due.tzinfo))}")
+    lines.append(f"Wait:        {task.data.wait.isoformat() if task.data.wait else '-'}")
+
+    if task.data.categories:
+        lines.append(f"Tags:        {', '.join(task.data.categories)}")
+    else:
+        lines.append("Tags:        -")
+
+    if task.data.project:
+        lines.append(f"Project:     {task.data.project}")
+
+    if task.data.parent:
+        ref = refs.get(task.data.parent) if refs is not None else None
+        lines.append(f"Parent:      {_format_task_ref(ref) if ref else task.data.parent}")
+
+    if task.data.url:
+        lines.append(f"URL:         {task.data.url}")
+
+    if task.data.attachments:
+        lines.append(f"Attachments: {len(task.data.attachments)}")
+        for i, attach in enumerate(task.data.attachments, 1):
+            fmttype_display = f" ({attach.fmttype})" if attach.fmttype else ""
+            lines.append(f"  [{i}] {attach.uri}{fmttype_display}")
+
+    for key, value in task.data.x_properties.items():
         if key != "X-PROJECT":
             lines.append(f"{key}: {value}")
+    if raw:
+        lines.append(f"Extra:       {json.dumps(task.data.x_properties)}")
 
     lines.append(f"UID:         {task.uid}")
     if task.href:
@@ -815,19 +2751,299 @@ def _format_task_detail(task: Task) -> str:
     return "\n".join(lines)
 
 
-async def _handle_show(args: argparse.Namespace) -> None:
+async def _task_histories(client: "CalDAVClient", tasks: Sequence[Task]) -> dict[str, list[dict[str, Any]]]:
+    """Each task's logged changes, oldest first, as JSON-ready entries keyed by uid."""
+    histories: dict[str, list[dict[str, Any]]] = {task.uid: [] for task in tasks}
+    for entry in await client.transaction_history(list(histories)):
+        for uid, diff in TaskSetDiff.from_json(entry.diff_json).diffs.items():
+            histories[uid].append(
+                {
+                    "id": entry.id,
+                    "at": datetime.fromtimestamp(entry.created_at).astimezone().isoformat(),
+                    "operation": entry.operation,
+                    "changes": {name: change.to_dict() for name, change in diff.changes().items()},
+                }
+            )
+    return histories
+
+
+def _format_history_value(value: Any) -> str:
+    if value is None:
+        return "-"
+    if isinstance(value, list):
+        return ", ".join(str(item.get("uri", item)) if isinstance(item, dict) else str(item) for item in value)
+    return str(value)
+
+
+def _format_task_history(history: Sequence[dict[str, Any]]) -> str:
+    if not history:
+        return "History:     -"
+    lines = [f"History ({len(history)}):"]
+    for entry in history:
+        at = datetime.fromisoformat(entry["at"]).strftime("%Y-%m-%d %H:%M")
+        lines.append(f"  {at} {entry['operation'] or 'change'}")
+        for name, change in entry["changes"].items():
+            before = _format_history_value(change["before"])
+            after = _format_history_value(change["after"])
+            lines.append(f"    {name}: {before} -> {after}")
+    return "\n".join(lines)
+
+
+_TABLE_MIN_SUMMARY_WIDTH = 11  # len("Description")
+_TABLE_TAGS_WIDTH = 20
+
+
+def _format_priority_cell(task: Task, letters: bool) -> str:
+    if letters and task.data.priority_letter:
+        return task.data.priority_letter
+    return str(task.data.priority) if task.data.priority is not None else "-"
+
+
+def _format_task_table(tasks: Sequence[Task], width: int, *, priority_letters: bool = False) -> str:
+    """Render tasks as a plain aligned table, one row per task, in the given order.
+
+    Columns are as wide as their widest value. Description takes whatever
+    is left of `width` and is ellipsized to fit. With priority_letters the
+    Pri column shows H/M/L instead of the number.
+    """
+    header = ["ID", "Pri", "Due", "Tags", "Description"]
+    rows = [
+        [
+            str(task.task_index) if task.task_index is not None else "?",
+            _format_priority_cell(task, priority_letters),
+            _format_due_date(task.data.due),
+            _truncate_value(",".join(task.data.tags) or "-", _TABLE_TAGS_WIDTH, ellipsize=True),
+            task.data.summary or "",
+        ]
+        for task in tasks
+    ]
+    widths = [max(len(row[col]) for row in [header, *rows]) for col in range(len(header))]
+    fixed = sum(widths[:-1]) + 2 * (len(header) - 1)
+    widths[-1] = min(widths[-1], max(width - fixed, _TABLE_MIN_SUMMARY_WIDTH))
+    lines = []
+    for row in [header, *rows]:
+        cells = [
+            row[0].rjust(widths[0]),
+            row[1].rjust(widths[1]),
+            row[2].ljust(widths[2]),
+            row[3].ljust(widths[3]),
+            _truncate_value(row[4], widths[4], ellipsize=True),
+        ]
+        lines.append("  ".join(cells).rstrip())
+    return "\n".join(lines)
+
+
+def _task_index_sort_key(task: Task) -> tuple[bool, int]:
+    return task.task_index is None, task.task_index or 0
+
+
+def _task_priority_sort_key(task: Task) -> tuple[int, datetime, int, str]:
+    return _priority_rank(task.data.priority), *_task_sort_key(task)
+
+
+_SHOW_SORT_KEYS: dict[str, Callable[[Task], object]] = {
+    "index": _task_index_sort_key,
+    "due": _task_sort_key,
+    "priority": _task_priority_sort_key,
+}
+
+
+def _task_tree_node(
+    task: Task, children: Mapping[str, list[Task]], placed: set[str], *, priority_letters: bool, raw: bool
+) -> dict[str, Any]:
+    placed.add(task.uid)
+    node: dict[str, Any] = {
+        "index": task.task_index,
+        "uid": task.uid,
+        "summary": task.data.summary,
+        "status": task.data.status,
+        "priority": task.data.priority,
+    }
+    if priority_letters:
+        node["priority_letter"] = task.data.priority_letter
+    node["due"] = task.data.due.isoformat() if task.data.due else None
+    node["parent"] = task.data.parent
+    if raw:
+        node["extra"] = dict(task.data.x_properties)
+    node["children"] = [
+        _task_tree_node(child, children, placed, priority_letters=priority_letters, raw=raw)
+        for child in children.get(task.uid, [])
+        if child.uid not in placed
+    ]
+    return node
+
+
+def _build_task_tree(
+    tasks: list[Task], roots: list[Task] | None = None, *, priority_letters: bool = False, raw: bool = False
+) -> list[dict[str, Any]]:
+    """Nest tasks under their parents, rooted at top-level tasks.
+
+    A task whose parent is not in tasks (completed, deleted, or never
+    pulled) is treated as top-level. Parent cycles are broken at the
+    lowest-indexed task. When roots is given, only those subtrees are built.
+    priority_letters adds each node's H/M/L priority_letter; raw adds its
+    X-properties, verbatim, as "extra".
+    """
+    ordered = sorted(tasks, key=_task_index_sort_key)
+    uids = {task.uid for task in ordered}
+    children: dict[str, list[Task]] = {}
+    top_level: list[Task] = []
+    for task in ordered:
+        parent = task.data.parent
+        if parent and parent in uids and parent != task.uid:
+            children.setdefault(parent, []).append(task)
+        else:
+            top_level.append(task)
+    placed: set[str] = set()
+    if roots is not None:
+        return [
+            _task_tree_node(task, children, placed, priority_letters=priority_letters, raw=raw)
+            for task in roots
+            if task.uid not in placed
+        ]
+    tree = [_task_tree_node(task, children, placed, priority_letters=priority_letters, raw=raw) for task in top_level]
+    for task in ordered:
+        if task.uid not in placed:
+            tree.append(_task_tree_node(task, children, placed, priority_letters=priority_letters, raw=raw))
+    return tree
+
+
+async def _handle_tree(args: argparse.Namespace) -> None:
     client = await _cache_client(args.env)
     try:
-        tasks = _select_tasks_for_filter(
-            await _sorted_tasks(client),
-            _effective_filter_indices(args.filter_indices),
+        tasks = await _sorted_tasks(client)
+        roots = None
+        if args.filter_indices:
+            roots = _select_tasks_for_filter(tasks, _effective_filter_indices(args.filter_indices))
+        print(json.dumps(_build_task_tree(tasks, roots, priority_letters=args.priority_letters, raw=args.raw), indent=2))
+    finally:
+        await client.close()
+
+
+def _build_tag_tree(tasks: Sequence[Task]) -> list[dict[str, Any]]:
+    """Nest tags on TAG_SEPARATOR, each node counting the tasks tagged with it or below it."""
+    counts: Counter[tuple[str, ...]] = Counter()
+    for task in tasks:
+        paths: set[tuple[str, ...]] = set()
+        for tag in task.data.tags:
+            parts = tuple(tag.split(TAG_SEPARATOR))
+            paths.update(parts[: depth + 1] for depth in range(len(parts)))
+        counts.update(paths)
+    roots: list[dict[str, Any]] = []
+    nodes: dict[tuple[str, ...], dict[str, Any]] = {}
+    # Sorted paths put every parent before its children
+    for path in sorted(counts):
+        node = {"name": path[-1], "tag": TAG_SEPARATOR.join(path), "count": counts[path], "children": []}
+        nodes[path] = node
+        (nodes[path[:-1]]["children"] if len(path) > 1 else roots).append(node)
+    return roots
+
+
+async def _handle_tag_tree(args: argparse.Namespace) -> None:
+    client = await _cache_client(args.env)
+    try:
+        tasks = await _select_tasks(client, args)
+    finally:
+        await client.close()
+    print(_json_text(_build_tag_tree(tasks), pretty=args.pretty))
+
+
+def _task_json(task: Task) -> dict[str, Any]:
+    """A task as one flat JSON object: index and uid, then every data field."""
+    return {"index": task.task_index, "uid": task.uid, **task.data.to_dict()}
+
+
+_TASK_JSON_FIELDS = tuple(_task_json(Task(uid="", data=TaskData())))
+
+
+def _parse_fields(raw: str) -> list[str]:
+    """Split a comma-separated --fields value, rejecting unknown names."""
+    fields = [name.strip() for name in raw.split(",") if name.strip()]
+    if not fields:
+        _exit_with_message("--fields requires at least one field name")
+    unknown = [name for name in fields if name not in _TASK_JSON_FIELDS]
+    if unknown:
+        _exit_with_message(
+            f"unknown field(s): {', '.join(unknown)} (expected {', '.join(_TASK_JSON_FIELDS)})"
         )
+    return fields
+
+
+async def _handle_show(args: argparse.Namespace) -> None:
+    fields = None
+    if args.fields is not None:
+        if args.format != "json":
+            _exit_with_message("--fields requires --format json")
+        fields = _parse_fields(args.fields)
+    if args.as_map and args.format != "json":
+        _exit_with_message("--as-map requires --format json")
+    if args.history and args.format == "table":
+        _exit_with_message("--history does not apply to --format table")
+    _check_envelope(args, args.format == "json", "--format json")
+    started = time.perf_counter()
+    client = await _cache_client(args.env)
+    try:
+        # Selection keeps the order indices were given in ("input")
+        tasks = await _select_tasks(client, args)
         if not tasks:
             _exit_with_message("no tasks match filter")
+        if args.sort != "input":
+            tasks = sorted(tasks, key=_SHOW_SORT_KEYS[args.sort])
+        refs = None
+        if args.resolve_parent:
+            # One lookup for every parent instead of one per task
+            refs = await client.resolve_task_refs([task.data.parent for task in tasks if task.data.parent])
+        histories = await _task_histories(client, tasks) if args.history else None
+        if args.format == "json":
+            rows = [_task_json(task) for task in tasks]
+            if refs is not None:
+                for row in rows:
+                    if row["parent"]:
+                        row["parent"] = _task_ref_json(row["parent"], refs)
+            if fields is not None:
+                rows = [{name: row[name] for name in fields} for row in rows]
+            if histories is not None:
+                for task, row in zip(tasks, rows):
+                    row["history"] = histories[task.uid]
+            output: list[dict[str, Any]] | dict[str, dict[str, Any]] = rows
+            if args.as_map:
+                # Keyed by index; a task without one falls back to its uid
+                output = {
+                    str(task.task_index) if task.task_index is not None else task.uid: row
+                    for task, row in zip(tasks, rows)
+                }
+            if args.envelope:
+                task_filter = getattr(args, "task_filter", None)
+                envelope = _json_envelope(output, total=len(rows), task_filter=task_filter, started=started)
+                print(json.dumps(envelope, indent=2))
+                return
+            print(json.dumps(output, indent=2))
+            return
+        if args.format == "table":
+            if args.raw:
+                _exit_with_message("--raw does not apply to --format table")
+            print(
+                _format_task_table(
+                    tasks,
+                    shutil.get_terminal_size().columns,
+                    priority_letters=args.priority_letters,
+                )
+            )
+            return
         for i, task in enumerate(tasks):
             if i > 0:
                 print()
-            print(_format_task_detail(task))
+            print(
+                _format_task_detail(
+                    task,
+                    with_relative=args.relative,
+                    priority_letters=args.priority_letters,
+                    raw=args.raw,
+                    refs=refs,
+                )
+            )
+            if histories is not None:
+                print(_format_task_history(histories[task.uid]))
     finally:
         await client.close()
 
@@ -868,45 +3084,19 @@ async def _handle_attach(args: argparse.Namespace) -> None:
             new_attachments = [a for a in existing_attachments if a.uri != args.url]
             if len(new_attachments) == len(existing_attachments):
                 _exit_with_message(f"attachment not found: {args.url}")
-            # Create a new task with filtered attachments
-            updated_data = TaskData(
-                summary=task.data.summary,
-                status=task.data.status,
-                due=task.data.due,
-                wait=task.data.wait,
-                priority=task.data.priority,
-                x_properties=task.data.x_properties,
-                categories=task.data.categories,
-                url=task.data.url,
-                attachments=new_attachments,
-            )
-            updated = Task(
-                uid=task.uid,
-                data=updated_data,
-                href=task.href,
-                task_index=task.task_index,
-            )
-            pending_action = await client.cache.get_pending_action(task.uid) if client.cache else None
-            action = "create" if pending_action == "create" else "update"
-            await client.cache.upsert_task(updated, pending_action=action)
+            # A patch only adds attachments, so store the filtered list directly
+            async with client.logged("attach"):
+                updated = await client.replace_task_data(task, replace(task.data, attachments=new_attachments))
             diff = TaskDiff(pre=task.data, post=updated.data)
             print(TaskSetDiff(diffs={task.task_index: diff}).pretty())
         else:
             # Add mode
             new_attachment = Attachment(uri=args.url, fmttype=args.fmttype)
             patch = TaskPatch(attachments=[new_attachment])
-            updated = await client.modify_task(task, patch)
+            async with client.logged("attach"):
+                updated = await client.modify_task(task, patch)
             diff = TaskDiff(pre=task.data, post=updated.data)
             print(TaskSetDiff(diffs={task.task_index: diff}).pretty())
-
-        # Log transaction
-        if client.cache:
-            uid_diff = TaskSetDiff(diffs={task.uid: diff})
-            await client.cache.log_transaction(
-                uid_diff,
-                operation="attach",
-                max_entries=client.config.cache.transaction_log_size,
-            )
     finally:
         await client.close()
 
@@ -968,10 +3158,53 @@ async def _handle_prioritize(args: argparse.Namespace) -> None:
         await client.close()
 
 
+async def _print_move_preview(
+    tasks: list[Task],
+    dest_client: "CalDAVClient",
+    source_env: str,
+    dest_env: str,
+    *,
+    as_json: bool,
+    pretty: bool,
+) -> None:
+    """Report what move would do without writing to either env.
+
+    Moved tasks get new UIDs, so the destination cannot collide on UID;
+    instead each task is matched against destination tasks with the same
+    summary (ignoring case), which is the usual sign of a repeated move.
+    """
+    existing: dict[str, Task] = {}
+    for task in await _sorted_tasks(dest_client):
+        existing.setdefault((task.data.summary or "").casefold(), task)
+    preview = [(task, existing.get((task.data.summary or "").casefold())) for task in tasks]
+    if as_json:
+        print(
+            _json_text(
+                {
+                    "dest_env": dest_env,
+                    "count": len(preview),
+                    "tasks": [
+                        {
+                            "uid": task.uid,
+                            "src_index": task.task_index,
+                            "summary": task.data.summary,
+                            "dest_duplicate_index": duplicate.task_index if duplicate else None,
+                        }
+                        for task, duplicate in preview
+                    ],
+                },
+                pretty=pretty,
+            )
+        )
+        return
+    print(f"Would move {len(preview)} task(s) from '{source_env}' to '{dest_env}':")
+    for task, duplicate in preview:
+        note = f" (same summary as [{duplicate.task_index}] in {dest_env})" if duplicate else ""
+        print(f"  [{task.task_index}] {task.data.summary}{note}")
+
+
 async def _handle_move(args: argparse.Namespace) -> None:
     """Move tasks from current environment to destination environment."""
-    from .caldav_client import CalDAVClient
-
     dest_env = args.dest_env
     source_env = args.env
 
@@ -989,8 +3222,8 @@ async def _handle_move(args: argparse.Namespace) -> None:
     # Get source client
     source_client = await _cache_client(source_env)
 
-    # Get destination client
-    dest_client = await CalDAVClient.create(dest_config)
+    # Get destination client; a dry run only reads it, so it is neither created nor migrated
+    dest_client = await _open_client(dest_config, read_only=args.dry_run, upgrade=not args.dry_run)
 
     try:
         # Select tasks from source
@@ -1001,74 +3234,70 @@ async def _handle_move(args: argparse.Namespace) -> None:
         if not tasks:
             _exit_with_message("no tasks match filter")
 
-        # Build index to uid mapping for source
-        index_to_uid: dict[int, str] = {
-            t.task_index: t.uid for t in tasks if t.task_index is not None
-        }
-
-        # Track diffs for transaction logging
-        source_diffs: dict[int, TaskDiff] = {}
-        dest_diffs: dict[int, TaskDiff] = {}
-        moved_tasks: list[tuple[Task, Task]] = []  # (source, dest) pairs
-
-        for task in tasks:
-            # Create payload for destination from source task data
-            payload = TaskPayload(
-                summary=task.data.summary,
-                status=task.data.status,
-                due=task.data.due,
-                wait=task.data.wait,
-                priority=task.data.priority,
-                x_properties=dict(task.data.x_properties),
-                categories=list(task.data.categories) if task.data.categories else None,
-                url=task.data.url,
-                attachments=list(task.data.attachments),
+        if args.dry_run:
+            await _print_move_preview(
+                tasks, dest_client, source_resolved, dest_env, as_json=args.json, pretty=args.pretty
             )
+            return
 
-            # Create in destination (gets new UID and index)
-            dest_task = await dest_client.create_task(payload)
+        moved_tasks: list[tuple[Task, Task, bool]] = []  # (source, dest, tombstone left) triples
+
+        # Each env logs its half of the move
+        async with source_client.logged("move-out"), dest_client.logged("move-in"):
+            for task in tasks:
+                # Create payload for destination from source task data
+                payload = TaskPayload(
+                    summary=task.data.summary,
+                    status=task.data.status,
+                    due=task.data.due,
+                    wait=task.data.wait,
+                    priority=task.data.priority,
+                    x_properties=dict(task.data.x_properties),
+                    categories=list(task.data.categories) if task.data.categories else None,
+                    url=task.data.url,
+                    attachments=list(task.data.attachments),
+                )
 
-            # Mark for deletion in source
-            await source_client.delete_task(task.uid)
+                # Create in destination (gets new UID and index)
+                dest_task = await dest_client.create_task(payload)
 
-            # Record diffs
-            if task.task_index is not None:
-                source_diffs[task.task_index] = TaskDiff(pre=task.data, post=None)
-            if dest_task.task_index is not None:
-                dest_diffs[dest_task.task_index] = TaskDiff(pre=None, post=dest_task.data)
-            moved_tasks.append((task, dest_task))
+                # Mark for deletion in source
+                tombstone = await source_client.delete_task(task.uid)
+                moved_tasks.append((task, dest_task, tombstone))
 
-        # Display results
-        print(f"Moved {len(moved_tasks)} task(s) from '{source_resolved}' to '{dest_env}':")
-        for src, dst in moved_tasks:
-            print(f"  [{src.task_index}] {src.data.summary} -> [{dst.task_index}] in {dest_env}")
+            # Copied inside the block so "move-in" stays the newest entry in the destination
+            copied = 0
+            if args.with_history:
+                history = await source_client.transaction_history([task.uid for task in tasks])
+                copied = await dest_client.import_transactions(
+                    history, {src.uid: dst.uid for src, dst, _ in moved_tasks}
+                )
 
-        # Log transaction for source environment
-        if source_client.cache and source_diffs:
-            source_uid_diff = TaskSetDiff(diffs=source_diffs).to_uid_keyed(
-                lambda idx: index_to_uid.get(idx, str(idx))
-            )
-            await source_client.cache.log_transaction(
-                source_uid_diff,
-                operation="move-out",
-                max_entries=source_client.config.cache.transaction_log_size,
+        if args.json:
+            print(
+                _json_text(
+                    [
+                        {
+                            "uid": src.uid,
+                            "src_index": src.task_index,
+                            "dest_uid": dst.uid,
+                            "dest_index": dst.task_index,
+                            "dest_env": dest_env,
+                            "tombstone": tombstone,
+                        }
+                        for src, dst, tombstone in moved_tasks
+                    ],
+                    pretty=args.pretty,
+                )
             )
+            return
 
-        # Log transaction for destination environment
-        if dest_client.cache and dest_diffs:
-            dest_index_to_uid: dict[int, str] = {
-                dst.task_index: dst.uid
-                for _, dst in moved_tasks
-                if dst.task_index is not None
-            }
-            dest_uid_diff = TaskSetDiff(diffs=dest_diffs).to_uid_keyed(
-                lambda idx: dest_index_to_uid.get(idx, str(idx))
-            )
-            await dest_client.cache.log_transaction(
-                dest_uid_diff,
-                operation="move-in",
-                max_entries=dest_client.config.cache.transaction_log_size,
-            )
+        # Display results
+        print(f"Moved {len(moved_tasks)} task(s) from '{source_resolved}' to '{dest_env}':")
+        for src, dst, _ in moved_tasks:
+            print(f"  [{src.task_index}] {src.data.summary} -> [{dst.task_index}] in {dest_env}")
+        if args.with_history:
+            print(f"Copied {copied} logged transaction(s) to '{dest_env}'")
 
     finally:
         await source_client.close()
@@ -1163,6 +3392,38 @@ async def _handle_undo(args: argparse.Namespace) -> None:
         inverse_diff = original_diff.inv()
         operation = entry.operation
 
+        if operation == "swap-index":
+            # Indices live outside the logged TaskData: swap the pair back
+            if len(original_diff.diffs) != 2:
+                _exit_with_message(
+                    f"cannot undo swap-index: the entry now names {len(original_diff.diffs)} task(s), not 2 "
+                    "(forget or move --with-history rewrote it)"
+                )
+            first_uid, second_uid = original_diff.diffs
+            try:
+                indices = await cache.swap_indices(first_uid, second_uid)
+            except KeyError as exc:
+                _exit_with_message(f"cannot undo swap-index: task {exc.args[0]} is no longer active")
+            print("Undid swap-index:")
+            for uid, index in zip((first_uid, second_uid), indices):
+                data = original_diff.diffs[uid].pre
+                summary = _truncate_summary((data.summary if data else None) or uid, SUMMARY_WIDTH)
+                print(f"  [{index}] {summary}")
+            return
+
+        if operation == "import":
+            # Every imported uid is logged, even a deleted row whose diff
+            # nets out to nothing: drop each one from whichever table holds it
+            async with cache.transaction():
+                for uid in original_diff.diffs:
+                    try:
+                        await cache.mark_for_deletion(uid)
+                    except KeyError:
+                        if await cache.get_deleted_task(uid):
+                            await cache.flush_deleted_tasks([uid])
+            print(f"Undid import of {len(original_diff.diffs)} task(s)")
+            return
+
         # Apply undo based on operation type
         for uid, diff in inverse_diff.diffs.items():
             if diff.is_noop:
@@ -1173,8 +3434,22 @@ async def _handle_undo(args: argparse.Namespace) -> None:
                 # The inverse diff has is_update with post.status != COMPLETED
                 if diff.is_update and diff.post and diff.post.status != "COMPLETED":
                     await cache.restore_from_completed(uid, status=diff.post.status or "NEEDS-ACTION")
-
-            elif operation == "delete":
+                # complete_mode "delete" dropped a never-synced task: create it again
+                elif diff.is_create and diff.post:
+                    await cache.upsert_task(Task(uid=uid, data=diff.post), pending_action="create")
+                    await cache.assign_index(uid)
+
+            elif operation == "reopen":
+                # Undo reopen: put the old data (and X-REOPENED count) back, then complete again
+                if diff.is_update and diff.post and diff.post.status == "COMPLETED":
+                    reopened = await cache.get_task(uid)
+                    if reopened:
+                        await cache.upsert_task(
+                            replace(reopened, data=diff.post), pending_action=await cache.get_pending_action(uid)
+                        )
+                        await cache.complete_task(uid)
+
+            elif operation in ("delete", "merge"):
                 # Undo delete: restore from deleted_tasks to tasks
                 if diff.is_create:
                     deleted_task = await cache.get_deleted_task(uid)
@@ -1186,11 +3461,19 @@ async def _handle_undo(args: argparse.Namespace) -> None:
                         for sql, params in sql_statements:
                             await cache._conn.execute(sql, params)
                         await cache._conn.commit()
+                else:
+                    # Revert the merged primary or orphaned subtasks to their original data
+                    sql_statements = TaskSetDiff(diffs={uid: diff}).as_sql()
+                    for sql, params in sql_statements:
+                        await cache._conn.execute(sql, params)
+                    await cache._conn.commit()
 
             elif operation == "add":
-                # Undo add: delete from tasks
+                # Undo add: an unpushed task just goes; a pushed one
+                # leaves a tombstone so the next push deletes it remotely
                 if diff.is_delete:
-                    await cache.delete_task(uid)
+                    with suppress(KeyError):
+                        await cache.mark_for_deletion(uid)
 
             else:
                 # Fallback for modify and other operations: use as_sql
@@ -1206,20 +3489,101 @@ async def _handle_undo(args: argparse.Namespace) -> None:
         await client.close()
 
 
+def _format_task_completion(task: Task, *, detailed: bool, now: datetime) -> str:
+    """Format one `index<TAB>description` completion candidate.
+
+    The detailed form appends priority, due date, and an overdue marker to
+    the summary so shell menus can show them.
+    """
+    summary = (task.data.summary or "")[:50]
+    if not detailed:
+        return f"{task.task_index}\t{summary}"
+    details: list[str] = []
+    if task.data.priority:
+        details.append(f"pri:{task.data.priority}")
+    if task.data.due is not None:
+        details.append(f"due:{_format_due_date(task.data.due)}")
+        if task.data.due < now:
+            details.append("OVERDUE")
+    if details:
+        summary = f"{summary} [{' '.join(details)}]"
+    return f"{task.task_index}\t{summary}"
+
+
+def _complete_project_paths(projects: set[str], prefix: str) -> set[str]:
+    """Complete "/"-separated project paths one segment at a time.
+
+    Like directory completion: for prefix "work/cl", projects "work/clientA"
+    and "work/clientB/web" yield "work/clientA" and "work/clientB/". A path
+    that is both a project and has sub-projects yields both forms.
+    """
+    base = prefix[: prefix.rfind("/") + 1]
+    candidates: set[str] = set()
+    for project in projects:
+        if not project.startswith(prefix):
+            continue
+        segment, slash, _ = project[len(base):].partition("/")
+        candidates.add(f"{base}{segment}{slash}")
+    return candidates
+
+
+def _completion_order(names: set[str], recency: Mapping[str, float] | None) -> list[str]:
+    """Names alphabetically, or most recently used first when recency is given.
+
+    A "/"-terminated project path takes the latest use of any project below it.
+    """
+    if recency is None:
+        return sorted(names)
+
+    def latest(name: str) -> float:
+        return max(
+            (used for key, used in recency.items() if key == name or (name.endswith("/") and key.startswith(name))),
+            default=0.0,
+        )
+
+    return sorted(names, key=lambda name: (-latest(name), name))
+
+
+def _fuzzy_rank(name: str, query: str) -> int | None:
+    """0 for a prefix match, 1 for a substring, 2 when query's letters appear in order, else None.
+
+    Case-insensitive, so "wrk" matches "Work".
+    """
+    lowered, needle = name.lower(), query.lower()
+    if lowered.startswith(needle):
+        return 0
+    if needle in lowered:
+        return 1
+    remaining = iter(lowered)
+    if all(char in remaining for char in needle):
+        return 2
+    return None
+
+
+def _filter_completions(names: list[str], query: str | None, *, exact: bool) -> list[str]:
+    """Names matching query, best rank first, keeping the given order within a rank.
+
+    An empty query keeps every name; exact keeps only prefix matches.
+    """
+    if not query:
+        return names
+    ranked: list[tuple[int, str]] = []
+    for name in names:
+        rank = _fuzzy_rank(name, query)
+        if rank is None or (exact and rank > 0):
+            continue
+        ranked.append((rank, name))
+    return [name for _, name in sorted(ranked, key=lambda item: item[0])]
+
+
 async def _handle_complete(args: argparse.Namespace) -> None:
     """Output completion data for shell autocompletion."""
     complete_type = args.complete_type
 
     if complete_type == "envs":
         # List available environment names from config files
-        config_home = Path.home() / ".config" / "tdo"
-        if config_home.exists():
-            for f in config_home.glob("config.*.toml"):
-                # Extract env name from config.<env>.toml
-                name = f.stem  # config.<env>
-                if name.startswith("config."):
-                    env_name = name[7:]  # Remove "config." prefix
-                    print(env_name)
+        for env_name in list_environments():
+            print(env_name)
 
     elif complete_type == "tasks":
         # List task indices with summaries
@@ -1227,10 +3591,10 @@ async def _handle_complete(args: argparse.Namespace) -> None:
             client = await _cache_client(args.env)
             try:
                 tasks = await client.list_active_tasks(exclude_waiting=False)
+                now = datetime.now()
                 for task in tasks:
                     if task.task_index is not None:
-                        summary = (task.data.summary or "")[:50]
-                        print(f"{task.task_index}\t{summary}")
+                        print(_format_task_completion(task, detailed=args.detailed, now=now))
             finally:
                 await client.close()
         except Exception:
@@ -1244,10 +3608,16 @@ async def _handle_complete(args: argparse.Namespace) -> None:
                 tasks = await client.list_active_tasks(exclude_waiting=False)
                 projects: set[str] = set()
                 for task in tasks:
-                    proj = task.data.x_properties.get("X-PROJECT")
+                    proj = task.data.project
                     if proj:
                         projects.add(proj)
-                for proj in sorted(projects):
+                if args.prefix is not None:
+                    projects = _complete_project_paths(projects, args.prefix)
+                recency = None
+                if args.sort == "recent":
+                    recency = (await client.tag_and_project_recency())["projects"]
+                ordered = _completion_order(projects, recency)
+                for proj in _filter_completions(ordered, args.query, exact=args.exact):
                     print(proj)
             finally:
                 await client.close()
@@ -1264,7 +3634,11 @@ async def _handle_complete(args: argparse.Namespace) -> None:
                 for task in tasks:
                     if task.data.categories:
                         tags.update(task.data.categories)
-                for tag in sorted(tags):
+                recency = None
+                if args.sort == "recent":
+                    recency = (await client.tag_and_project_recency())["tags"]
+                ordered = _completion_order(tags, recency)
+                for tag in _filter_completions(ordered, args.query, exact=args.exact):
                     print(tag)
             finally:
                 await client.close()
@@ -1278,7 +3652,7 @@ def _handle_config_init(args: argparse.Namespace) -> None:
     username_value = _require_value(args.username, "CalDAV username")
     password_value = args.password if args.password else None
     token_value = args.token if args.token else None
-    env = args.env if args.env else "default"
+    env = resolve_env(args.env, args.config_home)
     config = CaldavConfig(
         calendar_url=calendar_url_value,
         username=username_value,
@@ -1293,6 +3667,13 @@ def _handle_config_init(args: argparse.Namespace) -> None:
     print(f"created config file at {path}")
 
 
+def _handle_config_set_default_env(args: argparse.Namespace) -> None:
+    if args.name not in list_environments(args.config_home):
+        _exit_with_message(f"environment '{args.name}' is not configured; run `tdo --env {args.name} config init` first")
+    path = write_default_env(args.name, args.config_home)
+    print(f"default environment set to {args.name} in {path}")
+
+
 def _handle_config_help(args: argparse.Namespace) -> None:
     parser = getattr(args, "parser", None)
     if parser:
@@ -1301,33 +3682,258 @@ def _handle_config_help(args: argparse.Namespace) -> None:
         _exit_with_message("config command requires a subcommand")
 
 
+def _add_force_argument(parser: argparse.ArgumentParser) -> None:
+    parser.add_argument(
+        "--force",
+        dest="force",
+        action="store_true",
+        help="skip date checks (wait after due, due far in the past, --at in the future)",
+    )
+
+
+def _add_confirm_argument(parser: argparse.ArgumentParser) -> None:
+    parser.add_argument(
+        "--confirm",
+        dest="confirm",
+        action="store_true",
+        help="allow changing more tasks than the confirm_batch_size setting",
+    )
+
+
+def _add_priority_letters_argument(parser: argparse.ArgumentParser) -> None:
+    parser.add_argument(
+        "--priority-letters",
+        dest="priority_letters",
+        action="store_true",
+        help="also show priorities as H (1-3), M (4-6), or L (7-9)",
+    )
+
+
+def _add_raw_argument(parser: argparse.ArgumentParser) -> None:
+    parser.add_argument(
+        "--raw",
+        dest="raw",
+        action="store_true",
+        help="also print each task's X-properties verbatim as JSON",
+    )
+
+
+def _add_envelope_argument(parser: argparse.ArgumentParser) -> None:
+    parser.add_argument(
+        "--envelope",
+        dest="envelope",
+        action="store_true",
+        help="wrap the JSON array as {tasks, meta} with total and returned counts, filters, and elapsed_ms",
+    )
+
+
+def _add_idempotency_argument(parser: argparse.ArgumentParser) -> None:
+    parser.add_argument(
+        "--idempotency-key",
+        dest="idempotency_key",
+        default=None,
+        help="run at most once per KEY; a retry within the window replays the first result",
+    )
+
+
+def _add_relative_argument(parser: argparse.ArgumentParser) -> None:
+    parser.add_argument(
+        "--relative",
+        dest="relative",
+        action="store_true",
+        help="also show due dates relative to now (e.g. 'in 2 days')",
+    )
+
+
+def _add_all_envs_argument(parser: argparse.ArgumentParser) -> None:
+    parser.add_argument(
+        "--all-envs",
+        dest="all_envs",
+        action="store_true",
+        help="query every configured environment and merge the results",
+    )
+
+
 def _build_parser() -> argparse.ArgumentParser:
     parser = argparse.ArgumentParser(prog="tdo")
     parser.add_argument(
         "--version", action="version", version=f"%(prog)s {_get_version()}"
     )
     parser.add_argument("--env", dest="env", help="env name")
+    parser.add_argument(
+        "--pretty",
+        dest="pretty",
+        action="store_true",
+        help="indent JSON output for reading; compact single-line JSON is the default",
+    )
+    parser.add_argument(
+        "--timestamps",
+        dest="timestamps",
+        choices=["epoch", "iso"],
+        default="epoch",
+        help="write JSON times as epoch seconds (default) or RFC 3339 UTC strings",
+    )
     subparsers = parser.add_subparsers(dest="command")
 
     add_parser = subparsers.add_parser("add")
     add_parser.add_argument("tokens", nargs=argparse.REMAINDER, default=[], help="taskwarrior tokens")
+    add_parser.add_argument(
+        "--start",
+        dest="start",
+        action="store_true",
+        help="create the task already started (IN-PROCESS)",
+    )
+    add_parser.add_argument(
+        "--index",
+        dest="index",
+        type=int,
+        default=None,
+        help="create the task at this index instead of the next free one",
+    )
+    add_parser.add_argument(
+        "--index-taken",
+        dest="index_taken",
+        choices=["error", "next"],
+        default="error",
+        help="when --index is taken: fail (default) or use the next free index",
+    )
+    _add_force_argument(add_parser)
+    _add_idempotency_argument(add_parser)
     add_parser.set_defaults(func=_handle_add)
 
     modify_parser = subparsers.add_parser("modify")
     modify_parser.add_argument("tokens", nargs=argparse.REMAINDER, default=[], help="taskwarrior tokens")
+    modify_parser.add_argument(
+        "--append",
+        dest="append",
+        default=None,
+        help="add this text to the end of the summary",
+    )
+    modify_parser.add_argument(
+        "--append-separator",
+        dest="append_separator",
+        default=" ",
+        help="text placed between the summary and appended text (default: a space)",
+    )
+    _add_force_argument(modify_parser)
+    _add_idempotency_argument(modify_parser)
     modify_parser.set_defaults(func=_handle_modify)
 
     do_parser = subparsers.add_parser("do")
+    do_parser.add_argument("--note", dest="note", default=None, help="record why or how the task was finished")
+    do_parser.add_argument(
+        "--at",
+        dest="at",
+        default=None,
+        help="record the completion at this time instead of now (e.g. 2024-03-01, yesterday)",
+    )
+    do_parser.add_argument(
+        "--current",
+        dest="current",
+        action="store_true",
+        help="complete the one in-progress task (ignored when indices are given)",
+    )
+    do_parser.add_argument(
+        "--cascade",
+        dest="cascade",
+        action="store_true",
+        help="also complete all subtasks of the completed tasks",
+    )
+    do_parser.add_argument(
+        "--allow-completed",
+        dest="allow_completed",
+        action="store_true",
+        help="report indices whose task is already completed instead of failing, so retries succeed",
+    )
+    _add_force_argument(do_parser)
+    _add_confirm_argument(do_parser)
+    _add_idempotency_argument(do_parser)
     do_parser.set_defaults(func=_handle_do)
 
+    merge_parser = subparsers.add_parser("merge", help="merge duplicate tasks into the first index")
+    merge_parser.add_argument(
+        "--prefer-secondary",
+        dest="prefer_secondary",
+        action="store_true",
+        help="take conflicting priority/project/url from the secondary tasks instead of the primary",
+    )
+    _add_idempotency_argument(merge_parser)
+    merge_parser.set_defaults(func=_handle_merge)
+
     start_parser = subparsers.add_parser("start")
+    start_parser.add_argument(
+        "--force",
+        dest="force",
+        action="store_true",
+        help="with single_active set, start several tasks at once",
+    )
+    _add_idempotency_argument(start_parser)
     start_parser.set_defaults(func=_handle_start)
 
     stop_parser = subparsers.add_parser("stop")
+    _add_idempotency_argument(stop_parser)
     stop_parser.set_defaults(func=_handle_stop)
 
+    bump_parser = subparsers.add_parser("bump", help="raise priority one level (toward 1)")
+    _add_idempotency_argument(bump_parser)
+    bump_parser.set_defaults(func=_handle_bump)
+
+    lower_parser = subparsers.add_parser("lower", help="lower priority one level (toward 9)")
+    _add_idempotency_argument(lower_parser)
+    lower_parser.set_defaults(func=_handle_lower)
+
+    swap_parser = subparsers.add_parser("swap", help="exchange two tasks' indices or priorities")
+    swap_parser.add_argument(
+        "--field",
+        choices=("index", "priority"),
+        default="index",
+        help="what to exchange (default: index)",
+    )
+    _add_idempotency_argument(swap_parser)
+    swap_parser.set_defaults(func=_handle_swap)
+
     delete_parser = subparsers.add_parser("del")
-    delete_parser.set_defaults(func=_handle_delete)
+    children_group = delete_parser.add_mutually_exclusive_group()
+    children_group.add_argument(
+        "--cascade",
+        dest="children",
+        action="store_const",
+        const="cascade",
+        help="also delete all subtasks of the deleted tasks",
+    )
+    children_group.add_argument(
+        "--orphan",
+        dest="children",
+        action="store_const",
+        const="orphan",
+        help="keep subtasks as top-level tasks (the default unless delete_children is set)",
+    )
+    delete_parser.add_argument(
+        "--at",
+        dest="at",
+        default=None,
+        help="record the deletion at this time instead of now",
+    )
+    _add_force_argument(delete_parser)
+    _add_confirm_argument(delete_parser)
+    _add_idempotency_argument(delete_parser)
+    delete_parser.set_defaults(func=_handle_delete, children=None)
+
+    subtask_parser = subparsers.add_parser("subtask", help="create a task under the given parent index")
+    subtask_parser.add_argument("tokens", nargs=argparse.REMAINDER, default=[], help="taskwarrior tokens")
+    _add_force_argument(subtask_parser)
+    _add_idempotency_argument(subtask_parser)
+    subtask_parser.set_defaults(func=_handle_subtask)
+
+    tree_parser = subparsers.add_parser("tree", help="print tasks as nested JSON rooted at top-level tasks")
+    _add_priority_letters_argument(tree_parser)
+    _add_raw_argument(tree_parser)
+    tree_parser.set_defaults(func=_handle_tree)
+
+    tag_tree_parser = subparsers.add_parser(
+        "tag-tree", help="print tags nested on '::' as JSON, with task counts per level"
+    )
+    tag_tree_parser.set_defaults(func=_handle_tag_tree)
 
     list_parser = subparsers.add_parser("list")
     list_parser.add_argument(
@@ -1336,8 +3942,241 @@ def _build_parser() -> argparse.ArgumentParser:
         dest="no_reverse",
         help="disable reversed sort order (highest priority at top)",
     )
+    list_parser.add_argument(
+        "--activate-waited",
+        dest="activate_waited",
+        action="store_true",
+        help="clear the wait date on tasks whose wait has passed (marks them for push)",
+    )
+    list_parser.add_argument(
+        "--group-by",
+        dest="group_by",
+        choices=GROUP_BY_CHOICES,
+        default=None,
+        help="print one table per project, tag, status, or agenda due bucket",
+    )
+    list_parser.add_argument(
+        "--count-by",
+        dest="count_by",
+        choices=COUNT_BY_CHOICES,
+        default=None,
+        help="print how many tasks each project, tag, or status has instead of the tasks",
+    )
+    list_parser.add_argument(
+        "--json",
+        dest="json",
+        action="store_true",
+        help="with --count-by, print the counts as a JSON object",
+    )
+    list_parser.add_argument(
+        "--include-completed",
+        dest="include_completed",
+        action="store_true",
+        help="interleave completed tasks with active ones, ordered by when each last changed",
+    )
+    list_parser.add_argument(
+        "--last-changed",
+        dest="last_changed",
+        action="store_true",
+        help="show the current state of the tasks the newest undoable change touched",
+    )
+    _add_relative_argument(list_parser)
+    _add_all_envs_argument(list_parser)
     list_parser.set_defaults(func=_handle_list)
 
+    archive_parser = subparsers.add_parser("archive", help="move completed tasks into the archive")
+    archive_parser.add_argument(
+        "--days",
+        dest="days",
+        type=int,
+        default=None,
+        help="archive tasks completed more than N days ago",
+    )
+    archive_parser.add_argument(
+        "--uid",
+        dest="uids",
+        action="append",
+        default=None,
+        help="archive a specific completed task (repeatable)",
+    )
+    archive_parser.add_argument("--list", dest="list_only", action="store_true", help="list archived tasks")
+    _add_idempotency_argument(archive_parser)
+    archive_parser.set_defaults(func=_handle_archive)
+
+    completed_parser = subparsers.add_parser("completed", help="list completed tasks with their notes")
+    completed_parser.set_defaults(func=_handle_completed)
+
+    export_parser = subparsers.add_parser("export", help="write cached tasks as JSON Lines")
+    export_parser.add_argument(
+        "--output",
+        dest="output",
+        default=None,
+        help="stream to this file instead of stdout and report the bytes written",
+    )
+    export_parser.set_defaults(func=_handle_export)
+
+    import_parser = subparsers.add_parser("import", help="create tasks from a tdo export file")
+    import_parser.add_argument("file", help="JSON Lines file as written by tdo export")
+    import_parser.add_argument(
+        "--force",
+        action="store_true",
+        help="accept completed_at or deleted_at times in the future",
+    )
+    import_parser.set_defaults(func=_handle_import)
+
+    reopen_parser = subparsers.add_parser("reopen", help="move completed tasks back to the active list")
+    _add_idempotency_argument(reopen_parser)
+    reopen_parser.set_defaults(func=_handle_reopen)
+
+    search_parser = subparsers.add_parser("search", help="find tasks by summary, best match first")
+    search_parser.add_argument("text", nargs="+", help="text to look for in task summaries")
+    search_parser.add_argument("--limit", dest="limit", type=int, default=None, help="show at most N matches")
+    search_parser.add_argument(
+        "--weights",
+        dest="weights",
+        default=None,
+        help="rank by weighted matches in these fields, e.g. summary=3,project=2,tags=1 (default: summary only)",
+    )
+    search_parser.add_argument("--json", dest="json", action="store_true", help="print matches as JSON")
+    _add_envelope_argument(search_parser)
+    search_parser.add_argument(
+        "--include-deleted",
+        dest="include_deleted",
+        action="store_true",
+        help="also search deleted tasks that have not been pushed yet",
+    )
+    search_parser.add_argument(
+        "--include-archived",
+        dest="include_archived",
+        action="store_true",
+        help="also search archived tasks",
+    )
+    _add_priority_letters_argument(search_parser)
+    _add_all_envs_argument(search_parser)
+    search_parser.set_defaults(func=_handle_search)
+
+    validate_parser = subparsers.add_parser("validate", help="check the local cache for consistency problems")
+    validate_parser.add_argument("--json", dest="json", action="store_true", help="print problems as JSON")
+    validate_parser.set_defaults(func=_handle_validate)
+
+    info_parser = subparsers.add_parser("info", help="show the active environment and its cache file")
+    info_parser.add_argument("--json", dest="json", action="store_true", help="print the details as JSON")
+    info_parser.set_defaults(func=_handle_info)
+
+    repair_parser = subparsers.add_parser("repair", help="fix consistency problems found by validate")
+    repair_parser.add_argument(
+        "--dry-run",
+        dest="dry_run",
+        action="store_true",
+        help="report the fixes without applying them",
+    )
+    repair_parser.add_argument("--json", dest="json", action="store_true", help="print fixes as JSON")
+    repair_parser.set_defaults(func=_handle_repair)
+
+    forget_parser = subparsers.add_parser("forget", help="erase tasks by uid from every table and the undo log")
+    forget_parser.add_argument("uids", nargs="+", help="uids of the tasks to erase")
+    forget_parser.add_argument(
+        "--confirm",
+        dest="confirm",
+        action="store_true",
+        help="required: forgetting cannot be undone",
+    )
+    forget_parser.set_defaults(func=_handle_forget)
+
+    recompute_parser = subparsers.add_parser(
+        "recompute-dates", help="rederive the UTC due/wait columns after a timezone change"
+    )
+    recompute_parser.add_argument(
+        "--dry-run",
+        dest="dry_run",
+        action="store_true",
+        help="count the rows that would change without writing them",
+    )
+    recompute_parser.set_defaults(func=_handle_recompute_dates)
+
+    events_parser = subparsers.add_parser("events", help="show tasks that became overdue or stopped waiting")
+    events_parser.add_argument(
+        "--since",
+        dest="since",
+        required=True,
+        help="start of the interval: a time (@epoch, 2025-05-01 9am) or a duration back from now (15m)",
+    )
+    events_parser.add_argument("--json", dest="json", action="store_true", help="print the events and interval as JSON")
+    events_parser.set_defaults(func=_handle_events)
+
+    stale_parser = subparsers.add_parser("stale", help="show synced tasks not reconciled with the server recently")
+    stale_parser.add_argument(
+        "--days",
+        dest="days",
+        type=int,
+        default=DEFAULT_STALE_DAYS,
+        help=f"report tasks last synced more than N days ago (default: {DEFAULT_STALE_DAYS})",
+    )
+    stale_parser.add_argument("--json", dest="json", action="store_true", help="print stale tasks as JSON")
+    stale_parser.set_defaults(func=_handle_stale)
+
+    next_parser = subparsers.add_parser("next", help="show the task to work on next")
+    next_parser.add_argument(
+        "--order",
+        dest="order",
+        default=None,
+        help=f"comma-separated sort keys, most significant first, from {', '.join(NEXT_SORT_KEYS)} (config: [next] order)",
+    )
+    next_parser.add_argument("--count", dest="count", type=int, default=1, help="show the top N tasks")
+    next_parser.set_defaults(func=_handle_next)
+
+    agenda_parser = subparsers.add_parser("agenda", help="show tasks grouped by due-date horizon")
+    agenda_parser.add_argument(
+        "--soon-days",
+        dest="soon_days",
+        type=int,
+        default=None,
+        help="tasks due within this many days land in 'Soon' (config: [agenda] soon_days)",
+    )
+    agenda_parser.add_argument(
+        "--week-days",
+        dest="week_days",
+        type=int,
+        default=None,
+        help="tasks due within this many days land in 'This week' (config: [agenda] week_days)",
+    )
+    _add_all_envs_argument(agenda_parser)
+    agenda_parser.set_defaults(func=_handle_agenda)
+
+    workload_parser = subparsers.add_parser("workload", help="sum estimated minutes over filtered tasks")
+    workload_parser.add_argument("--json", dest="json", action="store_true", help="print the workload as JSON")
+    workload_parser.set_defaults(func=_handle_workload)
+
+    stats_parser = subparsers.add_parser("stats", help="show task counts per project and tag")
+    stats_parser.add_argument(
+        "--include-completed",
+        dest="include_completed",
+        action="store_true",
+        help="count completed tasks as well",
+    )
+    stats_parser.add_argument(
+        "--by-day",
+        dest="by_day",
+        action="store_true",
+        help="count completed tasks per local day instead",
+    )
+    stats_parser.add_argument(
+        "--time",
+        dest="time",
+        action="store_true",
+        help="sum time spent per project and tag instead",
+    )
+    stats_parser.add_argument(
+        "--days",
+        dest="days",
+        type=int,
+        default=30,
+        help="with --by-day, how many days back to count, including today (default 30)",
+    )
+    stats_parser.add_argument("--json", dest="json", action="store_true", help="print counts as JSON")
+    _add_all_envs_argument(stats_parser)
+    stats_parser.set_defaults(func=_handle_stats)
+
     waiting_parser = subparsers.add_parser("waiting")
     waiting_parser.set_defaults(func=_handle_wait)
 
@@ -1369,9 +4208,52 @@ def _build_parser() -> argparse.ArgumentParser:
     sync_parser.set_defaults(func=_handle_sync)
 
     show_parser = subparsers.add_parser("show")
+    show_parser.add_argument(
+        "--sort",
+        dest="sort",
+        choices=["input", "index", "due", "priority"],
+        default="input",
+        help="order of shown tasks: as given on the command line (default), by index, by due date, or by priority",
+    )
+    show_parser.add_argument(
+        "--format",
+        dest="format",
+        choices=["detail", "table", "json"],
+        default="detail",
+        help="print every field of each task (default), one aligned row per task, or a JSON array",
+    )
+    show_parser.add_argument(
+        "--fields",
+        dest="fields",
+        default=None,
+        help="with --format json, only include these comma-separated fields (e.g. index,summary,priority)",
+    )
+    show_parser.add_argument(
+        "--as-map",
+        dest="as_map",
+        action="store_true",
+        help="with --format json, print an object keyed by task index instead of an array",
+    )
+    _add_envelope_argument(show_parser)
+    show_parser.add_argument(
+        "--history",
+        dest="history",
+        action="store_true",
+        help="also list each task's logged changes, oldest first",
+    )
+    show_parser.add_argument(
+        "--resolve-parent",
+        dest="resolve_parent",
+        action="store_true",
+        help="name each parent task by index and summary instead of its uid",
+    )
+    _add_relative_argument(show_parser)
+    _add_priority_letters_argument(show_parser)
+    _add_raw_argument(show_parser)
     show_parser.set_defaults(func=_handle_show)
 
     undo_parser = subparsers.add_parser("undo")
+    _add_idempotency_argument(undo_parser)
     undo_parser.set_defaults(func=_handle_undo)
 
     attach_parser = subparsers.add_parser("attach")
@@ -1379,6 +4261,7 @@ def _build_parser() -> argparse.ArgumentParser:
     attach_parser.add_argument("--fmttype", dest="fmttype", help="MIME type for attachment")
     attach_parser.add_argument("--remove", dest="remove", action="store_true", help="remove attachment")
     attach_parser.add_argument("--list", dest="list_only", action="store_true", help="list attachments")
+    _add_idempotency_argument(attach_parser)
     attach_parser.set_defaults(func=_handle_attach)
 
     prioritize_parser = subparsers.add_parser("prioritize")
@@ -1386,10 +4269,60 @@ def _build_parser() -> argparse.ArgumentParser:
 
     move_parser = subparsers.add_parser("move")
     move_parser.add_argument("dest_env", help="destination environment name")
+    move_parser.add_argument(
+        "--json",
+        dest="json",
+        action="store_true",
+        help="print each task's source and destination index as JSON",
+    )
+    move_parser.add_argument(
+        "--dry-run",
+        dest="dry_run",
+        action="store_true",
+        help="list the tasks that would move, and same-summary tasks already in the destination",
+    )
+    move_parser.add_argument(
+        "--with-history",
+        dest="with_history",
+        action="store_true",
+        help="copy the moved tasks' transaction log entries to the destination, so undo history travels with them",
+    )
+    _add_idempotency_argument(move_parser)
     move_parser.set_defaults(func=_handle_move)
 
     complete_parser = subparsers.add_parser("complete", help="output completion data for shell autocompletion")
     complete_parser.add_argument("complete_type", choices=["envs", "tasks", "projects", "tags"], help="type of completion data")
+    complete_parser.add_argument(
+        "--detailed",
+        dest="detailed",
+        action="store_true",
+        help="include priority, due date, and overdue marker in task descriptions",
+    )
+    complete_parser.add_argument(
+        "--prefix",
+        dest="prefix",
+        default=None,
+        help="for projects: only complete the next '/'-separated segment of paths starting with PREFIX",
+    )
+    complete_parser.add_argument(
+        "--sort",
+        dest="sort",
+        choices=["alpha", "recent"],
+        default="alpha",
+        help="for projects and tags: alphabetical (default) or most recently changed tasks first",
+    )
+    complete_parser.add_argument(
+        "--query",
+        dest="query",
+        default=None,
+        help="for projects and tags: keep names matching QUERY by prefix, substring, or letters in order",
+    )
+    complete_parser.add_argument(
+        "--exact",
+        dest="exact",
+        action="store_true",
+        help="with --query: only keep names that start with QUERY",
+    )
     complete_parser.set_defaults(func=_handle_complete)
 
     config_parser = subparsers.add_parser("config")
@@ -1410,9 +4343,162 @@ def _build_parser() -> argparse.ArgumentParser:
     init_parser.add_argument("--force", dest="force", action="store_true", help="overwrite existing config")
     init_parser.set_defaults(func=_handle_config_init, parser=config_parser)
 
+    default_env_parser = config_subparsers.add_parser(
+        "set-default-env", help="set the env used when neither --env nor TDO_ENV is given"
+    )
+    default_env_parser.add_argument("name", help="name of a configured environment")
+    default_env_parser.add_argument(
+        "--config-home",
+        dest="config_home",
+        type=Path,
+        default=None,
+        help="override the config directory",
+    )
+    default_env_parser.set_defaults(func=_handle_config_set_default_env, parser=config_parser)
+
     return parser
 
 
+class _Tee(io.TextIOBase):
+    """Write-only stream that copies output to several streams."""
+
+    def __init__(self, *streams: TextIO) -> None:
+        self._streams = streams
+
+    def writable(self) -> bool:
+        return True
+
+    def write(self, text: str) -> int:
+        for stream in self._streams:
+            stream.write(text)
+        return len(text)
+
+    def flush(self) -> None:
+        for stream in self._streams:
+            stream.flush()
+
+
+def _idempotency_command(argv: Sequence[str]) -> str:
+    """The invocation minus its --idempotency-key, used to detect key reuse."""
+    kept: list[str] = []
+    skip = False
+    for token in argv:
+        if skip:
+            skip = False
+        elif token == "--idempotency-key":
+            skip = True
+        elif not token.startswith("--idempotency-key="):
+            kept.append(token)
+    return json.dumps(kept)
+
+
+async def _run_idempotent(
+    args: argparse.Namespace,
+    handler: Callable[[argparse.Namespace], Awaitable[None]],
+    command: str,
+) -> None:
+    """Run a mutating handler at most once per --idempotency-key.
+
+    The key is claimed before the handler runs, so two runs racing on it
+    cannot both mutate. A repeat within the window prints the original
+    output instead of mutating again, and one that finds the claim still
+    pending stops. Failed runs drop their claim, so they can be retried.
+    """
+    key = args.idempotency_key
+    # Kept with the tasks: a routed add records its key in the env it lands in
+    env = _landing_env(args)
+    client = await _cache_client(env)
+    try:
+        previous = await client.claim_idempotency_key(key, command)
+    finally:
+        await client.close()
+    if previous is not None:
+        if previous.command != command:
+            _exit_with_message(f"idempotency key {key} was already used for a different command")
+        if previous.pending:
+            _exit_with_message(f"idempotency key {key} is still running in another command; retry once it finishes")
+        sys.stdout.write(previous.result)
+        return
+    captured = io.StringIO()
+    try:
+        with redirect_stdout(_Tee(sys.stdout, captured)):
+            await handler(args)
+    except BaseException:
+        client = await _cache_client(env)
+        try:
+            await client.release_idempotency_key(key)
+        finally:
+            await client.close()
+        raise
+    client = await _cache_client(env)
+    try:
+        await client.record_idempotent_result(key, command, captured.getvalue())
+    finally:
+        await client.close()
+
+
+# Commands that write to the cache. They hold the env lock while they run so a
+# background sync and an interactive edit can't interleave their changes.
+_MUTATING_COMMANDS = {"add", "archive", "attach", "bump", "del", "do", "forget", "import", "lower", "merge", "modify", "move", "prioritize", "pull", "push", "recompute-dates", "reopen", "repair", "start", "stop", "subtask", "swap", "sync", "undo"}
+
+
+def _is_mutating(args: argparse.Namespace) -> bool:
+    if getattr(args, "dry_run", False):
+        return False
+    if args.command == "list":
+        return bool(getattr(args, "activate_waited", False))
+    return args.command in _MUTATING_COMMANDS
+
+
+# Commands that only read the cache. Unless _is_mutating says otherwise they open
+# it read-only, so frequent polling never takes a write lock.
+_READ_ONLY_COMMANDS = {"complete", "list", "search", "show", "stats", "tag-tree"}
+
+
+def _opens_read_only(args: argparse.Namespace) -> bool:
+    return args.command in _READ_ONLY_COMMANDS and not _is_mutating(args)
+
+
+def _landing_env(args: argparse.Namespace) -> str | None:
+    """The env a command's tasks end up in: where add is routed to, else its own."""
+    if args.command == "add":
+        return _route_env(args.env, _add_payload(args))
+    return args.env
+
+
+def _written_envs(args: argparse.Namespace) -> list[str | None]:
+    """Every env a mutating command writes to: its own, move's destination, or add's routed env."""
+    if args.command == "move":
+        return [args.env, args.dest_env]
+    return [args.env, _landing_env(args)]
+
+
+@asynccontextmanager
+async def _mutation_lock(args: argparse.Namespace) -> AsyncIterator[None]:
+    """Hold the lock of every env the command writes to; read commands run unlocked.
+
+    Locks are taken in lock-path order, so two commands writing to the same
+    pair of envs can't each hold one and wait for the other.
+    """
+    if not _is_mutating(args):
+        yield
+        return
+    from .sqlite_cache import CacheUnavailableError, EnvironmentBusyError, SqliteTaskCache, environment_lock
+
+    config = _resolve_config(args.env)
+    own, *others = _written_envs(args)
+    envs = {config.env, *(env for env in others if env is not None and env != own)}
+    # Envs sharing one cache file share its lock, which must only be taken once
+    paths = sorted({SqliteTaskCache.lock_path(env=env or "default") for env in envs})
+    async with AsyncExitStack() as stack:
+        try:
+            for path in paths:
+                await stack.enter_async_context(environment_lock(path, config.cache.lock_timeout_seconds))
+        except (CacheUnavailableError, EnvironmentBusyError) as exc:
+            _exit_with_message(str(exc))
+        yield
+
+
 async def _async_main(argv: Sequence[str] | None = None) -> int:
     input_args = list(argv if argv is not None else sys.argv[1:])
     filter_tokens, command_tokens = _split_filter_and_command(input_args)
@@ -1425,6 +4511,7 @@ async def _async_main(argv: Sequence[str] | None = None) -> int:
         else:
             parser.error(f"unrecognized arguments: {' '.join(remaining)}")
     # Parse filter tokens into TaskFilter
+    filter_tokens = await _resolve_index_selectors(filter_tokens, args.env)
     args.task_filter = _parse_task_filter(filter_tokens)
     # Backward compatibility: extract indices for commands that use filter_indices
     if args.task_filter and args.task_filter.indices:
@@ -1435,10 +4522,17 @@ async def _async_main(argv: Sequence[str] | None = None) -> int:
     if handler is None:
         parser.print_help()
         return 0
-    if asyncio.iscoroutinefunction(handler):
-        await handler(args)
-    else:
-        handler(args)
+    read_only_token = _read_only_cache.set(_opens_read_only(args))
+    try:
+        async with _mutation_lock(args):
+            if getattr(args, "idempotency_key", None):
+                await _run_idempotent(args, handler, _idempotency_command(input_args))
+            elif asyncio.iscoroutinefunction(handler):
+                await handler(args)
+            else:
+                handler(args)
+    finally:
+        _read_only_cache.reset(read_only_token)
     return 0
 
 
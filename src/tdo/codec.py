@@ -0,0 +1,76 @@
+from __future__ import annotations
+
+import json
+from typing import Sequence
+
+from .models import Attachment
+
+
+__all__ = [
+    "decode_attachments",
+    "decode_categories",
+    "decode_x_properties",
+    "encode_attachments",
+    "encode_categories",
+    "encode_x_properties",
+]
+
+
+def encode_categories(value: Sequence[str] | None) -> str:
+    """Encode categories (tags) as a JSON array."""
+    return json.dumps(list(value or []))
+
+
+def encode_x_properties(value: dict[str, str] | None) -> str:
+    """Encode X-properties as a JSON object."""
+    return json.dumps(value or {})
+
+
+def encode_attachments(attachments: Sequence[Attachment] | None) -> str:
+    """Encode attachments as a JSON array of {uri, fmttype} objects."""
+    if not attachments:
+        return "[]"
+    return json.dumps([{"uri": a.uri, "fmttype": a.fmttype} for a in attachments])
+
+
+def decode_categories(raw: str | None) -> list[str]:
+    """Decode a categories column; anything that is not a JSON array is empty."""
+    if not raw:
+        return []
+    try:
+        payload = json.loads(raw)
+    except json.JSONDecodeError:
+        return []
+    if isinstance(payload, list):
+        return [str(item) for item in payload if item is not None]
+    return []
+
+
+def decode_x_properties(raw: str | None) -> dict[str, str]:
+    """Decode an X-properties column; anything that is not a JSON object is empty."""
+    if not raw:
+        return {}
+    try:
+        payload = json.loads(raw)
+    except json.JSONDecodeError:
+        return {}
+    if isinstance(payload, dict):
+        return {str(key): str(value) for key, value in payload.items()}
+    return {}
+
+
+def decode_attachments(raw: str | None) -> list[Attachment]:
+    """Decode an attachments column, skipping entries that are not objects."""
+    if not raw:
+        return []
+    try:
+        payload = json.loads(raw)
+    except json.JSONDecodeError:
+        return []
+    if not isinstance(payload, list):
+        return []
+    return [
+        Attachment(uri=item.get("uri", ""), fmttype=item.get("fmttype"))
+        for item in payload
+        if isinstance(item, dict)
+    ]
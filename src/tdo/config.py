@@ -3,19 +3,84 @@ from __future__ import annotations
 import getpass
 import json
 import os
+import re
 import tomllib
 from dataclasses import dataclass, field
+from string import Formatter
 from pathlib import Path
 from typing import Iterable, Tuple, Union
 
 import keyring
 
 DEFAULT_TRANSACTION_LOG_SIZE = 32
+DEFAULT_TRANSACTION_LOG_MAX_AGE_DAYS = 0
+DEFAULT_IDEMPOTENCY_WINDOW_HOURS = 24
+DEFAULT_LOCK_TIMEOUT_SECONDS = 10
+DEFAULT_AGENDA_SOON_DAYS = 3
+DEFAULT_AGENDA_WEEK_DAYS = 7
+DEFAULT_MAX_PAST_DUE_DAYS = 3650
+DEFAULT_CONFIRM_BATCH_SIZE = 20
+DELETE_CHILDREN_MODES = ("orphan", "cascade")
+DEFAULT_DELETE_CHILDREN = "orphan"
+COMPLETE_MODES = ("archive", "delete")
+DEFAULT_COMPLETE_MODE = "archive"
+MODIFY_WORDS_MODES = ("replace", "append")
+DEFAULT_MODIFY_WORDS = "replace"
+OPEN_CHILDREN_MODES = ("warn", "error")
+DEFAULT_OPEN_CHILDREN = "warn"
+DEFAULT_UID_TEMPLATE = "{summary}-{uuid}"
+UID_TEMPLATE_FIELDS = ("summary", "uuid")
+# Literal template text must stay safe inside an iCalendar UID and a resource URL
+_UID_TEMPLATE_TEXT = re.compile(r"[A-Za-z0-9@._~+=-]*")
+NEXT_SORT_KEYS = ("overdue", "priority", "due", "index")
+DEFAULT_NEXT_ORDER = NEXT_SORT_KEYS
 
 
 @dataclass
 class CacheConfig:
     transaction_log_size: int = DEFAULT_TRANSACTION_LOG_SIZE
+    # Drop undo history older than this many days (0 keeps entries regardless of age)
+    transaction_log_max_age_days: int = DEFAULT_TRANSACTION_LOG_MAX_AGE_DAYS
+    # How long a repeated --idempotency-key replays the original result
+    idempotency_window_hours: int = DEFAULT_IDEMPOTENCY_WINDOW_HOURS
+    # How long a mutating command waits for another one to release the env
+    lock_timeout_seconds: int = DEFAULT_LOCK_TIMEOUT_SECONDS
+
+
+@dataclass
+class AgendaConfig:
+    """Bucket horizons (in days from today) used by `tdo agenda`."""
+
+    soon_days: int = DEFAULT_AGENDA_SOON_DAYS
+    week_days: int = DEFAULT_AGENDA_WEEK_DAYS
+
+
+@dataclass
+class NextConfig:
+    """How `tdo next` ranks tasks: sort keys from NEXT_SORT_KEYS, most significant first."""
+
+    order: list[str] = field(default_factory=lambda: list(DEFAULT_NEXT_ORDER))
+
+
+@dataclass
+class ValidationConfig:
+    """Guard rails applied when tasks are created or modified."""
+
+    # Reject new tasks due more than this many days ago (0 disables the check)
+    max_past_due_days: int = DEFAULT_MAX_PAST_DUE_DAYS
+    # `do` and `del` on more tasks than this need --confirm (0 disables the check)
+    confirm_batch_size: int = DEFAULT_CONFIRM_BATCH_SIZE
+    # Tags and projects `add` and `modify` accept (empty allows any)
+    allowed_tags: list[str] = field(default_factory=list)
+    allowed_projects: list[str] = field(default_factory=list)
+
+
+@dataclass
+class RoutingConfig:
+    """Environments that `tdo add` sends new tasks to, by project or tag."""
+
+    tags: dict[str, str] = field(default_factory=dict)
+    projects: dict[str, str] = field(default_factory=dict)
 
 
 @dataclass
@@ -26,7 +91,23 @@ class CaldavConfig:
     token: str | None = None
     env: str = "default"
     show_uids: bool = False
+    # What `tdo del` does with subtasks of a deleted task: "orphan" or "cascade"
+    delete_children: str = DEFAULT_DELETE_CHILDREN
+    # What `tdo do` does with never-synced tasks: "archive" keeps them as completed, "delete" drops them
+    complete_mode: str = DEFAULT_COMPLETE_MODE
+    # What `tdo do` without --cascade does when a task still has open subtasks: "warn" or "error"
+    open_children: str = DEFAULT_OPEN_CHILDREN
+    # What bare words do in `tdo modify`: "replace" the summary or "append" to it
+    modify_words: str = DEFAULT_MODIFY_WORDS
+    # How `tdo add` names new tasks; {summary} and {uuid} are filled in, e.g. "{uuid}@myhost"
+    uid_template: str = DEFAULT_UID_TEMPLATE
+    # When set, `tdo start` stops every other IN-PROCESS task so only one runs at a time
+    single_active: bool = False
     cache: CacheConfig = field(default_factory=CacheConfig)
+    agenda: AgendaConfig = field(default_factory=AgendaConfig)
+    next: NextConfig = field(default_factory=NextConfig)
+    validation: ValidationConfig = field(default_factory=ValidationConfig)
+    routing: RoutingConfig = field(default_factory=RoutingConfig)
 
     @property
     def keyring_service(self) -> str:
@@ -43,16 +124,55 @@ class CaldavConfig:
 
 
 
-def resolve_env(env: str | None = None) -> str:
+def _config_home(config_home: Path | None = None) -> Path:
+    return config_home or Path.home() / ".config" / "tdo"
+
+
+def default_env_path(config_home: Path | None = None) -> Path:
+    """Top-level file holding the env used when none is given."""
+    return _config_home(config_home) / "defaults.toml"
+
+
+def read_default_env(config_home: Path | None = None) -> str | None:
+    path = default_env_path(config_home)
+    if not path.exists():
+        return None
+    try:
+        data = tomllib.loads(path.read_text())
+    except tomllib.TOMLDecodeError:
+        return None
+    env = data.get("env")
+    return env if isinstance(env, str) and env else None
+
+
+def write_default_env(env: str, config_home: Path | None = None) -> Path:
+    path = default_env_path(config_home)
+    path.parent.mkdir(parents=True, exist_ok=True)
+    path.write_text(f"env = {json.dumps(env)}\n")
+    return path
+
+
+def resolve_env(env: str | None = None, config_home: Path | None = None) -> str:
+    """Resolve the env name: explicit env > TDO_ENV > defaults.toml > "default"."""
     if env is not None:
         return env
-    return os.environ.get("TDO_ENV", "default")
+    from_environ = os.environ.get("TDO_ENV")
+    if from_environ:
+        return from_environ
+    return read_default_env(config_home) or "default"
 
 
 def config_file_path(env: str | None = None, config_home: Path | None = None) -> Path:
-    base_home = config_home or Path.home() / ".config" / "tdo"
-    resolved_env = resolve_env(env)
-    return base_home / f"config.{resolved_env}.toml"
+    resolved_env = resolve_env(env, config_home)
+    return _config_home(config_home) / f"config.{resolved_env}.toml"
+
+
+def list_environments(config_home: Path | None = None) -> list[str]:
+    """Return the env names that have a config.<env>.toml file, sorted."""
+    base_home = _config_home(config_home)
+    if not base_home.exists():
+        return []
+    return sorted(path.stem[len("config."):] for path in base_home.glob("config.*.toml"))
 
 
 def write_config_file(path: Path, config: CaldavConfig, *, force: bool = False) -> Path:
@@ -69,12 +189,60 @@ def write_config_file(path: Path, config: CaldavConfig, *, force: bool = False)
         lines.append(f"token = {json.dumps(config.token)}")
     if config.env:
         lines.append(f"env = {json.dumps(config.env)}")
+    if config.delete_children != DEFAULT_DELETE_CHILDREN:
+        lines.append(f"delete_children = {json.dumps(config.delete_children)}")
+    if config.complete_mode != DEFAULT_COMPLETE_MODE:
+        lines.append(f"complete_mode = {json.dumps(config.complete_mode)}")
+    if config.open_children != DEFAULT_OPEN_CHILDREN:
+        lines.append(f"open_children = {json.dumps(config.open_children)}")
+    if config.modify_words != DEFAULT_MODIFY_WORDS:
+        lines.append(f"modify_words = {json.dumps(config.modify_words)}")
+    if config.uid_template != DEFAULT_UID_TEMPLATE:
+        lines.append(f"uid_template = {json.dumps(config.uid_template)}")
+    if config.single_active:
+        lines.append("single_active = true")
 
     # Write cache section if non-default
-    if config.cache.transaction_log_size != DEFAULT_TRANSACTION_LOG_SIZE:
+    if config.cache != CacheConfig():
         lines.append("")
         lines.append("[cache]")
         lines.append(f"transaction_log_size = {config.cache.transaction_log_size}")
+        lines.append(f"transaction_log_max_age_days = {config.cache.transaction_log_max_age_days}")
+        lines.append(f"idempotency_window_hours = {config.cache.idempotency_window_hours}")
+        lines.append(f"lock_timeout_seconds = {config.cache.lock_timeout_seconds}")
+
+    # Write agenda section if non-default
+    if config.agenda != AgendaConfig():
+        lines.append("")
+        lines.append("[agenda]")
+        lines.append(f"soon_days = {config.agenda.soon_days}")
+        lines.append(f"week_days = {config.agenda.week_days}")
+
+    # Write next section if non-default
+    if config.next != NextConfig():
+        lines.append("")
+        lines.append("[next]")
+        lines.append(f"order = {json.dumps(config.next.order)}")
+
+    # Write validation section if non-default
+    if config.validation != ValidationConfig():
+        lines.append("")
+        lines.append("[validation]")
+        lines.append(f"max_past_due_days = {config.validation.max_past_due_days}")
+        lines.append(f"confirm_batch_size = {config.validation.confirm_batch_size}")
+        if config.validation.allowed_tags:
+            lines.append(f"allowed_tags = {json.dumps(config.validation.allowed_tags)}")
+        if config.validation.allowed_projects:
+            lines.append(f"allowed_projects = {json.dumps(config.validation.allowed_projects)}")
+
+    # Write routing tables if any rules are set
+    for kind, routes in (("tags", config.routing.tags), ("projects", config.routing.projects)):
+        if not routes:
+            continue
+        lines.append("")
+        lines.append(f"[routing.{kind}]")
+        for name, target in routes.items():
+            lines.append(f"{json.dumps(name)} = {json.dumps(target)}")
 
     path.write_text("\n".join(lines) + "\n")
     return path
@@ -91,9 +259,9 @@ def _parse_config_file(path: Path) -> Iterable[Tuple[str, str]]:
         yield key.strip().lower(), value.strip()
 
 
-def _parse_toml_file(path: Path) -> dict[str, Union[str, bool, int]]:
+def _parse_toml_file(path: Path) -> dict[str, Union[str, bool, int, dict[str, str], list[str]]]:
     data = tomllib.loads(path.read_text())
-    result: dict[str, Union[str, bool, int]] = {}
+    result: dict[str, Union[str, bool, int, dict[str, str], list[str]]] = {}
 
     # Parse [caldav] section
     caldav_section = data.get("caldav")
@@ -103,18 +271,28 @@ def _parse_toml_file(path: Path) -> dict[str, Union[str, bool, int]]:
                 continue
             result[key.lower()] = value
 
-    # Parse [cache] section
-    cache_section = data.get("cache")
-    if isinstance(cache_section, dict):
-        for key, value in cache_section.items():
+    # Parse [cache], [agenda], [next], and [validation] sections into prefixed keys
+    for section_name in ("cache", "agenda", "next", "validation"):
+        section = data.get(section_name)
+        if not isinstance(section, dict):
+            continue
+        for key, value in section.items():
             if value is None:
                 continue
-            result[f"cache.{key.lower()}"] = value
+            result[f"{section_name}.{key.lower()}"] = value
+
+    # Parse [routing.tags] and [routing.projects] tables, keeping name case
+    routing_section = data.get("routing")
+    if isinstance(routing_section, dict):
+        for kind in ("tags", "projects"):
+            table = routing_section.get(kind)
+            if isinstance(table, dict):
+                result[f"routing.{kind}"] = {str(name): str(env) for name, env in table.items() if env}
 
     return result
 
 
-def _load_file_values(path: Path) -> dict[str, Union[str, bool, int]]:
+def _load_file_values(path: Path) -> dict[str, Union[str, bool, int, dict[str, str], list[str]]]:
     if path.suffix == ".toml":
         return _parse_toml_file(path)
     return dict(_parse_config_file(path))
@@ -131,6 +309,30 @@ def _retrieve_password_from_keyring(service: str, username: str) -> str | None:
         return None
 
 
+def uid_template_problem(template: str) -> str | None:
+    """Why template cannot name new tasks, or None when it can.
+
+    It must contain {uuid}, so every uid is unique, and may also use
+    {summary}. Other text is limited to letters, digits, and @._~+=-.
+    """
+    try:
+        parts = list(Formatter().parse(template))
+    except ValueError as exc:
+        return str(exc)
+    fields = [name for _, name, _, _ in parts if name is not None]
+    unknown = [name for name in fields if name not in UID_TEMPLATE_FIELDS]
+    if unknown:
+        return f"unknown placeholder {{{unknown[0]}}} (expected {{summary}} or {{uuid}})"
+    if "uuid" not in fields:
+        return "must contain {uuid}"
+    for text, _, spec, conversion in parts:
+        if spec or conversion:
+            return "placeholders take no format spec or conversion"
+        if not _UID_TEMPLATE_TEXT.fullmatch(text):
+            return f"unsafe text {text!r} (allowed: letters, digits, @._~+=-)"
+    return None
+
+
 def _parse_bool_like(value: str | bool | None) -> bool | None:
     if value is None:
         return None
@@ -156,15 +358,48 @@ def _parse_int_like(value: str | int | None, default: int) -> int:
         return default
 
 
+def _parse_name_list(value: object) -> list[str]:
+    """A TOML array, or a comma-separated string from INI files and env vars."""
+    if isinstance(value, list):
+        names = [str(item).strip() for item in value]
+    elif isinstance(value, str):
+        names = [segment.strip() for segment in value.split(",")]
+    else:
+        return []
+    return [name for name in names if name]
+
+
+def _parse_routes(value: object) -> dict[str, str]:
+    if not isinstance(value, dict):
+        return {}
+    return {str(name): str(env) for name, env in value.items()}
+
+
 def load_config(env: str | None = None, config_home: Path | None = None) -> CaldavConfig:
-    resolved_env = resolve_env(env)
-    values: dict[str, str | bool | int | None] = {
+    resolved_env = resolve_env(env, config_home)
+    values: dict[str, str | bool | int | dict[str, str] | list[str] | None] = {
         "calendar_url": os.environ.get("TDO_CALDAV_URL"),
         "username": os.environ.get("TDO_USERNAME"),
         "password": os.environ.get("TDO_PASSWORD"),
         "token": os.environ.get("TDO_TOKEN"),
         "show_uids": os.environ.get("TDO_SHOW_UIDS"),
+        "delete_children": os.environ.get("TDO_DELETE_CHILDREN"),
+        "complete_mode": os.environ.get("TDO_COMPLETE_MODE"),
+        "open_children": os.environ.get("TDO_OPEN_CHILDREN"),
+        "modify_words": os.environ.get("TDO_MODIFY_WORDS"),
+        "uid_template": os.environ.get("TDO_UID_TEMPLATE"),
+        "single_active": os.environ.get("TDO_SINGLE_ACTIVE"),
         "cache.transaction_log_size": os.environ.get("TDO_TRANSACTION_LOG_SIZE"),
+        "cache.transaction_log_max_age_days": os.environ.get("TDO_TRANSACTION_LOG_MAX_AGE_DAYS"),
+        "cache.idempotency_window_hours": os.environ.get("TDO_IDEMPOTENCY_WINDOW_HOURS"),
+        "cache.lock_timeout_seconds": os.environ.get("TDO_LOCK_TIMEOUT_SECONDS"),
+        "agenda.soon_days": os.environ.get("TDO_AGENDA_SOON_DAYS"),
+        "agenda.week_days": os.environ.get("TDO_AGENDA_WEEK_DAYS"),
+        "next.order": os.environ.get("TDO_NEXT_ORDER"),
+        "validation.max_past_due_days": os.environ.get("TDO_MAX_PAST_DUE_DAYS"),
+        "validation.confirm_batch_size": os.environ.get("TDO_CONFIRM_BATCH_SIZE"),
+        "validation.allowed_tags": os.environ.get("TDO_ALLOWED_TAGS"),
+        "validation.allowed_projects": os.environ.get("TDO_ALLOWED_PROJECTS"),
     }
 
     path = config_file_path(resolved_env, config_home)
@@ -187,19 +422,76 @@ def load_config_from_path(path: Path, env: str | None = None) -> CaldavConfig:
 
 
 def _build_config(
-    values: dict[str, str | bool | int | None], resolved_env: str
+    values: dict[str, str | bool | int | dict[str, str] | list[str] | None], resolved_env: str
 ) -> CaldavConfig:
     url = values.get("calendar_url")
     username = values.get("username")
     password = values.get("password")
     token = values.get("token")
     show_uids = _parse_bool_like(values.get("show_uids"))
+    single_active = _parse_bool_like(values.get("single_active"))
+    delete_children = str(values.get("delete_children") or DEFAULT_DELETE_CHILDREN).strip().lower()
+    if delete_children not in DELETE_CHILDREN_MODES:
+        delete_children = DEFAULT_DELETE_CHILDREN
+    complete_mode = str(values.get("complete_mode") or DEFAULT_COMPLETE_MODE).strip().lower()
+    if complete_mode not in COMPLETE_MODES:
+        complete_mode = DEFAULT_COMPLETE_MODE
+    open_children = str(values.get("open_children") or DEFAULT_OPEN_CHILDREN).strip().lower()
+    if open_children not in OPEN_CHILDREN_MODES:
+        open_children = DEFAULT_OPEN_CHILDREN
+    modify_words = str(values.get("modify_words") or DEFAULT_MODIFY_WORDS).strip().lower()
+    if modify_words not in MODIFY_WORDS_MODES:
+        modify_words = DEFAULT_MODIFY_WORDS
+    uid_template = str(values.get("uid_template") or DEFAULT_UID_TEMPLATE).strip()
+    problem = uid_template_problem(uid_template)
+    if problem:
+        raise RuntimeError(f"invalid uid_template {uid_template!r}: {problem}")
 
     # Build cache config
     transaction_log_size = _parse_int_like(
         values.get("cache.transaction_log_size"), DEFAULT_TRANSACTION_LOG_SIZE
     )
-    cache_config = CacheConfig(transaction_log_size=transaction_log_size)
+    transaction_log_max_age_days = _parse_int_like(
+        values.get("cache.transaction_log_max_age_days"), DEFAULT_TRANSACTION_LOG_MAX_AGE_DAYS
+    )
+    idempotency_window_hours = _parse_int_like(
+        values.get("cache.idempotency_window_hours"), DEFAULT_IDEMPOTENCY_WINDOW_HOURS
+    )
+    lock_timeout_seconds = _parse_int_like(
+        values.get("cache.lock_timeout_seconds"), DEFAULT_LOCK_TIMEOUT_SECONDS
+    )
+    cache_config = CacheConfig(
+        transaction_log_size=transaction_log_size,
+        transaction_log_max_age_days=transaction_log_max_age_days,
+        idempotency_window_hours=idempotency_window_hours,
+        lock_timeout_seconds=lock_timeout_seconds,
+    )
+
+    # Build agenda config
+    agenda_config = AgendaConfig(
+        soon_days=_parse_int_like(values.get("agenda.soon_days"), DEFAULT_AGENDA_SOON_DAYS),
+        week_days=_parse_int_like(values.get("agenda.week_days"), DEFAULT_AGENDA_WEEK_DAYS),
+    )
+
+    # Unknown keys are dropped; an order left empty falls back to the default
+    next_order = [key.lower() for key in _parse_name_list(values.get("next.order")) if key.lower() in NEXT_SORT_KEYS]
+    next_config = NextConfig(order=next_order or list(DEFAULT_NEXT_ORDER))
+
+    routing_config = RoutingConfig(
+        tags=_parse_routes(values.get("routing.tags")),
+        projects=_parse_routes(values.get("routing.projects")),
+    )
+
+    validation_config = ValidationConfig(
+        max_past_due_days=_parse_int_like(
+            values.get("validation.max_past_due_days"), DEFAULT_MAX_PAST_DUE_DAYS
+        ),
+        confirm_batch_size=_parse_int_like(
+            values.get("validation.confirm_batch_size"), DEFAULT_CONFIRM_BATCH_SIZE
+        ),
+        allowed_tags=_parse_name_list(values.get("validation.allowed_tags")),
+        allowed_projects=_parse_name_list(values.get("validation.allowed_projects")),
+    )
 
     if not url or not username:
         raise RuntimeError("caldav configuration requires calendar_url and username")
@@ -211,5 +503,15 @@ def _build_config(
         token=token,
         env=resolved_env,
         show_uids=show_uids if show_uids is not None else False,
+        delete_children=delete_children,
+        complete_mode=complete_mode,
+        open_children=open_children,
+        modify_words=modify_words,
+        uid_template=uid_template,
+        single_active=bool(single_active),
         cache=cache_config,
+        agenda=agenda_config,
+        next=next_config,
+        validation=validation_config,
+        routing=routing_config,
     )
@@ -1,17 +1,18 @@
 from __future__ import annotations
 
 import json
-from dataclasses import dataclass, field
+from dataclasses import dataclass, field, fields
 from datetime import datetime
 from typing import TYPE_CHECKING, Any, Callable, Generic, Mapping, Sequence, TypeVar
 
-from .models import Attachment, Task, TaskData
+from .codec import encode_attachments, encode_categories, encode_x_properties
+from .models import Task, TaskData
 
 if TYPE_CHECKING:
     from .sqlite_cache import SqliteTaskCache
 
 
-__all__ = ["DiffMismatchError", "TaskDiff", "TaskSetDiff"]
+__all__ = ["DiffMismatchError", "FieldChange", "TaskDiff", "TaskSetDiff"]
 
 
 class DiffMismatchError(Exception):
@@ -23,6 +24,58 @@ class DiffMismatchError(Exception):
 K = TypeVar("K")
 
 
+@dataclass(frozen=True)
+class FieldChange:
+    """One field's value before and after a change (None when unset)."""
+
+    before: Any
+    after: Any
+
+    @property
+    def added(self) -> list[Any]:
+        """For list fields (tags, attachments): items only in after."""
+        before = self.before or []
+        return [item for item in self.after or [] if item not in before]
+
+    @property
+    def removed(self) -> list[Any]:
+        """For list fields (tags, attachments): items only in before."""
+        after = self.after or []
+        return [item for item in self.before or [] if item not in after]
+
+    def to_dict(self) -> dict[str, Any]:
+        return {"before": _json_value(self.before), "after": _json_value(self.after)}
+
+
+def _json_value(value: Any) -> Any:
+    if isinstance(value, datetime):
+        return value.isoformat()
+    if isinstance(value, list):
+        return [item.to_dict() if hasattr(item, "to_dict") else item for item in value]
+    return value
+
+
+def _field_values(data: TaskData[datetime] | None) -> dict[str, Any]:
+    """A task's fields as changes() compares them.
+
+    No categories and [] both mean no tags, and each X-property is its own
+    field under its property name (a project change is "X-PROJECT").
+    """
+    if data is None:
+        return {}
+    values: dict[str, Any] = {}
+    for data_field in fields(data):
+        if data_field.name == "x_properties":
+            values.update(data.x_properties)
+        elif data_field.name == "categories":
+            values["categories"] = data.tags or None
+        elif data_field.name == "attachments":
+            values["attachments"] = data.attachments or None
+        else:
+            values[data_field.name] = getattr(data, data_field.name)
+    return values
+
+
 @dataclass(frozen=True)
 class TaskDiff:
     """Represents a change to a single task's data.
@@ -76,6 +129,19 @@ class TaskDiff:
         """True if this diff represents no change."""
         return self.pre == self.post
 
+    def changes(self) -> dict[str, FieldChange]:
+        """The fields that differ between pre and post, in field order.
+
+        A create lists every field post sets, and a delete every field pre had.
+        """
+        before = _field_values(self.pre)
+        after = _field_values(self.post)
+        changed: dict[str, FieldChange] = {}
+        for name in [*before, *(name for name in after if name not in before)]:
+            if before.get(name) != after.get(name):
+                changed[name] = FieldChange(before.get(name), after.get(name))
+        return changed
+
     def to_dict(self) -> dict[str, Any]:
         """Serialize TaskDiff to a JSON-compatible dict."""
         return {
@@ -224,8 +290,8 @@ class TaskSetDiff(Generic[K]):
                 # Determine target table based on status
                 if post.status == "COMPLETED":
                     sql = """
-                        INSERT INTO completed_tasks (uid, summary, status, due, wait, due_utc, wait_utc, priority, x_properties, categories, url, attachments, updated_at, completed_at)
-                        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                        INSERT INTO completed_tasks (uid, summary, status, due, wait, due_utc, wait_utc, priority, x_properties, categories, url, attachments, parent, updated_at, completed_at)
+                        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
                         ON CONFLICT(uid) DO UPDATE SET
                             summary = excluded.summary,
                             status = excluded.status,
@@ -238,6 +304,7 @@ class TaskSetDiff(Generic[K]):
                             categories = excluded.categories,
                             url = excluded.url,
                             attachments = excluded.attachments,
+                            parent = excluded.parent,
                             updated_at = excluded.updated_at,
                             completed_at = excluded.completed_at
                     """
@@ -251,17 +318,18 @@ class TaskSetDiff(Generic[K]):
                         _to_utc_timestamp(post.due),
                         _to_utc_timestamp(post.wait),
                         post.priority,
-                        _serialize_map(post.x_properties),
-                        _serialize_list(post.categories),
+                        encode_x_properties(post.x_properties),
+                        encode_categories(post.categories),
                         post.url,
-                        _serialize_attachments(post.attachments),
+                        encode_attachments(post.attachments),
+                        post.parent,
                         now,
                         now,  # completed_at
                     )
                 else:
                     sql = """
-                        INSERT INTO tasks (uid, summary, status, due, wait, due_utc, wait_utc, priority, x_properties, categories, url, attachments, updated_at)
-                        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                        INSERT INTO tasks (uid, summary, status, due, wait, due_utc, wait_utc, priority, x_properties, categories, url, attachments, parent, updated_at)
+                        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
                         ON CONFLICT(uid) DO UPDATE SET
                             summary = excluded.summary,
                             status = excluded.status,
@@ -274,6 +342,7 @@ class TaskSetDiff(Generic[K]):
                             categories = excluded.categories,
                             url = excluded.url,
                             attachments = excluded.attachments,
+                            parent = excluded.parent,
                             updated_at = excluded.updated_at
                     """
                     params = (
@@ -285,10 +354,11 @@ class TaskSetDiff(Generic[K]):
                         _to_utc_timestamp(post.due),
                         _to_utc_timestamp(post.wait),
                         post.priority,
-                        _serialize_map(post.x_properties),
-                        _serialize_list(post.categories),
+                        encode_x_properties(post.x_properties),
+                        encode_categories(post.categories),
                         post.url,
-                        _serialize_attachments(post.attachments),
+                        encode_attachments(post.attachments),
+                        post.parent,
                         datetime.now().timestamp(),
                     )
                 statements.append((sql.strip(), params))
@@ -309,6 +379,7 @@ class TaskSetDiff(Generic[K]):
                         categories = ?,
                         url = ?,
                         attachments = ?,
+                        parent = ?,
                         updated_at = ?
                     WHERE uid = ?
                 """
@@ -320,10 +391,11 @@ class TaskSetDiff(Generic[K]):
                     _to_utc_timestamp(post.due),
                     _to_utc_timestamp(post.wait),
                     post.priority,
-                    _serialize_map(post.x_properties),
-                    _serialize_list(post.categories),
+                    encode_x_properties(post.x_properties),
+                    encode_categories(post.categories),
                     post.url,
-                    _serialize_attachments(post.attachments),
+                    encode_attachments(post.attachments),
+                    post.parent,
                     datetime.now().timestamp(),
                     uid,
                 )
@@ -452,23 +524,6 @@ class TaskSetDiff(Generic[K]):
         return cls(diffs=diffs)
 
 
-def _serialize_map(value: dict[str, str] | None) -> str:
-    """Serialize a dict to JSON string."""
-    return json.dumps(value or {})
-
-
-def _serialize_list(value: list[str] | None) -> str:
-    """Serialize a list to JSON string."""
-    return json.dumps(list(value or []))
-
-
-def _serialize_attachments(attachments: list[Attachment] | None) -> str:
-    """Serialize attachments to JSON string."""
-    if not attachments:
-        return "[]"
-    return json.dumps([{"uri": a.uri, "fmttype": a.fmttype} for a in attachments])
-
-
 def _to_utc_timestamp(dt: datetime | None) -> float | None:
     """Convert datetime to UTC Unix timestamp."""
     if dt is None:
@@ -1,8 +1,11 @@
 from __future__ import annotations
 
-from dataclasses import dataclass, field
-from datetime import datetime
-from typing import Any, Dict, Generic, Optional, TypeVar
+from dataclasses import dataclass, field, replace
+from datetime import datetime, time, timedelta
+from typing import TYPE_CHECKING, Any, Dict, Generic, Iterable, Mapping, Optional, TypeVar
+
+if TYPE_CHECKING:
+    from .diff import TaskDiff
 
 
 T = TypeVar("T")
@@ -34,6 +37,60 @@ class TaskData(Generic[T]):
     categories: list[str] | None = None
     url: str | None = None
     attachments: list[Attachment] = field(default_factory=list)
+    # UID of the parent task (iCalendar RELATED-TO;RELTYPE=PARENT)
+    parent: str | None = None
+
+    @property
+    def tags(self) -> list[str]:
+        """Categories as a list, empty when unset."""
+        return list(self.categories or [])
+
+    @property
+    def priority_letter(self) -> str | None:
+        """H for priority 1-3, M for 4-6, L for 7-9; None when unset or 0."""
+        if self.priority is None or not 1 <= self.priority <= 9:
+            return None
+        return "HML"[(self.priority - 1) // 3]
+
+    @property
+    def project(self) -> str | None:
+        return self.x_property("X-PROJECT")
+
+    def x_property(self, key: str) -> str | None:
+        """Value of an X-property; an empty string reads as unset."""
+        return self.x_properties.get(key) or None
+
+    def _x_int(self, key: str) -> int | None:
+        raw = self.x_property(key)
+        return int(raw) if raw and raw.isdigit() else None
+
+    def with_x_property(self, key: str, value: str | None) -> TaskData[T]:
+        """Copy with one X-property set, or removed when value is None."""
+        x_properties = dict(self.x_properties)
+        if value is None:
+            x_properties.pop(key, None)
+        else:
+            x_properties[key] = value
+        return replace(self, x_properties=x_properties)
+
+    def with_tags(self, tags: Iterable[str]) -> TaskData[T]:
+        """Copy with categories replaced, dropping duplicates in order."""
+        return replace(self, categories=list(dict.fromkeys(tags)))
+
+    @property
+    def estimate(self) -> int | None:
+        """Estimated effort in minutes, stored as the X-ESTIMATE property."""
+        return self._x_int("X-ESTIMATE")
+
+    @property
+    def custom_status(self) -> str | None:
+        """Workflow state beyond the CalDAV statuses, stored as X-CUSTOM-STATUS."""
+        return self.x_property("X-CUSTOM-STATUS")
+
+    @property
+    def started_at(self) -> int | None:
+        """Epoch seconds when the open time-tracking interval began (X-STARTED-AT)."""
+        return self._x_int("X-STARTED-AT")
 
     def to_dict(self) -> dict[str, Any]:
         """Serialize TaskData to a JSON-compatible dict."""
@@ -55,6 +112,7 @@ class TaskData(Generic[T]):
             "categories": list(self.categories) if self.categories else None,
             "url": self.url,
             "attachments": [a.to_dict() for a in self.attachments],
+            "parent": self.parent,
         }
 
     @classmethod
@@ -73,6 +131,7 @@ class TaskData(Generic[T]):
             categories=data.get("categories"),
             url=data.get("url"),
             attachments=[Attachment.from_dict(a) for a in attachments_raw],
+            parent=data.get("parent"),
         )
 
 
@@ -82,6 +141,59 @@ class Task:
     data: TaskData[datetime]
     href: str | None = None
     task_index: int | None = None
+    # Set when tasks from several environments are merged into one view
+    env: str | None = None
+    # Local note recorded by `tdo do --note`; only set on completed tasks
+    completion_note: str | None = None
+    # Server ETag as of the last sync, for detecting remote changes
+    etag: str | None = None
+
+    def effective_priority(self, tasks_by_uid: Mapping[str, Task]) -> int | None:
+        """Return the task's priority, inheriting from ancestors when unset.
+
+        Walks the parent chain through tasks_by_uid and stops at a missing
+        parent or a cycle. The stored priority is never modified.
+        """
+        seen: set[str] = set()
+        current: Task | None = self
+        while current is not None and current.uid not in seen:
+            if current.data.priority:
+                return current.data.priority
+            seen.add(current.uid)
+            parent_uid = current.data.parent
+            current = tasks_by_uid.get(parent_uid) if parent_uid else None
+        return None
+
+    def time_spent(self, now: datetime | None = None) -> int:
+        """Seconds tracked on this task (X-TIMESPENT).
+
+        When `now` is given, an interval that is still open counts up to it.
+        """
+        spent = self.data._x_int("X-TIMESPENT") or 0
+        started = self.data.started_at
+        if now is not None and started is not None:
+            spent += max(0, int(now.timestamp()) - started)
+        return spent
+
+    def diff(self, other: Task) -> TaskDiff:
+        """The change that turns this task's data into other's.
+
+        The result serializes like a transaction log entry, and
+        TaskDiff.changes() lists the fields that differ.
+        """
+        from .diff import TaskDiff
+
+        return TaskDiff(pre=self.data, post=other.data)
+
+    @property
+    def custom_status(self) -> str | None:
+        """Workflow state kept alongside the CalDAV status (see TaskData.custom_status)."""
+        return self.data.custom_status
+
+    @property
+    def reopened(self) -> int:
+        """Times `tdo reopen` brought this task back after completion (X-REOPENED)."""
+        return self.data._x_int("X-REOPENED") or 0
 
     def to_dict(self) -> dict[str, Any]:
         """Serialize Task to a JSON-compatible dict."""
@@ -90,6 +202,9 @@ class Task:
             "data": self.data.to_dict(),
             "href": self.href,
             "task_index": self.task_index,
+            "env": self.env,
+            "completion_note": self.completion_note,
+            "etag": self.etag,
         }
 
     @classmethod
@@ -100,15 +215,73 @@ class Task:
             data=TaskData.from_dict(data["data"]),
             href=data.get("href"),
             task_index=data.get("task_index"),
+            env=data.get("env"),
+            completion_note=data.get("completion_note"),
+            etag=data.get("etag"),
         )
 
 
 TaskPayload = TaskData[datetime]
 TaskPatch = TaskData[datetime]
 
+# Separates the levels of a nested tag such as area::health::gym
+TAG_SEPARATOR = "::"
+
 
 @dataclass
 class TaskFilter:
     project: str | None = None
     tags: list[str] = field(default_factory=list)
+    # "all": a task needs every tag; "any": one of them is enough
+    tags_mode: str = "all"
+    # "nested": a tag also matches the tags nested under it; "exact": only itself
+    tags_match: str = "nested"
     indices: list[int] = field(default_factory=list)
+    status: str | None = None
+    # Matches X-CUSTOM-STATUS exactly; independent of status
+    custom_status: str | None = None
+    due_before: datetime | None = None
+    # Due no later than this many days from now, overdue included; tasks without a due never match
+    due_within_days: int | None = None
+    # Stretch the due_within_days window to the end of its last local day
+    align_to_day: bool = False
+    # Tasks with any of these tags or projects are left out, even if they match the rest
+    exclude_tags: list[str] = field(default_factory=list)
+    exclude_projects: list[str] = field(default_factory=list)
+    # True keeps only tasks with a URL (or attachments), False only those without
+    has_url: bool | None = None
+    has_attachments: bool | None = None
+    # Age bounds on the creation (entry) and last-update times; tasks without one never match
+    created_before: datetime | None = None
+    created_after: datetime | None = None
+    updated_before: datetime | None = None
+    updated_after: datetime | None = None
+
+    @property
+    def has_metadata(self) -> bool:
+        """Return True if the filter constrains anything besides indices."""
+        return bool(
+            self.project
+            or self.tags
+            or self.status
+            or self.custom_status
+            or self.due_before
+            or self.due_within_days is not None
+            or self.exclude_tags
+            or self.exclude_projects
+            or self.has_url is not None
+            or self.has_attachments is not None
+            or self.created_before
+            or self.created_after
+            or self.updated_before
+            or self.updated_after
+        )
+
+    def due_window_end(self, now: datetime) -> datetime | None:
+        """Latest due date due_within_days admits, counted from now (inclusive)."""
+        if self.due_within_days is None:
+            return None
+        end = now + timedelta(days=self.due_within_days)
+        if self.align_to_day:
+            end = datetime.combine(end.date(), time.max, tzinfo=end.tzinfo)
+        return end
@@ -1,22 +1,100 @@
 from __future__ import annotations
 
 import asyncio
+import fcntl
 import json
 import os
+import sqlite3
 import time
-from dataclasses import dataclass
+from contextlib import asynccontextmanager
+from dataclasses import dataclass, replace
 from datetime import datetime
 from pathlib import Path
-from typing import TYPE_CHECKING, Sequence
+from typing import TYPE_CHECKING, AsyncIterator, Mapping, Sequence
 
 import aiosqlite
 
-from .models import Attachment, Task, TaskData, TaskFilter
+from .codec import (
+    decode_attachments,
+    decode_categories,
+    decode_x_properties,
+    encode_attachments,
+    encode_categories,
+    encode_x_properties,
+)
+from .models import Task, TaskData, TaskFilter
+from .task_query import TASK_TABLES, TaskQuery
 
 if TYPE_CHECKING:
     from .diff import TaskSetDiff
 
 
+# Recorded in PRAGMA user_version; bump when _migrate_schema gains a step.
+# 2: entry columns and the due_utc/wait_utc/task_index indexes
+# 3: idempotency_keys.pending, for keys claimed by a run still in progress
+# 4: idempotency_keys.log_id and .uids, naming the tasks a keyed run touched
+SCHEMA_VERSION = 4
+
+
+class CacheUnavailableError(RuntimeError):
+    """The cache file cannot be created or written at its resolved path."""
+
+    def __init__(self, path: Path, reason: str) -> None:
+        super().__init__(
+            f"cannot use task cache {path}: {reason}; "
+            "set TDO_TASK_CACHE_FILE to a file in a writable directory"
+        )
+        self.path = path
+
+
+class ReadOnlyCacheError(RuntimeError):
+    """A write was attempted through a cache opened with read_only=True."""
+
+    def __init__(self, path: Path, action: str) -> None:
+        super().__init__(f"cannot {action}: task cache {path} was opened read-only")
+        self.path = path
+
+
+class EnvironmentBusyError(RuntimeError):
+    """Another tdo process held the environment lock for the whole timeout."""
+
+    def __init__(self, path: Path, timeout: float) -> None:
+        super().__init__(
+            f"environment busy: another tdo command is changing it (waited {timeout:g}s for {path})"
+        )
+        self.path = path
+
+
+@asynccontextmanager
+async def environment_lock(path: Path, timeout: float) -> AsyncIterator[None]:
+    """Hold an exclusive advisory lock on ``path`` while the block runs.
+
+    Polls until ``timeout`` seconds pass, then raises EnvironmentBusyError.
+    The lock is released on exit, or by the OS if the process dies.
+    """
+    try:
+        path.parent.mkdir(parents=True, exist_ok=True)
+        fd = os.open(path, os.O_RDWR | os.O_CREAT, 0o600)
+    except OSError as exc:
+        raise CacheUnavailableError(path, f"cannot open lock file ({exc.strerror})") from exc
+    try:
+        deadline = time.monotonic() + timeout
+        while True:
+            try:
+                fcntl.flock(fd, fcntl.LOCK_EX | fcntl.LOCK_NB)
+                break
+            except BlockingIOError:
+                if time.monotonic() >= deadline:
+                    raise EnvironmentBusyError(path, timeout) from None
+                await asyncio.sleep(0.05)
+        try:
+            yield
+        finally:
+            fcntl.flock(fd, fcntl.LOCK_UN)
+    finally:
+        os.close(fd)
+
+
 @dataclass
 class DirtyTask:
     task: Task
@@ -25,65 +103,179 @@ class DirtyTask:
 
 
 @dataclass
-class TransactionLogEntry:
-    id: int
-    diff_json: str
-    operation: str | None
-    created_at: float
+class StaleTask:
+    task: Task
+    last_synced: float | None
+    completed: bool
 
 
-def _serialize_properties(value: Sequence[str] | None) -> str:
-    return json.dumps(list(value or []))
+@dataclass
+class BoundaryEvent:
+    """An active task whose due or wait time passed inside a polled interval."""
 
+    task: Task
+    # "overdue" (due passed) or "unwaited" (wait passed)
+    kind: str
+    at: float
 
-def _serialize_map(value: dict[str, str] | None) -> str:
-    return json.dumps(value or {})
 
+@dataclass
+class DeletedMatch:
+    """A deleted task found by search, with when it was deleted."""
 
-def _serialize_attachments(attachments: list[Attachment] | None) -> str:
-    if not attachments:
-        return "[]"
-    return json.dumps([{"uri": a.uri, "fmttype": a.fmttype} for a in attachments])
+    task: Task
+    deleted_at: float
 
 
-def _parse_attachments(raw: str | None) -> list[Attachment]:
-    if not raw:
-        return []
-    try:
-        payload = json.loads(raw)
-    except json.JSONDecodeError:
-        return []
-    if not isinstance(payload, list):
-        return []
-    return [
-        Attachment(uri=item.get("uri", ""), fmttype=item.get("fmttype"))
-        for item in payload
-        if isinstance(item, dict)
-    ]
-
-
-def _parse_json(raw: str | None) -> dict[str, str]:
-    if not raw:
-        return {}
-    try:
-        payload = json.loads(raw)
-    except json.JSONDecodeError:
-        return {}
-    if isinstance(payload, dict):
-        return {str(key): str(value) for key, value in payload.items()}
-    return {}
+@dataclass
+class ArchivedMatch:
+    """An archived task found by search, with when it was archived."""
+
+    task: Task
+    archived_at: float
+
+
+@dataclass
+class TimeSpent:
+    """Tracked seconds per tag and per project, and over all tasks."""
+
+    tags: dict[str, int]
+    projects: dict[str, int]
+    total: int
+
+
+@dataclass
+class ForgetReport:
+    """What forget_tasks erased for one uid; empty tables means it was not found."""
+
+    uid: str
+    summary: str | None
+    # Task tables the uid had rows in
+    tables: list[str]
+    # Transaction log entries the uid was scrubbed from
+    log_entries: int
+    # Saved --idempotency-key outputs dropped because they mention the summary
+    idempotency_keys: int
+
+
+@dataclass
+class TimelineEntry:
+    """A task, the table it is in, and the time it last changed state."""
+
+    task: Task
+    # "active" or "completed"; touched_tasks adds "deleted" and "removed"
+    state: str
+    # updated_at for active tasks, completed_at for completed ones
+    at: float
+
+
+@dataclass
+class IntegrityProblem:
+    """One finding from SqliteTaskCache.check_integrity."""
+
+    check: str
+    table: str
+    uid: str | None
+    detail: str
+
+
+# JSON columns and the JSON type each must hold
+_JSON_COLUMNS = (("categories", "array"), ("x_properties", "object"), ("attachments", "array"))
+
+
+@dataclass
+class CacheInfo:
+    """Where a cache lives and how many rows each task table holds."""
 
+    path: Path
+    schema_version: int
+    size_bytes: int
+    active: int
+    completed: int
+    deleted: int
+    archived: int
 
-def _parse_list(raw: str | None) -> list[str]:
-    if not raw:
-        return []
+
+@dataclass
+class TaskRef:
+    """Just enough of a related task to name it: index, summary, and whether it is done."""
+
+    uid: str
+    index: int | None
+    summary: str | None
+    done: bool
+
+
+@dataclass
+class TransactionLogEntry:
+    id: int
+    diff_json: str
+    operation: str | None
+    created_at: float
+
+
+def _match_rank(summary: str, needle: str) -> int | None:
+    """0 for an exact match, 1 for a prefix, 2 for a substring, else None."""
+    if summary == needle:
+        return 0
+    if summary.startswith(needle):
+        return 1
+    if needle in summary:
+        return 2
+    return None
+
+
+# Fields search can weigh, and the weights used when none are given
+SEARCH_FIELDS = ("summary", "project", "tags")
+DEFAULT_SEARCH_WEIGHTS: Mapping[str, int] = {"summary": 1}
+
+
+def _search_score(row: aiosqlite.Row, needle: str, weights: Mapping[str, int]) -> int | None:
+    """Weighted relevance of a row, or None when no weighted field matches.
+
+    Each field scores 3 for an exact match, 2 for a prefix, and 1 for a
+    substring, times its weight; tags score their best-matching tag.
+    """
+    score = 0
+    matched = False
+    for name, weight in weights.items():
+        if weight <= 0:
+            continue
+        if name == "summary":
+            values = [row["summary"] or ""]
+        elif name == "project":
+            values = [decode_x_properties(row["x_properties"]).get("X-PROJECT") or ""]
+        else:
+            values = decode_categories(row["categories"])
+        ranks = [rank for value in values if (rank := _match_rank(value.casefold(), needle)) is not None]
+        if ranks:
+            matched = True
+            score += weight * (3 - min(ranks))
+    return score if matched else None
+
+
+def _recover_json_column(column: str, raw: str) -> str:
+    """Re-encode a malformed JSON column, keeping whatever can be salvaged.
+
+    Plain-text categories are split on commas; anything else that cannot
+    be read as the expected type becomes empty.
+    """
     try:
         payload = json.loads(raw)
     except json.JSONDecodeError:
-        return []
-    if isinstance(payload, list):
-        return [str(item) for item in payload if item is not None]
-    return []
+        payload = None
+        if column == "categories":
+            payload = [part.strip() for part in raw.split(",") if part.strip()]
+    if column == "categories":
+        if isinstance(payload, str):
+            payload = [payload]
+        return encode_categories([str(item) for item in payload] if isinstance(payload, list) else [])
+    if column == "x_properties":
+        if not isinstance(payload, dict):
+            return encode_x_properties({})
+        return encode_x_properties({str(key): str(value) for key, value in payload.items() if value is not None})
+    items = payload if isinstance(payload, list) else []
+    return json.dumps([item for item in items if isinstance(item, dict) and item.get("uri")])
 
 
 def _to_utc_timestamp(dt: datetime | None) -> float | None:
@@ -93,25 +285,183 @@ def _to_utc_timestamp(dt: datetime | None) -> float | None:
     return dt.timestamp()
 
 
+def _utc_from_text(text: str | None) -> float | None:
+    """UTC timestamp for a stored due/wait string; naive values read as local time."""
+    if not text:
+        return None
+    try:
+        return datetime.fromisoformat(text).timestamp()
+    except ValueError:
+        return None
+
+
+def _refreshed_utc(text: str | None, stored: float | None) -> float | None:
+    """Re-derived UTC timestamp, keeping the stored one when the text no longer parses."""
+    if not text:
+        return None
+    fresh = _utc_from_text(text)
+    return stored if fresh is None else fresh
+
+
+@dataclass
+class TransactionLogRetention:
+    """How much undo and retry history the cache keeps.
+
+    max_age_days of 0 disables age-based trimming of the transaction log.
+    Idempotency keys expire after idempotency_window_hours.
+    """
+
+    max_entries: int = 32
+    max_age_days: int = 0
+    idempotency_window_hours: int = 24
+
+
+@dataclass
+class IdempotentResult:
+    command: str
+    result: str
+    created_at: float
+    # Claimed by a run that has not recorded its result yet
+    pending: bool = False
+
+
 class SqliteTaskCache:
-    def __init__(self, path: Path | None = None, *, env: str = "default"):
+    def __init__(
+        self,
+        path: Path | None = None,
+        *,
+        env: str = "default",
+        retention: TransactionLogRetention | None = None,
+        read_only: bool = False,
+        upgrade: bool = True,
+    ):
         resolved = self._resolve_path(path, env)
-        resolved.parent.mkdir(parents=True, exist_ok=True)
+        try:
+            resolved.parent.mkdir(parents=True, exist_ok=True)
+        except OSError as exc:
+            raise CacheUnavailableError(resolved, f"cannot create {resolved.parent} ({exc.strerror})") from exc
+        if not os.access(resolved.parent, os.W_OK | os.X_OK):
+            raise CacheUnavailableError(resolved, f"{resolved.parent} is not writable")
         self.path = resolved
+        self.retention = retention or TransactionLogRetention()
+        self.read_only = read_only
+        self.upgrade = upgrade
         self._conn: aiosqlite.Connection | None = None
         self._index_lock = asyncio.Lock()
+        # Open transaction() blocks; commits wait until the outermost one ends
+        self._transaction_depth = 0
 
     @classmethod
-    async def create(cls, path: Path | None = None, *, env: str = "default") -> SqliteTaskCache:
-        instance = cls(path, env=env)
+    async def create(
+        cls,
+        path: Path | None = None,
+        *,
+        env: str = "default",
+        retention: TransactionLogRetention | None = None,
+        read_only: bool = False,
+        upgrade: bool = True,
+    ) -> SqliteTaskCache:
+        """Open the cache, creating or migrating its schema as needed.
+
+        With ``read_only`` the database is opened with SQLite's read-only
+        flag, so readers never take a write lock. A cache that does not
+        exist yet or still needs migrating is opened read-write instead,
+        unless ``upgrade`` is False: then a missing cache reads as empty and
+        an older schema raises CacheUnavailableError, so nothing is written.
+        """
+        instance = cls(path, env=env, retention=retention, read_only=read_only, upgrade=upgrade)
         await instance._connect()
         return instance
 
     async def _connect(self) -> None:
-        self._conn = await aiosqlite.connect(str(self.path))
+        # A reader that finds an older schema migrates it before reading
+        migrating_for_read = False
+        if self.read_only:
+            if await self._connect_read_only():
+                return
+            migrating_for_read = self.path.exists()
+            if not self.upgrade:
+                await self._connect_without_upgrade()
+                return
+        self.read_only = False
+        try:
+            self._conn = await aiosqlite.connect(str(self.path))
+            self._conn.row_factory = aiosqlite.Row
+            await self._ensure_schema()
+        except sqlite3.OperationalError as exc:
+            # e.g. a read-only database file in a writable directory
+            await self.close()
+            reason = str(exc)
+            if migrating_for_read:
+                reason = f"its schema must be migrated to version {SCHEMA_VERSION} before it can be read ({exc})"
+            raise CacheUnavailableError(self.path, reason) from exc
+
+    async def _connect_read_only(self) -> bool:
+        """Open an existing, current cache read-only; False if it needs a writer."""
+        if not self.path.exists():
+            return False
+        try:
+            self._conn = await aiosqlite.connect(f"{self.path.resolve().as_uri()}?mode=ro", uri=True)
+            self._conn.row_factory = aiosqlite.Row
+            async with self._conn.execute("PRAGMA user_version") as cursor:
+                row = await cursor.fetchone()
+        except sqlite3.OperationalError as exc:
+            await self.close()
+            raise CacheUnavailableError(self.path, str(exc)) from exc
+        if row[0] == SCHEMA_VERSION:
+            return True
+        await self.close()
+        return False
+
+    async def _connect_without_upgrade(self) -> None:
+        """Read-only fallback that leaves the disk alone when the cache is missing or old."""
+        if self.path.exists():
+            raise CacheUnavailableError(
+                self.path, f"its schema must be migrated to version {SCHEMA_VERSION}; run a command that changes it first"
+            )
+        # Nothing cached yet: read an empty in-memory cache instead of creating the file
+        self._conn = await aiosqlite.connect(":memory:")
         self._conn.row_factory = aiosqlite.Row
         await self._ensure_schema()
 
+    def require_writable(self, action: str) -> None:
+        """Raise ReadOnlyCacheError if this cache was opened read-only."""
+        if self.read_only:
+            raise ReadOnlyCacheError(self.path, action)
+
+    @asynccontextmanager
+    async def transaction(self) -> AsyncIterator[None]:
+        """Make every write inside the block one SQLite transaction.
+
+        Methods called inside the block skip their own commits, so the
+        block commits once at the end or rolls back entirely when it
+        raises. Nested blocks join the outermost one.
+        """
+        assert self._conn is not None
+        self._transaction_depth += 1
+        try:
+            yield
+        except BaseException:
+            self._transaction_depth -= 1
+            if not self._transaction_depth:
+                await self._conn.rollback()
+            raise
+        self._transaction_depth -= 1
+        if not self._transaction_depth:
+            await self._conn.commit()
+
+    async def _commit(self) -> None:
+        """Commit, unless a transaction() block will commit later."""
+        assert self._conn is not None
+        if not self._transaction_depth:
+            await self._conn.commit()
+
+    async def _rollback(self) -> None:
+        """Roll back after a failed write; inside transaction() the block rolls back."""
+        assert self._conn is not None
+        if not self._transaction_depth:
+            await self._conn.rollback()
+
     async def close(self) -> None:
         if self._conn:
             await self._conn.close()
@@ -131,6 +481,12 @@ class SqliteTaskCache:
         safe_env = SqliteTaskCache._normalize_env(env)
         return base / ".cache" / "tdo" / safe_env / "tasks.db"
 
+    @staticmethod
+    def lock_path(path: Path | None = None, env: str = "default") -> Path:
+        """Lock file that serializes mutating commands on this cache."""
+        resolved = SqliteTaskCache._resolve_path(path, env)
+        return resolved.with_name(resolved.name + ".lock")
+
     @staticmethod
     def _normalize_env(env: str | None) -> str:
         candidate = (env or "").strip()
@@ -156,18 +512,18 @@ class SqliteTaskCache:
             categories TEXT,
             url TEXT,
             attachments TEXT,
+            parent TEXT,
             href TEXT,
+            etag TEXT,
             pending_action TEXT,
             last_synced REAL,
             updated_at REAL NOT NULL,
+            entry REAL,
             task_index INTEGER UNIQUE
         );
         CREATE INDEX IF NOT EXISTS idx_tasks_due ON tasks(due);
         CREATE INDEX IF NOT EXISTS idx_tasks_status ON tasks(status);
         CREATE INDEX IF NOT EXISTS idx_tasks_dirty ON tasks(pending_action);
-        CREATE INDEX IF NOT EXISTS idx_tasks_index ON tasks(task_index);
-        CREATE INDEX IF NOT EXISTS idx_tasks_due_utc ON tasks(due_utc);
-        CREATE INDEX IF NOT EXISTS idx_tasks_wait_utc ON tasks(wait_utc);
 
         CREATE TABLE IF NOT EXISTS completed_tasks (
             uid TEXT PRIMARY KEY,
@@ -182,15 +538,45 @@ class SqliteTaskCache:
             categories TEXT,
             url TEXT,
             attachments TEXT,
+            parent TEXT,
             href TEXT,
+            etag TEXT,
             pending_action TEXT,
             last_synced REAL,
             updated_at REAL NOT NULL,
+            entry REAL,
             completed_at REAL NOT NULL,
-            task_index INTEGER
+            task_index INTEGER,
+            completion_note TEXT
         );
         CREATE INDEX IF NOT EXISTS idx_completed_tasks_completed_at ON completed_tasks(completed_at);
 
+        CREATE TABLE IF NOT EXISTS archived_tasks (
+            uid TEXT PRIMARY KEY,
+            summary TEXT NOT NULL,
+            status TEXT NOT NULL,
+            due TEXT,
+            wait TEXT,
+            due_utc REAL,
+            wait_utc REAL,
+            priority INTEGER,
+            x_properties TEXT,
+            categories TEXT,
+            url TEXT,
+            attachments TEXT,
+            parent TEXT,
+            href TEXT,
+            etag TEXT,
+            last_synced REAL,
+            updated_at REAL NOT NULL,
+            entry REAL,
+            completed_at REAL NOT NULL,
+            archived_at REAL NOT NULL,
+            task_index INTEGER,
+            completion_note TEXT
+        );
+        CREATE INDEX IF NOT EXISTS idx_archived_tasks_completed_at ON archived_tasks(completed_at);
+
         CREATE TABLE IF NOT EXISTS deleted_tasks (
             uid TEXT PRIMARY KEY,
             summary TEXT NOT NULL,
@@ -204,8 +590,11 @@ class SqliteTaskCache:
             categories TEXT,
             url TEXT,
             attachments TEXT,
+            parent TEXT,
             href TEXT,
+            etag TEXT,
             last_synced REAL,
+            entry REAL,
             deleted_at REAL NOT NULL,
             task_index INTEGER
         );
@@ -217,11 +606,32 @@ class SqliteTaskCache:
             created_at REAL NOT NULL
         );
         CREATE INDEX IF NOT EXISTS idx_transaction_log_created ON transaction_log(created_at);
+
+        CREATE TABLE IF NOT EXISTS idempotency_keys (
+            key TEXT PRIMARY KEY,
+            command TEXT NOT NULL,
+            result TEXT NOT NULL,
+            created_at REAL NOT NULL,
+            pending INTEGER NOT NULL DEFAULT 0,
+            log_id INTEGER,
+            uids TEXT
+        );
+        CREATE INDEX IF NOT EXISTS idx_idempotency_keys_created ON idempotency_keys(created_at);
         """
         assert self._conn is not None
         await self._conn.executescript(script)
-        await self._conn.commit()
+        await self._commit()
         await self._migrate_schema()
+        # Indexes on columns that older caches gain only during migration
+        await self._conn.executescript(
+            """
+            CREATE INDEX IF NOT EXISTS idx_tasks_index ON tasks(task_index);
+            CREATE INDEX IF NOT EXISTS idx_tasks_due_utc ON tasks(due_utc);
+            CREATE INDEX IF NOT EXISTS idx_tasks_wait_utc ON tasks(wait_utc);
+            """
+        )
+        await self._conn.execute(f"PRAGMA user_version = {SCHEMA_VERSION}")
+        await self._commit()
 
     async def _migrate_schema(self) -> None:
         assert self._conn is not None
@@ -231,11 +641,11 @@ class SqliteTaskCache:
             await self._conn.execute(
                 "ALTER TABLE tasks ADD COLUMN task_index INTEGER UNIQUE"
             )
-            await self._conn.commit()
+            await self._commit()
             await self._assign_indices_to_existing_tasks()
         if "wait" not in columns:
             await self._conn.execute("ALTER TABLE tasks ADD COLUMN wait TEXT")
-            await self._conn.commit()
+            await self._commit()
 
         # Migration: move deleted=1 rows to deleted_tasks, completed to completed_tasks
         if "deleted" in columns:
@@ -249,7 +659,7 @@ class SqliteTaskCache:
             await self._conn.execute("ALTER TABLE completed_tasks ADD COLUMN wait_utc REAL")
             await self._conn.execute("ALTER TABLE deleted_tasks ADD COLUMN due_utc REAL")
             await self._conn.execute("ALTER TABLE deleted_tasks ADD COLUMN wait_utc REAL")
-            await self._conn.commit()
+            await self._commit()
             await self._backfill_utc_columns()
 
         # Migration: add url and attachments columns
@@ -260,7 +670,52 @@ class SqliteTaskCache:
             await self._conn.execute("ALTER TABLE completed_tasks ADD COLUMN attachments TEXT")
             await self._conn.execute("ALTER TABLE deleted_tasks ADD COLUMN url TEXT")
             await self._conn.execute("ALTER TABLE deleted_tasks ADD COLUMN attachments TEXT")
-            await self._conn.commit()
+            await self._commit()
+
+        # Migration: add parent column (checked per table; archived_tasks is newer)
+        for table in ("tasks", "completed_tasks", "deleted_tasks", "archived_tasks"):
+            cursor = await self._conn.execute(f"PRAGMA table_info({table})")
+            table_columns = {row[1] for row in await cursor.fetchall()}
+            if "parent" not in table_columns:
+                await self._conn.execute(f"ALTER TABLE {table} ADD COLUMN parent TEXT")
+        await self._commit()
+
+        # Migration: add completion_note to the tables that hold completed tasks
+        for table in ("completed_tasks", "archived_tasks"):
+            cursor = await self._conn.execute(f"PRAGMA table_info({table})")
+            table_columns = {row[1] for row in await cursor.fetchall()}
+            if "completion_note" not in table_columns:
+                await self._conn.execute(f"ALTER TABLE {table} ADD COLUMN completion_note TEXT")
+        await self._commit()
+
+        # Migration: add etag to every table that mirrors a server object
+        for table in ("tasks", "completed_tasks", "deleted_tasks", "archived_tasks"):
+            cursor = await self._conn.execute(f"PRAGMA table_info({table})")
+            table_columns = {row[1] for row in await cursor.fetchall()}
+            if "etag" not in table_columns:
+                await self._conn.execute(f"ALTER TABLE {table} ADD COLUMN etag TEXT")
+        await self._commit()
+
+        # Migration: add entry (creation time); rows that predate it stay NULL
+        for table in ("tasks", "completed_tasks", "deleted_tasks", "archived_tasks"):
+            cursor = await self._conn.execute(f"PRAGMA table_info({table})")
+            table_columns = {row[1] for row in await cursor.fetchall()}
+            if "entry" not in table_columns:
+                await self._conn.execute(f"ALTER TABLE {table} ADD COLUMN entry REAL")
+        await self._commit()
+
+        # Migration: mark idempotency keys claimed by a run that has not finished
+        cursor = await self._conn.execute("PRAGMA table_info(idempotency_keys)")
+        if "pending" not in {row[1] for row in await cursor.fetchall()}:
+            await self._conn.execute("ALTER TABLE idempotency_keys ADD COLUMN pending INTEGER NOT NULL DEFAULT 0")
+            await self._commit()
+
+        # Migration: remember which tasks a keyed run touched; older keys stay NULL
+        cursor = await self._conn.execute("PRAGMA table_info(idempotency_keys)")
+        if "uids" not in {row[1] for row in await cursor.fetchall()}:
+            await self._conn.execute("ALTER TABLE idempotency_keys ADD COLUMN log_id INTEGER")
+            await self._conn.execute("ALTER TABLE idempotency_keys ADD COLUMN uids TEXT")
+            await self._commit()
 
     async def _migrate_to_three_tables(self) -> None:
         """Migrate from single tasks table with deleted flag to three tables."""
@@ -346,7 +801,7 @@ class SqliteTaskCache:
         await self._conn.execute("CREATE INDEX IF NOT EXISTS idx_tasks_dirty ON tasks(pending_action)")
         await self._conn.execute("CREATE INDEX IF NOT EXISTS idx_tasks_index ON tasks(task_index)")
 
-        await self._conn.commit()
+        await self._commit()
 
     async def _assign_indices_to_existing_tasks(self) -> None:
         assert self._conn is not None
@@ -359,7 +814,7 @@ class SqliteTaskCache:
                 "UPDATE tasks SET task_index = ? WHERE uid = ?",
                 (idx, row[0])
             )
-        await self._conn.commit()
+        await self._commit()
 
     async def _backfill_utc_columns(self) -> None:
         """Backfill due_utc and wait_utc from existing TEXT columns."""
@@ -373,30 +828,15 @@ class SqliteTaskCache:
 
             for row in rows:
                 uid, due_text, wait_text = row
-                due_utc = None
-                wait_utc = None
-
-                if due_text:
-                    try:
-                        dt = datetime.fromisoformat(due_text)
-                        due_utc = dt.timestamp()
-                    except ValueError:
-                        pass
-
-                if wait_text:
-                    try:
-                        dt = datetime.fromisoformat(wait_text)
-                        wait_utc = dt.timestamp()
-                    except ValueError:
-                        pass
-
+                due_utc = _utc_from_text(due_text)
+                wait_utc = _utc_from_text(wait_text)
                 if due_utc is not None or wait_utc is not None:
                     await self._conn.execute(
                         f"UPDATE {table} SET due_utc = ?, wait_utc = ? WHERE uid = ?",
                         (due_utc, wait_utc, uid)
                     )
 
-        await self._conn.commit()
+        await self._commit()
 
     async def _next_available_index(self) -> int:
         """Find smallest hole or increment max."""
@@ -420,22 +860,68 @@ class SqliteTaskCache:
         # No holes, return max + 1
         return indices[-1] + 1
 
-    async def assign_index(self, uid: str) -> int:
-        """Assign next available index to a task.
+    async def index_available(self, index: int) -> bool:
+        """Whether no active task holds index."""
+        assert self._conn is not None
+        async with self._conn.execute(
+            "SELECT 1 FROM tasks WHERE task_index = ?", (index,)
+        ) as cursor:
+            return await cursor.fetchone() is None
+
+    async def assign_index(self, uid: str, index: int | None = None) -> int:
+        """Assign index, or the next available one, to a task.
 
         Uses a lock to prevent race conditions when multiple
-        tasks are assigned indices concurrently.
+        tasks are assigned indices concurrently. A requested index
+        that another task holds raises ValueError.
         """
         async with self._index_lock:
-            index = await self._next_available_index()
+            if index is None:
+                index = await self._next_available_index()
+            elif not await self.index_available(index):
+                raise ValueError(f"index {index} is already taken")
             assert self._conn is not None
             await self._conn.execute(
                 "UPDATE tasks SET task_index = ? WHERE uid = ?",
                 (index, uid)
             )
-            await self._conn.commit()
+            await self._commit()
             return index
 
+    async def swap_indices(self, first_uid: str, second_uid: str) -> tuple[int, int]:
+        """Exchange two active tasks' indices in one transaction.
+
+        The first task is parked at NULL while the second takes its index,
+        so the UNIQUE constraint never sees a duplicate. Returns the new
+        (first, second) indices; a task without an index raises KeyError.
+        """
+        assert self._conn is not None
+        async with self._index_lock:
+            indices: dict[str, int] = {}
+            for uid in (first_uid, second_uid):
+                async with self._conn.execute(
+                    "SELECT task_index FROM tasks WHERE uid = ?", (uid,)
+                ) as cursor:
+                    row = await cursor.fetchone()
+                if row is None or row[0] is None:
+                    raise KeyError(uid)
+                indices[uid] = row[0]
+            try:
+                await self._conn.execute(
+                    "UPDATE tasks SET task_index = NULL WHERE uid = ?", (first_uid,)
+                )
+                await self._conn.execute(
+                    "UPDATE tasks SET task_index = ? WHERE uid = ?", (indices[first_uid], second_uid)
+                )
+                await self._conn.execute(
+                    "UPDATE tasks SET task_index = ? WHERE uid = ?", (indices[second_uid], first_uid)
+                )
+                await self._commit()
+            except Exception:
+                await self._rollback()
+                raise
+            return indices[second_uid], indices[first_uid]
+
     async def get_task_by_index(self, index: int) -> Task | None:
         """Get active task by its stable index."""
         assert self._conn is not None
@@ -447,39 +933,11 @@ class SqliteTaskCache:
         return self._build_task(row) if row else None
 
     async def list_tasks(self) -> list[Task]:
-        assert self._conn is not None
-        async with self._conn.execute(
-            "SELECT * FROM tasks ORDER BY due IS NULL, due"
-        ) as cursor:
-            rows = await cursor.fetchall()
-        return [self._build_task(row) for row in rows]
+        return await self._fetch_tasks(TaskQuery().order_by("due", nulls_last=True))
 
     async def list_tasks_filtered(self, task_filter: TaskFilter | None = None) -> list[Task]:
-        assert self._conn is not None
-        conditions: list[str] = []
-        params: list[str] = []
-
-        if task_filter:
-            if task_filter.project:
-                conditions.append("json_extract(x_properties, '$.X-PROJECT') = ?")
-                params.append(task_filter.project)
-            for tag in task_filter.tags:
-                conditions.append("categories LIKE ?")
-                params.append(f'%"{tag}"%')
-            if task_filter.indices:
-                placeholders = ",".join("?" for _ in task_filter.indices)
-                conditions.append(f"task_index IN ({placeholders})")
-                params.extend(str(i) for i in task_filter.indices)
-
-        if conditions:
-            where = " WHERE " + " AND ".join(conditions)
-        else:
-            where = ""
-        query = f"SELECT * FROM tasks{where} ORDER BY due IS NULL, due"
-
-        async with self._conn.execute(query, params) as cursor:
-            rows = await cursor.fetchall()
-        return [self._build_task(row) for row in rows]
+        query = TaskQuery().apply_filter(task_filter).order_by("due", nulls_last=True)
+        return await self._fetch_tasks(query)
 
     async def list_active_tasks(
         self,
@@ -491,35 +949,12 @@ class SqliteTaskCache:
 
         Uses UTC columns for date comparisons.
         """
-        assert self._conn is not None
-        conditions: list[str] = []
-        params: list[float | str] = []
-
+        query = TaskQuery()
         # Exclude waiting tasks by comparing wait_utc to current time
         if exclude_waiting:
-            now_utc = time.time()
-            conditions.append("(wait_utc IS NULL OR wait_utc <= ?)")
-            params.append(now_utc)
-
-        # Apply metadata filters
-        if task_filter:
-            if task_filter.project:
-                conditions.append("json_extract(x_properties, '$.X-PROJECT') = ?")
-                params.append(task_filter.project)
-            for tag in task_filter.tags:
-                conditions.append("categories LIKE ?")
-                params.append(f'%"{tag}"%')
-            if task_filter.indices:
-                placeholders = ",".join("?" for _ in task_filter.indices)
-                conditions.append(f"task_index IN ({placeholders})")
-                params.extend(str(i) for i in task_filter.indices)
-
-        where_clause = " WHERE " + " AND ".join(conditions) if conditions else ""
-        query = f"SELECT * FROM tasks{where_clause} ORDER BY due_utc IS NULL, due_utc"
-
-        async with self._conn.execute(query, params) as cursor:
-            rows = await cursor.fetchall()
-        return [self._build_task(row) for row in rows]
+            query.where("(wait_utc IS NULL OR wait_utc <= ?)", time.time())
+        query.apply_filter(task_filter).order_by("due_utc", nulls_last=True)
+        return await self._fetch_tasks(query)
 
     async def list_unprioritized_tasks(
         self,
@@ -530,32 +965,14 @@ class SqliteTaskCache:
 
         Excludes waiting tasks (same as list_active_tasks).
         """
-        assert self._conn is not None
-        now_utc = time.time()
-        conditions: list[str] = [
-            "priority IS NULL",
-            "(wait_utc IS NULL OR wait_utc <= ?)",
-        ]
-        params: list[float | str] = [now_utc]
-
-        if task_filter:
-            if task_filter.project:
-                conditions.append("json_extract(x_properties, '$.X-PROJECT') = ?")
-                params.append(task_filter.project)
-            for tag in task_filter.tags:
-                conditions.append("categories LIKE ?")
-                params.append(f'%"{tag}"%')
-            if task_filter.indices:
-                placeholders = ",".join("?" for _ in task_filter.indices)
-                conditions.append(f"task_index IN ({placeholders})")
-                params.extend(str(i) for i in task_filter.indices)
-
-        where_clause = " WHERE " + " AND ".join(conditions)
-        query = f"SELECT * FROM tasks{where_clause} ORDER BY due_utc IS NULL, due_utc"
-
-        async with self._conn.execute(query, params) as cursor:
-            rows = await cursor.fetchall()
-        return [self._build_task(row) for row in rows]
+        query = (
+            TaskQuery()
+            .where("priority IS NULL")
+            .where("(wait_utc IS NULL OR wait_utc <= ?)", time.time())
+            .apply_filter(task_filter)
+            .order_by("due_utc", nulls_last=True)
+        )
+        return await self._fetch_tasks(query)
 
     async def list_waiting_tasks(
         self,
@@ -563,30 +980,413 @@ class SqliteTaskCache:
         task_filter: TaskFilter | None = None,
     ) -> list[Task]:
         """List tasks with future wait dates."""
+        query = (
+            TaskQuery()
+            .where("wait_utc IS NOT NULL")
+            .where("wait_utc > ?", time.time())
+            .apply_filter(task_filter)
+            .order_by("wait_utc")
+        )
+        return await self._fetch_tasks(query)
+
+    async def list_boundary_events(
+        self, *, since: float, until: float, task_filter: TaskFilter | None = None
+    ) -> list[BoundaryEvent]:
+        """Active tasks whose due or wait time falls in (since, until], oldest first.
+
+        The interval is open at since, so polls that pass the previous
+        until as the next since report each crossing once. A task whose due
+        and wait both passed yields two events.
+        """
+        events: list[BoundaryEvent] = []
+        for kind, column in (("overdue", "due_utc"), ("unwaited", "wait_utc")):
+            query = (
+                TaskQuery()
+                .where(f"{column} > ? AND {column} <= ?", since, until)
+                .apply_filter(task_filter)
+                .order_by(column)
+            )
+            sql, params = query.build()
+            assert self._conn is not None
+            async with self._conn.execute(sql, params) as cursor:
+                rows = await cursor.fetchall()
+            events.extend(BoundaryEvent(self._build_task(row), kind, row[column]) for row in rows)
+        events.sort(key=lambda event: event.at)
+        return events
+
+    async def activate_waited_tasks(self) -> list[Task]:
+        """Clear the wait date on tasks whose wait has passed.
+
+        Activated tasks are marked for push ("create" stays "create"). The
+        select and update share one transaction and one cutoff, so the
+        returned tasks, as they were before activation, are exactly the
+        ones updated.
+        """
+        assert self._conn is not None
+        now = time.time()
+        query = TaskQuery().where("wait_utc IS NOT NULL").where("wait_utc <= ?", now).order_by("wait_utc")
+        sql, params = query.build()
+        try:
+            async with self._conn.execute(sql, params) as cursor:
+                rows = await cursor.fetchall()
+            await self._conn.execute(
+                """
+                UPDATE tasks
+                SET wait = NULL,
+                    wait_utc = NULL,
+                    updated_at = ?,
+                    pending_action = CASE WHEN pending_action = 'create' THEN 'create' ELSE 'update' END
+                WHERE wait_utc IS NOT NULL AND wait_utc <= ?
+                """,
+                (now, now),
+            )
+            await self._commit()
+        except Exception:
+            await self._rollback()
+            raise
+        return [self._build_task(row) for row in rows]
+
+    async def search_tasks(
+        self,
+        text: str,
+        *,
+        task_filter: TaskFilter | None = None,
+        limit: int | None = None,
+        weights: Mapping[str, int] | None = None,
+    ) -> list[Task]:
+        """Active tasks whose summary contains text, best match first.
+
+        An exact summary match ranks above a prefix match, which ranks above
+        any other substring match; matching is case-insensitive. Ties go to
+        the higher priority, then the most recently updated task.
+
+        weights maps SEARCH_FIELDS to how much a match in each counts; the
+        default searches the summary only. The highest weighted score wins.
+        """
+        assert self._conn is not None
+        needle = text.strip().casefold()
+        weights = DEFAULT_SEARCH_WEIGHTS if weights is None else weights
+        sql, params = TaskQuery().apply_filter(task_filter).build()
+        async with self._conn.execute(sql, params) as cursor:
+            rows = await cursor.fetchall()
+        scored: list[tuple[tuple[int, int, float], Task]] = []
+        for row in rows:
+            score = _search_score(row, needle, weights)
+            if score is None:
+                continue
+            priority = row["priority"] or 10
+            scored.append(((-score, priority, -(row["updated_at"] or 0.0)), self._build_task(row)))
+        scored.sort(key=lambda entry: entry[0])
+        matches = [task for _, task in scored]
+        return matches[:limit] if limit is not None else matches
+
+    async def search_deleted_tasks(
+        self,
+        text: str,
+        *,
+        task_filter: TaskFilter | None = None,
+        limit: int | None = None,
+        weights: Mapping[str, int] | None = None,
+    ) -> list[DeletedMatch]:
+        """Deleted tasks whose summary contains text, best match first.
+
+        Ranked like search_tasks, except that ties go to the most recently
+        deleted task. Only deletions not yet pushed are still here.
+        """
         assert self._conn is not None
-        now_utc = time.time()
-        conditions: list[str] = ["wait_utc IS NOT NULL", "wait_utc > ?"]
-        params: list[float | str] = [now_utc]
-
-        if task_filter:
-            if task_filter.project:
-                conditions.append("json_extract(x_properties, '$.X-PROJECT') = ?")
-                params.append(task_filter.project)
-            for tag in task_filter.tags:
-                conditions.append("categories LIKE ?")
-                params.append(f'%"{tag}"%')
-            if task_filter.indices:
-                placeholders = ",".join("?" for _ in task_filter.indices)
-                conditions.append(f"task_index IN ({placeholders})")
-                params.extend(str(i) for i in task_filter.indices)
-
-        where_clause = " WHERE " + " AND ".join(conditions)
-        query = f"SELECT * FROM tasks{where_clause} ORDER BY wait_utc"
-
-        async with self._conn.execute(query, params) as cursor:
+        needle = text.strip().casefold()
+        weights = DEFAULT_SEARCH_WEIGHTS if weights is None else weights
+        sql, params = TaskQuery("deleted_tasks").apply_filter(task_filter).build()
+        async with self._conn.execute(sql, params) as cursor:
+            rows = await cursor.fetchall()
+        scored: list[tuple[tuple[int, int, float], DeletedMatch]] = []
+        for row in rows:
+            score = _search_score(row, needle, weights)
+            if score is None:
+                continue
+            priority = row["priority"] or 10
+            match = DeletedMatch(task=self._build_deleted_task(row), deleted_at=row["deleted_at"])
+            scored.append(((-score, priority, -row["deleted_at"]), match))
+        scored.sort(key=lambda entry: entry[0])
+        matches = [match for _, match in scored]
+        return matches[:limit] if limit is not None else matches
+
+    async def search_archived_tasks(
+        self,
+        text: str,
+        *,
+        task_filter: TaskFilter | None = None,
+        limit: int | None = None,
+        weights: Mapping[str, int] | None = None,
+    ) -> list[ArchivedMatch]:
+        """Archived tasks whose summary contains text, best match first.
+
+        Ranked like search_tasks, except that ties go to the most recently
+        archived task.
+        """
+        assert self._conn is not None
+        needle = text.strip().casefold()
+        weights = DEFAULT_SEARCH_WEIGHTS if weights is None else weights
+        sql, params = TaskQuery("archived_tasks").apply_filter(task_filter).build()
+        async with self._conn.execute(sql, params) as cursor:
+            rows = await cursor.fetchall()
+        scored: list[tuple[tuple[int, int, float], ArchivedMatch]] = []
+        for row in rows:
+            score = _search_score(row, needle, weights)
+            if score is None:
+                continue
+            priority = row["priority"] or 10
+            match = ArchivedMatch(task=self._build_completed_task(row), archived_at=row["archived_at"])
+            scored.append(((-score, priority, -row["archived_at"]), match))
+        scored.sort(key=lambda entry: entry[0])
+        matches = [match for _, match in scored]
+        return matches[:limit] if limit is not None else matches
+
+    async def _fetch_tasks(self, query: TaskQuery) -> list[Task]:
+        assert self._conn is not None
+        sql, params = query.build()
+        async with self._conn.execute(sql, params) as cursor:
             rows = await cursor.fetchall()
         return [self._build_task(row) for row in rows]
 
+    async def list_timeline(self, *, task_filter: TaskFilter | None = None) -> list[TimelineEntry]:
+        """Active (non-waiting) and completed tasks interleaved, oldest first.
+
+        Active tasks are placed by updated_at and completed ones by
+        completed_at, so a task shows up where it last moved.
+        """
+        assert self._conn is not None
+        entries: list[TimelineEntry] = []
+        active = (
+            TaskQuery()
+            .where("(wait_utc IS NULL OR wait_utc <= ?)", time.time())
+            .apply_filter(task_filter)
+        )
+        sql, params = active.build()
+        async with self._conn.execute(sql, params) as cursor:
+            for row in await cursor.fetchall():
+                entries.append(TimelineEntry(self._build_task(row), "active", row["updated_at"] or 0.0))
+        sql, params = TaskQuery("completed_tasks").apply_filter(task_filter).build()
+        async with self._conn.execute(sql, params) as cursor:
+            for row in await cursor.fetchall():
+                entries.append(TimelineEntry(self._build_completed_task(row), "completed", row["completed_at"]))
+        entries.sort(key=lambda entry: entry.at)
+        return entries
+
+    async def iter_tasks_for_export(
+        self, *, task_filter: TaskFilter | None = None
+    ) -> AsyncIterator[tuple[str, Task]]:
+        """Active then completed tasks as ("active" | "completed", task) pairs.
+
+        Rows are yielded as the cursor reads them, so an export never holds
+        the whole cache in memory.
+        """
+        assert self._conn is not None
+        tables = (("tasks", "active", self._build_task), ("completed_tasks", "completed", self._build_completed_task))
+        for table, state, build in tables:
+            sql, params = TaskQuery(table).apply_filter(task_filter).build()
+            async with self._conn.execute(sql, params) as cursor:
+                async for row in cursor:
+                    yield state, build(row)
+
+    async def count_tags_and_projects(
+        self,
+        *,
+        include_completed: bool = False,
+    ) -> dict[str, dict[str, int]]:
+        """Tally tasks per tag and per project.
+
+        Tags live in a JSON array, so rows are scanned once and counted in
+        Python instead of issuing one query per tag. Completed tasks are
+        excluded unless include_completed is set.
+
+        Returns:
+            {"tags": {tag: count}, "projects": {project: count}}
+        """
+        assert self._conn is not None
+        tables = ["tasks", "completed_tasks"] if include_completed else ["tasks"]
+        tags: dict[str, int] = {}
+        projects: dict[str, int] = {}
+        for table in tables:
+            async with self._conn.execute(
+                f"SELECT x_properties, categories FROM {table}"
+            ) as cursor:
+                rows = await cursor.fetchall()
+            for row in rows:
+                project = decode_x_properties(row["x_properties"]).get("X-PROJECT")
+                if project:
+                    projects[project] = projects.get(project, 0) + 1
+                for tag in set(decode_categories(row["categories"])):
+                    tags[tag] = tags.get(tag, 0) + 1
+        return {"tags": tags, "projects": projects}
+
+    async def sum_time_spent(
+        self,
+        *,
+        include_completed: bool = False,
+        now: datetime | None = None,
+    ) -> TimeSpent:
+        """Tracked seconds per tag and per project, like count_tags_and_projects.
+
+        An interval that is still running counts up to now. The total covers
+        every task, including those without a tag or project; tasks with no
+        tracked time are left out of the tallies.
+        """
+        assert self._conn is not None
+        now = now or datetime.now()
+        tables = (
+            [("tasks", self._build_task), ("completed_tasks", self._build_completed_task)]
+            if include_completed
+            else [("tasks", self._build_task)]
+        )
+        tags: dict[str, int] = {}
+        projects: dict[str, int] = {}
+        total = 0
+        for table, build in tables:
+            async with self._conn.execute(f"SELECT * FROM {table}") as cursor:
+                rows = await cursor.fetchall()
+            for row in rows:
+                task = build(row)
+                spent = task.time_spent(now)
+                if not spent:
+                    continue
+                total += spent
+                if task.data.project:
+                    projects[task.data.project] = projects.get(task.data.project, 0) + spent
+                for tag in set(task.data.categories or []):
+                    tags[tag] = tags.get(tag, 0) + spent
+        return TimeSpent(tags=tags, projects=projects, total=total)
+
+    async def count_tasks_by(
+        self,
+        key: str,
+        *,
+        task_filter: TaskFilter | None = None,
+        include_completed: bool = False,
+    ) -> dict[str, int]:
+        """Tally tasks per project, tag, or status, scoped like list.
+
+        Waiting tasks are left out and completed ones count only with
+        include_completed. Projects and statuses are grouped in SQL; tags
+        are tallied here, once per tag a task carries. Tasks without a
+        project or tag count under "".
+        """
+        assert self._conn is not None
+        queries = [TaskQuery().where("(wait_utc IS NULL OR wait_utc <= ?)", time.time())]
+        if include_completed:
+            queries.append(TaskQuery("completed_tasks"))
+        counts: dict[str, int] = {}
+        for query in queries:
+            query.apply_filter(task_filter)
+            if key == "tag":
+                sql, params = query.build()
+                async with self._conn.execute(sql, params) as cursor:
+                    rows = await cursor.fetchall()
+                for row in rows:
+                    for tag in set(decode_categories(row["categories"])) or {""}:
+                        counts[tag] = counts.get(tag, 0) + 1
+                continue
+            sql, params = query.build_counts(key)
+            async with self._conn.execute(sql, params) as cursor:
+                for name, count in await cursor.fetchall():
+                    counts[name] = counts.get(name, 0) + count
+        return counts
+
+    async def tag_and_project_recency(self) -> dict[str, dict[str, float]]:
+        """Latest updated_at among active tasks carrying each tag and project.
+
+        Returns:
+            {"tags": {tag: updated_at}, "projects": {project: updated_at}}
+        """
+        assert self._conn is not None
+        async with self._conn.execute(
+            "SELECT x_properties, categories, updated_at FROM tasks"
+        ) as cursor:
+            rows = await cursor.fetchall()
+        tags: dict[str, float] = {}
+        projects: dict[str, float] = {}
+        for row in rows:
+            updated_at = row["updated_at"] or 0.0
+            project = decode_x_properties(row["x_properties"]).get("X-PROJECT")
+            if project:
+                projects[project] = max(projects.get(project, 0.0), updated_at)
+            for tag in decode_categories(row["categories"]):
+                tags[tag] = max(tags.get(tag, 0.0), updated_at)
+        return {"tags": tags, "projects": projects}
+
+    async def count_completed_by_day(self, since: float) -> dict[str, int]:
+        """Count completed tasks per local day, from the since timestamp on.
+
+        Archived tasks still count on the day they were completed.
+
+        Returns:
+            {"YYYY-MM-DD": count}, only for days with at least one completion
+        """
+        assert self._conn is not None
+        async with self._conn.execute(
+            """
+            SELECT date(completed_at, 'unixepoch', 'localtime') AS day, COUNT(*) AS n
+            FROM (
+                SELECT completed_at FROM completed_tasks
+                UNION ALL
+                SELECT completed_at FROM archived_tasks
+            )
+            WHERE completed_at >= ?
+            GROUP BY day
+            """,
+            (since,),
+        ) as cursor:
+            rows = await cursor.fetchall()
+        return {row["day"]: row["n"] for row in rows}
+
+    async def resolve_refs(self, uids: Sequence[str]) -> dict[str, TaskRef]:
+        """Look up active and completed tasks by uid in one query.
+
+        Uids found in neither table are left out of the result.
+        """
+        assert self._conn is not None
+        wanted = list(dict.fromkeys(uids))
+        if not wanted:
+            return {}
+        placeholders = ", ".join("?" for _ in wanted)
+        async with self._conn.execute(
+            f"""
+            SELECT uid, task_index, summary, 0 AS done FROM tasks WHERE uid IN ({placeholders})
+            UNION ALL
+            SELECT uid, task_index, summary, 1 AS done FROM completed_tasks WHERE uid IN ({placeholders})
+            """,
+            (*wanted, *wanted),
+        ) as cursor:
+            rows = await cursor.fetchall()
+        return {
+            row["uid"]: TaskRef(
+                uid=row["uid"], index=row["task_index"], summary=row["summary"], done=bool(row["done"])
+            )
+            for row in rows
+        }
+
+    async def info(self) -> CacheInfo:
+        """Read-only summary of the cache file and its table sizes."""
+        assert self._conn is not None
+        async with self._conn.execute("PRAGMA user_version") as cursor:
+            row = await cursor.fetchone()
+        schema_version = row[0] if row else 0
+        counts: dict[str, int] = {}
+        for table in ("tasks", "completed_tasks", "deleted_tasks", "archived_tasks"):
+            async with self._conn.execute(f"SELECT COUNT(*) FROM {table}") as cursor:
+                row = await cursor.fetchone()
+            counts[table] = row[0] if row else 0
+        path = self.path.resolve()
+        return CacheInfo(
+            path=path,
+            schema_version=schema_version,
+            size_bytes=path.stat().st_size if path.exists() else 0,
+            active=counts["tasks"],
+            completed=counts["completed_tasks"],
+            deleted=counts["deleted_tasks"],
+            archived=counts["archived_tasks"],
+        )
+
     async def dirty_tasks(self) -> list[DirtyTask]:
         """Return all tasks with pending changes to sync.
 
@@ -642,6 +1442,11 @@ class SqliteTaskCache:
         Routes tasks to appropriate tables based on status:
         - COMPLETED -> completed_tasks
         - Other statuses -> tasks (active)
+
+        A task with a pending local update keeps the local copy when its
+        etag matches the stored one, since the server has not changed it
+        since the last sync. A different or missing etag means the server
+        copy wins, as before.
         """
         timestamp = time.time()
         assert self._conn is not None
@@ -658,25 +1463,69 @@ class SqliteTaskCache:
         )
         existing_indices.update({row[0]: row[1] for row in await cursor.fetchall()})
 
+        # Completion notes are local-only, so carry them across the refresh
+        cursor = await self._conn.execute(
+            "SELECT uid, completion_note FROM completed_tasks WHERE completion_note IS NOT NULL"
+        )
+        existing_notes = {row[0]: row[1] for row in await cursor.fetchall()}
+
+        # Completion times are local too; a pull must not reset them to now
+        cursor = await self._conn.execute("SELECT uid, completed_at FROM completed_tasks")
+        existing_completed_at = {row[0]: row[1] for row in await cursor.fetchall()}
+
+        # Archived tasks stay archived while the server still reports them completed
+        cursor = await self._conn.execute("SELECT uid FROM archived_tasks")
+        archived_uids = {row[0] for row in await cursor.fetchall()}
+
+        # Etags of rows with a local edit waiting to be pushed
+        cursor = await self._conn.execute(
+            """
+            SELECT uid, etag FROM tasks WHERE pending_action = 'update' AND etag IS NOT NULL
+            UNION ALL
+            SELECT uid, etag FROM completed_tasks WHERE pending_action = 'update' AND etag IS NOT NULL
+            """
+        )
+        pending_etags = {row[0]: row[1] for row in await cursor.fetchall()}
+
+        # Creation times are local-only; updated_at only moves when the server copy changed
+        cursor = await self._conn.execute(
+            """
+            SELECT uid, entry, etag, updated_at FROM tasks WHERE pending_action IS NULL
+            UNION ALL
+            SELECT uid, entry, etag, updated_at FROM completed_tasks WHERE pending_action IS NULL
+            """
+        )
+        previous_stamps = [tuple(row) for row in await cursor.fetchall()]
+
         # Delete non-pending tasks from both tables
         await self._conn.execute("DELETE FROM tasks WHERE pending_action IS NULL")
         await self._conn.execute("DELETE FROM completed_tasks WHERE pending_action IS NULL")
-        await self._conn.commit()
+        await self._commit()
 
         # Track which active tasks need new indices
         tasks_needing_indices: list[str] = []
 
         for task in tasks:
+            if task.etag is not None and pending_etags.get(task.uid) == task.etag:
+                # Unchanged on the server since the last sync; the local edit wins
+                continue
             preserved_index = existing_indices.get(task.uid)
 
+            if task.data.status == "COMPLETED" and task.uid in archived_uids:
+                continue
+            if task.uid in archived_uids:
+                # Reopened on the server: drop the archived copy
+                await self._conn.execute("DELETE FROM archived_tasks WHERE uid = ?", (task.uid,))
+
             if task.data.status == "COMPLETED":
                 # Insert into completed_tasks
                 await self._insert_completed_task(
                     task,
                     pending_action=None,
                     last_synced=timestamp,
-                    completed_at=timestamp,
+                    completed_at=existing_completed_at.get(task.uid, timestamp),
                     task_index=preserved_index,
+                    completion_note=existing_notes.get(task.uid),
                 )
             else:
                 # Insert into active tasks
@@ -690,6 +1539,18 @@ class SqliteTaskCache:
                 if preserved_index is None:
                     tasks_needing_indices.append(task.uid)
 
+        for table in ("tasks", "completed_tasks"):
+            await self._conn.executemany(
+                f"""
+                UPDATE {table}
+                SET entry = ?,
+                    updated_at = CASE WHEN etag IS NOT NULL AND etag = ? THEN ? ELSE updated_at END
+                WHERE uid = ?
+                """,
+                [(entry, etag, updated_at, uid) for uid, entry, etag, updated_at in previous_stamps],
+            )
+        await self._commit()
+
         # Assign indices to new active tasks
         for uid in tasks_needing_indices:
             await self.assign_index(uid)
@@ -711,10 +1572,20 @@ class SqliteTaskCache:
             task_index=task_index,
         )
 
-    async def delete_task(self, uid: str) -> None:
+    async def delete_task(self, uid: str) -> bool:
+        """Hard-remove an active task that has no local change to push.
+
+        Only synced rows and never-pushed creates are removed; a row with a
+        pending update is kept so the change is not silently lost. Returns
+        whether the row was removed.
+        """
         assert self._conn is not None
-        await self._conn.execute("DELETE FROM tasks WHERE uid = ?", (uid,))
-        await self._conn.commit()
+        cursor = await self._conn.execute(
+            "DELETE FROM tasks WHERE uid = ? AND (pending_action IS NULL OR pending_action = 'create')",
+            (uid,),
+        )
+        await self._commit()
+        return cursor.rowcount > 0
 
     async def get_task(self, uid: str) -> Task | None:
         assert self._conn is not None
@@ -749,10 +1620,10 @@ class SqliteTaskCache:
         due_utc = _to_utc_timestamp(task.data.due)
         wait_utc = _to_utc_timestamp(task.data.wait)
         priority = task.data.priority
-        x_props = _serialize_map(task.data.x_properties)
-        categories = _serialize_properties(task.data.categories)
+        x_props = encode_x_properties(task.data.x_properties)
+        categories = encode_categories(task.data.categories)
         url = task.data.url
-        attachments = _serialize_attachments(task.data.attachments)
+        attachments = encode_attachments(task.data.attachments)
         href = task.href
         assert self._conn is not None
         async with self._conn.execute(
@@ -785,12 +1656,15 @@ class SqliteTaskCache:
                 categories,
                 url,
                 attachments,
+                parent,
                 href,
+                etag,
                 pending_action,
                 last_synced,
                 updated_at,
+                entry,
                 task_index
-            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             ON CONFLICT(uid) DO UPDATE SET
                 summary = excluded.summary,
                 status = excluded.status,
@@ -803,7 +1677,9 @@ class SqliteTaskCache:
                 categories = excluded.categories,
                 url = excluded.url,
                 attachments = excluded.attachments,
+                parent = excluded.parent,
                 href = excluded.href,
+                etag = excluded.etag,
                 pending_action = ?,
                 last_synced = ?,
                 updated_at = excluded.updated_at,
@@ -822,16 +1698,19 @@ class SqliteTaskCache:
                 categories,
                 url,
                 attachments,
+                task.data.parent,
                 href,
+                task.etag,
                 resolved_pending,
                 resolved_last_synced,
                 now,
+                now,
                 resolved_index,
                 resolved_pending,
                 resolved_last_synced,
             ),
         )
-        await self._conn.commit()
+        await self._commit()
 
     async def _insert_completed_task(
         self,
@@ -841,8 +1720,13 @@ class SqliteTaskCache:
         last_synced: float | None,
         completed_at: float,
         task_index: int | None = None,
+        completion_note: str | None = None,
     ) -> None:
-        """Insert or update a task in the completed_tasks table."""
+        """Insert or update a task in the completed_tasks table.
+
+        An existing completion note is kept unless a new one is given, and
+        an existing completed_at is always kept so syncing never moves it.
+        """
         summary = task.data.summary or task.uid
         status = task.data.status or "COMPLETED"
         due_value = task.data.due.isoformat() if task.data.due else None
@@ -850,10 +1734,10 @@ class SqliteTaskCache:
         due_utc = _to_utc_timestamp(task.data.due)
         wait_utc = _to_utc_timestamp(task.data.wait)
         priority = task.data.priority
-        x_props = _serialize_map(task.data.x_properties)
-        categories = _serialize_properties(task.data.categories)
+        x_props = encode_x_properties(task.data.x_properties)
+        categories = encode_categories(task.data.categories)
         url = task.data.url
-        attachments = _serialize_attachments(task.data.attachments)
+        attachments = encode_attachments(task.data.attachments)
         href = task.href
         now = time.time()
         assert self._conn is not None
@@ -872,13 +1756,17 @@ class SqliteTaskCache:
                 categories,
                 url,
                 attachments,
+                parent,
                 href,
+                etag,
                 pending_action,
                 last_synced,
                 updated_at,
+                entry,
                 completed_at,
-                task_index
-            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                task_index,
+                completion_note
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             ON CONFLICT(uid) DO UPDATE SET
                 summary = excluded.summary,
                 status = excluded.status,
@@ -891,12 +1779,15 @@ class SqliteTaskCache:
                 categories = excluded.categories,
                 url = excluded.url,
                 attachments = excluded.attachments,
+                parent = excluded.parent,
                 href = excluded.href,
+                etag = excluded.etag,
                 pending_action = excluded.pending_action,
                 last_synced = excluded.last_synced,
                 updated_at = excluded.updated_at,
-                completed_at = excluded.completed_at,
-                task_index = COALESCE(excluded.task_index, task_index)
+                completed_at = COALESCE(completed_tasks.completed_at, excluded.completed_at),
+                task_index = COALESCE(excluded.task_index, task_index),
+                completion_note = COALESCE(excluded.completion_note, completion_note)
             """,
             (
                 task.uid,
@@ -911,15 +1802,19 @@ class SqliteTaskCache:
                 categories,
                 url,
                 attachments,
+                task.data.parent,
                 href,
+                task.etag,
                 pending_action,
                 last_synced,
                 now,
+                now,
                 completed_at,
                 task_index,
+                completion_note,
             ),
         )
-        await self._conn.commit()
+        await self._commit()
 
     async def _insert_deleted_task(
         self,
@@ -936,10 +1831,10 @@ class SqliteTaskCache:
         due_utc = _to_utc_timestamp(task.data.due)
         wait_utc = _to_utc_timestamp(task.data.wait)
         priority = task.data.priority
-        x_props = _serialize_map(task.data.x_properties)
-        categories = _serialize_properties(task.data.categories)
+        x_props = encode_x_properties(task.data.x_properties)
+        categories = encode_categories(task.data.categories)
         url = task.data.url
-        attachments = _serialize_attachments(task.data.attachments)
+        attachments = encode_attachments(task.data.attachments)
         assert self._conn is not None
         await self._conn.execute(
             """
@@ -956,11 +1851,14 @@ class SqliteTaskCache:
                 categories,
                 url,
                 attachments,
+                parent,
                 href,
+                etag,
                 last_synced,
+                entry,
                 deleted_at,
                 task_index
-            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             ON CONFLICT(uid) DO UPDATE SET
                 summary = excluded.summary,
                 status = excluded.status,
@@ -973,7 +1871,9 @@ class SqliteTaskCache:
                 categories = excluded.categories,
                 url = excluded.url,
                 attachments = excluded.attachments,
+                parent = excluded.parent,
                 href = excluded.href,
+                etag = excluded.etag,
                 last_synced = excluded.last_synced,
                 deleted_at = excluded.deleted_at,
                 task_index = COALESCE(excluded.task_index, task_index)
@@ -991,18 +1891,30 @@ class SqliteTaskCache:
                 categories,
                 url,
                 attachments,
+                task.data.parent,
                 task.href,
+                task.etag,
                 None,  # last_synced
+                time.time(),
                 deleted_at,
                 task_index,
             ),
         )
-        await self._conn.commit()
+        await self._commit()
+
+    async def _carry_entry(self, table: str, uid: str, entry: float | None) -> None:
+        """Keep a task's creation time when it moves to another table."""
+        assert self._conn is not None
+        await self._conn.execute(f"UPDATE {table} SET entry = ? WHERE uid = ?", (entry, uid))
 
-    async def complete_task(self, uid: str) -> None:
+    async def complete_task(
+        self, uid: str, *, note: str | None = None, completed_at: float | None = None
+    ) -> None:
         """Move a task from tasks to completed_tasks.
 
         The task's original index is preserved in completed_tasks for undo.
+        An optional note is stored with the completed row. completed_at
+        backdates the completion (defaults to now).
         """
         assert self._conn is not None
 
@@ -1017,23 +1929,11 @@ class SqliteTaskCache:
 
         task = self._build_task(row)
         pending = await self.get_pending_action(uid)
-        now = time.time()
+        now = completed_at if completed_at is not None else time.time()
 
-        # Insert into completed_tasks with status COMPLETED
-        completed_task = Task(
-            uid=task.uid,
-            data=TaskData(
-                summary=task.data.summary,
-                status="COMPLETED",
-                due=task.data.due,
-                wait=task.data.wait,
-                priority=task.data.priority,
-                x_properties=task.data.x_properties,
-                categories=task.data.categories,
-            ),
-            href=task.href,
-            task_index=task.task_index,
-        )
+        # Insert into completed_tasks with status COMPLETED, keeping every
+        # other field (url, attachments, ...) as it was
+        completed_task = replace(task, data=replace(task.data, status="COMPLETED"))
 
         # If task was never synced (pending create), completion is also a create
         # Otherwise it's an update
@@ -1045,20 +1945,27 @@ class SqliteTaskCache:
             last_synced=row["last_synced"],
             completed_at=now,
             task_index=task.task_index,  # Preserve original index
+            completion_note=note,
         )
+        await self._carry_entry("completed_tasks", uid, row["entry"])
 
         # Remove from active tasks
         await self._conn.execute("DELETE FROM tasks WHERE uid = ?", (uid,))
-        await self._conn.commit()
+        await self._commit()
 
-    async def mark_for_deletion(self, uid: str) -> None:
+    async def mark_for_deletion(
+        self, uid: str, *, deleted_at: float | None = None, keep_unsynced: bool = False
+    ) -> bool:
         """Move a task to deleted_tasks (pending deletion).
 
         Works for both active tasks and completed tasks.
-        The task's original index is preserved for undo.
+        The task's original index is preserved for undo. Returns whether a
+        tombstone was left for the next push; a never-synced task is just
+        removed unless keep_unsynced is set, as imports do to record when a
+        task was deleted. deleted_at backdates the deletion (defaults to now).
         """
         assert self._conn is not None
-        now = time.time()
+        now = deleted_at if deleted_at is not None else time.time()
 
         # Try to find in active tasks first
         async with self._conn.execute(
@@ -1071,10 +1978,10 @@ class SqliteTaskCache:
             pending = await self.get_pending_action(uid)
 
             # If task was never synced, just delete it entirely
-            if pending == "create":
+            if pending == "create" and not keep_unsynced:
                 await self._conn.execute("DELETE FROM tasks WHERE uid = ?", (uid,))
-                await self._conn.commit()
-                return
+                await self._commit()
+                return False
 
             # Move to deleted_tasks
             await self._insert_deleted_task(
@@ -1082,9 +1989,10 @@ class SqliteTaskCache:
                 deleted_at=now,
                 task_index=task.task_index,
             )
+            await self._carry_entry("deleted_tasks", uid, row["entry"])
             await self._conn.execute("DELETE FROM tasks WHERE uid = ?", (uid,))
-            await self._conn.commit()
-            return
+            await self._commit()
+            return True
 
         # Try completed_tasks
         async with self._conn.execute(
@@ -1097,10 +2005,10 @@ class SqliteTaskCache:
             pending = row["pending_action"]
 
             # If completion was never synced, just delete it entirely
-            if pending == "create":
+            if pending == "create" and not keep_unsynced:
                 await self._conn.execute("DELETE FROM completed_tasks WHERE uid = ?", (uid,))
-                await self._conn.commit()
-                return
+                await self._commit()
+                return False
 
             # Move to deleted_tasks
             await self._insert_deleted_task(
@@ -1108,17 +2016,25 @@ class SqliteTaskCache:
                 deleted_at=now,
                 task_index=task.task_index,
             )
+            await self._carry_entry("deleted_tasks", uid, row["entry"])
             await self._conn.execute("DELETE FROM completed_tasks WHERE uid = ?", (uid,))
-            await self._conn.commit()
-            return
+            await self._commit()
+            return True
 
         raise KeyError(f"task {uid} not found")
 
-    async def flush_deleted_tasks(self) -> None:
-        """Delete all rows from deleted_tasks table (called after push)."""
+    async def flush_deleted_tasks(self, uids: Sequence[str]) -> None:
+        """Drop the tombstones for uids once their DELETE reached the server.
+
+        Tombstones whose delete has not been pushed stay, so a failed push
+        retries them next time.
+        """
         assert self._conn is not None
-        await self._conn.execute("DELETE FROM deleted_tasks")
-        await self._conn.commit()
+        if not uids:
+            return
+        placeholders = ", ".join("?" for _ in uids)
+        await self._conn.execute(f"DELETE FROM deleted_tasks WHERE uid IN ({placeholders})", tuple(uids))
+        await self._commit()
 
     async def list_completed_tasks(self) -> list[Task]:
         """List all completed tasks."""
@@ -1129,6 +2045,371 @@ class SqliteTaskCache:
             rows = await cursor.fetchall()
         return [self._build_completed_task(row) for row in rows]
 
+    async def check_integrity(self) -> list[IntegrityProblem]:
+        """Run read-only consistency checks over the task tables.
+
+        Checks for duplicate or missing indices among active tasks, COMPLETED
+        rows left in the active table, JSON columns that do not parse to the
+        expected type, and parent uids that match no known task.
+        """
+        assert self._conn is not None
+        problems: list[IntegrityProblem] = []
+
+        async with self._conn.execute(
+            """
+            SELECT task_index, group_concat(uid, ', ') FROM tasks
+            WHERE task_index IS NOT NULL
+            GROUP BY task_index HAVING COUNT(*) > 1
+            """
+        ) as cursor:
+            for task_index, uids in await cursor.fetchall():
+                problems.append(
+                    IntegrityProblem("duplicate_index", "tasks", None, f"index {task_index} is shared by {uids}")
+                )
+
+        async with self._conn.execute("SELECT uid FROM tasks WHERE task_index IS NULL") as cursor:
+            for (uid,) in await cursor.fetchall():
+                problems.append(IntegrityProblem("missing_index", "tasks", uid, "active task has no index"))
+
+        async with self._conn.execute("SELECT uid FROM tasks WHERE status = 'COMPLETED'") as cursor:
+            for (uid,) in await cursor.fetchall():
+                problems.append(
+                    IntegrityProblem("completed_in_active", "tasks", uid, "COMPLETED task is in the active table")
+                )
+
+        for table, column, uid, raw in await self._malformed_json_rows():
+            json_type = dict(_JSON_COLUMNS)[column]
+            problems.append(
+                IntegrityProblem("malformed_json", table, uid, f"{column} is not a JSON {json_type}: {raw[:40]!r}")
+            )
+
+        for table, uid, parent in await self._orphaned_parent_rows():
+            problems.append(IntegrityProblem("orphaned_parent", table, uid, f"parent {parent} matches no task"))
+        return problems
+
+    async def _malformed_json_rows(self) -> list[tuple[str, str, str, str]]:
+        """(table, column, uid, raw) for JSON columns that are invalid or of the wrong type."""
+        assert self._conn is not None
+        found: list[tuple[str, str, str, str]] = []
+        for table in sorted(TASK_TABLES):
+            for column, json_type in _JSON_COLUMNS:
+                # json_type() raises on invalid JSON, so only call it on valid values
+                async with self._conn.execute(
+                    f"""
+                    SELECT uid, {column} FROM {table}
+                    WHERE {column} IS NOT NULL AND {column} != ''
+                      AND CASE WHEN json_valid({column}) THEN json_type({column}) != ? ELSE 1 END
+                    """,
+                    (json_type,),
+                ) as cursor:
+                    found.extend((table, column, uid, raw) for uid, raw in await cursor.fetchall())
+        return found
+
+    async def _orphaned_parent_rows(self) -> list[tuple[str, str, str]]:
+        """(table, uid, parent) for tasks whose parent uid matches no kept task."""
+        assert self._conn is not None
+        found: list[tuple[str, str, str]] = []
+        for table in ("tasks", "completed_tasks"):
+            async with self._conn.execute(
+                f"""
+                SELECT uid, parent FROM {table}
+                WHERE parent IS NOT NULL AND parent != ''
+                  AND parent NOT IN (
+                      SELECT uid FROM tasks
+                      UNION SELECT uid FROM completed_tasks
+                      UNION SELECT uid FROM archived_tasks
+                  )
+                """
+            ) as cursor:
+                found.extend((table, uid, parent) for uid, parent in await cursor.fetchall())
+        return found
+
+    async def repair(self, *, dry_run: bool = False) -> list[IntegrityProblem]:
+        """Fix the problems check_integrity can safely fix, in one transaction.
+
+        Malformed JSON columns are re-encoded, COMPLETED rows in the active
+        table move to completed_tasks, dangling parent uids are cleared (and
+        the change queued for push), and duplicate or missing active indices
+        are reassigned. Returns one entry per fix, with the fix as the detail.
+        With dry_run the transaction is rolled back instead of committed.
+        """
+        assert self._conn is not None
+        fixes: list[IntegrityProblem] = []
+        now = time.time()
+        async with self._index_lock:
+            try:
+                for table, column, uid, raw in await self._malformed_json_rows():
+                    fixed = _recover_json_column(column, raw)
+                    await self._conn.execute(f"UPDATE {table} SET {column} = ? WHERE uid = ?", (fixed, uid))
+                    fixes.append(IntegrityProblem("malformed_json", table, uid, f"{column} rewritten as {fixed}"))
+
+                async with self._conn.execute(
+                    """
+                    SELECT uid, uid IN (SELECT uid FROM completed_tasks) FROM tasks
+                    WHERE status = 'COMPLETED'
+                    """
+                ) as cursor:
+                    stray = await cursor.fetchall()
+                for uid, already_completed in stray:
+                    await self._conn.execute(
+                        """
+                        INSERT OR IGNORE INTO completed_tasks (
+                            uid, summary, status, due, wait, due_utc, wait_utc, priority,
+                            x_properties, categories, url, attachments, parent, href, etag,
+                            pending_action, last_synced, updated_at, entry, completed_at, task_index
+                        )
+                        SELECT
+                            uid, summary, status, due, wait, due_utc, wait_utc, priority,
+                            x_properties, categories, url, attachments, parent, href, etag,
+                            pending_action, last_synced, updated_at, entry, ?, task_index
+                        FROM tasks WHERE uid = ?
+                        """,
+                        (now, uid),
+                    )
+                    await self._conn.execute("DELETE FROM tasks WHERE uid = ?", (uid,))
+                    action = "removed; already in completed_tasks" if already_completed else "moved to completed_tasks"
+                    fixes.append(IntegrityProblem("completed_in_active", "tasks", uid, action))
+
+                for table, uid, parent in await self._orphaned_parent_rows():
+                    await self._conn.execute(
+                        f"""
+                        UPDATE {table}
+                        SET parent = NULL,
+                            updated_at = ?,
+                            pending_action = CASE WHEN pending_action = 'create' THEN 'create' ELSE 'update' END
+                        WHERE uid = ?
+                        """,
+                        (now, uid),
+                    )
+                    fixes.append(IntegrityProblem("orphaned_parent", table, uid, f"parent {parent} cleared"))
+
+                # Keep the oldest holder of a duplicated index; the rest get new ones
+                async with self._conn.execute(
+                    """
+                    SELECT uid, task_index FROM tasks AS t
+                    WHERE task_index IS NOT NULL
+                      AND rowid != (SELECT MIN(rowid) FROM tasks WHERE task_index = t.task_index)
+                    ORDER BY task_index, rowid
+                    """
+                ) as cursor:
+                    duplicates = await cursor.fetchall()
+                for uid, _ in duplicates:
+                    await self._conn.execute("UPDATE tasks SET task_index = NULL WHERE uid = ?", (uid,))
+                old_indices = {uid: task_index for uid, task_index in duplicates}
+                async with self._conn.execute("SELECT uid FROM tasks WHERE task_index IS NULL ORDER BY rowid") as cursor:
+                    unindexed = [row[0] for row in await cursor.fetchall()]
+                for uid in unindexed:
+                    index = await self._next_available_index()
+                    await self._conn.execute("UPDATE tasks SET task_index = ? WHERE uid = ?", (index, uid))
+                    if uid in old_indices:
+                        fixes.append(
+                            IntegrityProblem(
+                                "duplicate_index", "tasks", uid, f"index {old_indices[uid]} reassigned to {index}"
+                            )
+                        )
+                    else:
+                        fixes.append(IntegrityProblem("missing_index", "tasks", uid, f"assigned index {index}"))
+
+                if dry_run:
+                    await self._conn.rollback()
+                else:
+                    await self._commit()
+            except Exception:
+                await self._rollback()
+                raise
+        return fixes
+
+    async def recompute_utc_dates(self, *, dry_run: bool = False) -> int:
+        """Rewrite due_utc and wait_utc from the stored due/wait text, in one transaction.
+
+        Naive dates are read in the current local timezone, so this repairs
+        sorting and filtering after the system timezone changes. Text that
+        does not parse keeps its stored timestamp. Returns how many rows
+        changed. With dry_run the transaction is rolled back.
+        """
+        assert self._conn is not None
+        changed = 0
+        try:
+            for table in ("tasks", "completed_tasks", "archived_tasks", "deleted_tasks"):
+                async with self._conn.execute(f"SELECT uid, due, wait, due_utc, wait_utc FROM {table}") as cursor:
+                    rows = await cursor.fetchall()
+                for uid, due_text, wait_text, due_utc, wait_utc in rows:
+                    fresh = (_refreshed_utc(due_text, due_utc), _refreshed_utc(wait_text, wait_utc))
+                    if fresh == (due_utc, wait_utc):
+                        continue
+                    await self._conn.execute(
+                        f"UPDATE {table} SET due_utc = ?, wait_utc = ? WHERE uid = ?", (*fresh, uid)
+                    )
+                    changed += 1
+            if dry_run:
+                await self._conn.rollback()
+            else:
+                await self._commit()
+        except Exception:
+            await self._rollback()
+            raise
+        return changed
+
+    async def forget_tasks(self, uids: Sequence[str]) -> list[ForgetReport]:
+        """Erase tasks from every table and the undo log, in one transaction.
+
+        Unlike mark_for_deletion no tombstone is left, so the server is never
+        told; a synced task comes back on the next pull unless it was deleted
+        there first. Log entries that only touched forgotten tasks are dropped.
+        """
+        assert self._conn is not None
+        reports: list[ForgetReport] = []
+        async with self._index_lock:
+            try:
+                for uid in uids:
+                    summary: str | None = None
+                    tables: list[str] = []
+                    for table in ("tasks", "completed_tasks", "archived_tasks", "deleted_tasks"):
+                        async with self._conn.execute(f"SELECT summary FROM {table} WHERE uid = ?", (uid,)) as cursor:
+                            row = await cursor.fetchone()
+                        if row is None:
+                            continue
+                        summary = summary or row[0]
+                        tables.append(table)
+                        await self._conn.execute(f"DELETE FROM {table} WHERE uid = ?", (uid,))
+
+                    log_entries = 0
+                    async with self._conn.execute("SELECT id, diff_json FROM transaction_log") as cursor:
+                        entries = await cursor.fetchall()
+                    for entry_id, diff_json in entries:
+                        diffs = json.loads(diff_json)
+                        if uid not in diffs:
+                            continue
+                        del diffs[uid]
+                        log_entries += 1
+                        if diffs:
+                            await self._conn.execute(
+                                "UPDATE transaction_log SET diff_json = ? WHERE id = ?", (json.dumps(diffs), entry_id)
+                            )
+                        else:
+                            await self._conn.execute("DELETE FROM transaction_log WHERE id = ?", (entry_id,))
+
+                    # Keys name the tasks their run touched; ones recorded before
+                    # that was tracked fall back to mentioning the summary
+                    cursor = await self._conn.execute(
+                        """
+                        DELETE FROM idempotency_keys
+                        WHERE EXISTS (SELECT 1 FROM json_each(idempotency_keys.uids) WHERE value = ?)
+                           OR (uids IS NULL AND ? IS NOT NULL AND instr(result, ?) > 0)
+                        """,
+                        (uid, summary or None, summary or None),
+                    )
+                    idempotency_keys = cursor.rowcount
+                    reports.append(ForgetReport(uid, summary, tables, log_entries, idempotency_keys))
+                await self._commit()
+            except Exception:
+                await self._rollback()
+                raise
+        return reports
+
+    async def list_stale_tasks(self, *, synced_before: float) -> list[StaleTask]:
+        """List synced tasks whose last_synced is older than synced_before.
+
+        Covers the active and completed tables. Tasks without an href were
+        never synced and are excluded; a missing last_synced counts as stale.
+        """
+        assert self._conn is not None
+        stale: list[StaleTask] = []
+        for table, builder, completed in (
+            ("tasks", self._build_task, False),
+            ("completed_tasks", self._build_completed_task, True),
+        ):
+            async with self._conn.execute(
+                f"""
+                SELECT * FROM {table}
+                WHERE href IS NOT NULL
+                  AND (last_synced IS NULL OR last_synced < ?)
+                """,
+                (synced_before,),
+            ) as cursor:
+                rows = await cursor.fetchall()
+            stale.extend(
+                StaleTask(task=builder(row), last_synced=row["last_synced"], completed=completed)
+                for row in rows
+            )
+        stale.sort(key=lambda entry: entry.last_synced or 0.0)
+        return stale
+
+    async def archive_completed_tasks(
+        self,
+        *,
+        completed_before: float | None = None,
+        uids: Sequence[str] | None = None,
+    ) -> list[Task]:
+        """Move completed tasks into archived_tasks.
+
+        Selects completed tasks finished before ``completed_before`` and/or
+        matching ``uids``. Tasks with unsynced changes are left in place so
+        the next push still sees them.
+
+        Returns:
+            The archived tasks
+        """
+        if completed_before is None and uids is None:
+            raise ValueError("archive requires a cutoff or explicit uids")
+        assert self._conn is not None
+        conditions = ["pending_action IS NULL"]
+        params: list[float | str] = []
+        if completed_before is not None:
+            conditions.append("completed_at < ?")
+            params.append(completed_before)
+        if uids is not None:
+            if not uids:
+                return []
+            placeholders = ",".join("?" for _ in uids)
+            conditions.append(f"uid IN ({placeholders})")
+            params.extend(uids)
+        where_clause = " AND ".join(conditions)
+
+        async with self._conn.execute(
+            f"SELECT * FROM completed_tasks WHERE {where_clause} ORDER BY completed_at",
+            params,
+        ) as cursor:
+            rows = await cursor.fetchall()
+        if not rows:
+            return []
+
+        now = time.time()
+        archived_uids = [row["uid"] for row in rows]
+        placeholders = ",".join("?" for _ in archived_uids)
+        await self._conn.execute(
+            f"""
+            INSERT OR REPLACE INTO archived_tasks (
+                uid, summary, status, due, wait, due_utc, wait_utc, priority,
+                x_properties, categories, url, attachments, parent, href, etag,
+                last_synced, updated_at, entry, completed_at, archived_at, task_index,
+                completion_note
+            )
+            SELECT
+                uid, summary, status, due, wait, due_utc, wait_utc, priority,
+                x_properties, categories, url, attachments, parent, href, etag,
+                last_synced, updated_at, entry, completed_at, ?, task_index,
+                completion_note
+            FROM completed_tasks WHERE uid IN ({placeholders})
+            """,
+            [now, *archived_uids],
+        )
+        await self._conn.execute(
+            f"DELETE FROM completed_tasks WHERE uid IN ({placeholders})",
+            archived_uids,
+        )
+        await self._commit()
+        return [self._build_completed_task(row) for row in rows]
+
+    async def list_archived_tasks(self) -> list[Task]:
+        """List all archived tasks, most recently completed first."""
+        assert self._conn is not None
+        async with self._conn.execute(
+            "SELECT * FROM archived_tasks ORDER BY completed_at DESC"
+        ) as cursor:
+            rows = await cursor.fetchall()
+        return [self._build_completed_task(row) for row in rows]
+
     async def list_deleted_tasks(self) -> list[Task]:
         """List all tasks pending deletion."""
         assert self._conn is not None
@@ -1169,16 +2450,9 @@ class SqliteTaskCache:
         # Create restored task with new status
         restored_task = Task(
             uid=task.uid,
-            data=TaskData(
-                summary=task.data.summary,
-                status=status,
-                due=task.data.due,
-                wait=task.data.wait,
-                priority=task.data.priority,
-                x_properties=task.data.x_properties,
-                categories=task.data.categories,
-            ),
+            data=replace(task.data, status=status),
             href=task.href,
+            etag=task.etag,
             task_index=resolved_index,
         )
 
@@ -1190,6 +2464,7 @@ class SqliteTaskCache:
             clear_pending=False,
             task_index=resolved_index,
         )
+        await self._carry_entry("tasks", uid, row["entry"])
 
         # Assign new index if original was unavailable
         if resolved_index is None:
@@ -1198,7 +2473,7 @@ class SqliteTaskCache:
 
         # Remove from completed_tasks
         await self._conn.execute("DELETE FROM completed_tasks WHERE uid = ?", (uid,))
-        await self._conn.commit()
+        await self._commit()
 
         return restored_task
 
@@ -1231,6 +2506,7 @@ class SqliteTaskCache:
             uid=task.uid,
             data=task.data,
             href=task.href,
+            etag=task.etag,
             task_index=resolved_index,
         )
 
@@ -1242,6 +2518,7 @@ class SqliteTaskCache:
             clear_pending=True,
             task_index=resolved_index,
         )
+        await self._carry_entry("tasks", uid, row["entry"])
 
         # Assign new index if original was unavailable
         if resolved_index is None:
@@ -1250,7 +2527,7 @@ class SqliteTaskCache:
 
         # Remove from deleted_tasks
         await self._conn.execute("DELETE FROM deleted_tasks WHERE uid = ?", (uid,))
-        await self._conn.commit()
+        await self._commit()
 
         return restored_task
 
@@ -1316,12 +2593,14 @@ class SqliteTaskCache:
                 due=due,
                 wait=wait,
                 priority=row["priority"],
-                x_properties=_parse_json(row["x_properties"]),
-                categories=_parse_list(row["categories"]),
+                x_properties=decode_x_properties(row["x_properties"]),
+                categories=decode_categories(row["categories"]),
                 url=row["url"],
-                attachments=_parse_attachments(row["attachments"]),
+                attachments=decode_attachments(row["attachments"]),
+                parent=row["parent"],
             ),
             href=row["href"],
+            etag=row["etag"],
             task_index=row["task_index"],
         )
 
@@ -1349,13 +2628,16 @@ class SqliteTaskCache:
                 due=due,
                 wait=wait,
                 priority=row["priority"],
-                x_properties=_parse_json(row["x_properties"]),
-                categories=_parse_list(row["categories"]),
+                x_properties=decode_x_properties(row["x_properties"]),
+                categories=decode_categories(row["categories"]),
                 url=row["url"],
-                attachments=_parse_attachments(row["attachments"]),
+                attachments=decode_attachments(row["attachments"]),
+                parent=row["parent"],
             ),
             href=row["href"],
+            etag=row["etag"],
             task_index=row["task_index"],
+            completion_note=row["completion_note"],
         )
 
     def _build_deleted_task(self, row: aiosqlite.Row) -> Task:
@@ -1382,36 +2664,51 @@ class SqliteTaskCache:
                 due=due,
                 wait=wait,
                 priority=row["priority"],
-                x_properties=_parse_json(row["x_properties"]),
-                categories=_parse_list(row["categories"]),
+                x_properties=decode_x_properties(row["x_properties"]),
+                categories=decode_categories(row["categories"]),
                 url=row["url"],
-                attachments=_parse_attachments(row["attachments"]),
+                attachments=decode_attachments(row["attachments"]),
+                parent=row["parent"],
             ),
             href=None,  # deleted_tasks doesn't have href
+            etag=row["etag"],
             task_index=row["task_index"],
         )
 
+    def _build_transaction_entry(self, row: aiosqlite.Row) -> TransactionLogEntry:
+        return TransactionLogEntry(
+            id=row["id"],
+            diff_json=row["diff_json"],
+            operation=row["operation"],
+            created_at=row["created_at"],
+        )
+
     async def log_transaction(
         self,
         diff: "TaskSetDiff[str]",
         *,
         operation: str | None = None,
-        max_entries: int = 32,
-    ) -> None:
+        max_entries: int | None = None,
+    ) -> int:
         """Record a TaskSetDiff to the transaction log.
 
-        Maintains a FIFO queue of max_entries. Oldest entries are dropped
-        when the limit is exceeded.
+        After inserting, trims the log to the cache's retention policy:
+        entries older than max_age_days, then the oldest entries beyond
+        max_entries.
 
         Args:
             diff: The diff to record (must be uid-keyed)
             operation: Optional operation type (e.g., "pull", "push", "add")
-            max_entries: Maximum log entries to retain
+            max_entries: Overrides the retention policy's entry limit
+
+        Returns:
+            Number of old entries trimmed
         """
         assert self._conn is not None
 
         diff_json = diff.to_json()
         now = time.time()
+        limit = max_entries if max_entries is not None else self.retention.max_entries
 
         # Insert new entry
         await self._conn.execute(
@@ -1421,9 +2718,23 @@ class SqliteTaskCache:
             """,
             (diff_json, operation, now),
         )
+        trimmed = await self._trim_transaction_log(now, limit)
+        await self._commit()
+        return trimmed
 
-        # Prune oldest entries beyond max_entries
-        await self._conn.execute(
+    async def _trim_transaction_log(self, now: float, limit: int) -> int:
+        """Apply the retention policy to the log; returns the entries trimmed."""
+        assert self._conn is not None
+        trimmed = 0
+        if self.retention.max_age_days > 0:
+            cursor = await self._conn.execute(
+                "DELETE FROM transaction_log WHERE created_at < ?",
+                (now - self.retention.max_age_days * 86400,),
+            )
+            trimmed += cursor.rowcount
+
+        # Prune oldest entries beyond the entry limit
+        cursor = await self._conn.execute(
             """
             DELETE FROM transaction_log
             WHERE id NOT IN (
@@ -1432,10 +2743,56 @@ class SqliteTaskCache:
                 LIMIT ?
             )
             """,
-            (max_entries,),
+            (limit,),
         )
+        trimmed += cursor.rowcount
+        return trimmed
+
+    async def transaction_history(self, uids: Sequence[str]) -> list[TransactionLogEntry]:
+        """Log entries that touched any of uids, oldest first.
 
-        await self._conn.commit()
+        Each entry's diff is narrowed to those uids; other tasks changed in
+        the same transaction are left out.
+        """
+        assert self._conn is not None
+        wanted = set(uids)
+        history: list[TransactionLogEntry] = []
+        async with self._conn.execute(
+            "SELECT id, diff_json, operation, created_at FROM transaction_log ORDER BY id"
+        ) as cursor:
+            rows = await cursor.fetchall()
+        for row in rows:
+            diffs = {uid: diff for uid, diff in json.loads(row["diff_json"]).items() if uid in wanted}
+            if diffs:
+                history.append(replace(self._build_transaction_entry(row), diff_json=json.dumps(diffs)))
+        return history
+
+    async def import_transactions(
+        self, entries: Sequence[TransactionLogEntry], uid_map: Mapping[str, str]
+    ) -> int:
+        """Append entries from another environment's log, renaming uids via uid_map.
+
+        Entries keep their operation and time. Those the retention policy
+        would drop (too old, or beyond max_entries counting from the newest)
+        are skipped, and the log is trimmed as after any other write.
+        Returns the number of entries added.
+        """
+        assert self._conn is not None
+        now = time.time()
+        kept = list(entries)
+        if self.retention.max_age_days > 0:
+            cutoff = now - self.retention.max_age_days * 86400
+            kept = [entry for entry in kept if entry.created_at >= cutoff]
+        kept = kept[-self.retention.max_entries:] if self.retention.max_entries > 0 else []
+        for entry in kept:
+            diffs = {uid_map.get(uid, uid): diff for uid, diff in json.loads(entry.diff_json).items()}
+            await self._conn.execute(
+                "INSERT INTO transaction_log (diff_json, operation, created_at) VALUES (?, ?, ?)",
+                (json.dumps(diffs), entry.operation, entry.created_at),
+            )
+        await self._trim_transaction_log(now, self.retention.max_entries)
+        await self._commit()
+        return len(kept)
 
     async def get_transaction_log(
         self,
@@ -1458,15 +2815,7 @@ class SqliteTaskCache:
         async with self._conn.execute(query) as cursor:
             rows = await cursor.fetchall()
 
-        return [
-            TransactionLogEntry(
-                id=row[0],
-                diff_json=row[1],
-                operation=row[2],
-                created_at=row[3],
-            )
-            for row in rows
-        ]
+        return [self._build_transaction_entry(row) for row in rows]
 
     async def clear_transaction_log(self) -> int:
         """Clear all transaction log entries.
@@ -1481,36 +2830,119 @@ class SqliteTaskCache:
             count = row[0] if row else 0
 
         await self._conn.execute("DELETE FROM transaction_log")
-        await self._conn.commit()
+        await self._commit()
 
         return count
 
-    async def pop_transaction(self) -> TransactionLogEntry | None:
-        """Pop the newest transaction log entry.
+    async def get_idempotent_result(self, key: str) -> IdempotentResult | None:
+        """Return the result recorded for key, if it is still inside the window."""
+        assert self._conn is not None
+        cutoff = time.time() - self.retention.idempotency_window_hours * 3600
+        async with self._conn.execute(
+            "SELECT command, result, created_at, pending FROM idempotency_keys WHERE key = ? AND created_at >= ?",
+            (key, cutoff),
+        ) as cursor:
+            row = await cursor.fetchone()
+        if row is None:
+            return None
+        return IdempotentResult(
+            command=row["command"], result=row["result"], created_at=row["created_at"], pending=bool(row["pending"])
+        )
 
-        Returns the entry and deletes it from the log.
-        Returns None if the log is empty.
+    async def claim_idempotency_key(self, key: str, command: str) -> IdempotentResult | None:
+        """Claim key for a run of command, or return whoever holds it.
+
+        The claim is one INSERT, so of two runs racing on the same key only
+        one gets None back and goes ahead; the other sees the claim, marked
+        pending until record_idempotent_result stores the output. Expired
+        keys are pruned first, so a key past its window can be claimed again.
+        """
+        assert self._conn is not None
+        now = time.time()
+        await self._conn.execute(
+            "DELETE FROM idempotency_keys WHERE created_at < ?",
+            (now - self.retention.idempotency_window_hours * 3600,),
+        )
+        # log_id marks where the run's log entries will start
+        cursor = await self._conn.execute(
+            """
+            INSERT INTO idempotency_keys (key, command, result, created_at, pending, log_id)
+            VALUES (?, ?, '', ?, 1, (SELECT COALESCE(MAX(id), 0) FROM transaction_log))
+            ON CONFLICT(key) DO NOTHING
+            """,
+            (key, command, now),
+        )
+        claimed = cursor.rowcount == 1
+        await self._commit()
+        if claimed:
+            return None
+        return await self.get_idempotent_result(key)
+
+    async def release_idempotency_key(self, key: str) -> None:
+        """Drop a claim whose run failed, so the key can be retried."""
+        assert self._conn is not None
+        await self._conn.execute("DELETE FROM idempotency_keys WHERE key = ? AND pending = 1", (key,))
+        await self._commit()
+
+    async def record_idempotent_result(self, key: str, command: str, result: str) -> int:
+        """Store the result of a keyed command, settling its claim, and prune expired keys.
+
+        When the key was claimed, the uids in log entries written since the
+        claim are stored with it, so forget_tasks can find the outputs that
+        belong to a task.
+
+        Returns:
+            Number of expired keys pruned
         """
         assert self._conn is not None
+        now = time.time()
+        uids: list[str] | None = None
+        async with self._conn.execute("SELECT log_id FROM idempotency_keys WHERE key = ?", (key,)) as cursor:
+            row = await cursor.fetchone()
+        if row is not None and row["log_id"] is not None:
+            async with self._conn.execute(
+                "SELECT diff_json FROM transaction_log WHERE id > ? ORDER BY id", (row["log_id"],)
+            ) as cursor:
+                entries = await cursor.fetchall()
+            uids = list(dict.fromkeys(uid for (diff_json,) in entries for uid in json.loads(diff_json)))
+        cursor = await self._conn.execute(
+            "DELETE FROM idempotency_keys WHERE created_at < ?",
+            (now - self.retention.idempotency_window_hours * 3600,),
+        )
+        pruned = cursor.rowcount
+        await self._conn.execute(
+            """
+            INSERT OR REPLACE INTO idempotency_keys (key, command, result, created_at, pending, uids)
+            VALUES (?, ?, ?, ?, 0, ?)
+            """,
+            (key, command, result, now, json.dumps(uids) if uids is not None else None),
+        )
+        await self._commit()
+        return pruned
 
-        # Get the newest entry
+    async def peek_transaction(self) -> TransactionLogEntry | None:
+        """The newest transaction log entry, left in place; None if the log is empty."""
+        assert self._conn is not None
         async with self._conn.execute(
             "SELECT id, diff_json, operation, created_at FROM transaction_log ORDER BY id DESC LIMIT 1"
         ) as cursor:
             row = await cursor.fetchone()
+        return self._build_transaction_entry(row) if row else None
 
-        if row is None:
-            return None
+    async def pop_transaction(self) -> TransactionLogEntry | None:
+        """Pop the newest transaction log entry.
 
-        entry = TransactionLogEntry(
-            id=row[0],
-            diff_json=row[1],
-            operation=row[2],
-            created_at=row[3],
-        )
+        Returns the entry and deletes it from the log.
+        Returns None if the log is empty.
+        """
+        assert self._conn is not None
+
+        entry = await self.peek_transaction()
+        if entry is None:
+            return None
 
         # Delete the entry
         await self._conn.execute("DELETE FROM transaction_log WHERE id = ?", (entry.id,))
-        await self._conn.commit()
+        await self._commit()
 
         return entry
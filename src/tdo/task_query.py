@@ -0,0 +1,145 @@
+from __future__ import annotations
+
+from datetime import datetime
+from typing import Any
+
+from .models import TAG_SEPARATOR, TaskFilter
+
+__all__ = ["TaskQuery", "COUNT_KEYS", "SORTABLE_COLUMNS", "TASK_TABLES"]
+
+TASK_TABLES = frozenset({"tasks", "completed_tasks", "deleted_tasks", "archived_tasks"})
+SORTABLE_COLUMNS = frozenset(
+    {"due", "due_utc", "wait_utc", "priority", "summary", "task_index", "updated_at", "completed_at"}
+)
+_DIRECTIONS = {"asc": "ASC", "desc": "DESC"}
+# Keys build_counts can group by; tags live in a JSON array and are tallied by the caller
+COUNT_KEYS = {
+    "project": "COALESCE(json_extract(x_properties, '$.X-PROJECT'), '')",
+    "status": "status",
+}
+# deleted_tasks keeps no updated_at, so updated.* filters match none of its rows
+_UPDATED_AT_TABLES = frozenset({"tasks", "completed_tasks", "archived_tasks"})
+
+
+def _tag_condition(tags: list[str], nested: bool) -> tuple[str, list[Any]]:
+    """Match a json_each row against any of tags, and with nested also their descendants.
+
+    Nesting is read from the tag text alone: area::health matches
+    area::health::gym by its "area::health::" prefix.
+    """
+    placeholders = ",".join("?" for _ in tags)
+    condition = f"json_each.value IN ({placeholders})"
+    params: list[Any] = list(tags)
+    if nested:
+        for tag in tags:
+            prefix = tag + TAG_SEPARATOR
+            condition += " OR substr(json_each.value, 1, ?) = ?"
+            params.extend([len(prefix), prefix])
+    return f"({condition})", params
+
+
+class TaskQuery:
+    """Build a SELECT over one task table with every value bound as a parameter.
+
+    Only identifiers from fixed allow-lists (table, sort column, direction)
+    are spliced into the SQL text; everything else goes through `?`.
+    """
+
+    def __init__(self, table: str = "tasks") -> None:
+        if table not in TASK_TABLES:
+            raise ValueError(f"unknown task table: {table!r}")
+        self._table = table
+        self._conditions: list[str] = []
+        self._params: list[Any] = []
+        self._order: list[str] = []
+
+    def where(self, condition: str, *params: Any) -> TaskQuery:
+        """Add a condition written in this package, with one param per `?`."""
+        if condition.count("?") != len(params):
+            raise ValueError(f"expected {condition.count('?')} parameters for {condition!r}, got {len(params)}")
+        self._conditions.append(condition)
+        self._params.extend(params)
+        return self
+
+    def apply_filter(self, task_filter: TaskFilter | None) -> TaskQuery:
+        if not task_filter:
+            return self
+        if task_filter.project:
+            self.where("json_extract(x_properties, '$.X-PROJECT') = ?", task_filter.project)
+        nested = task_filter.tags_match != "exact"
+        if task_filter.tags_mode == "any" and task_filter.tags:
+            condition, params = _tag_condition(task_filter.tags, nested)
+            self.where(f"EXISTS (SELECT 1 FROM json_each(categories) WHERE {condition})", *params)
+        else:
+            for tag in task_filter.tags:
+                condition, params = _tag_condition([tag], nested)
+                self.where(f"EXISTS (SELECT 1 FROM json_each(categories) WHERE {condition})", *params)
+        if task_filter.exclude_tags:
+            condition, params = _tag_condition(task_filter.exclude_tags, nested)
+            self.where(f"NOT EXISTS (SELECT 1 FROM json_each(categories) WHERE {condition})", *params)
+        if task_filter.exclude_projects:
+            placeholders = ",".join("?" for _ in task_filter.exclude_projects)
+            self.where(
+                f"COALESCE(json_extract(x_properties, '$.X-PROJECT'), '') NOT IN ({placeholders})",
+                *task_filter.exclude_projects,
+            )
+        if task_filter.has_url is not None:
+            has_url = "COALESCE(url, '') != ''"
+            self.where(has_url if task_filter.has_url else f"NOT ({has_url})")
+        if task_filter.has_attachments is not None:
+            # CASE keeps json_array_length away from malformed JSON
+            has_attachments = (
+                "CASE WHEN json_valid(attachments) THEN json_array_length(attachments) ELSE 0 END > 0"
+            )
+            self.where(has_attachments if task_filter.has_attachments else f"NOT ({has_attachments})")
+        if task_filter.status:
+            self.where("status = ?", task_filter.status)
+        if task_filter.custom_status:
+            self.where("json_extract(x_properties, '$.X-CUSTOM-STATUS') = ?", task_filter.custom_status)
+        if task_filter.due_before:
+            self.where("due_utc IS NOT NULL AND due_utc < ?", task_filter.due_before.timestamp())
+        due_window_end = task_filter.due_window_end(datetime.now().astimezone())
+        if due_window_end is not None:
+            self.where("due_utc IS NOT NULL AND due_utc <= ?", due_window_end.timestamp())
+        updated_column = "updated_at" if self._table in _UPDATED_AT_TABLES else "NULL"
+        for column, bound, operator in (
+            ("entry", task_filter.created_before, "<"),
+            ("entry", task_filter.created_after, ">"),
+            (updated_column, task_filter.updated_before, "<"),
+            (updated_column, task_filter.updated_after, ">"),
+        ):
+            if bound is not None:
+                self.where(f"{column} IS NOT NULL AND {column} {operator} ?", bound.timestamp())
+        if task_filter.indices:
+            placeholders = ",".join("?" for _ in task_filter.indices)
+            self.where(f"task_index IN ({placeholders})", *(int(i) for i in task_filter.indices))
+        return self
+
+    def order_by(self, column: str, direction: str = "asc", *, nulls_last: bool = False) -> TaskQuery:
+        if column not in SORTABLE_COLUMNS:
+            raise ValueError(f"cannot sort by {column!r}")
+        keyword = _DIRECTIONS.get(direction.lower())
+        if keyword is None:
+            raise ValueError(f"invalid sort direction: {direction!r}")
+        if nulls_last:
+            self._order.append(f"{column} IS NULL")
+        self._order.append(f"{column} {keyword}")
+        return self
+
+    def build_counts(self, key: str) -> tuple[str, list[Any]]:
+        """One (key, count) row per value of an allow-listed key, ignoring order_by."""
+        expression = COUNT_KEYS.get(key)
+        if expression is None:
+            raise ValueError(f"cannot count by {key!r}")
+        query = f"SELECT {expression} AS key, COUNT(*) AS count FROM {self._table}"
+        if self._conditions:
+            query += " WHERE " + " AND ".join(self._conditions)
+        return query + " GROUP BY key", list(self._params)
+
+    def build(self) -> tuple[str, list[Any]]:
+        query = f"SELECT * FROM {self._table}"
+        if self._conditions:
+            query += " WHERE " + " AND ".join(self._conditions)
+        if self._order:
+            query += " ORDER BY " + ", ".join(self._order)
+        return query, list(self._params)
@@ -61,7 +61,11 @@ _TIME_OF_DAY_RE = re.compile(
     r"^(?P<h>\d{1,2})(?::(?P<m>\d{1,2}))?(?::(?P<s>\d{1,2}))?\s*(?P<suffix>a|am|p|pm)?$",
     re.IGNORECASE,
 )
+_DATE_AND_TIME_RE = re.compile(r"^(?P<y>\d{4})-(?P<mo>\d{2})-(?P<d>\d{2})\s+(?P<time>\S.*)$")
 _YEAR_DURATION_RE = re.compile(r"^(\d+)y$", re.IGNORECASE)
+# Unix epoch seconds: always with an "@" prefix; bare only with 9+ integer
+# digits (1973 onwards), so a year such as "2025" is not read as seconds
+_EPOCH_RE = re.compile(r"^@(\d+(?:\.\d+)?)$|^(\d{9,}(?:\.\d+)?)$")
 
 
 def _parse_relative_duration(raw: str) -> timedelta | None:
@@ -105,13 +109,23 @@ def parse_due_value(raw: str, reference: arrow.Arrow | None = None) -> arrow.Arr
         return _previous_ordinal_day(now, int(match.group(1)))
     if lowered in _MONTH_MAP:
         return _previous_month_start(now, _MONTH_MAP[lowered])
-    if lowered.isdigit():
-        return arrow.get(int(lowered))
+    if match := _EPOCH_RE.fullmatch(lowered):
+        return arrow.get(float(match.group(1) or match.group(2)))
 
     # Try parsing as relative duration (2w, 3d, 1y, P1D, etc.)
     if duration := _parse_relative_duration(candidate):
         return now.shift(seconds=duration.total_seconds())
 
+    # "YYYY-MM-DD HH:MM" and "YYYY-MM-DD 3pm": a time of day on that local date.
+    # Times with an offset or fractional seconds are left to arrow below.
+    if match := _DATE_AND_TIME_RE.fullmatch(candidate):
+        try:
+            day = now.replace(year=int(match.group("y")), month=int(match.group("mo")), day=int(match.group("d")))
+        except ValueError:
+            return None
+        if parsed := _parse_time_of_day(match.group("time"), day):
+            return parsed
+
     try:
         return arrow.get(candidate)
     except (ParserError, ValueError):
@@ -120,6 +134,17 @@ def parse_due_value(raw: str, reference: arrow.Arrow | None = None) -> arrow.Arr
         return None
 
 
+def parse_age_value(raw: str, reference: arrow.Arrow | None = None) -> arrow.Arrow | None:
+    """Parse a bound for an age filter: a bare duration (90d, 2w) counts back from now.
+
+    Anything else (dates, "today", "monday", ...) is read like a due value.
+    """
+    now = reference or arrow.now()
+    if duration := _parse_relative_duration(raw or ""):
+        return now.shift(seconds=-duration.total_seconds())
+    return parse_due_value(raw, now)
+
+
 def _start_of_day(value: arrow.Arrow) -> arrow.Arrow:
     return value.floor("day")
 
@@ -220,6 +245,10 @@ def _static_work_week_end(value: arrow.Arrow) -> arrow.Arrow:
 
 
 def _parse_time_of_day(value: str, reference: arrow.Arrow) -> arrow.Arrow | None:
+    """Parse "15:00", "3pm", or "3:30 pm" as a time on the reference's date.
+
+    Out-of-range fields (hour 24, minute 60, "13pm") are rejected.
+    """
     match = _TIME_OF_DAY_RE.fullmatch(value)
     if not match:
         return None
@@ -227,6 +256,8 @@ def _parse_time_of_day(value: str, reference: arrow.Arrow) -> arrow.Arrow | None
     minute = int(match.group("m") or 0)
     second = int(match.group("s") or 0)
     suffix = match.group("suffix")
+    if minute > 59 or second > 59 or hour > (12 if suffix else 23) or (suffix and hour == 0):
+        return None
     if suffix:
         normalized = suffix.lower()
         if normalized in {"p", "pm"} and hour < 12:
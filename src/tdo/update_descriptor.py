@@ -12,3 +12,5 @@ __all__ = ["UpdateDescriptor"]
 class UpdateDescriptor:
     add_data: TaskData[str] = field(default_factory=lambda: TaskData[str]())
     remove_data: TaskData[str] = field(default_factory=lambda: TaskData[str]())
+    # The bare words when they make up add_data.summary; None when summary: overrides them
+    description: str | None = None
@@ -1,9 +1,16 @@
 from __future__ import annotations
 
+import re
+
 from .models import TaskData
 from .update_descriptor import UpdateDescriptor
 
-__all__ = ["parse_update"]
+__all__ = ["METADATA_KEYS", "parse_update"]
+
+# Keys whose token sets a field instead of adding summary words
+METADATA_KEYS = frozenset(
+    {"project", "pro", "estimate", "custom_status", "due", "wait", "pri", "priority", "status", "summary", "x", "url"}
+)
 
 
 def _parse_priority(raw: str) -> int | None:
@@ -23,8 +30,23 @@ def _parse_priority(raw: str) -> int | None:
         return None
 
 
+# A token runs to the next unquoted whitespace; "..." may hold spaces, a lone " is literal
+_TOKEN = re.compile(r'(?:[^\s"]+|"[^"]*"|")+')
+_QUOTED = re.compile(r'"([^"]*)"')
+
+
+def _split_tokens(raw: str) -> list[str]:
+    """Split on whitespace, keeping double-quoted runs whole and quoted."""
+    return _TOKEN.findall(raw)
+
+
+def _unquote(value: str) -> str:
+    """Drop the quotes around a key:"value" token's value; summary words keep theirs."""
+    return _QUOTED.sub(r"\1", value)
+
+
 def parse_update(raw: str) -> UpdateDescriptor:
-    tokens = [segment for segment in raw.strip().split() if segment]
+    tokens = [segment for segment in _split_tokens(raw) if segment]
     description_parts: list[str] = []
     additions: list[str] = []
     removals: list[str] = []
@@ -48,19 +70,26 @@ def parse_update(raw: str) -> UpdateDescriptor:
         # Key-value metadata
         if ":" in token:
             key, rest = token.split(":", 1)
+            rest = _unquote(rest)
             key_lower = key.strip().lower()
             value = rest.strip()
 
-            if key_lower == "project":
+            if key_lower in ("project", "pro"):
                 x_properties["X-PROJECT"] = value  # Empty string signals "unset"
                 continue
+            if key_lower == "estimate":
+                x_properties["X-ESTIMATE"] = value  # Minutes; empty string signals "unset"
+                continue
+            if key_lower == "custom_status":
+                x_properties["X-CUSTOM-STATUS"] = value  # Empty string signals "unset"
+                continue
             if key_lower == "due":
                 due = value  # Keep empty string to signal "unset"
                 continue
             if key_lower == "wait":
                 wait = value  # Keep empty string to signal "unset"
                 continue
-            if key_lower == "pri":
+            if key_lower in ("pri", "priority"):
                 if not value:
                     priority = 0  # Use 0 to signal "unset"
                 else:
@@ -110,4 +139,8 @@ def parse_update(raw: str) -> UpdateDescriptor:
         categories=list(removal_set) if removal_set else None,
     )
 
-    return UpdateDescriptor(add_data=add_data, remove_data=remove_data)
+    return UpdateDescriptor(
+        add_data=add_data,
+        remove_data=remove_data,
+        description=description if summary is None and description else None,
+    )
@@ -74,13 +74,17 @@ class _UpdateVisitor(NodeVisitor):
         if key and isinstance(key, str):
             key_lower = key.lower()
 
-            if key_lower == "project":
+            if key_lower in ("project", "pro"):
                 self.x_properties["X-PROJECT"] = value  # Empty string signals "unset"
+            elif key_lower == "estimate":
+                self.x_properties["X-ESTIMATE"] = value  # Minutes; empty string signals "unset"
+            elif key_lower == "custom_status":
+                self.x_properties["X-CUSTOM-STATUS"] = value  # Empty string signals "unset"
             elif key_lower == "due":
                 self.due = value  # Keep empty string to signal "unset"
             elif key_lower == "wait":
                 self.wait = value  # Keep empty string to signal "unset"
-            elif key_lower == "pri":
+            elif key_lower in ("pri", "priority"):
                 if not value:
                     self.priority = 0  # Use 0 to signal "unset"
                 else:
@@ -135,7 +139,11 @@ class _UpdateVisitor(NodeVisitor):
             categories=list(removals) if removals else None,
         )
 
-        return UpdateDescriptor(add_data=add_data, remove_data=remove_data)
+        return UpdateDescriptor(
+            add_data=add_data,
+            remove_data=remove_data,
+            description=description if self.summary is None and description else None,
+        )
 
     def generic_visit(self, node, visited_children):
         return visited_children or node.text
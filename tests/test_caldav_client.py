@@ -1,13 +1,17 @@
 from __future__ import annotations
 
-from datetime import datetime
+from dataclasses import replace
+from datetime import datetime, timedelta
 from pathlib import Path
+from types import SimpleNamespace
 
 import pytest
 
-from tdo.caldav_client import CalDAVClient
+from tdo.caldav_client import CalDAVClient, EtagConflictError, PullResult
 from tdo.config import CaldavConfig
+from tdo.diff import TaskSetDiff
 from tdo.models import Task, TaskData, TaskPatch, TaskPayload
+from tdo.sqlite_cache import ReadOnlyCacheError
 
 
 CALENDAR_CONFIG = CaldavConfig(calendar_url="https://example.com/calendars/main", username="alice")
@@ -68,6 +72,25 @@ def test_task_from_data_parses_fields() -> None:
     assert task.data.categories == ["plan", "review"]
 
 
+def test_task_from_data_round_trips_parent() -> None:
+    client = CalDAVClient(CALENDAR_CONFIG)
+    body = client._build_ics("Child", None, None, None, {}, None, "child-1", None, parent="parent-1")
+    assert "RELATED-TO;RELTYPE=PARENT:parent-1" in body
+    assert client._task_from_data(body).data.parent == "parent-1"
+    sibling = body.replace("RELATED-TO;RELTYPE=PARENT", "RELATED-TO;RELTYPE=SIBLING")
+    assert client._task_from_data(sibling).data.parent is None
+
+
+async def test_parent_survives_cache_round_trip(client: CalDAVClient) -> None:
+    parent = await client.create_task(TaskPayload(summary="Parent"))
+    child = await client.create_task(TaskPayload(summary="Child", parent=parent.uid))
+    cached = await client._ensure_cache().get_task(child.uid)
+    assert cached is not None
+    assert cached.data.parent == parent.uid
+    orphaned = await client.modify_task(cached, TaskPatch(parent=""))
+    assert orphaned.data.parent is None
+
+
 def test_ensure_calendar_raises_when_not_initialized() -> None:
     client = CalDAVClient(CALENDAR_CONFIG)
     with pytest.raises(RuntimeError):
@@ -90,6 +113,28 @@ async def test_create_task_persists_in_cache(client: CalDAVClient) -> None:
     assert await client.cache.get_pending_action(created.uid) == "create"
 
 
+async def test_create_task_uses_configured_uid_template(tmp_path: Path) -> None:
+    config = replace(CALENDAR_CONFIG, uid_template="{uuid}@myhost")
+    client = await CalDAVClient.create(config, cache_path=tmp_path / "cache.db")
+    try:
+        created = await client.create_task(TaskPayload(summary="Interop task"))
+        assert created.uid.endswith("@myhost")
+        assert "Interop" not in created.uid
+        assert await client.cache.get_task(created.uid) is not None
+    finally:
+        await client.close()
+
+
+async def test_create_task_honors_requested_index(client: CalDAVClient) -> None:
+    created = await client.create_task(TaskPayload(summary="Imported"), index=5)
+    assert created.task_index == 5
+    assert (await client.create_task(TaskPayload(summary="Next"))).task_index == 1
+
+    with pytest.raises(ValueError, match="index 5 is already taken"):
+        await client.create_task(TaskPayload(summary="Clash"), index=5)
+    assert sorted(task.data.summary for task in await client.list_tasks()) == ["Imported", "Next"]
+
+
 async def test_modify_task_marks_update_for_remote_task(client: CalDAVClient) -> None:
     base = Task(uid="remote", data=TaskData(summary="Remote"))
     await client.cache.upsert_task(base)
@@ -114,3 +159,385 @@ async def test_delete_marks_remote_task_as_pending(client: CalDAVClient) -> None
     deleted_task = await client.cache.get_deleted_task(existing.uid)
     assert deleted_task is not None
     assert deleted_task.uid == existing.uid
+
+
+async def test_complete_mode_delete_drops_only_unsynced_tasks(tmp_path: Path) -> None:
+    config = replace(CALENDAR_CONFIG, complete_mode="delete")
+    client = await CalDAVClient.create(config, cache_path=tmp_path / "cache.db")
+    try:
+        local = await client.create_task(TaskPayload(summary="Local"))
+        remote = Task(uid="remote", data=TaskData(summary="Remote"))
+        await client.cache.upsert_task(remote)
+        assert await client.complete_task(local.uid) == "delete"
+        assert await client.complete_task(remote.uid) == "archive"
+        assert await client.cache.get_task(local.uid) is None
+        assert await client.cache.get_completed_task(local.uid) is None
+        assert await client.cache.get_deleted_task(local.uid) is None
+        assert await client.cache.get_completed_task(remote.uid) is not None
+    finally:
+        await client.close()
+
+
+async def test_complete_mode_archive_keeps_unsynced_tasks(client: CalDAVClient) -> None:
+    created = await client.create_task(TaskPayload(summary="Local"))
+    assert await client.complete_task(created.uid) == "archive"
+    assert await client.cache.get_completed_task(created.uid) is not None
+
+
+async def test_reopen_counts_each_reopen_and_queues_a_push(client: CalDAVClient) -> None:
+    remote = Task(
+        uid="synced",
+        data=TaskData(summary="Synced", url="https://example.com/ticket"),
+        href="/cal/synced.ics",
+    )
+    await client.cache.replace_remote_tasks([remote])
+    for expected in (1, 2):
+        await client.complete_task(remote.uid)
+        reopened = await client.reopen_task(remote.uid)
+        assert reopened.reopened == expected
+    assert reopened.data.status == "NEEDS-ACTION"
+    assert reopened.data.url == "https://example.com/ticket"
+    assert await client.cache.get_pending_action(remote.uid) == "update"
+    assert await client.cache.get_completed_task(remote.uid) is None
+    with pytest.raises(KeyError):
+        await client.reopen_task(remote.uid)
+
+
+async def test_touched_tasks_reports_where_each_task_ended_up(client: CalDAVClient) -> None:
+    assert await client.peek_transaction() is None
+    await client.cache.replace_remote_tasks(
+        [Task(uid=uid, data=TaskData(summary=uid), href=f"/cal/{uid}.ics") for uid in ("keep", "done", "gone")]
+    )
+    local = await client.create_task(TaskPayload(summary="local"))
+    async with client.logged("bulk"):
+        await client.modify_task(await client.cache.get_task("keep"), TaskPatch(priority=1))
+        await client.complete_task("done")
+        await client.delete_task("gone")
+        await client.delete_task(local.uid)
+    entry = await client.peek_transaction()
+    assert entry is not None and entry.operation == "bulk"
+    touched = {item.task.uid: item for item in await client.touched_tasks(entry)}
+    assert {uid: item.state for uid, item in touched.items()} == {
+        "keep": "active",
+        "done": "completed",
+        "gone": "deleted",
+        local.uid: "removed",
+    }
+    assert touched["keep"].task.data.priority == 1
+    assert touched[local.uid].task.data.summary == "local"
+    # Peeking leaves the entry for undo
+    assert (await client.peek_transaction()).id == entry.id
+
+
+async def test_modify_task_skips_write_when_nothing_changes(client: CalDAVClient) -> None:
+    cache = client._ensure_cache()
+    remote = Task(
+        uid="synced",
+        data=TaskData(summary="Synced", status="NEEDS-ACTION", categories=["a"], x_properties={"X-PROJECT": "work"}),
+        href="/cal/synced.ics",
+    )
+    await cache.replace_remote_tasks([remote])
+    cached = await cache.get_task("synced")
+    assert cached is not None
+
+    same, changed = await client.modify_task_if_changed(
+        cached, TaskPatch(x_properties={"X-PROJECT": "work"}, categories=["a"])
+    )
+    assert not changed
+    assert same is cached
+    assert await cache.get_pending_action("synced") is None
+
+    moved, changed = await client.modify_task_if_changed(
+        cached, TaskPatch(x_properties={"X-PROJECT": "home"}, categories=["a", "b"])
+    )
+    assert changed
+    assert moved.data.x_properties["X-PROJECT"] == "home"
+    assert await cache.get_pending_action("synced") == "update"
+
+
+async def test_modify_task_with_empty_patch_runs_no_sql(client: CalDAVClient) -> None:
+    cache = client._ensure_cache()
+    created = await client.create_task(TaskPayload(summary="Untouched"))
+    before = await client.peek_transaction()
+    statements: list[str] = []
+    assert cache._conn is not None
+    await cache._conn.set_trace_callback(statements.append)
+    try:
+        same, changed = await client.modify_task_if_changed(created, TaskPatch())
+        assert await client.modify_task(created, TaskPatch()) is created
+    finally:
+        await cache._conn.set_trace_callback(None)
+    assert not changed
+    assert same is created
+    assert statements == []
+    assert (await client.peek_transaction()).id == before.id
+
+
+async def test_mutations_log_themselves(client: CalDAVClient) -> None:
+    cache = client._ensure_cache()
+    created = await client.create_task(TaskPayload(summary="Logged"))
+    await client.modify_task(created, TaskPatch(priority=2))
+
+    modify, add = await cache.get_transaction_log()
+    assert (add.operation, modify.operation) == ("add", "modify")
+    diff = TaskSetDiff.from_json(modify.diff_json).diffs[created.uid]
+    assert diff.pre.priority is None
+    assert diff.post.priority == 2
+
+
+async def test_logged_block_keeps_earliest_pre_and_latest_post(client: CalDAVClient) -> None:
+    cache = client._ensure_cache()
+    base = Task(uid="remote", data=TaskData(summary="Remote"))
+    await cache.upsert_task(base)
+    other = Task(uid="other", data=TaskData(summary="Other"))
+    await cache.upsert_task(other)
+
+    async with client.logged("do"):
+        async with client.logged("ignored"):
+            started = await client.modify_task(base, TaskPatch(x_properties={"X-TIMESPENT": "60"}))
+        await client.complete_task(started.uid)
+        await client.delete_task(other.uid)
+
+    entries = await cache.get_transaction_log()
+    assert [entry.operation for entry in entries] == ["do"]
+    diffs = TaskSetDiff.from_json(entries[0].diff_json).diffs
+    assert diffs["remote"].pre == base.data
+    assert diffs["remote"].post.status == "COMPLETED"
+    assert diffs["remote"].post.x_properties == {"X-TIMESPENT": "60"}
+    assert diffs["other"].post is None
+
+
+async def test_logged_block_writes_changes_and_log_together(
+    client: CalDAVClient, monkeypatch: pytest.MonkeyPatch
+) -> None:
+    cache = client._ensure_cache()
+    base = Task(uid="remote", data=TaskData(summary="Remote"))
+    await cache.upsert_task(base)
+
+    # A failing log write takes the changes with it
+    async def log_transaction(diff, *, operation=None, max_entries=None) -> int:
+        raise RuntimeError("disk full")
+
+    monkeypatch.setattr(cache, "log_transaction", log_transaction)
+    with pytest.raises(RuntimeError, match="disk full"):
+        async with client.logged("modify"):
+            await client.modify_task(base, TaskPatch(priority=1))
+    monkeypatch.undo()
+    assert (await cache.get_task("remote")).data.priority is None
+
+    # So does a block that fails after some changes
+    with pytest.raises(RuntimeError, match="halfway"):
+        async with client.logged("do"):
+            await client.modify_task(base, TaskPatch(priority=2))
+            raise RuntimeError("halfway")
+    assert (await cache.get_task("remote")).data.priority is None
+    assert await cache.get_transaction_log() == []
+
+    async with client.logged("modify"):
+        await client.modify_task(base, TaskPatch(priority=3))
+    assert (await cache.get_task("remote")).data.priority == 3
+    assert [entry.operation for entry in await cache.get_transaction_log()] == ["modify"]
+
+
+async def test_activate_waited_tasks_and_its_log_entry_fail_together(
+    client: CalDAVClient, monkeypatch: pytest.MonkeyPatch
+) -> None:
+    cache = client._ensure_cache()
+    await cache.upsert_task(Task(uid="waited", data=TaskData(summary="Waited", wait=datetime(2000, 1, 1))))
+
+    async def log_transaction(diff, *, operation=None, max_entries=None) -> int:
+        raise RuntimeError("disk full")
+
+    monkeypatch.setattr(cache, "log_transaction", log_transaction)
+    with pytest.raises(RuntimeError, match="disk full"):
+        await client.activate_waited_tasks()
+    monkeypatch.undo()
+    assert (await cache.get_task("waited")).data.wait == datetime(2000, 1, 1)
+
+    assert [task.uid for task in await client.activate_waited_tasks()] == ["waited"]
+    assert (await cache.get_task("waited")).data.wait is None
+    assert [entry.operation for entry in await cache.get_transaction_log()] == ["activate"]
+
+
+async def test_swap_indices_and_its_log_entry_fail_together(
+    client: CalDAVClient, monkeypatch: pytest.MonkeyPatch
+) -> None:
+    first = await client.create_task(TaskPayload(summary="First"))
+    second = await client.create_task(TaskPayload(summary="Second"))
+    cache = client._ensure_cache()
+
+    async def log_transaction(diff, *, operation=None, max_entries=None) -> int:
+        raise RuntimeError("disk full")
+
+    monkeypatch.setattr(cache, "log_transaction", log_transaction)
+    with pytest.raises(RuntimeError, match="disk full"):
+        await client.swap_indices(first, second)
+    monkeypatch.undo()
+    assert (await cache.get_task(first.uid)).task_index == first.task_index
+    assert (await cache.get_task(second.uid)).task_index == second.task_index
+
+    swapped = await client.swap_indices(first, second)
+    assert [task.task_index for task in swapped] == [second.task_index, first.task_index]
+    assert [entry.operation for entry in await cache.get_transaction_log()].count("swap-index") == 1
+
+
+async def test_unchanged_modify_is_not_logged(client: CalDAVClient) -> None:
+    base = Task(uid="remote", data=TaskData(summary="Remote"))
+    await client._ensure_cache().upsert_task(base)
+    async with client.logged("modify"):
+        await client.modify_task_if_changed(base, TaskPatch(summary="Remote"))
+    assert await client._ensure_cache().get_transaction_log() == []
+
+
+async def test_push_keeps_tombstone_when_delete_fails(client: CalDAVClient, monkeypatch: pytest.MonkeyPatch) -> None:
+    cache = client._ensure_cache()
+    await cache.replace_remote_tasks([
+        Task(uid="gone", data=TaskData(summary="Gone")),
+        Task(uid="stuck", data=TaskData(summary="Stuck")),
+    ])
+    await client.delete_task("gone")
+    await client.delete_task("stuck")
+
+    def push_delete(task: Task, calendar: object) -> None:
+        if task.uid == "stuck":
+            raise RuntimeError("server unavailable")
+
+    monkeypatch.setattr(client, "_ensure_calendar", lambda: object())
+    monkeypatch.setattr(client, "_push_delete", push_delete)
+    result = await client.push()
+
+    assert [error.uid for error in result.errors] == ["stuck"]
+    assert [task.uid for task in await cache.list_deleted_tasks()] == ["stuck"]
+
+
+async def test_completion_days_survive_push_and_pull(client: CalDAVClient, monkeypatch: pytest.MonkeyPatch) -> None:
+    cache = client._ensure_cache()
+    await cache.replace_remote_tasks([
+        Task(uid="old", data=TaskData(summary="Old")),
+        Task(uid="kept", data=TaskData(summary="Kept")),
+    ])
+    three_days_ago = (datetime.now() - timedelta(days=3)).timestamp()
+    ten_days_ago = (datetime.now() - timedelta(days=10)).timestamp()
+    await client.complete_task("old", completed_at=three_days_ago)
+    await client.complete_task("kept", completed_at=ten_days_ago)
+
+    monkeypatch.setattr(client, "_ensure_calendar", lambda: object())
+    monkeypatch.setattr(client, "_push_update", lambda task, calendar: replace(task, etag='"2"'))
+    await client.push()
+    await cache.replace_remote_tasks([
+        Task(uid="old", data=TaskData(summary="Old", status="COMPLETED"), etag='"2"'),
+        Task(uid="kept", data=TaskData(summary="Kept", status="COMPLETED"), etag='"2"'),
+    ])
+    await cache.archive_completed_tasks(uids=["kept"])
+
+    assert await cache.count_completed_by_day(0) == {
+        datetime.fromtimestamp(three_days_ago).date().isoformat(): 1,
+        datetime.fromtimestamp(ten_days_ago).date().isoformat(): 1,
+    }
+
+
+async def test_backdated_completion_of_a_new_task_survives_push(
+    client: CalDAVClient, monkeypatch: pytest.MonkeyPatch
+) -> None:
+    created = await client.create_task(TaskPayload(summary="Imported"))
+    await client.complete_task(created.uid, completed_at=1_700_000_000.0)
+
+    monkeypatch.setattr(client, "_ensure_calendar", lambda: object())
+    monkeypatch.setattr(client, "_push_create", lambda task, calendar: replace(task, etag='"1"'))
+    await client.push()
+
+    assert await client._ensure_cache().count_completed_by_day(0) == {
+        datetime.fromtimestamp(1_700_000_000).date().isoformat(): 1
+    }
+
+
+async def test_kept_unsynced_deletion_keeps_its_time(client: CalDAVClient) -> None:
+    created = await client.create_task(TaskPayload(summary="Imported"))
+    assert await client.delete_task(created.uid, deleted_at=1_600_000_000.0, keep_unsynced=True)
+    matches = await client._ensure_cache().search_deleted_tasks("imported")
+    assert [match.deleted_at for match in matches] == [1_600_000_000.0]
+
+
+class FakeDAVClient:
+    """Answers PUT and HEAD with canned responses and records what was sent."""
+
+    def __init__(self, *responses: SimpleNamespace) -> None:
+        self.responses = list(responses)
+        self.requests: list[tuple[str, str, dict[str, str]]] = []
+
+    def put(self, url: str, body: str, headers: dict[str, str]) -> SimpleNamespace:
+        self.requests.append(("PUT", url, headers))
+        return self.responses.pop(0)
+
+    def request(self, url: str, method: str = "GET", body: str = "", headers=None) -> SimpleNamespace:
+        self.requests.append((method, url, headers or {}))
+        return self.responses.pop(0)
+
+
+def _response(status: int, etag: str | None = None) -> SimpleNamespace:
+    return SimpleNamespace(status=status, reason="", headers={"ETag": etag} if etag else {})
+
+
+def test_push_update_sends_if_match_and_stores_the_new_etag(monkeypatch: pytest.MonkeyPatch) -> None:
+    client = CalDAVClient(CALENDAR_CONFIG)
+    url = "https://example.com/calendars/main/t.ics"
+    monkeypatch.setattr(client, "_resource_for_update", lambda task, calendar: SimpleNamespace(url=url))
+    task = Task(uid="t", data=TaskData(summary="T"), href=url, etag='"1"', task_index=3)
+
+    client.client = FakeDAVClient(_response(204, '"2"'))
+    synced = client._push_update(task, calendar=None)
+    assert client.client.requests[0][2]["If-Match"] == '"1"'
+    assert (synced.uid, synced.href, synced.etag, synced.task_index) == ("t", url, '"2"', 3)
+
+    # A server that leaves the etag out of the reply is asked for it
+    client.client = FakeDAVClient(_response(204), _response(200, '"3"'))
+    assert client._push_update(task, calendar=None).etag == '"3"'
+    assert [method for method, _, _ in client.client.requests] == ["PUT", "HEAD"]
+
+    client.client = FakeDAVClient(_response(412))
+    with pytest.raises(EtagConflictError, match="changed on the server"):
+        client._push_update(task, calendar=None)
+
+
+async def test_push_keeps_the_local_edit_when_the_server_copy_changed(
+    client: CalDAVClient, monkeypatch: pytest.MonkeyPatch
+) -> None:
+    cache = client._ensure_cache()
+    await cache.replace_remote_tasks([Task(uid="t", data=TaskData(summary="T"), etag='"1"')])
+    await client.modify_task(await cache.get_task("t"), TaskPatch(summary="Local"))
+    pulls: list[bool] = []
+
+    def push_update(task: Task, calendar) -> Task:
+        assert task.etag == '"1"'
+        raise EtagConflictError(task.uid)
+
+    async def pull(*, dry_run: bool = False) -> PullResult:
+        pulls.append(dry_run)
+        return PullResult(tasks=[], diff=TaskSetDiff(diffs={}))
+
+    monkeypatch.setattr(client, "_ensure_calendar", lambda: object())
+    monkeypatch.setattr(client, "_push_update", push_update)
+    monkeypatch.setattr(client, "pull", pull)
+    result = await client.push()
+
+    assert [(error.uid, error.action) for error in result.errors] == [("t", "update")]
+    assert "kept the local edit pending" in result.errors[0].error
+    assert pulls == []
+    assert (await cache.get_task("t")).data.summary == "Local"
+    assert await cache.get_pending_action("t") == "update"
+
+
+@pytest.mark.asyncio
+async def test_read_only_client_rejects_mutations(tmp_path: Path) -> None:
+    writer = await CalDAVClient.create(CALENDAR_CONFIG, cache_path=tmp_path / "cache.db")
+    try:
+        await writer.create_task(TaskPayload(summary="Existing"))
+    finally:
+        await writer.close()
+    reader = await CalDAVClient.create(CALENDAR_CONFIG, cache_path=tmp_path / "cache.db", read_only=True)
+    try:
+        assert [task.data.summary for task in await reader.list_tasks()] == ["Existing"]
+        with pytest.raises(ReadOnlyCacheError, match="cannot create a task"):
+            await reader.create_task(TaskPayload(summary="New"))
+    finally:
+        await reader.close()
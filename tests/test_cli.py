@@ -1,15 +1,24 @@
 from __future__ import annotations
 
+import asyncio
+import fcntl
 import io
-from contextlib import redirect_stdout
-from datetime import datetime
+import json
+import os
+from contextlib import asynccontextmanager, redirect_stdout
+from dataclasses import replace
+from datetime import datetime, timedelta, timezone
 from pathlib import Path
+from typing import AsyncIterator
 
 import pytest
 
 from tdo import cli
-from tdo.config import CaldavConfig
+from tdo.caldav_client import CalDAVClient
+from tdo.config import CaldavConfig, RoutingConfig
+from tdo.diff import TaskDiff, TaskSetDiff
 from tdo.models import Task, TaskData, TaskPatch, TaskPayload
+from tdo.sqlite_cache import ArchivedMatch, BoundaryEvent, CacheInfo, DeletedMatch, ForgetReport, IdempotentResult, IntegrityProblem, SqliteTaskCache, StaleTask, TaskRef, TimelineEntry, TimeSpent, TransactionLogEntry
 
 
 def run_cli(arguments: list[str]) -> tuple[int, str]:
@@ -27,10 +36,14 @@ class DummyClient:
     last_patch: TaskPatch | None = None
     last_modified_uid: str | None = None
     deleted: list[str] = []
+    completed: list[str] = []
+    completed_tasks: list[Task] = []
     default_tasks: list[Task] = [
         Task(uid="list-task", data=TaskData(summary="List task", due=None, priority=3), task_index=1)
     ]
     list_entries: list[Task] = list(default_tasks)
+    idempotency: dict[str, IdempotentResult] = {}
+    integrity_problems: list[IntegrityProblem] = []
     _next_index: int = 1
 
     def __init__(self, config: CaldavConfig) -> None:
@@ -43,7 +56,11 @@ class DummyClient:
         cls.last_patch = None
         cls.last_modified_uid = None
         cls.deleted = []
+        cls.completed = []
+        cls.completed_tasks = []
         cls.list_entries = list(cls.default_tasks)
+        cls.idempotency = {}
+        cls.integrity_problems = []
         cls._next_index = 2  # default_tasks has index 1
 
     def __enter__(self) -> DummyClient:
@@ -55,9 +72,19 @@ class DummyClient:
     async def close(self) -> None:
         pass
 
-    async def create_task(self, payload: TaskPayload) -> Task:
+    @asynccontextmanager
+    async def logged(self, operation: str) -> AsyncIterator[None]:
+        yield
+
+    async def index_available(self, index: int) -> bool:
+        return all(task.task_index != index for task in DummyClient.list_entries)
+
+    async def swap_indices(self, first: Task, second: Task) -> tuple[Task, Task]:
+        return replace(first, task_index=second.task_index), replace(second, task_index=first.task_index)
+
+    async def create_task(self, payload: TaskPayload, *, index: int | None = None) -> Task:
         DummyClient.last_payload = payload
-        task_index = DummyClient._next_index
+        task_index = DummyClient._next_index if index is None else index
         DummyClient._next_index += 1
         return Task(
             uid="dummy-task",
@@ -88,9 +115,49 @@ class DummyClient:
             task_index=task.task_index,
         )
 
-    async def delete_task(self, uid: str) -> str:
+    async def modify_task_if_changed(self, task: Task, patch: TaskPatch) -> tuple[Task, bool]:
+        updated = await self.modify_task(task, patch)
+        return updated, updated.data != task.data
+
+    async def check_integrity(self) -> list[IntegrityProblem]:
+        return list(DummyClient.integrity_problems)
+
+    async def repair(self, *, dry_run: bool = False) -> list[IntegrityProblem]:
+        fixes = [replace(problem, detail="fixed") for problem in DummyClient.integrity_problems]
+        if not dry_run:
+            DummyClient.integrity_problems = []
+        return fixes
+
+    async def get_idempotent_result(self, key: str) -> IdempotentResult | None:
+        return DummyClient.idempotency.get(key)
+
+    async def claim_idempotency_key(self, key: str, command: str) -> IdempotentResult | None:
+        if key in DummyClient.idempotency:
+            return DummyClient.idempotency[key]
+        DummyClient.idempotency[key] = IdempotentResult(command=command, result="", created_at=0.0, pending=True)
+        return None
+
+    async def release_idempotency_key(self, key: str) -> None:
+        if DummyClient.idempotency.get(key, IdempotentResult("", "", 0.0)).pending:
+            del DummyClient.idempotency[key]
+
+    async def record_idempotent_result(self, key: str, command: str, result: str) -> int:
+        DummyClient.idempotency[key] = IdempotentResult(command=command, result=result, created_at=0.0)
+        return 0
+
+    async def delete_task(self, uid: str, *, deleted_at: float | None = None, keep_unsynced: bool = False) -> bool:
         DummyClient.deleted.append(uid)
-        return uid
+        return True
+
+    async def complete_task(self, uid: str, *, note: str | None = None, completed_at: float | None = None) -> None:
+        DummyClient.completed.append(uid)
+        DummyClient.completed_tasks.extend(
+            replace(t, completion_note=note) for t in DummyClient.list_entries if t.uid == uid
+        )
+        DummyClient.list_entries = [t for t in DummyClient.list_entries if t.uid != uid]
+
+    async def list_completed_tasks(self) -> list[Task]:
+        return list(DummyClient.completed_tasks)
 
     async def list_tasks(self, force_refresh: bool = False) -> list[Task]:
         return list(DummyClient.list_entries)
@@ -103,8 +170,22 @@ class DummyClient:
         result = tasks
         if task_filter.project:
             result = [t for t in result if t.data.x_properties.get("X-PROJECT") == task_filter.project]
-        for tag in task_filter.tags:
-            result = [t for t in result if tag in (t.data.categories or [])]
+        if task_filter.tags_mode == "any" and task_filter.tags:
+            result = [t for t in result if set(task_filter.tags) & set(t.data.tags)]
+        elif task_filter.tags:
+            result = [t for t in result if set(task_filter.tags) <= set(t.data.tags)]
+        if task_filter.exclude_tags:
+            result = [t for t in result if not set(task_filter.exclude_tags) & set(t.data.tags)]
+        if task_filter.exclude_projects:
+            result = [t for t in result if t.data.project not in task_filter.exclude_projects]
+        if task_filter.has_url is not None:
+            result = [t for t in result if bool(t.data.url) == task_filter.has_url]
+        if task_filter.has_attachments is not None:
+            result = [t for t in result if bool(t.data.attachments) == task_filter.has_attachments]
+        if task_filter.status:
+            result = [t for t in result if t.data.status == task_filter.status]
+        if task_filter.due_before:
+            result = [t for t in result if t.data.due is not None and t.data.due < task_filter.due_before]
         if task_filter.indices:
             result = [t for t in result if t.task_index in task_filter.indices]
         return result
@@ -136,8 +217,9 @@ async def _mock_cache_client(env: str | None) -> DummyClient:
 
 
 @pytest.fixture(autouse=True)
-def stub_cal_dav(monkeypatch: pytest.MonkeyPatch) -> None:
+def stub_cal_dav(monkeypatch: pytest.MonkeyPatch, tmp_path: Path) -> None:
     DummyClient.reset()
+    monkeypatch.setenv("TDO_TASK_CACHE_FILE", str(tmp_path / "tasks.db"))
     monkeypatch.setattr(cli, "_cache_client", _mock_cache_client)
     monkeypatch.setattr(
         cli,
@@ -157,6 +239,80 @@ def stub_cal_dav(monkeypatch: pytest.MonkeyPatch) -> None:
     )
 
 
+def test_mutating_commands_wait_for_the_env_lock(monkeypatch: pytest.MonkeyPatch, tmp_path: Path) -> None:
+    config = CaldavConfig(calendar_url="https://example.com/cal", username="tester")
+    config.cache.lock_timeout_seconds = 0
+    monkeypatch.setattr(cli, "load_config", lambda env: config)
+    lock_path = tmp_path / "tasks.db.lock"
+    fd = os.open(lock_path, os.O_RDWR | os.O_CREAT)
+    try:
+        fcntl.flock(fd, fcntl.LOCK_EX)
+        exit_code, stdout = run_cli(["add", "Blocked"])
+        assert exit_code == 1
+        assert "environment busy" in stdout
+        assert DummyClient.last_payload is None
+        # Reads and dry runs don't take the lock
+        assert run_cli(["list"])[0] == 0
+        assert run_cli(["repair", "--dry-run"])[0] == 0
+    finally:
+        os.close(fd)
+    assert run_cli(["add", "Unblocked"])[0] == 0
+
+
+def test_commands_lock_every_env_they_write_to(monkeypatch: pytest.MonkeyPatch, tmp_path: Path) -> None:
+    def load(env: str | None) -> CaldavConfig:
+        name = env or "default"
+        config = CaldavConfig(
+            calendar_url="https://example.com/cal",
+            username="tester",
+            env=name,
+            routing=RoutingConfig(tags={"personal": "home"}) if name == "work" else RoutingConfig(),
+        )
+        config.cache.lock_timeout_seconds = 0
+        return config
+
+    monkeypatch.setattr(cli, "load_config", load)
+    monkeypatch.delenv("TDO_TASK_CACHE_FILE")
+    monkeypatch.setenv("HOME", str(tmp_path))
+    home_lock = tmp_path / ".cache" / "tdo" / "home" / "tasks.db.lock"
+    home_lock.parent.mkdir(parents=True)
+    fd = os.open(home_lock, os.O_RDWR | os.O_CREAT)
+    try:
+        fcntl.flock(fd, fcntl.LOCK_EX)
+        # Routed into the locked env
+        exit_code, stdout = run_cli(["--env", "work", "add", "Buy", "milk", "+personal"])
+        assert exit_code == 1
+        assert "environment busy" in stdout
+        assert DummyClient.last_payload is None
+        # Moving into it
+        exit_code, stdout = run_cli(["--env", "work", "1", "move", "home"])
+        assert exit_code == 1
+        assert "environment busy" in stdout
+        # Tasks that stay in work don't wait for it
+        assert run_cli(["--env", "work", "add", "Report"])[0] == 0
+    finally:
+        os.close(fd)
+    assert run_cli(["--env", "work", "add", "Buy", "milk", "+personal"])[0] == 0
+    assert cli._written_envs(cli._build_parser().parse_args(["--env", "work", "move", "home"])) == ["work", "home"]
+
+
+def test_read_commands_open_the_cache_read_only(monkeypatch: pytest.MonkeyPatch) -> None:
+    opened: list[bool] = []
+
+    async def recording_client(env: str | None) -> DummyClient:
+        opened.append(cli._read_only_cache.get())
+        return await _mock_cache_client(env)
+
+    monkeypatch.setattr(cli, "_cache_client", recording_client)
+    for argv in (["list"], ["show"], ["complete", "tasks"]):
+        opened.clear()
+        assert run_cli(argv)[0] == 0
+        assert opened and all(opened), argv
+    opened.clear()
+    assert run_cli(["add", "Write"])[0] == 0
+    assert opened == [False]
+
+
 def test_add_command_parses_tokens() -> None:
     exit_code, stdout = run_cli(["add", "Create", "pri:H", "x:X-TEST:value"])
     assert exit_code == 0
@@ -166,6 +322,193 @@ def test_add_command_parses_tokens() -> None:
     assert DummyClient.last_payload.x_properties == {"X-TEST": "value"}
 
 
+def test_add_start_creates_task_in_process() -> None:
+    exit_code, stdout = run_cli(["add", "--start", "Begin", "now"])
+    assert exit_code == 0
+    payload = DummyClient.last_payload
+    assert payload is not None
+    assert payload.summary == "Begin now"
+    assert payload.status == "IN-PROCESS"
+    assert "X-STARTED-AT" in payload.x_properties
+    assert "Created (1):" in stdout
+
+
+def test_add_start_rejects_conflicting_status() -> None:
+    exit_code, _ = run_cli(["add", "--start", "Done", "status:COMPLETED"])
+    assert exit_code == 1
+    assert DummyClient.last_payload is None
+
+
+def test_add_rejects_wait_after_due_unless_forced() -> None:
+    tokens = ["Late", "due:2030-01-01T09:00:00", "wait:2030-01-05T09:00:00"]
+    exit_code, stdout = run_cli(["add", *tokens])
+    assert exit_code == 1
+    assert "wait 2030-01-05 09:00 is after due 2030-01-01 09:00" in stdout
+    assert DummyClient.last_payload is None
+
+    exit_code, _ = run_cli(["add", "--force", *tokens])
+    assert exit_code == 0
+    assert DummyClient.last_payload is not None
+
+
+def test_add_rejects_due_far_in_the_past() -> None:
+    exit_code, stdout = run_cli(["add", "Typo", "due:1925-01-01T09:00:00"])
+    assert exit_code == 1
+    assert "days in the past" in stdout
+    assert DummyClient.last_payload is None
+
+
+def test_modify_checks_wait_against_existing_due() -> None:
+    DummyClient.list_entries = [
+        Task(uid="a", data=TaskData(summary="A", due=datetime(2030, 1, 1)), task_index=1),
+        Task(uid="b", data=TaskData(summary="B"), task_index=2),
+    ]
+    exit_code, stdout = run_cli(["1,2", "modify", "wait:2030-02-01T00:00:00"])
+    assert exit_code == 1
+    assert "task 1:" in stdout
+    # No task is modified when any of them fails the check
+    assert DummyClient.last_patch is None
+
+    exit_code, _ = run_cli(["1,2", "modify", "--force", "wait:2030-02-01T00:00:00"])
+    assert exit_code == 0
+
+
+def _routing_configs(monkeypatch: pytest.MonkeyPatch, rules: dict[str, RoutingConfig]) -> list[str | None]:
+    """Give each env its own routing rules and record which env add opens."""
+    opened: list[str | None] = []
+
+    def load(env: str | None) -> CaldavConfig:
+        name = env or "default"
+        return CaldavConfig(
+            calendar_url="https://example.com/cal",
+            username="tester",
+            env=name,
+            routing=rules.get(name, RoutingConfig()),
+        )
+
+    async def client_for(env: str | None) -> DummyClient:
+        opened.append(env)
+        return await _mock_cache_client(env)
+
+    monkeypatch.setattr(cli, "load_config", load)
+    monkeypatch.setattr(cli, "_cache_client", client_for)
+    return opened
+
+
+def test_add_routes_tagged_task_to_configured_env(monkeypatch: pytest.MonkeyPatch) -> None:
+    opened = _routing_configs(
+        monkeypatch,
+        {
+            "work": RoutingConfig(tags={"personal": "home"}),
+            "home": RoutingConfig(projects={"garden": "outdoors"}),
+        },
+    )
+    exit_code, stdout = run_cli(["--env", "work", "add", "Buy", "milk", "+personal"])
+    assert exit_code == 0
+    assert opened == ["home"]
+    assert stdout.splitlines()[-1] == "Routed to env: home"
+
+    # Rules are followed hop by hop, and untagged tasks stay put
+    exit_code, stdout = run_cli(["--env", "work", "add", "Weed", "+personal", "project:garden"])
+    assert exit_code == 0
+    assert opened[-1] == "outdoors"
+    exit_code, stdout = run_cli(["--env", "work", "add", "Report"])
+    assert opened[-1] == "work"
+    assert "Routed" not in stdout
+
+
+def test_routed_add_keeps_its_idempotency_key_in_the_destination(monkeypatch: pytest.MonkeyPatch) -> None:
+    opened = _routing_configs(monkeypatch, {"work": RoutingConfig(tags={"personal": "home"})})
+    argv = ["--env", "work", "add", "--idempotency-key", "k1", "Buy", "milk", "+personal"]
+    exit_code, first = run_cli(argv)
+    assert exit_code == 0
+    # Claimed, created, and recorded in home, never in work
+    assert opened == ["home", "home", "home"]
+
+    DummyClient.last_payload = None
+    exit_code, second = run_cli(argv)
+    assert second == first
+    assert DummyClient.last_payload is None
+    assert opened[3:] == ["home"]
+
+
+def test_add_routing_rejects_loops_and_ambiguous_tags(monkeypatch: pytest.MonkeyPatch) -> None:
+    opened = _routing_configs(
+        monkeypatch,
+        {
+            "work": RoutingConfig(tags={"personal": "home", "ops": "oncall"}),
+            "home": RoutingConfig(tags={"personal": "work"}),
+        },
+    )
+    exit_code, stdout = run_cli(["--env", "work", "add", "Ping", "+personal"])
+    assert exit_code == 1
+    assert "routing rules loop: work -> home -> work" in stdout
+    exit_code, stdout = run_cli(["--env", "work", "add", "Both", "+personal", "+ops"])
+    assert exit_code == 1
+    assert "tags route to different environments: home, oncall" in stdout
+    assert opened == []
+    assert DummyClient.last_payload is None
+
+
+def test_add_index_uses_requested_index_or_fails_when_taken() -> None:
+    exit_code, stdout = run_cli(["add", "--index", "7", "Imported"])
+    assert exit_code == 0
+    assert "[7]" in stdout
+
+    DummyClient.list_entries = [Task(uid="seven", data=TaskData(summary="Seven"), task_index=7)]
+    DummyClient.last_payload = None
+    exit_code, stdout = run_cli(["add", "--index", "7", "Clash"])
+    assert exit_code == 1
+    assert "index 7 is already taken" in stdout
+    assert DummyClient.last_payload is None
+
+    exit_code, stdout = run_cli(["add", "--index", "7", "--index-taken", "next", "Clash"])
+    assert exit_code == 0
+    assert "[7]" not in stdout
+
+
+def test_add_with_idempotency_key_replays_first_result() -> None:
+    exit_code, first = run_cli(["add", "--idempotency-key", "k1", "Once"])
+    assert exit_code == 0
+    assert "Created (1):" in first
+    DummyClient.last_payload = None
+
+    exit_code, second = run_cli(["add", "--idempotency-key", "k1", "Once"])
+    assert exit_code == 0
+    assert second == first
+    assert DummyClient.last_payload is None
+
+
+def test_idempotency_key_rejects_reuse_for_different_command() -> None:
+    run_cli(["add", "--idempotency-key", "k1", "Once"])
+    DummyClient.last_payload = None
+
+    exit_code, _ = run_cli(["add", "--idempotency-key", "k1", "Other"])
+    assert exit_code == 1
+    assert DummyClient.last_payload is None
+
+
+def test_idempotency_key_claimed_by_a_running_command_is_not_reused() -> None:
+    DummyClient.idempotency["k1"] = IdempotentResult(command=json.dumps(["add", "Once"]), result="", created_at=0.0, pending=True)
+    exit_code, stdout = run_cli(["add", "--idempotency-key", "k1", "Once"])
+    assert exit_code == 1
+    assert "idempotency key k1 is still running in another command" in stdout
+    assert DummyClient.last_payload is None
+
+
+def test_failed_idempotent_run_releases_its_key() -> None:
+    exit_code, _ = run_cli(["99", "do", "--idempotency-key", "k1"])
+    assert exit_code == 1
+    assert "k1" not in DummyClient.idempotency
+
+    DummyClient.list_entries = [Task(uid="t", data=TaskData(summary="T"), task_index=99)]
+    exit_code, stdout = run_cli(["99", "do", "--idempotency-key", "k1"])
+    assert exit_code == 0
+    assert DummyClient.completed == ["t"]
+    assert not DummyClient.idempotency["k1"].pending
+    assert DummyClient.idempotency["k1"].result == stdout
+
+
 def test_add_command_parses_tags_and_project() -> None:
     exit_code, stdout = run_cli(
         [
@@ -214,6 +557,18 @@ def test_add_command_allows_description_tokens_after_metadata() -> None:
     assert set(payload.categories) == {"tag"}
 
 
+def test_add_command_keeps_spaces_in_a_quoted_argv_value() -> None:
+    # The shell hands over "project:Home Stuff" as one token
+    exit_code, _ = run_cli(["add", "buy", "milk", "project:Home Stuff", "due:2025-01-01 03:00", "note: call first"])
+    assert exit_code == 0
+    payload = DummyClient.last_payload
+    assert payload is not None
+    assert payload.x_properties.get("X-PROJECT") == "Home Stuff"
+    assert payload.due == datetime(2025, 1, 1, 3, 0, 0)
+    # Unknown keys are summary words, split as before
+    assert payload.summary == "buy milk note: call first"
+
+
 def test_modify_command_accepts_summary_patch() -> None:
     exit_code, stdout = run_cli(["modify", "existing", "summary:Updated", "pri:L"])
     assert exit_code == 0
@@ -222,6 +577,42 @@ def test_modify_command_accepts_summary_patch() -> None:
     assert DummyClient.last_patch.priority == 9
 
 
+def test_modify_append_extends_summary_after_any_replacement() -> None:
+    DummyClient.list_entries = [Task(uid="first", data=TaskData(summary="Fix login"), task_index=1)]
+    exit_code, _ = run_cli(["1", "modify", "--append", "(see PR 12)"])
+    assert exit_code == 0
+    assert DummyClient.last_patch is not None
+    assert DummyClient.last_patch.summary == "Fix login (see PR 12)"
+
+    exit_code, _ = run_cli(["1", "modify", "--append", "blocked", "--append-separator", " - ", "summary:Signup"])
+    assert exit_code == 0
+    assert DummyClient.last_patch.summary == "Signup - blocked"
+
+    exit_code, stdout = run_cli(["1", "modify", "--append", " "])
+    assert exit_code == 1
+    assert "--append requires text" in stdout
+
+
+def test_modify_words_append_when_configured(monkeypatch: pytest.MonkeyPatch) -> None:
+    async def appending_client(env: str | None) -> DummyClient:
+        return DummyClient(
+            CaldavConfig(calendar_url="https://example.com/cal", username="tester", modify_words="append")
+        )
+
+    monkeypatch.setattr(cli, "_cache_client", appending_client)
+    DummyClient.list_entries = [Task(uid="first", data=TaskData(summary="Fix login"), task_index=1)]
+    exit_code, _ = run_cli(["1", "modify", "on", "mobile", "+urgent", "pro:work"])
+    assert exit_code == 0
+    assert DummyClient.last_patch is not None
+    assert DummyClient.last_patch.summary == "Fix login on mobile"
+    assert DummyClient.last_patch.project == "work"
+
+    # summary: still replaces outright
+    exit_code, _ = run_cli(["1", "modify", "summary:Signup"])
+    assert exit_code == 0
+    assert DummyClient.last_patch.summary == "Signup"
+
+
 def test_modify_command_adds_tag_without_other_changes() -> None:
     exit_code, stdout = run_cli(["1", "modify", "+foo2"])
     assert exit_code == 0
@@ -230,6 +621,31 @@ def test_modify_command_adds_tag_without_other_changes() -> None:
     assert DummyClient.last_patch.categories == ["foo2"]
 
 
+def test_add_and_modify_reject_values_missing_from_allow_lists(monkeypatch: pytest.MonkeyPatch) -> None:
+    async def cache_client(env: str | None) -> DummyClient:
+        config = CaldavConfig(calendar_url="https://example.com/cal", username="tester")
+        config.validation.allowed_tags = ["home", "work"]
+        config.validation.allowed_projects = ["ops"]
+        return DummyClient(config)
+
+    monkeypatch.setattr(cli, "_cache_client", cache_client)
+    exit_code, stdout = run_cli(["add", "Typo", "+wrok"])
+    assert exit_code == 1
+    assert "tag 'wrok' is not allowed; allowed tags: home, work" in stdout
+    exit_code, stdout = run_cli(["add", "Other", "project:misc"])
+    assert exit_code == 1
+    assert "project 'misc' is not allowed; allowed projects: ops" in stdout
+    assert DummyClient.last_payload is None
+    assert run_cli(["add", "Fine", "+work", "project:ops"])[0] == 0
+
+    DummyClient.list_entries = [Task(uid="legacy", data=TaskData(summary="Old", categories=["misc"]), task_index=1)]
+    # A tag the task already has doesn't block unrelated edits
+    assert run_cli(["1", "modify", "+home"])[0] == 0
+    exit_code, stdout = run_cli(["1", "modify", "+chores"])
+    assert exit_code == 1
+    assert "task 1: tag 'chores' is not allowed" in stdout
+
+
 def test_delete_command_accepts_filter_indices() -> None:
     DummyClient.list_entries = [
         Task(uid="first", data=TaskData(summary="Alpha", due=None, priority=1), task_index=1),
@@ -254,6 +670,304 @@ def test_delete_command_accepts_numeric_identifiers() -> None:
     assert DummyClient.deleted == ["first"]
 
 
+def test_do_command_completes_tasks_matching_status_and_tag() -> None:
+    DummyClient.list_entries = [
+        Task(
+            uid="started-today",
+            data=TaskData(summary="Started today", status="IN-PROCESS", categories=["done-today"]),
+            task_index=1,
+        ),
+        Task(
+            uid="started-other",
+            data=TaskData(summary="Started other", status="IN-PROCESS", categories=["later"]),
+            task_index=2,
+        ),
+        Task(
+            uid="backlog-today",
+            data=TaskData(summary="Backlog today", status="NEEDS-ACTION", categories=["done-today"]),
+            task_index=3,
+        ),
+        Task(
+            uid="started-today-2",
+            data=TaskData(summary="Another started", status="IN-PROCESS", categories=["done-today"]),
+            task_index=4,
+        ),
+    ]
+    exit_code, stdout = run_cli(["status:in-process", "+done-today", "do"])
+    assert exit_code == 0
+    assert "Updated (2):" in stdout
+    assert sorted(DummyClient.completed) == ["started-today", "started-today-2"]
+    assert [t.uid for t in DummyClient.list_entries] == ["started-other", "backlog-today"]
+
+
+def test_time_tracking_starts_once_and_accumulates_on_stop() -> None:
+    now = datetime(2026, 1, 1, 12, 0)
+    stamp = int(now.timestamp())
+    idle = Task(uid="t", data=TaskData(summary="T", x_properties={"X-TIMESPENT": "60"}))
+    assert cli._time_tracking_properties(idle, "IN-PROCESS", now) == {"X-STARTED-AT": str(stamp)}
+    assert cli._time_tracking_properties(idle, "NEEDS-ACTION", now) == {}
+
+    running = Task(
+        uid="t",
+        data=TaskData(summary="T", x_properties={"X-TIMESPENT": "60", "X-STARTED-AT": str(stamp - 600)}),
+    )
+    # A second start keeps the open interval instead of restarting it
+    assert cli._time_tracking_properties(running, "IN-PROCESS", now) == {}
+    assert cli._time_tracking_properties(running, "NEEDS-ACTION", now) == {
+        "X-TIMESPENT": "660",
+        "X-STARTED-AT": "",
+    }
+
+
+def test_start_stamps_and_do_closes_running_interval() -> None:
+    exit_code, _ = run_cli(["1", "start"])
+    assert exit_code == 0
+    assert DummyClient.last_patch is not None
+    assert DummyClient.last_patch.status == "IN-PROCESS"
+    assert "X-STARTED-AT" in DummyClient.last_patch.x_properties
+
+    started = int(datetime.now().timestamp()) - 300
+    DummyClient.list_entries = [
+        Task(
+            uid="running",
+            data=TaskData(summary="Running", status="IN-PROCESS", x_properties={"X-STARTED-AT": str(started)}),
+            task_index=1,
+        )
+    ]
+    exit_code, _ = run_cli(["1", "do"])
+    assert exit_code == 0
+    assert DummyClient.completed == ["running"]
+    assert DummyClient.last_patch is not None
+    assert DummyClient.last_patch.x_properties["X-STARTED-AT"] == ""
+    assert 300 <= int(DummyClient.last_patch.x_properties["X-TIMESPENT"]) < 310
+
+
+def test_single_active_start_stops_the_running_task(monkeypatch: pytest.MonkeyPatch) -> None:
+    started = int(datetime.now().timestamp()) - 60
+    DummyClient.list_entries = [
+        Task(
+            uid="running",
+            data=TaskData(summary="Running", status="IN-PROCESS", x_properties={"X-STARTED-AT": str(started)}),
+            task_index=1,
+        ),
+        Task(uid="next", data=TaskData(summary="Next", status="NEEDS-ACTION"), task_index=2),
+        Task(uid="later", data=TaskData(summary="Later", status="NEEDS-ACTION"), task_index=3),
+    ]
+    patches: list[tuple[str, TaskPatch]] = []
+    modify_task = DummyClient.modify_task
+
+    async def recording_modify(self: DummyClient, task: Task, patch: TaskPatch) -> Task:
+        patches.append((task.uid, patch))
+        return await modify_task(self, task, patch)
+
+    async def single_active_client(env: str | None) -> DummyClient:
+        return DummyClient(CaldavConfig(calendar_url="https://example.com/cal", username="tester", single_active=True))
+
+    monkeypatch.setattr(DummyClient, "modify_task", recording_modify)
+    monkeypatch.setattr(cli, "_cache_client", single_active_client)
+    exit_code, stdout = run_cli(["2", "start"])
+    assert exit_code == 0
+    assert [(uid, patch.status) for uid, patch in patches] == [("running", "NEEDS-ACTION"), ("next", "IN-PROCESS")]
+    assert 60 <= int(patches[0][1].x_properties["X-TIMESPENT"]) < 70
+    assert "Stopped (1):\n  ~ [1] Running" in stdout
+
+    patches.clear()
+    exit_code, stdout = run_cli(["2,3", "start"])
+    assert exit_code == 1
+    assert "single_active allows one started task; pass --force to start 2 at once" in stdout
+    assert patches == []
+
+    exit_code, _ = run_cli(["2,3", "start", "--force"])
+    assert exit_code == 0
+    assert [uid for uid, _ in patches] == ["running", "next", "later"]
+
+
+def test_do_note_is_listed_with_completed_tasks() -> None:
+    exit_code, _ = run_cli(["1", "do", "--note", "fixed by reverting the config"])
+    assert exit_code == 0
+    exit_code, stdout = run_cli(["completed"])
+    assert exit_code == 0
+    assert stdout.splitlines() == [
+        "Completed (1):",
+        "  [1] List task",
+        "      note: fixed by reverting the config",
+    ]
+
+
+def test_do_and_del_pass_backdated_times(monkeypatch: pytest.MonkeyPatch) -> None:
+    seen: list[tuple[str, float | None]] = []
+
+    async def complete_task(self, uid: str, *, note: str | None = None, completed_at: float | None = None) -> None:
+        seen.append(("do", completed_at))
+
+    async def delete_task(self, uid: str, *, deleted_at: float | None = None) -> bool:
+        seen.append(("del", deleted_at))
+        return True
+
+    monkeypatch.setattr(DummyClient, "complete_task", complete_task)
+    monkeypatch.setattr(DummyClient, "delete_task", delete_task)
+    assert run_cli(["1", "do", "--at", "@1700000000"])[0] == 0
+    assert run_cli(["1", "del", "--at", "@1600000000"])[0] == 0
+    assert seen == [("do", 1_700_000_000.0), ("del", 1_600_000_000.0)]
+
+
+def test_import_keeps_completion_and_deletion_times(monkeypatch: pytest.MonkeyPatch, tmp_path: Path) -> None:
+    seen: list[tuple[str, float | None]] = []
+
+    class Cache:
+        async def complete_task(self, uid: str, *, note: str | None = None, completed_at: float | None = None) -> None:
+            seen.append(("completed", completed_at))
+
+    async def delete_task(self, uid: str, *, deleted_at: float | None = None, keep_unsynced: bool = False) -> bool:
+        assert keep_unsynced
+        seen.append(("deleted", deleted_at))
+        return True
+
+    monkeypatch.setattr(DummyClient, "_ensure_cache", lambda self: Cache(), raising=False)
+    monkeypatch.setattr(DummyClient, "delete_task", delete_task)
+    path = tmp_path / "tasks.jsonl"
+    rows = [
+        {"state": "active", "data": {"summary": "Open"}},
+        {"state": "completed", "data": {"summary": "Shipped", "status": "COMPLETED"}, "completed_at": "2024-03-01T09:00:00+00:00"},
+        {"state": "deleted", "data": {"summary": "Dropped"}, "deleted_at": 1_600_000_000},
+    ]
+    path.write_text("\n".join(json.dumps(row) for row in rows) + "\n")
+    exit_code, stdout = run_cli(["import", str(path)])
+    assert exit_code == 0
+    assert f"Imported 3 task(s) from {path} (1 active, 1 completed, 1 deleted)" in stdout
+    assert seen == [("completed", datetime(2024, 3, 1, 9, tzinfo=timezone.utc).timestamp()), ("deleted", 1_600_000_000.0)]
+
+    # Bad rows stop the import before anything is written
+    path.write_text(json.dumps({"state": "completed", "data": {"summary": "Later"}, "completed_at": "2999-01-01"}) + "\n")
+    exit_code, stdout = run_cli(["import", str(path)])
+    assert exit_code == 1
+    assert f"{path}:1: completed_at 2999-01-01 00:00 is in the future" in stdout
+    assert len(seen) == 2
+
+
+def test_import_archives_completed_rows_and_undo_removes_every_row(
+    monkeypatch: pytest.MonkeyPatch, tmp_path: Path
+) -> None:
+    config = CaldavConfig(calendar_url="https://example.com/cal", username="tester", complete_mode="delete")
+
+    async def cache_client(env: str | None) -> CalDAVClient:
+        return await CalDAVClient.create(config, cache_path=tmp_path / "cache.db")
+
+    async def table_counts() -> dict[str, int]:
+        cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+        try:
+            counts = {}
+            for table in ("tasks", "completed_tasks", "deleted_tasks"):
+                async with cache._conn.execute(f"SELECT COUNT(*) FROM {table}") as cursor:
+                    counts[table] = (await cursor.fetchone())[0]
+            return counts
+        finally:
+            await cache.close()
+
+    monkeypatch.setattr(cli, "_cache_client", cache_client)
+    path = tmp_path / "tasks.jsonl"
+    rows = [
+        {"state": "active", "data": {"summary": "Open"}},
+        {"state": "completed", "data": {"summary": "Shipped"}, "completed_at": 1_700_000_000},
+        {"state": "deleted", "data": {"summary": "Dropped"}, "deleted_at": 1_600_000_000},
+    ]
+    path.write_text("\n".join(json.dumps(row) for row in rows) + "\n")
+    assert run_cli(["import", str(path)])[0] == 0
+    # complete_mode "delete" does not drop the imported completion
+    assert asyncio.run(table_counts()) == {"tasks": 1, "completed_tasks": 1, "deleted_tasks": 1}
+
+    exit_code, stdout = run_cli(["undo"])
+    assert exit_code == 0
+    assert "Undid import of 3 task(s)" in stdout
+    assert asyncio.run(table_counts()) == {"tasks": 0, "completed_tasks": 0, "deleted_tasks": 0}
+
+
+def test_do_allow_completed_treats_retries_as_success() -> None:
+    DummyClient.list_entries = [
+        Task(uid="first", data=TaskData(summary="First"), task_index=1),
+        Task(uid="second", data=TaskData(summary="Second"), task_index=2),
+    ]
+    assert run_cli(["1", "do"])[0] == 0
+
+    exit_code, stdout = run_cli(["1", "do"])
+    assert exit_code == 1
+    assert "filter 1 did not match any task" in stdout
+
+    exit_code, stdout = run_cli(["1", "do", "--allow-completed"])
+    assert exit_code == 0
+    assert stdout.splitlines() == [
+        "Already completed (1; no active task holds these indices):",
+        "  [1] First  (first)",
+    ]
+    assert DummyClient.completed == ["first"]
+
+    exit_code, stdout = run_cli(["1,2", "do", "--allow-completed"])
+    assert exit_code == 0
+    assert DummyClient.completed == ["first", "second"]
+    assert "Already completed (1; no active task holds these indices):" in stdout
+
+    # A reused index goes to the active task, and the output names the completed one
+    DummyClient.list_entries = [Task(uid="newer", data=TaskData(summary="Newer"), task_index=1)]
+    exit_code, stdout = run_cli(["1", "do", "--allow-completed"])
+    assert exit_code == 0
+    assert DummyClient.completed == ["first", "second", "newer"]
+    assert (
+        "Note: index 1 belongs to an active task, so that task was completed; "
+        "completed task first (First) held it before"
+    ) in stdout
+    assert "Already completed" not in stdout
+
+    # An index no task ever held is still an error
+    DummyClient.list_entries = [Task(uid="third", data=TaskData(summary="Third"), task_index=3)]
+    exit_code, stdout = run_cli(["7", "do", "--allow-completed"])
+    assert exit_code == 1
+    assert "filter 7 did not match any task" in stdout
+
+
+def test_do_rejects_future_at_without_force(monkeypatch: pytest.MonkeyPatch) -> None:
+    future = int((datetime.now() + timedelta(days=2)).timestamp())
+    exit_code, stdout = run_cli(["1", "do", "--at", f"@{future}"])
+    assert exit_code == 1
+    assert "is in the future; pass --force to keep it" in stdout
+    assert DummyClient.completed == []
+
+    exit_code, stdout = run_cli(["1", "do", "--at", "whenever"])
+    assert exit_code == 1
+    assert "invalid --at value: 'whenever'" in stdout
+
+    exit_code, _ = run_cli(["1", "do", "--at", f"@{future}", "--force"])
+    assert exit_code == 0
+    assert DummyClient.completed == ["list-task"]
+
+
+def test_do_current_completes_the_single_started_task() -> None:
+    exit_code, stdout = run_cli(["do", "--current"])
+    assert exit_code == 1
+    assert "no task is in progress" in stdout
+
+    DummyClient.list_entries = [
+        Task(uid="a", data=TaskData(summary="A", status="IN-PROCESS", categories=["work"]), task_index=1),
+        Task(uid="b", data=TaskData(summary="B"), task_index=2),
+        Task(uid="c", data=TaskData(summary="C", status="IN-PROCESS"), task_index=3),
+    ]
+    exit_code, stdout = run_cli(["do", "--current"])
+    assert exit_code == 1
+    assert "2 tasks are in progress ([1], [3]); pass an index" in stdout
+    assert DummyClient.completed == []
+
+    # A filter narrows the candidates, and explicit indices win over the flag
+    assert run_cli(["+work", "do", "--current"])[0] == 0
+    assert run_cli(["2", "do", "--current"])[0] == 0
+    assert DummyClient.completed == ["a", "b"]
+
+
+def test_do_command_with_unmatched_status_filter_fails() -> None:
+    exit_code, stdout = run_cli(["status:IN-PROCESS", "do"])
+    assert exit_code == 1
+    assert "no tasks match filter" in stdout
+    assert DummyClient.completed == []
+
+
 def test_list_command_outputs_tasks() -> None:
     exit_code, stdout = run_cli(["list"])
     assert exit_code == 0
@@ -263,6 +977,205 @@ def test_list_command_outputs_tasks() -> None:
     assert "list-task" not in stdout
 
 
+def test_list_include_completed_interleaves_by_time(monkeypatch: pytest.MonkeyPatch) -> None:
+    entries = [
+        TimelineEntry(Task(uid="old", data=TaskData(summary="Old active"), task_index=1), "active", 100.0),
+        TimelineEntry(Task(uid="done", data=TaskData(summary="Finished", status="COMPLETED"), task_index=1), "completed", 200.0),
+        TimelineEntry(Task(uid="new", data=TaskData(summary="New active"), task_index=2), "active", 300.0),
+    ]
+
+    async def list_timeline(self: DummyClient, *, task_filter: "TaskFilter | None" = None) -> list[TimelineEntry]:
+        return list(entries)
+
+    monkeypatch.setattr(DummyClient, "list_timeline", list_timeline, raising=False)
+    exit_code, stdout = run_cli(["list", "--include-completed"])
+    assert exit_code == 0
+    assert "State" in stdout
+    assert stdout.index("Old active") < stdout.index("Finished") < stdout.index("New active")
+    finished_row = next(line for line in stdout.splitlines() if "Finished" in line)
+    assert "done" in finished_row
+
+    exit_code, stdout = run_cli(["list", "--include-completed", "--no-reverse"])
+    assert exit_code == 0
+    assert stdout.index("New active") < stdout.index("Finished") < stdout.index("Old active")
+
+
+def test_list_last_changed_shows_touched_tasks_with_state(monkeypatch: pytest.MonkeyPatch) -> None:
+    entry = TransactionLogEntry(id=3, diff_json="{}", operation="do", created_at=0.0)
+    latest: list[TransactionLogEntry] = []
+
+    async def peek_transaction(self) -> TransactionLogEntry | None:
+        return latest[0] if latest else None
+
+    async def touched_tasks(self, seen: TransactionLogEntry) -> list[TimelineEntry]:
+        assert seen is entry
+        return [
+            TimelineEntry(Task(uid="a", data=TaskData(summary="Finished"), task_index=4), "completed", 0.0),
+            TimelineEntry(Task(uid="b", data=TaskData(summary="Dropped")), "removed", 0.0),
+        ]
+
+    monkeypatch.setattr(DummyClient, "peek_transaction", peek_transaction, raising=False)
+    monkeypatch.setattr(DummyClient, "touched_tasks", touched_tasks, raising=False)
+    exit_code, stdout = run_cli(["list", "--last-changed"])
+    assert exit_code == 0
+    assert stdout.strip() == "no transactions recorded"
+
+    latest.append(entry)
+    exit_code, stdout = run_cli(["list", "--last-changed"])
+    assert exit_code == 0
+    assert "Last change: do" in stdout
+    finished = next(line for line in stdout.splitlines() if "Finished" in line)
+    dropped = next(line for line in stdout.splitlines() if "Dropped" in line)
+    assert "done" in finished and "removed" in dropped
+
+    exit_code, stdout = run_cli(["list", "--last-changed", "--group-by", "tag"])
+    assert exit_code == 1
+    assert "--group-by cannot be combined with --last-changed" in stdout
+
+
+def test_forget_requires_confirm_and_reports_removals(monkeypatch: pytest.MonkeyPatch) -> None:
+    seen: list[list[str]] = []
+
+    async def forget_tasks(self, uids: list[str]) -> list[ForgetReport]:
+        seen.append(uids)
+        return [
+            ForgetReport("a", "Secret", ["completed_tasks"], 2, 1),
+            ForgetReport("zzz", None, [], 0, 0),
+        ]
+
+    monkeypatch.setattr(DummyClient, "forget_tasks", forget_tasks, raising=False)
+    exit_code, stdout = run_cli(["forget", "a"])
+    assert exit_code == 1
+    assert "pass --confirm" in stdout
+    assert seen == []
+
+    exit_code, stdout = run_cli(["forget", "a", "zzz", "a", "--confirm"])
+    assert exit_code == 1
+    assert seen == [["a", "zzz"]]
+    assert stdout.splitlines() == [
+        "Forgot (1):",
+        "  a Secret",
+        "      removed from: completed_tasks, transaction_log (2), idempotency_keys (1)",
+        "no task with uid zzz",
+    ]
+
+
+def test_next_ranks_tasks_by_the_configured_order(monkeypatch: pytest.MonkeyPatch) -> None:
+    now = datetime.now()
+    DummyClient.list_entries = [
+        Task(uid="late", data=TaskData(summary="Late", due=now - timedelta(days=1), priority=5), task_index=3),
+        Task(uid="urgent", data=TaskData(summary="Urgent", due=now + timedelta(days=5), priority=1), task_index=2),
+        Task(uid="soon", data=TaskData(summary="Soon", due=now + timedelta(days=1)), task_index=1),
+    ]
+
+    def next_summaries(arguments: list[str]) -> list[str]:
+        exit_code, stdout = run_cli(["next", *arguments])
+        assert exit_code == 0
+        return [line.split(maxsplit=1)[1] for line in stdout.splitlines() if line.startswith("Summary:")]
+
+    assert next_summaries([]) == ["Late"]
+    assert next_summaries(["--count", "3"]) == ["Late", "Urgent", "Soon"]
+    assert next_summaries(["--order", "priority", "--count", "3"]) == ["Urgent", "Late", "Soon"]
+    assert next_summaries(["--order", "due,priority", "--count", "2"]) == ["Late", "Soon"]
+    assert next_summaries(["--order", "index"]) == ["Soon"]
+
+    config = CaldavConfig(calendar_url="https://example.com/cal", username="tester")
+    config.next.order = ["priority", "overdue"]
+    monkeypatch.setattr(cli, "load_config", lambda env: config)
+    assert next_summaries([]) == ["Urgent"]
+
+    exit_code, stdout = run_cli(["next", "--order", "priority,size"])
+    assert exit_code == 1
+    assert "unknown --order key: size (expected overdue, priority, due, index)" in stdout
+
+
+def test_export_streams_json_lines_to_stdout_or_a_file(monkeypatch: pytest.MonkeyPatch, tmp_path: Path) -> None:
+    async def iter_tasks_for_export(self, *, task_filter=None) -> AsyncIterator[tuple[str, Task]]:
+        yield "active", Task(uid="a", data=TaskData(summary="Open"), task_index=1)
+        yield "completed", Task(uid="b", data=TaskData(summary="Done", status="COMPLETED"), task_index=2)
+
+    monkeypatch.setattr(DummyClient, "iter_tasks_for_export", iter_tasks_for_export, raising=False)
+    exit_code, stdout = run_cli(["export"])
+    assert exit_code == 0
+    rows = [json.loads(line) for line in stdout.splitlines()]
+    assert [(row["state"], row["uid"], row["data"]["summary"]) for row in rows] == [
+        ("active", "a", "Open"),
+        ("completed", "b", "Done"),
+    ]
+
+    target = tmp_path / "tasks.jsonl"
+    exit_code, stdout = run_cli(["export", "--output", str(target)])
+    assert exit_code == 0
+    size = target.stat().st_size
+    assert stdout.strip() == f"Exported 2 task(s) to {target} ({size} bytes)"
+    assert [json.loads(line)["uid"] for line in target.read_text().splitlines()] == ["a", "b"]
+
+    exit_code, stdout = run_cli(["export", "--output", str(tmp_path / "missing" / "tasks.jsonl")])
+    assert exit_code == 1
+    assert "cannot write" in stdout
+
+
+def test_group_tasks_orders_groups_and_repeats_multi_tag_tasks() -> None:
+    now = datetime(2026, 5, 4, 12, 0)
+    tasks = [
+        Task(uid="a", data=TaskData(summary="A", categories=["b", "a"], x_properties={"X-PROJECT": "work"})),
+        Task(uid="b", data=TaskData(summary="B", status="IN-PROCESS", due=datetime(2026, 5, 1))),
+        Task(uid="c", data=TaskData(summary="C", categories=["a"], x_properties={"X-PROJECT": "home"})),
+    ]
+
+    def grouped(group_by: str) -> dict[str, list[str]]:
+        groups = cli._group_tasks(tasks, group_by, now, soon_days=3, week_days=7)
+        return {key: [task.uid for task in group] for key, group in groups.items()}
+
+    assert list(grouped("project").items()) == [("home", ["c"]), ("work", ["a"]), ("No project", ["b"])]
+    assert list(grouped("tag").items()) == [("a", ["a", "c"]), ("b", ["a"]), ("No tag", ["b"])]
+    assert list(grouped("status").items()) == [("IN-PROCESS", ["b"]), ("NEEDS-ACTION", ["a", "c"])]
+    assert list(grouped("due").items()) == [("Overdue", ["b"]), ("No due date", ["a", "c"])]
+
+
+def test_list_group_by_prints_one_table_per_group() -> None:
+    DummyClient.list_entries = [
+        Task(uid="a", data=TaskData(summary="Alpha", categories=["x", "y"]), task_index=1),
+        Task(uid="b", data=TaskData(summary="Bravo"), task_index=2),
+    ]
+    exit_code, stdout = run_cli(["list", "--group-by", "tag"])
+    assert exit_code == 0
+    assert stdout.count("Alpha") == 2
+    assert stdout.index("x") < stdout.index("No tag")
+    assert "Backlog" not in stdout
+
+    exit_code, stdout = run_cli(["list", "--group-by", "tag", "--include-completed"])
+    assert exit_code == 1
+    assert "--group-by cannot be combined with --include-completed" in stdout
+
+
+def test_list_count_by_prints_counts_instead_of_tasks(monkeypatch: pytest.MonkeyPatch) -> None:
+    calls: list[tuple[str, object, bool]] = []
+
+    async def count_tasks_by(self, key: str, *, task_filter=None, include_completed: bool = False) -> dict[str, int]:
+        calls.append((key, task_filter, include_completed))
+        return {"home": 2, "": 1}
+
+    monkeypatch.setattr(DummyClient, "count_tasks_by", count_tasks_by, raising=False)
+    exit_code, stdout = run_cli(["+work", "list", "--count-by", "project", "--include-completed", "--json"])
+    assert exit_code == 0
+    assert json.loads(stdout) == {"": 1, "home": 2}
+    key, task_filter, include_completed = calls[0]
+    assert key == "project" and task_filter.tags == ["work"] and include_completed
+
+    exit_code, stdout = run_cli(["list", "--count-by", "project"])
+    assert exit_code == 0
+    assert "home" in stdout and "No project" in stdout
+    assert "Backlog" not in stdout
+
+    exit_code, stdout = run_cli(["list", "--count-by", "tag", "--group-by", "tag"])
+    assert exit_code == 1
+    assert "--group-by cannot be combined with --count-by" in stdout
+    exit_code, stdout = run_cli(["list", "--json"])
+    assert exit_code == 1
+    assert "--json requires --count-by" in stdout
+
+
 def test_list_command_hides_completed_tasks() -> None:
     DummyClient.list_entries = [
         Task(uid="active", data=TaskData(summary="Active task", due=None, priority=1), task_index=1),
@@ -324,10 +1237,98 @@ def test_modify_command_accepts_numeric_identifier() -> None:
     assert DummyClient.last_modified_uid == "first"
 
 
-def test_list_command_shows_uids_when_enabled(tmp_path, monkeypatch: pytest.MonkeyPatch) -> None:
-    config_path = tmp_path / "config.radicale-test.toml"
-    config_path.write_text(
-        "[caldav]\ncalendar_url = \"https://example.com/cal\"\nusername = \"tester\"\nshow_uids = true\n"
+def test_modify_command_targets_tasks_by_metadata_filter() -> None:
+    DummyClient.list_entries = [
+        Task(uid="first", data=TaskData(summary="First", categories=["today", "work"]), task_index=1),
+        Task(uid="second", data=TaskData(summary="Second", categories=["work"]), task_index=2),
+        Task(uid="third", data=TaskData(summary="Third", categories=["today", "home"]), task_index=3),
+    ]
+    exit_code, stdout = run_cli(["+today", "modify", "-today"])
+    assert exit_code == 0
+    assert "Updated (2):" in stdout
+    assert "[1] First" in stdout and "[3] Third" in stdout
+    assert "Second" not in stdout
+
+    exit_code, stdout = run_cli(["+someday", "modify", "-today"])
+    assert exit_code == 1
+    assert "no tasks match filter" in stdout
+
+
+def _bump_entries() -> list[Task]:
+    return [
+        Task(uid="top", data=TaskData(summary="Top", priority=1), task_index=1),
+        Task(uid="mid", data=TaskData(summary="Mid", priority=5), task_index=2),
+        Task(uid="unset", data=TaskData(summary="Unset", priority=0), task_index=3),
+        Task(uid="bottom", data=TaskData(summary="Bottom", priority=9), task_index=4),
+    ]
+
+
+def test_bump_raises_priority_one_level_and_clamps() -> None:
+    DummyClient.list_entries = _bump_entries()
+    exit_code, stdout = run_cli(["1,2,3", "bump"])
+    assert exit_code == 0
+    assert "Bumped (2):" in stdout
+    assert "[2] Mid: 5 -> 4" in stdout
+    assert "[3] Unset: - -> 9" in stdout
+    assert "1 task(s) already at the highest priority" in stdout
+
+    exit_code, stdout = run_cli(["1", "bump"])
+    assert exit_code == 0
+    assert "already at the highest priority; nothing to change" in stdout
+
+
+def test_lower_drops_priority_one_level_and_leaves_unset_alone() -> None:
+    DummyClient.list_entries = _bump_entries()
+    exit_code, stdout = run_cli(["1,2,3,4", "lower"])
+    assert exit_code == 0
+    assert "Lowered (2):" in stdout
+    assert "[1] Top: 1 -> 2" in stdout
+    assert "[2] Mid: 5 -> 6" in stdout
+    assert "2 task(s) already at the lowest priority" in stdout
+
+
+def test_swap_exchanges_indices_or_priorities() -> None:
+    DummyClient.list_entries = _bump_entries()
+    exit_code, stdout = run_cli(["1,2", "swap"])
+    assert exit_code == 0
+    assert "Swapped index:" in stdout
+    assert "[2] Top: index 2" in stdout
+    assert "[1] Mid: index 1" in stdout
+
+    exit_code, stdout = run_cli(["2,3", "swap", "--field", "priority"])
+    assert exit_code == 0
+    assert "[2] Mid: priority -" in stdout
+    assert "[3] Unset: priority 5" in stdout
+
+
+def test_undo_swap_rejects_an_entry_rewritten_to_one_task(monkeypatch: pytest.MonkeyPatch) -> None:
+    diff: TaskSetDiff[str] = TaskSetDiff(diffs={"kept": TaskDiff(pre=TaskData(summary="Kept"), post=TaskData(summary="Kept"))})
+
+    class Cache:
+        async def pop_transaction(self) -> TransactionLogEntry:
+            return TransactionLogEntry(id=1, diff_json=diff.to_json(), operation="swap-index", created_at=0.0)
+
+    monkeypatch.setattr(DummyClient, "_ensure_cache", lambda self: Cache(), raising=False)
+    exit_code, stdout = run_cli(["undo"])
+    assert exit_code == 1
+    assert "cannot undo swap-index: the entry now names 1 task(s), not 2" in stdout
+
+
+def test_swap_requires_exactly_two_tasks() -> None:
+    DummyClient.list_entries = _bump_entries()
+    exit_code, stdout = run_cli(["1,2,3", "swap"])
+    assert exit_code == 1
+    assert "swap needs exactly two tasks, got 3" in stdout
+
+    exit_code, stdout = run_cli(["1,1", "swap"])
+    assert exit_code == 1
+    assert "swap needs two different tasks, got [1] twice" in stdout
+
+
+def test_list_command_shows_uids_when_enabled(tmp_path, monkeypatch: pytest.MonkeyPatch) -> None:
+    config_path = tmp_path / "config.radicale-test.toml"
+    config_path.write_text(
+        "[caldav]\ncalendar_url = \"https://example.com/cal\"\nusername = \"tester\"\nshow_uids = true\n"
     )
 
     def fake_load(path: Path, env: str | None = None) -> CaldavConfig:
@@ -393,11 +1394,25 @@ def test_config_init_command_writes_file(tmp_path) -> None:
     assert "env = \"test\"" in contents
 
 
+def test_config_set_default_env_requires_configured_env(tmp_path: Path) -> None:
+    exit_code, stdout = run_cli(["config", "set-default-env", "work", "--config-home", str(tmp_path)])
+    assert exit_code == 1
+    assert "not configured" in stdout
+    assert not (tmp_path / "defaults.toml").exists()
+
+    (tmp_path / "config.work.toml").write_text("[caldav]\n")
+    exit_code, stdout = run_cli(["config", "set-default-env", "work", "--config-home", str(tmp_path)])
+    assert exit_code == 0
+    assert (tmp_path / "defaults.toml").read_text() == 'env = "work"\n'
+
+
 class DestDummyClient:
     """Separate dummy client for destination environment in move tests."""
 
     last_payload: TaskPayload | None = None
     _next_index: int = 100  # Start at different index than source
+    list_entries: list[Task] = []
+    imported: list[tuple[list[TransactionLogEntry], dict[str, str]]] = []
 
     def __init__(self, config: CaldavConfig) -> None:
         self.config = config
@@ -407,11 +1422,24 @@ class DestDummyClient:
     def reset(cls) -> None:
         cls.last_payload = None
         cls._next_index = 100
+        cls.list_entries = []
+        cls.imported = []
+
+    async def list_tasks(self) -> list[Task]:
+        return list(DestDummyClient.list_entries)
 
     async def close(self) -> None:
         pass
 
-    async def create_task(self, payload: TaskPayload) -> Task:
+    @asynccontextmanager
+    async def logged(self, operation: str) -> AsyncIterator[None]:
+        yield
+
+    async def import_transactions(self, entries: list[TransactionLogEntry], uid_map: dict[str, str]) -> int:
+        DestDummyClient.imported.append((list(entries), dict(uid_map)))
+        return len(entries)
+
+    async def create_task(self, payload: TaskPayload, *, index: int | None = None) -> Task:
         DestDummyClient.last_payload = payload
         task_index = DestDummyClient._next_index
         DestDummyClient._next_index += 1
@@ -443,7 +1471,9 @@ def test_move_command_moves_task_to_dest_env(monkeypatch: pytest.MonkeyPatch) ->
     ]
 
     # Mock CalDAVClient.create for destination
-    async def mock_caldav_create(config: CaldavConfig) -> DestDummyClient:
+    async def mock_caldav_create(
+        config: CaldavConfig, *, read_only: bool = False, upgrade: bool = True
+    ) -> DestDummyClient:
         return DestDummyClient(config)
 
     from tdo import caldav_client
@@ -463,6 +1493,102 @@ def test_move_command_moves_task_to_dest_env(monkeypatch: pytest.MonkeyPatch) ->
     assert DestDummyClient.last_payload.categories == ["tag1"]
 
 
+def test_move_command_json_reports_index_mapping(monkeypatch: pytest.MonkeyPatch) -> None:
+    DummyClient.reset()
+    DestDummyClient.reset()
+    DummyClient.list_entries = [
+        Task(uid="first", data=TaskData(summary="First"), task_index=1),
+        Task(uid="second", data=TaskData(summary="Second"), task_index=2),
+    ]
+
+    async def mock_caldav_create(
+        config: CaldavConfig, *, read_only: bool = False, upgrade: bool = True
+    ) -> DestDummyClient:
+        return DestDummyClient(config)
+
+    from tdo import caldav_client
+
+    monkeypatch.setattr(caldav_client, "CalDAVClient", type("CalDAVClient", (), {"create": mock_caldav_create}))
+
+    exit_code, stdout = run_cli(["1,2", "move", "work", "--json"])
+    assert exit_code == 0
+    assert json.loads(stdout) == [
+        {"uid": "first", "src_index": 1, "dest_uid": "dest-task", "dest_index": 100, "dest_env": "work", "tombstone": True},
+        {"uid": "second", "src_index": 2, "dest_uid": "dest-task", "dest_index": 101, "dest_env": "work", "tombstone": True},
+    ]
+
+
+def test_move_with_history_copies_log_entries(monkeypatch: pytest.MonkeyPatch) -> None:
+    DummyClient.reset()
+    DestDummyClient.reset()
+    DummyClient.list_entries = [Task(uid="first", data=TaskData(summary="First"), task_index=1)]
+    history = [TransactionLogEntry(id=4, diff_json='{"first": {}}', operation="modify", created_at=1.0)]
+    requested: list[list[str]] = []
+
+    async def transaction_history(self: DummyClient, uids: list[str]) -> list[TransactionLogEntry]:
+        requested.append(list(uids))
+        return history
+
+    async def mock_caldav_create(
+        config: CaldavConfig, *, read_only: bool = False, upgrade: bool = True
+    ) -> DestDummyClient:
+        return DestDummyClient(config)
+
+    from tdo import caldav_client
+
+    monkeypatch.setattr(DummyClient, "transaction_history", transaction_history, raising=False)
+    monkeypatch.setattr(caldav_client, "CalDAVClient", type("CalDAVClient", (), {"create": mock_caldav_create}))
+
+    exit_code, stdout = run_cli(["1", "move", "work"])
+    assert exit_code == 0
+    assert requested == [] and DestDummyClient.imported == []
+    assert "Copied" not in stdout
+
+    DummyClient.list_entries = [Task(uid="first", data=TaskData(summary="First"), task_index=1)]
+    exit_code, stdout = run_cli(["1", "move", "work", "--with-history"])
+    assert exit_code == 0
+    assert requested == [["first"]]
+    assert DestDummyClient.imported == [(history, {"first": "dest-task"})]
+    assert "Copied 1 logged transaction(s) to 'work'" in stdout
+
+
+def test_move_dry_run_previews_without_writing(monkeypatch: pytest.MonkeyPatch) -> None:
+    DummyClient.reset()
+    DestDummyClient.reset()
+    DummyClient.list_entries = [
+        Task(uid="first", data=TaskData(summary="Pay rent"), task_index=1),
+        Task(uid="second", data=TaskData(summary="Water plants"), task_index=2),
+    ]
+    DestDummyClient.list_entries = [Task(uid="there", data=TaskData(summary="pay rent"), task_index=7)]
+
+    dest_opens: list[bool] = []
+
+    async def mock_caldav_create(
+        config: CaldavConfig, *, read_only: bool = False, upgrade: bool = True
+    ) -> DestDummyClient:
+        dest_opens.append(read_only and not upgrade)
+        return DestDummyClient(config)
+
+    from tdo import caldav_client
+
+    monkeypatch.setattr(caldav_client, "CalDAVClient", type("CalDAVClient", (), {"create": mock_caldav_create}))
+
+    exit_code, stdout = run_cli(["1,2", "move", "work", "--dry-run", "--json"])
+    assert exit_code == 0
+    preview = json.loads(stdout)
+    assert preview["count"] == 2
+    assert [(entry["src_index"], entry["dest_duplicate_index"]) for entry in preview["tasks"]] == [(1, 7), (2, None)]
+
+    exit_code, stdout = run_cli(["1,2", "move", "work", "--dry-run"])
+    assert exit_code == 0
+    assert "Would move 2 task(s)" in stdout
+    assert "[1] Pay rent (same summary as [7] in work)" in stdout
+    assert DummyClient.deleted == []
+    assert DestDummyClient.last_payload is None
+    # The destination is only read, so it is never created or migrated
+    assert dest_opens == [True, True]
+
+
 def test_move_command_rejects_same_env(monkeypatch: pytest.MonkeyPatch) -> None:
     """Test that moving to the same environment is rejected."""
     # Mock resolve_env to return "default"
@@ -505,7 +1631,9 @@ def test_move_command_preserves_task_metadata(monkeypatch: pytest.MonkeyPatch) -
         ),
     ]
 
-    async def mock_caldav_create(config: CaldavConfig) -> DestDummyClient:
+    async def mock_caldav_create(
+        config: CaldavConfig, *, read_only: bool = False, upgrade: bool = True
+    ) -> DestDummyClient:
         return DestDummyClient(config)
 
     from tdo import caldav_client
@@ -524,3 +1652,1307 @@ def test_move_command_preserves_task_metadata(monkeypatch: pytest.MonkeyPatch) -
     assert payload.x_properties.get("X-PROJECT") == "myproject"
     assert payload.x_properties.get("X-CUSTOM") == "value"
     assert payload.url == "https://example.com/task"
+
+
+def test_complete_tasks_defaults_to_index_and_summary() -> None:
+    DummyClient.list_entries = [
+        Task(uid="a", data=TaskData(summary="Alpha", due=datetime(2000, 1, 1), priority=1), task_index=1),
+    ]
+    exit_code, stdout = run_cli(["complete", "tasks"])
+    assert exit_code == 0
+    assert stdout.splitlines() == ["1\tAlpha"]
+
+
+def test_complete_tasks_detailed_includes_priority_due_and_overdue() -> None:
+    DummyClient.list_entries = [
+        Task(uid="a", data=TaskData(summary="Alpha", due=datetime(2000, 1, 1), priority=1), task_index=1),
+        Task(uid="b", data=TaskData(summary="Bravo", due=datetime(2999, 1, 1)), task_index=2),
+        Task(uid="c", data=TaskData(summary="Charlie"), task_index=3),
+    ]
+    exit_code, stdout = run_cli(["complete", "tasks", "--detailed"])
+    assert exit_code == 0
+    assert stdout.splitlines() == [
+        "1\tAlpha [pri:1 due:2000-01-01 OVERDUE]",
+        "2\tBravo [due:2999-01-01]",
+        "3\tCharlie",
+    ]
+
+
+def _project_entries() -> list[Task]:
+    projects = ["work/clientA", "work/clientB/web", "work/clientB", "home", "workshop"]
+    return [
+        Task(uid=name, data=TaskData(summary=name, x_properties={"X-PROJECT": name}), task_index=i)
+        for i, name in enumerate(projects, 1)
+    ]
+
+
+def test_complete_projects_is_flat_without_prefix() -> None:
+    DummyClient.list_entries = _project_entries()
+    exit_code, stdout = run_cli(["complete", "projects"])
+    assert exit_code == 0
+    assert stdout.splitlines() == ["home", "work/clientA", "work/clientB", "work/clientB/web", "workshop"]
+
+
+@pytest.mark.parametrize(
+    ("prefix", "expected"),
+    [
+        ("", ["home", "work/", "workshop"]),
+        ("work/", ["work/clientA", "work/clientB", "work/clientB/"]),
+        ("work/clientB/", ["work/clientB/web"]),
+        ("work/cl", ["work/clientA", "work/clientB", "work/clientB/"]),
+        ("nothing", []),
+    ],
+)
+def test_complete_projects_with_prefix_returns_next_segment(prefix: str, expected: list[str]) -> None:
+    DummyClient.list_entries = _project_entries()
+    exit_code, stdout = run_cli(["complete", "projects", "--prefix", prefix])
+    assert exit_code == 0
+    assert stdout.splitlines() == expected
+
+
+def test_complete_sort_recent_orders_by_latest_use(monkeypatch: pytest.MonkeyPatch) -> None:
+    DummyClient.list_entries = _project_entries()
+    for entry, tags in zip(DummyClient.list_entries, (["a"], ["b"], ["c"], [], [])):
+        entry.data.categories = tags
+    recency = {
+        "projects": {"home": 1.0, "work/clientA": 2.0, "work/clientB/web": 5.0, "workshop": 3.0},
+        "tags": {"a": 1.0, "b": 3.0, "c": 3.0},
+    }
+
+    async def fake_recency(self: DummyClient) -> dict[str, dict[str, float]]:
+        return recency
+
+    monkeypatch.setattr(DummyClient, "tag_and_project_recency", fake_recency, raising=False)
+    exit_code, stdout = run_cli(["complete", "projects", "--prefix", "", "--sort", "recent"])
+    assert exit_code == 0
+    assert stdout.splitlines() == ["work/", "workshop", "home"]
+    exit_code, stdout = run_cli(["complete", "tags", "--sort", "recent"])
+    assert exit_code == 0
+    assert stdout.splitlines() == ["b", "c", "a"]
+    exit_code, stdout = run_cli(["complete", "tags"])
+    assert stdout.splitlines() == ["a", "b", "c"]
+
+
+@pytest.mark.parametrize(
+    ("query", "exact", "expected"),
+    [
+        ("", False, ["home", "work/clientA", "work/clientB", "work/clientB/web", "workshop"]),
+        ("wrk", False, ["work/clientA", "work/clientB", "work/clientB/web", "workshop"]),
+        ("shop", False, ["workshop"]),
+        ("cb", False, ["work/clientB", "work/clientB/web"]),
+        ("HO", False, ["home", "workshop"]),
+        ("wrk", True, []),
+        ("work/", True, ["work/clientA", "work/clientB", "work/clientB/web"]),
+    ],
+)
+def test_complete_query_ranks_prefix_before_fuzzy_matches(query: str, exact: bool, expected: list[str]) -> None:
+    DummyClient.list_entries = _project_entries()
+    exit_code, stdout = run_cli(["complete", "projects", "--query", query, *(["--exact"] if exact else [])])
+    assert exit_code == 0
+    assert stdout.splitlines() == expected
+
+
+def test_complete_tags_query_puts_prefix_matches_first() -> None:
+    DummyClient.list_entries = [
+        Task(uid="a", data=TaskData(summary="A", categories=["network", "work", "awkward"]), task_index=1),
+    ]
+    exit_code, stdout = run_cli(["complete", "tags", "--query", "wor"])
+    assert exit_code == 0
+    assert stdout.splitlines() == ["work", "network"]
+    exit_code, stdout = run_cli(["complete", "tags", "--query", "wr"])
+    assert stdout.splitlines() == ["awkward", "network", "work"]
+
+
+def _show_entries() -> list[Task]:
+    return [
+        Task(uid="first", data=TaskData(summary="Alpha", due=datetime(2999, 1, 1)), task_index=1),
+        Task(uid="second", data=TaskData(summary="Bravo", due=datetime(2000, 1, 1)), task_index=2),
+        Task(uid="third", data=TaskData(summary="Charlie"), task_index=3),
+    ]
+
+
+def _shown_ids(stdout: str) -> list[str]:
+    return [line.split()[1] for line in stdout.splitlines() if line.startswith("ID:")]
+
+
+def test_show_command_preserves_requested_index_order() -> None:
+    DummyClient.list_entries = _show_entries()
+    exit_code, stdout = run_cli(["3,1", "show"])
+    assert exit_code == 0
+    assert _shown_ids(stdout) == ["3", "1"]
+
+
+def test_show_command_sorts_by_index() -> None:
+    DummyClient.list_entries = _show_entries()
+    exit_code, stdout = run_cli(["3,1", "show", "--sort", "index"])
+    assert exit_code == 0
+    assert _shown_ids(stdout) == ["1", "3"]
+
+
+def test_show_resolve_parent_names_parent_tasks(monkeypatch: pytest.MonkeyPatch) -> None:
+    DummyClient.list_entries = [
+        Task(uid="child", data=TaskData(summary="Make tea", parent="milk"), task_index=1),
+        Task(uid="orphan", data=TaskData(summary="Orphan", parent="gone"), task_index=2),
+    ]
+    lookups: list[list[str]] = []
+
+    async def resolve_task_refs(self: DummyClient, uids: list[str]) -> dict[str, TaskRef]:
+        lookups.append(list(uids))
+        return {"milk": TaskRef(uid="milk", index=7, summary="Buy milk", done=True)}
+
+    monkeypatch.setattr(DummyClient, "resolve_task_refs", resolve_task_refs, raising=False)
+    exit_code, stdout = run_cli(["1,2", "show", "--resolve-parent"])
+    assert exit_code == 0
+    assert "Parent:      [7] Buy milk (done)" in stdout
+    assert "Parent:      gone" in stdout
+    assert lookups == [["milk", "gone"]]
+
+    exit_code, stdout = run_cli(["1,2", "show", "--resolve-parent", "--format", "json", "--fields", "index,parent"])
+    assert exit_code == 0
+    assert json.loads(stdout) == [
+        {"index": 1, "parent": {"uid": "milk", "index": 7, "summary": "Buy milk", "done": True}},
+        {"index": 2, "parent": {"uid": "gone", "index": None, "summary": None, "done": None}},
+    ]
+
+
+def _priority_entries() -> list[Task]:
+    return [
+        Task(uid="zero", data=TaskData(summary="Zero", priority=0), task_index=1),
+        Task(uid="unset", data=TaskData(summary="Unset"), task_index=2),
+        Task(uid="low", data=TaskData(summary="Low", priority=9), task_index=3),
+        Task(uid="high", data=TaskData(summary="High", priority=1), task_index=4),
+    ]
+
+
+def test_show_command_sorts_by_priority_with_undefined_last() -> None:
+    DummyClient.list_entries = _priority_entries()
+    exit_code, stdout = run_cli(["1,2,3,4", "show", "--sort", "priority"])
+    assert exit_code == 0
+    assert _shown_ids(stdout) == ["4", "3", "2", "1"]
+
+
+@pytest.mark.parametrize("reverse", [False, True])
+def test_task_sort_key_ranks_undefined_priority_least_urgent(reverse: bool) -> None:
+    ordered = [task.uid for task in sorted(_priority_entries(), key=cli._task_sort_key, reverse=reverse)]
+    expected = ["high", "low", "unset", "zero"]
+    assert ordered == (expected[::-1] if reverse else expected)
+
+
+def test_show_command_accepts_due_filter() -> None:
+    DummyClient.list_entries = _show_entries()
+    exit_code, stdout = run_cli(["due.before:now", "show"])
+    assert exit_code == 0
+    assert _shown_ids(stdout) == ["2"]
+
+
+def test_tags_mode_filter_switches_to_any_tag() -> None:
+    DummyClient.list_entries = [
+        Task(uid="both", data=TaskData(summary="Both", categories=["work", "urgent"]), task_index=1),
+        Task(uid="work", data=TaskData(summary="Work", categories=["work"]), task_index=2),
+    ]
+    exit_code, stdout = run_cli(["+work", "+urgent", "show"])
+    assert exit_code == 0
+    assert _shown_ids(stdout) == ["1"]
+
+    exit_code, stdout = run_cli(["+work", "+urgent", "tags.mode:any", "show"])
+    assert exit_code == 0
+    assert _shown_ids(stdout) == ["1", "2"]
+
+    exit_code, stdout = run_cli(["+work", "tags.mode:some", "show"])
+    assert exit_code == 1
+    assert "invalid tags.mode value: some" in stdout
+
+
+def test_exclusion_filters_leave_out_tags_and_projects() -> None:
+    DummyClient.list_entries = [
+        Task(uid="ready", data=TaskData(summary="Ready", categories=["work"]), task_index=1),
+        Task(uid="blocked", data=TaskData(summary="Blocked", categories=["work", "waiting"]), task_index=2),
+        Task(uid="garden", data=TaskData(summary="Garden", x_properties={"X-PROJECT": "home"}), task_index=3),
+    ]
+    exit_code, stdout = run_cli(["tag.not:waiting", "project.not:home", "show"])
+    assert exit_code == 0
+    assert _shown_ids(stdout) == ["1"]
+
+
+def test_has_filters_select_tasks_with_or_without_urls() -> None:
+    DummyClient.list_entries = [
+        Task(uid="bookmark", data=TaskData(summary="Read", url="https://example.com"), task_index=1),
+        Task(uid="plain", data=TaskData(summary="Plain"), task_index=2),
+    ]
+    exit_code, stdout = run_cli(["has:url", "show"])
+    assert exit_code == 0
+    assert _shown_ids(stdout) == ["1"]
+
+    exit_code, stdout = run_cli(["has.not:url", "show"])
+    assert _shown_ids(stdout) == ["2"]
+
+    exit_code, stdout = run_cli(["has:notes", "show"])
+    assert exit_code == 1
+    assert "invalid has value: notes" in stdout
+
+
+def test_age_filters_parse_relative_and_absolute_bounds() -> None:
+    before = datetime.now().astimezone() - timedelta(days=90)
+    task_filter = cli._parse_task_filter(["updated.before:90d", "created.after:2025-01-01", "+work"])
+    assert task_filter is not None and task_filter.tags == ["work"]
+    assert task_filter.updated_before is not None
+    assert abs((task_filter.updated_before - before).total_seconds()) < 60
+    assert task_filter.created_after is not None and task_filter.created_after.year == 2025
+    assert task_filter.created_before is None and task_filter.updated_after is None
+
+    exit_code, stdout = run_cli(["updated.after:whenever", "list"])
+    assert exit_code == 1
+    assert "invalid updated.after value: whenever" in stdout
+
+
+def test_due_within_filter_parses_days_and_alignment() -> None:
+    task_filter = cli._parse_task_filter(["due.within:3d", "due.align:day"])
+    assert task_filter is not None
+    assert task_filter.due_within_days == 3 and task_filter.align_to_day
+    assert cli._split_filter_and_command(["due.within:3", "list"]) == (["due.within:3"], ["list"])
+
+    exit_code, stdout = run_cli(["due.within:soon", "list"])
+    assert exit_code == 1
+    assert "invalid due.within value: soon" in stdout
+    exit_code, stdout = run_cli(["due.within:3", "due.align:week", "list"])
+    assert exit_code == 1
+    assert "invalid due.align value: week (expected day)" in stdout
+
+
+def test_custom_status_filter_token_sits_beside_status() -> None:
+    assert cli._split_filter_and_command(["custom_status:waiting-on-reply", "list"]) == (
+        ["custom_status:waiting-on-reply"],
+        ["list"],
+    )
+    task_filter = cli._parse_task_filter(["custom_status:waiting-on-reply", "status:in-process"])
+    assert task_filter is not None
+    assert task_filter.custom_status == "waiting-on-reply"
+    assert task_filter.status == "IN-PROCESS"
+
+
+def test_show_command_relative_due_only_with_flag() -> None:
+    DummyClient.list_entries = _show_entries()
+    exit_code, stdout = run_cli(["2,3", "show"])
+    assert exit_code == 0
+    assert "When:" not in stdout
+    exit_code, stdout = run_cli(["2,3", "show", "--relative"])
+    assert exit_code == 0
+    when_lines = [line for line in stdout.splitlines() if line.startswith("When:")]
+    assert len(when_lines) == 1
+    assert when_lines[0].endswith("days ago")
+
+
+def test_add_and_modify_set_estimate() -> None:
+    exit_code, _ = run_cli(["add", "Write", "report", "estimate:45"])
+    assert exit_code == 0
+    assert DummyClient.last_payload is not None
+    assert DummyClient.last_payload.x_properties == {"X-ESTIMATE": "45"}
+
+    exit_code, _ = run_cli(["1", "modify", "estimate:90"])
+    assert exit_code == 0
+    assert DummyClient.last_patch is not None
+    assert DummyClient.last_patch.x_properties == {"X-ESTIMATE": "90"}
+
+
+def test_add_and_modify_set_custom_status_beside_status() -> None:
+    exit_code, _ = run_cli(["add", "Ping", "vendor", "custom_status:waiting-on-reply"])
+    assert exit_code == 0
+    assert DummyClient.last_payload is not None
+    assert DummyClient.last_payload.status == "NEEDS-ACTION"
+    assert DummyClient.last_payload.x_properties == {"X-CUSTOM-STATUS": "waiting-on-reply"}
+
+    exit_code, _ = run_cli(["1", "modify", "custom_status:"])
+    assert exit_code == 0
+    assert DummyClient.last_patch is not None
+    assert DummyClient.last_patch.x_properties == {"X-CUSTOM-STATUS": ""}
+
+
+def test_estimate_must_be_whole_minutes() -> None:
+    exit_code, _ = run_cli(["add", "Vague", "estimate:1h"])
+    assert exit_code == 1
+    assert DummyClient.last_payload is None
+
+
+def _workload_entries() -> list[Task]:
+    return [
+        Task(uid="a", data=TaskData(summary="Draft", x_properties={"X-ESTIMATE": "90"}), task_index=1),
+        Task(uid="b", data=TaskData(summary="Review", x_properties={"X-ESTIMATE": "25"}), task_index=2),
+        Task(uid="c", data=TaskData(summary="Call"), task_index=3),
+    ]
+
+
+def test_workload_sums_estimates_and_lists_unestimated() -> None:
+    DummyClient.list_entries = _workload_entries()
+    exit_code, stdout = run_cli(["workload"])
+    assert exit_code == 0
+    lines = stdout.splitlines()
+    assert lines[0] == "Workload: 1h 55m across 2 estimated task(s)"
+    assert lines[-2:] == ["Without estimate (1):", "  [3] Call"]
+
+
+def test_workload_respects_filter_and_json() -> None:
+    DummyClient.list_entries = _workload_entries()
+    exit_code, stdout = run_cli(["2,3", "workload", "--json"])
+    assert exit_code == 0
+    assert json.loads(stdout) == {
+        "total_minutes": 25,
+        "spent_seconds": 0,
+        "estimated": [{"index": 2, "minutes": 25}],
+        "unestimated": [3],
+    }
+
+
+def test_show_command_table_format_prints_one_row_per_task() -> None:
+    DummyClient.list_entries = _show_entries()
+    exit_code, stdout = run_cli(["3,1", "show", "--format", "table"])
+    assert exit_code == 0
+    lines = stdout.splitlines()
+    assert lines[0].split() == ["ID", "Pri", "Due", "Tags", "Description"]
+    assert [line.split()[0] for line in lines[1:]] == ["3", "1"]
+    assert "ID:" not in stdout
+
+
+def test_format_task_table_ellipsizes_description_to_width() -> None:
+    tasks = [
+        Task(uid="a", data=TaskData(summary="x" * 80, priority=1, categories=["work"]), task_index=7),
+        Task(uid="b", data=TaskData(summary="short", due=datetime(2025, 3, 4)), task_index=12),
+    ]
+    lines = cli._format_task_table(tasks, width=40).splitlines()
+    assert all(len(line) <= 40 for line in lines)
+    assert lines[1].endswith("...")
+    assert lines[1].startswith(" 7    1  -")
+    assert lines[2].split() == ["12", "-", "2025-03-04", "-", "short"]
+    # Narrow terminals still keep a readable description column
+    assert cli._format_task_table(tasks, width=10).splitlines()[1].endswith("x" * 8 + "...")
+
+
+@pytest.mark.parametrize(
+    ("delta", "expected"),
+    [
+        (timedelta(seconds=20), "now"),
+        (timedelta(minutes=1, seconds=5), "in 1 minute"),
+        (timedelta(hours=5, minutes=59), "in 5 hours"),
+        (timedelta(days=2, hours=3), "in 2 days"),
+        (-timedelta(days=3), "3 days ago"),
+        (-timedelta(minutes=45), "45 minutes ago"),
+    ],
+)
+def test_format_relative_due_uses_coarse_units(delta: timedelta, expected: str) -> None:
+    now = datetime(2025, 6, 1, 12, 0, 0)
+    assert cli._format_relative_due(now + delta, now) == expected
+    assert cli._format_relative_due(None, now) is None
+
+
+def test_merge_command_unions_into_primary_and_deletes_secondaries() -> None:
+    DummyClient.list_entries = [
+        Task(
+            uid="primary",
+            data=TaskData(
+                summary="Write report",
+                due=datetime(2025, 3, 1),
+                priority=1,
+                categories=["work"],
+                x_properties={"X-PROJECT": "alpha"},
+            ),
+            task_index=1,
+        ),
+        Task(
+            uid="dupe",
+            data=TaskData(
+                summary="Report draft",
+                due=datetime(2025, 2, 1),
+                priority=5,
+                categories=["work", "writing"],
+                x_properties={"X-PROJECT": "beta"},
+            ),
+            task_index=2,
+        ),
+    ]
+    exit_code, stdout = run_cli(["1,2", "merge"])
+    assert exit_code == 0
+    assert "Merged 1 task(s) into [1]" in stdout
+    assert DummyClient.last_modified_uid == "primary"
+    patch = DummyClient.last_patch
+    assert patch is not None
+    assert patch.summary == "Write report; Report draft"
+    assert patch.categories == ["work", "writing"]
+    assert patch.due == datetime(2025, 2, 1)
+    assert patch.priority == 1
+    assert patch.x_properties["X-PROJECT"] == "alpha"
+    assert DummyClient.deleted == ["dupe"]
+
+
+def test_merge_command_prefer_secondary_takes_conflicting_scalars() -> None:
+    DummyClient.list_entries = [
+        Task(uid="primary", data=TaskData(summary="A", priority=1, x_properties={"X-PROJECT": "alpha"}), task_index=1),
+        Task(uid="dupe", data=TaskData(summary="A", priority=5, x_properties={"X-PROJECT": "beta"}), task_index=2),
+    ]
+    exit_code, _ = run_cli(["1,2", "merge", "--prefer-secondary"])
+    assert exit_code == 0
+    patch = DummyClient.last_patch
+    assert patch is not None
+    assert patch.summary == "A"
+    assert patch.priority == 5
+    assert patch.x_properties["X-PROJECT"] == "beta"
+
+
+def test_merge_command_requires_two_indices() -> None:
+    exit_code, stdout = run_cli(["1", "merge"])
+    assert exit_code == 1
+    assert "at least one secondary" in stdout
+
+
+def _agenda_titles(stdout: str) -> list[str]:
+    return [line for line in stdout.splitlines() if line in cli.AGENDA_BUCKETS]
+
+
+def test_agenda_command_groups_tasks_into_buckets() -> None:
+    now = datetime.now()
+    DummyClient.list_entries = [
+        Task(uid="late", data=TaskData(summary="Late", due=now - timedelta(days=2)), task_index=1),
+        Task(uid="soon", data=TaskData(summary="Soon-ish", due=now + timedelta(days=2)), task_index=2),
+        Task(uid="week", data=TaskData(summary="Week-ish", due=now + timedelta(days=5)), task_index=3),
+        Task(uid="far", data=TaskData(summary="Far", due=now + timedelta(days=30)), task_index=4),
+        Task(uid="none", data=TaskData(summary="Whenever"), task_index=5),
+    ]
+    exit_code, stdout = run_cli(["agenda"])
+    assert exit_code == 0
+    assert _agenda_titles(stdout) == ["Overdue", "Soon", "This week", "Later", "No due date"]
+
+
+def test_agenda_command_honors_custom_horizons() -> None:
+    now = datetime.now()
+    DummyClient.list_entries = [
+        Task(uid="week", data=TaskData(summary="Week-ish", due=now + timedelta(days=5)), task_index=1),
+        Task(uid="far", data=TaskData(summary="Far", due=now + timedelta(days=12)), task_index=2),
+    ]
+    exit_code, stdout = run_cli(["agenda", "--soon-days", "6", "--week-days", "14"])
+    assert exit_code == 0
+    assert _agenda_titles(stdout) == ["Soon", "This week"]
+
+
+def test_agenda_command_rejects_inverted_horizons() -> None:
+    exit_code, stdout = run_cli(["agenda", "--soon-days", "9", "--week-days", "7"])
+    assert exit_code == 1
+    assert "soon_days <= week_days" in stdout
+
+
+def test_list_all_envs_merges_results_with_env_and_uid(monkeypatch: pytest.MonkeyPatch) -> None:
+    opened: list[str | None] = []
+
+    async def per_env_client(env: str | None) -> DummyClient:
+        opened.append(env)
+        return await _mock_cache_client(env)
+
+    monkeypatch.setattr(cli, "_cache_client", per_env_client)
+    monkeypatch.setattr(cli, "list_environments", lambda: ["home", "work"])
+    exit_code, stdout = run_cli(["list", "--all-envs"])
+    assert exit_code == 0
+    assert opened == ["home", "work"]
+    assert "Env" in stdout
+    assert "home" in stdout and "work" in stdout
+    assert "list-task" in stdout
+
+
+def test_search_all_envs_merges_matches_with_env_and_uid(monkeypatch: pytest.MonkeyPatch) -> None:
+    opened: list[str | None] = []
+
+    async def search_tasks(self, text: str, *, task_filter=None, limit: int | None = None, weights=None) -> list[Task]:
+        return [Task(uid=f"{opened[-1]}-milk", data=TaskData(summary="Buy milk"), task_index=1)]
+
+    async def per_env_client(env: str | None) -> DummyClient:
+        opened.append(env)
+        return await _mock_cache_client(env)
+
+    monkeypatch.setattr(DummyClient, "search_tasks", search_tasks, raising=False)
+    monkeypatch.setattr(cli, "_cache_client", per_env_client)
+    monkeypatch.setattr(cli, "list_environments", lambda: ["home", "work"])
+    exit_code, stdout = run_cli(["search", "milk", "--all-envs"])
+    assert exit_code == 0
+    assert opened == ["home", "work"]
+    assert stdout.splitlines() == [
+        "Matches (2):",
+        "  home  [1] Buy milk  (home-milk)",
+        "  work  [1] Buy milk  (work-milk)",
+    ]
+
+    opened.clear()
+    exit_code, stdout = run_cli(["search", "milk", "--all-envs", "--json", "--envelope"])
+    assert exit_code == 0
+    rows = json.loads(stdout)["tasks"]
+    assert [(row["env"], row["uid"], row["index"]) for row in rows] == [
+        ("home", "home-milk", 1),
+        ("work", "work-milk", 1),
+    ]
+
+
+def test_all_envs_rejects_index_filters(monkeypatch: pytest.MonkeyPatch) -> None:
+    monkeypatch.setattr(cli, "list_environments", lambda: ["home", "work"])
+    exit_code, stdout = run_cli(["1", "list", "--all-envs"])
+    assert exit_code == 1
+    assert "cannot be combined with --all-envs" in stdout
+
+
+def _family_tasks() -> list[Task]:
+    return [
+        Task(uid="parent", data=TaskData(summary="Parent", priority=1), task_index=1),
+        Task(uid="child", data=TaskData(summary="Child", parent="parent"), task_index=2),
+        Task(uid="grandchild", data=TaskData(summary="Grandchild", parent="child"), task_index=3),
+        Task(uid="orphan", data=TaskData(summary="Orphan", parent="completed-parent"), task_index=4),
+    ]
+
+
+def test_subtask_command_sets_parent_uid() -> None:
+    DummyClient.list_entries = _family_tasks()
+    exit_code, stdout = run_cli(["1", "subtask", "Nested", "step"])
+    assert exit_code == 0
+    assert "Created (1):" in stdout
+    assert DummyClient.last_payload is not None
+    assert DummyClient.last_payload.summary == "Nested step"
+    assert DummyClient.last_payload.parent == "parent"
+
+
+def test_subtask_command_requires_single_parent() -> None:
+    DummyClient.list_entries = _family_tasks()
+    exit_code, _ = run_cli(["subtask", "Nested"])
+    assert exit_code == 1
+    exit_code, _ = run_cli(["1,2", "subtask", "Nested"])
+    assert exit_code == 1
+    assert DummyClient.last_payload is None
+
+
+def test_delete_orphans_direct_children_by_default() -> None:
+    DummyClient.list_entries = _family_tasks()
+    exit_code, stdout = run_cli(["1", "del"])
+    assert exit_code == 0
+    assert DummyClient.deleted == ["parent"]
+    assert DummyClient.last_modified_uid == "child"
+    assert DummyClient.last_patch is not None
+    assert DummyClient.last_patch.parent == ""
+
+
+def test_delete_cascade_removes_all_descendants() -> None:
+    DummyClient.list_entries = _family_tasks()
+    exit_code, _ = run_cli(["1", "del", "--cascade"])
+    assert exit_code == 0
+    assert DummyClient.deleted == ["parent", "child", "grandchild"]
+    assert DummyClient.last_patch is None
+
+
+def test_do_and_del_report_freed_indices() -> None:
+    DummyClient.list_entries = _show_entries()
+    exit_code, stdout = run_cli(["3,1", "do"])
+    assert exit_code == 0
+    assert stdout.splitlines()[-1] == "Freed indices: 1, 3"
+
+    DummyClient.list_entries = _family_tasks()
+    exit_code, stdout = run_cli(["1", "del", "--cascade"])
+    assert exit_code == 0
+    assert stdout.splitlines()[-1] == "Freed indices: 1, 2, 3"
+
+
+def test_do_cascade_completes_all_descendants() -> None:
+    DummyClient.list_entries = _family_tasks()
+    exit_code, stdout = run_cli(["1", "do", "--cascade"])
+    assert exit_code == 0
+    assert DummyClient.completed == ["parent", "child", "grandchild"]
+    assert "open subtask" not in stdout
+
+
+def test_do_on_parent_with_open_children_warns_or_errors(monkeypatch: pytest.MonkeyPatch) -> None:
+    DummyClient.list_entries = _family_tasks()
+    exit_code, stdout = run_cli(["1", "do"])
+    assert exit_code == 0
+    assert DummyClient.completed == ["parent"]
+    assert "[1] Parent has 1 open subtask(s); pass --cascade to complete them too" in stdout
+
+    async def strict_client(env: str | None) -> DummyClient:
+        return DummyClient(
+            CaldavConfig(calendar_url="https://example.com/cal", username="tester", open_children="error")
+        )
+
+    monkeypatch.setattr(cli, "_cache_client", strict_client)
+    DummyClient.completed = []
+    DummyClient.list_entries = _family_tasks()
+    exit_code, stdout = run_cli(["2", "do"])
+    assert exit_code == 1
+    assert "[2] Child has 1 open subtask(s)" in stdout
+    assert DummyClient.completed == []
+
+    # Completing parent and child together leaves nothing open
+    exit_code, _ = run_cli(["2,3", "do"])
+    assert exit_code == 0
+    assert DummyClient.completed == ["child", "grandchild"]
+
+
+def test_large_batches_need_confirm(monkeypatch: pytest.MonkeyPatch) -> None:
+    async def small_batches(env: str | None) -> DummyClient:
+        config = CaldavConfig(calendar_url="https://example.com/cal", username="tester")
+        config.validation.confirm_batch_size = 2
+        return DummyClient(config)
+
+    monkeypatch.setattr(cli, "_cache_client", small_batches)
+    DummyClient.list_entries = [
+        Task(uid=f"task-{i}", data=TaskData(summary=f"Task {i}"), task_index=i) for i in range(1, 4)
+    ]
+    exit_code, stdout = run_cli(["1,2,3", "del"])
+    assert exit_code == 1
+    assert "too many tasks (3, limit 2)" in stdout
+    assert DummyClient.deleted == []
+
+    exit_code, stdout = run_cli(["1,2,3", "do"])
+    assert exit_code == 1
+    assert "pass --confirm" in stdout
+
+    exit_code, _ = run_cli(["1,2,3", "del", "--confirm"])
+    assert exit_code == 0
+    assert DummyClient.deleted == ["task-1", "task-2", "task-3"]
+
+
+def test_tag_tree_nests_tags_and_counts_each_task_once() -> None:
+    DummyClient.list_entries = [
+        Task(uid="gym", data=TaskData(summary="Gym", categories=["area::health::gym", "area::health::diet"]), task_index=1),
+        Task(uid="doc", data=TaskData(summary="Doctor", categories=["area::health"]), task_index=2),
+        Task(uid="tax", data=TaskData(summary="Taxes", categories=["area::money", "urgent"]), task_index=3),
+    ]
+    exit_code, stdout = run_cli(["tag-tree"])
+    assert exit_code == 0
+    tree = json.loads(stdout)
+    assert [(node["tag"], node["count"]) for node in tree] == [("area", 3), ("urgent", 1)]
+    health, money = tree[0]["children"]
+    assert (health["name"], health["count"]) == ("health", 2)
+    assert [(node["tag"], node["count"]) for node in health["children"]] == [
+        ("area::health::diet", 1),
+        ("area::health::gym", 1),
+    ]
+    assert (money["tag"], money["count"], money["children"]) == ("area::money", 1, [])
+
+    assert cli._parse_task_filter(["+area::health"]).tags_match == "nested"
+    assert cli._parse_task_filter(["+area::health", "tags.match:exact"]).tags_match == "exact"
+    exit_code, stdout = run_cli(["+work", "tags.match:prefix", "show"])
+    assert exit_code == 1
+    assert "invalid tags.match value: prefix" in stdout
+
+
+def test_tree_nests_children_and_promotes_orphans() -> None:
+    DummyClient.list_entries = _family_tasks()
+    exit_code, stdout = run_cli(["tree"])
+    assert exit_code == 0
+    tree = json.loads(stdout)
+    assert [node["uid"] for node in tree] == ["parent", "orphan"]
+    assert tree[0]["children"][0]["uid"] == "child"
+    assert tree[0]["children"][0]["children"][0]["uid"] == "grandchild"
+    assert tree[1]["parent"] == "completed-parent"
+
+    exit_code, stdout = run_cli(["2", "tree"])
+    assert exit_code == 0
+    assert [node["uid"] for node in json.loads(stdout)] == ["child"]
+
+
+def test_build_task_tree_breaks_parent_cycles() -> None:
+    tasks = [
+        Task(uid="a", data=TaskData(summary="A", parent="b"), task_index=1),
+        Task(uid="b", data=TaskData(summary="B", parent="a"), task_index=2),
+    ]
+    tree = cli._build_task_tree(tasks)
+    assert [node["uid"] for node in tree] == ["a"]
+    assert tree[0]["children"][0]["uid"] == "b"
+    assert tree[0]["children"][0]["children"] == []
+
+
+def test_priority_letters_flag_adds_letters_to_show_and_tree() -> None:
+    DummyClient.list_entries = [
+        Task(uid="urgent", data=TaskData(summary="Urgent", priority=2), task_index=1),
+        Task(uid="someday", data=TaskData(summary="Someday"), task_index=2),
+    ]
+    exit_code, stdout = run_cli(["tree", "--priority-letters"])
+    assert exit_code == 0
+    nodes = json.loads(stdout)
+    assert [(node["priority"], node["priority_letter"]) for node in nodes] == [(2, "H"), (None, None)]
+
+    exit_code, stdout = run_cli(["tree"])
+    assert "priority_letter" not in stdout
+
+    exit_code, stdout = run_cli(["1", "show", "--priority-letters"])
+    assert "Priority:    H (2)" in stdout
+
+    exit_code, stdout = run_cli(["1,2", "show", "--format", "table", "--priority-letters"])
+    assert [line.split()[1] for line in stdout.splitlines()[1:]] == ["H", "-"]
+
+
+def test_show_json_projects_requested_fields() -> None:
+    DummyClient.list_entries = [
+        Task(uid="first", data=TaskData(summary="First", priority=2, categories=["home"]), task_index=1),
+    ]
+    exit_code, stdout = run_cli(["1", "show", "--format", "json"])
+    assert exit_code == 0
+    full = json.loads(stdout)[0]
+    assert full["uid"] == "first"
+    assert full["categories"] == ["home"]
+
+    exit_code, stdout = run_cli(["1", "show", "--format", "json", "--fields", "index,summary,priority"])
+    assert exit_code == 0
+    assert json.loads(stdout) == [{"index": 1, "summary": "First", "priority": 2}]
+
+    exit_code, stdout = run_cli(["1", "show", "--format", "json", "--fields", "summary,colour"])
+    assert exit_code == 1
+    assert "unknown field(s): colour" in stdout
+
+    exit_code, stdout = run_cli(["1", "show", "--fields", "summary"])
+    assert exit_code == 1
+    assert "--fields requires --format json" in stdout
+
+
+def test_show_json_as_map_keys_tasks_by_index() -> None:
+    DummyClient.list_entries = [
+        Task(uid="first", data=TaskData(summary="First", categories=["home"]), task_index=1),
+        Task(uid="second", data=TaskData(summary="Second", categories=["home"]), task_index=2),
+        Task(uid="unindexed", data=TaskData(summary="Loose", categories=["home"])),
+    ]
+    exit_code, stdout = run_cli(["+home", "show", "--format", "json", "--fields", "summary", "--as-map"])
+    assert exit_code == 0
+    assert json.loads(stdout) == {
+        "1": {"summary": "First"},
+        "2": {"summary": "Second"},
+        "unindexed": {"summary": "Loose"},
+    }
+
+    exit_code, stdout = run_cli(["1", "show", "--as-map"])
+    assert exit_code == 1
+    assert "--as-map requires --format json" in stdout
+
+
+def test_show_history_lists_logged_changes_oldest_first(monkeypatch: pytest.MonkeyPatch) -> None:
+    created = TaskData(summary="Audit", priority=2)
+    modified = TaskData(summary="Audit", priority=1)
+    DummyClient.list_entries = [
+        Task(uid="audited", data=modified, task_index=1),
+        Task(uid="fresh", data=TaskData(summary="Fresh"), task_index=2),
+    ]
+    log = [
+        TransactionLogEntry(
+            id=1,
+            diff_json=TaskSetDiff(diffs={"audited": TaskDiff(post=created)}).to_json(),
+            operation="add",
+            created_at=1.0,
+        ),
+        TransactionLogEntry(
+            id=2,
+            diff_json=TaskSetDiff(diffs={"audited": TaskDiff(pre=created, post=modified)}).to_json(),
+            operation="modify",
+            created_at=2.0,
+        ),
+    ]
+
+    async def transaction_history(self: DummyClient, uids: list[str]) -> list[TransactionLogEntry]:
+        return [entry for entry in log if set(json.loads(entry.diff_json)) & set(uids)]
+
+    monkeypatch.setattr(DummyClient, "transaction_history", transaction_history, raising=False)
+    exit_code, stdout = run_cli(["1,2", "show", "--format", "json", "--fields", "summary", "--history"])
+    assert exit_code == 0
+    audited, fresh = json.loads(stdout)
+    assert [(entry["id"], entry["operation"]) for entry in audited["history"]] == [(1, "add"), (2, "modify")]
+    assert audited["history"][1]["changes"] == {"priority": {"before": 2, "after": 1}}
+    assert fresh == {"summary": "Fresh", "history": []}
+
+    exit_code, stdout = run_cli(["1", "show", "--history"])
+    assert exit_code == 0
+    assert "History (2):" in stdout
+    assert "    priority: 2 -> 1" in stdout
+
+    exit_code, stdout = run_cli(["1", "show", "--format", "table", "--history"])
+    assert exit_code == 1
+    assert "--history does not apply to --format table" in stdout
+
+
+def test_raw_flag_includes_x_properties_in_show_and_tree() -> None:
+    extra = {"X-PROJECT": "home", "X-ESTIMATE": "30"}
+    DummyClient.list_entries = [
+        Task(uid="raw", data=TaskData(summary="Raw", x_properties=extra), task_index=1),
+    ]
+    exit_code, stdout = run_cli(["tree", "--raw"])
+    assert exit_code == 0
+    assert json.loads(stdout)[0]["extra"] == extra
+
+    exit_code, stdout = run_cli(["tree"])
+    assert "extra" not in json.loads(stdout)[0]
+
+    exit_code, stdout = run_cli(["1", "show", "--raw"])
+    assert f"Extra:       {json.dumps(extra)}" in stdout
+
+    exit_code, stdout = run_cli(["1", "show", "--format", "table", "--raw"])
+    assert exit_code == 1
+
+
+def test_stats_by_day_fills_days_without_completions(monkeypatch: pytest.MonkeyPatch) -> None:
+    today = datetime.now().date()
+    starts: list[float] = []
+
+    async def count_completed_by_day(self, since: float) -> dict[str, int]:
+        starts.append(since)
+        return {today.isoformat(): 2, (today - timedelta(days=2)).isoformat(): 1}
+
+    monkeypatch.setattr(DummyClient, "count_completed_by_day", count_completed_by_day, raising=False)
+    exit_code, stdout = run_cli(["stats", "--by-day", "--days", "3", "--json"])
+    assert exit_code == 0
+    assert json.loads(stdout) == [
+        {"date": (today - timedelta(days=2)).isoformat(), "count": 1},
+        {"date": (today - timedelta(days=1)).isoformat(), "count": 0},
+        {"date": today.isoformat(), "count": 2},
+    ]
+    assert datetime.fromtimestamp(starts[0]) == datetime.combine(today - timedelta(days=2), datetime.min.time())
+
+
+def test_stats_time_reports_time_spent(monkeypatch: pytest.MonkeyPatch) -> None:
+    calls: list[bool] = []
+
+    async def sum_time_spent(self, *, include_completed: bool = False) -> TimeSpent:
+        calls.append(include_completed)
+        return TimeSpent(tags={"work": 5400, "deep": 1500}, projects={"tdo": 3900}, total=7200)
+
+    monkeypatch.setattr(DummyClient, "sum_time_spent", sum_time_spent, raising=False)
+    exit_code, stdout = run_cli(["stats", "--time", "--include-completed"])
+    assert exit_code == 0
+    assert stdout.splitlines()[0] == "Time spent: 2h"
+    assert "Spent" in stdout
+    for name, spent in (("tdo", "1h 5m"), ("work", "1h 30m"), ("deep", "25m")):
+        assert any(name in line and line.rstrip().endswith(spent) for line in stdout.splitlines())
+    assert calls == [True]
+
+    exit_code, stdout = run_cli(["stats", "--time", "--json"])
+    assert json.loads(stdout) == {
+        "projects": {"tdo": 3900},
+        "tags": {"deep": 1500, "work": 5400},
+        "total_seconds": 7200,
+    }
+
+    exit_code, stdout = run_cli(["stats", "--time", "--by-day"])
+    assert exit_code == 1
+    assert "--by-day cannot be combined with --time" in stdout
+
+
+def test_info_command_prints_env_and_cache_details(monkeypatch: pytest.MonkeyPatch) -> None:
+    info = CacheInfo(
+        path=Path("/home/me/.cache/tdo/default/tasks.db"),
+        schema_version=1,
+        size_bytes=4096,
+        active=3,
+        completed=2,
+        deleted=1,
+        archived=0,
+    )
+
+    async def cache_info(self) -> CacheInfo:
+        return info
+
+    monkeypatch.setattr(DummyClient, "cache_info", cache_info, raising=False)
+    exit_code, stdout = run_cli(["info", "--json"])
+    assert exit_code == 0
+    assert json.loads(stdout) == {
+        "env": "default",
+        "db_path": "/home/me/.cache/tdo/default/tasks.db",
+        "schema_version": 1,
+        "size_bytes": 4096,
+        "active": 3,
+        "completed": 2,
+        "deleted": 1,
+        "archived": 0,
+    }
+
+    exit_code, stdout = run_cli(["info"])
+    assert exit_code == 0
+    assert "Size:        4096 bytes" in stdout
+
+
+def test_stale_command_prints_json_ages(monkeypatch: pytest.MonkeyPatch) -> None:
+    synced = (datetime.now() - timedelta(days=10)).timestamp()
+    stale = [StaleTask(task=Task(uid="old", data=TaskData(summary="Old"), href="/old.ics"), last_synced=synced, completed=False)]
+    thresholds: list[float] = []
+
+    async def list_stale_tasks(self, *, synced_before: float) -> list[StaleTask]:
+        thresholds.append(synced_before)
+        return stale
+
+    monkeypatch.setattr(DummyClient, "list_stale_tasks", list_stale_tasks, raising=False)
+    exit_code, stdout = run_cli(["stale", "--days", "3", "--json"])
+    assert exit_code == 0
+    payload = json.loads(stdout)
+    assert payload[0]["uid"] == "old"
+    assert payload[0]["href"] == "/old.ics"
+    assert payload[0]["age_seconds"] >= 10 * 86400 - 5
+    assert thresholds[0] == pytest.approx((datetime.now() - timedelta(days=3)).timestamp(), abs=5)
+
+    exit_code, stdout = run_cli(["stale"])
+    assert exit_code == 0
+    assert "old" in stdout and "10d" in stdout
+
+
+def test_timestamps_iso_writes_rfc3339_in_json(monkeypatch: pytest.MonkeyPatch) -> None:
+    # A fractional epoch must round-trip to a fixed millisecond string
+    synced = 1767225600.1239
+    stale = [StaleTask(task=Task(uid="old", data=TaskData(summary="Old")), last_synced=synced, completed=False)]
+
+    async def list_stale_tasks(self, *, synced_before: float) -> list[StaleTask]:
+        return stale
+
+    monkeypatch.setattr(DummyClient, "list_stale_tasks", list_stale_tasks, raising=False)
+    exit_code, stdout = run_cli(["stale", "--json"])
+    assert exit_code == 0
+    assert json.loads(stdout)[0]["last_synced"] == synced
+
+    exit_code, stdout = run_cli(["--timestamps", "iso", "stale", "--json"])
+    assert exit_code == 0
+    assert json.loads(stdout)[0]["last_synced"] == "2026-01-01T00:00:00.123+00:00"
+    assert cli._json_timestamp(None, "iso") is None
+
+
+def test_events_command_reports_crossings_since(monkeypatch: pytest.MonkeyPatch) -> None:
+    crossed = datetime.now() - timedelta(minutes=2)
+    task = Task(uid="rent", data=TaskData(summary="Pay rent", due=crossed, categories=["home"]), task_index=3)
+    calls: list[tuple[float, float, object]] = []
+
+    async def list_boundary_events(self, *, since: float, until: float, task_filter=None) -> list[BoundaryEvent]:
+        calls.append((since, until, task_filter))
+        return [BoundaryEvent(task, "overdue", crossed.timestamp())]
+
+    monkeypatch.setattr(DummyClient, "list_boundary_events", list_boundary_events, raising=False)
+    exit_code, stdout = run_cli(["+home", "events", "--since", "15m", "--json"])
+    assert exit_code == 0
+    payload = json.loads(stdout)
+    assert payload["since"] == pytest.approx((datetime.now() - timedelta(minutes=15)).timestamp(), abs=5)
+    assert payload["until"] == calls[0][1]
+    assert payload["events"][0]["event"] == "overdue"
+    assert payload["events"][0]["index"] == 3 and payload["events"][0]["summary"] == "Pay rent"
+    assert calls[0][2].tags == ["home"]
+
+    exit_code, stdout = run_cli(["events", "--since", "15m"])
+    assert exit_code == 0
+    assert stdout.splitlines()[1].split()[:4] == ["overdue", "[3]", "Pay", "rent"]
+
+    exit_code, stdout = run_cli(["events", "--since", "whenever"])
+    assert exit_code == 1
+    assert "invalid --since value: whenever" in stdout
+
+
+def test_search_command_passes_limit_and_filter(monkeypatch: pytest.MonkeyPatch) -> None:
+    calls: list[tuple[str, object, int | None]] = []
+    match = Task(uid="report", data=TaskData(summary="Report", priority=1), task_index=4)
+
+    async def search_tasks(self, text: str, *, task_filter=None, limit: int | None = None, weights=None) -> list[Task]:
+        calls.append((text, task_filter, limit))
+        return [match]
+
+    monkeypatch.setattr(DummyClient, "search_tasks", search_tasks, raising=False)
+    exit_code, stdout = run_cli(["project:work", "search", "weekly", "report", "--limit", "3", "--json"])
+    assert exit_code == 0
+    assert json.loads(stdout) == [{"index": 4, "uid": "report", "summary": "Report", "priority": 1}]
+    text, task_filter, limit = calls[0]
+    assert (text, limit) == ("weekly report", 3)
+    assert task_filter.project == "work"
+
+    exit_code, stdout = run_cli(["search", "report"])
+    assert exit_code == 0
+    assert "[4] Report" in stdout
+
+    exit_code, stdout = run_cli(["search", "report", "--limit", "0"])
+    assert exit_code == 1
+    assert "--limit must be positive" in stdout
+
+
+def test_search_weights_are_parsed_and_passed(monkeypatch: pytest.MonkeyPatch) -> None:
+    passed: list[object] = []
+
+    async def search_tasks(self, text: str, *, task_filter=None, limit: int | None = None, weights=None) -> list[Task]:
+        passed.append(weights)
+        return []
+
+    monkeypatch.setattr(DummyClient, "search_tasks", search_tasks, raising=False)
+    assert run_cli(["search", "gym"])[0] == 0
+    assert run_cli(["search", "gym", "--weights", "summary=3, project=2,tags=1"])[0] == 0
+    assert passed == [None, {"summary": 3, "project": 2, "tags": 1}]
+
+    for raw, message in [
+        ("notes=2", "unknown search field: notes (expected summary, project, tags)"),
+        ("summary=high", "invalid weight for summary: high (expected a whole number)"),
+        ("summary=0,tags=0", "--weights needs at least one field with a positive weight"),
+    ]:
+        exit_code, stdout = run_cli(["search", "gym", "--weights", raw])
+        assert exit_code == 1
+        assert message in stdout
+
+
+def test_search_include_deleted_lists_deleted_matches_by_uid(monkeypatch: pytest.MonkeyPatch) -> None:
+    active = Task(uid="report", data=TaskData(summary="Report"), task_index=4)
+    gone = Task(uid="old-report", data=TaskData(summary="Old report"), task_index=2)
+    deleted_at = datetime(2026, 9, 1, 8, 30).timestamp()
+    limits: list[int | None] = []
+
+    async def search_tasks(self, text: str, *, task_filter=None, limit: int | None = None, weights=None) -> list[Task]:
+        return [active]
+
+    async def search_deleted_tasks(self, text: str, *, task_filter=None, limit: int | None = None, weights=None) -> list[DeletedMatch]:
+        limits.append(limit)
+        return [DeletedMatch(task=gone, deleted_at=deleted_at)]
+
+    monkeypatch.setattr(DummyClient, "search_tasks", search_tasks, raising=False)
+    monkeypatch.setattr(DummyClient, "search_deleted_tasks", search_deleted_tasks, raising=False)
+    exit_code, stdout = run_cli(["search", "report"])
+    assert "Deleted" not in stdout
+    assert limits == []
+
+    exit_code, stdout = run_cli(["search", "report", "--include-deleted"])
+    assert exit_code == 0
+    assert "[4] Report" in stdout
+    assert "Deleted (1):" in stdout
+    assert "old-report  Old report  (deleted 2026-09-01 08:30)" in stdout
+
+    exit_code, stdout = run_cli(["search", "report", "--include-deleted", "--limit", "3", "--json"])
+    assert exit_code == 0
+    assert json.loads(stdout) == [
+        {"index": 4, "uid": "report", "summary": "Report", "priority": None, "table": "tasks", "deleted_at": None},
+        {
+            "index": 2,
+            "uid": "old-report",
+            "summary": "Old report",
+            "priority": None,
+            "table": "deleted_tasks",
+            "deleted_at": "2026-09-01T08:30:00",
+        },
+    ]
+    assert limits == [None, 2]
+
+
+def test_search_include_archived_lists_archived_matches_by_uid(monkeypatch: pytest.MonkeyPatch) -> None:
+    active = Task(uid="report", data=TaskData(summary="Report"), task_index=4)
+    gone = Task(uid="old-report", data=TaskData(summary="Old report"), task_index=2)
+    shelved = Task(uid="q1-report", data=TaskData(summary="Q1 report", status="COMPLETED"), task_index=2)
+    deleted_at = datetime(2026, 9, 1, 8, 30).timestamp()
+    archived_at = datetime(2026, 4, 2, 17, 0).timestamp()
+    limits: list[int | None] = []
+
+    async def search_tasks(self, text: str, *, task_filter=None, limit: int | None = None, weights=None) -> list[Task]:
+        return [active]
+
+    async def search_deleted_tasks(self, text: str, *, task_filter=None, limit: int | None = None, weights=None) -> list[DeletedMatch]:
+        return [DeletedMatch(task=gone, deleted_at=deleted_at)]
+
+    async def search_archived_tasks(self, text: str, *, task_filter=None, limit: int | None = None, weights=None) -> list[ArchivedMatch]:
+        limits.append(limit)
+        return [ArchivedMatch(task=shelved, archived_at=archived_at)]
+
+    monkeypatch.setattr(DummyClient, "search_tasks", search_tasks, raising=False)
+    monkeypatch.setattr(DummyClient, "search_deleted_tasks", search_deleted_tasks, raising=False)
+    monkeypatch.setattr(DummyClient, "search_archived_tasks", search_archived_tasks, raising=False)
+    exit_code, stdout = run_cli(["search", "report"])
+    assert "Archived" not in stdout
+    assert limits == []
+
+    exit_code, stdout = run_cli(["search", "report", "--include-archived"])
+    assert exit_code == 0
+    assert "[4] Report" in stdout
+    assert "Deleted" not in stdout
+    assert "Archived (1):" in stdout
+    assert "q1-report  Q1 report  (archived 2026-04-02 17:00)" in stdout
+
+    exit_code, stdout = run_cli(["search", "report", "--include-archived", "--json"])
+    assert exit_code == 0
+    assert [(row["uid"], row["table"], row["archived_at"]) for row in json.loads(stdout)] == [
+        ("report", "tasks", None),
+        ("q1-report", "archived_tasks", "2026-04-02T17:00:00"),
+    ]
+    assert "deleted_at" not in json.loads(stdout)[0]
+
+    exit_code, stdout = run_cli(["search", "report", "--include-deleted", "--include-archived", "--limit", "3", "--json"])
+    assert exit_code == 0
+    assert [(row["table"], row["deleted_at"], row["archived_at"]) for row in json.loads(stdout)] == [
+        ("tasks", None, None),
+        ("deleted_tasks", "2026-09-01T08:30:00", None),
+        ("archived_tasks", None, "2026-04-02T17:00:00"),
+    ]
+    assert limits == [None, None, 1]
+
+
+def test_search_envelope_reports_total_before_limit(monkeypatch: pytest.MonkeyPatch) -> None:
+    found = [Task(uid=f"t{i}", data=TaskData(summary=f"Report {i}"), task_index=i) for i in (1, 2, 3)]
+    limits: list[int | None] = []
+
+    async def search_tasks(self, text: str, *, task_filter=None, limit: int | None = None, weights=None) -> list[Task]:
+        limits.append(limit)
+        return found[:limit] if limit is not None else list(found)
+
+    monkeypatch.setattr(DummyClient, "search_tasks", search_tasks, raising=False)
+    exit_code, stdout = run_cli(["+work", "search", "report", "--limit", "2", "--json", "--envelope"])
+    assert exit_code == 0
+    payload = json.loads(stdout)
+    assert [row["uid"] for row in payload["tasks"]] == ["t1", "t2"]
+    assert payload["meta"]["total"] == 3
+    assert payload["meta"]["returned"] == 2
+    assert payload["meta"]["filters"] == {"tags": ["work"]}
+    assert payload["meta"]["elapsed_ms"] >= 0
+    assert limits == [None]
+
+    exit_code, stdout = run_cli(["search", "report", "--limit", "2", "--json"])
+    assert isinstance(json.loads(stdout), list)
+    assert limits[-1] == 2
+
+    exit_code, stdout = run_cli(["search", "report", "--envelope"])
+    assert exit_code == 1
+    assert "--envelope requires --json" in stdout
+
+
+def test_show_envelope_wraps_json_rows() -> None:
+    DummyClient.list_entries = _show_entries()
+    exit_code, stdout = run_cli(["1,3", "show", "--format", "json", "--fields", "index", "--envelope"])
+    assert exit_code == 0
+    payload = json.loads(stdout)
+    assert payload["tasks"] == [{"index": 1}, {"index": 3}]
+    assert payload["meta"]["total"] == payload["meta"]["returned"] == 2
+    assert payload["meta"]["filters"] == {"indices": [1, 3]}
+
+    exit_code, stdout = run_cli(["1", "show", "--envelope"])
+    assert exit_code == 1
+    assert "--envelope requires --format json" in stdout
+
+
+def test_list_activate_waited_is_opt_in(monkeypatch: pytest.MonkeyPatch) -> None:
+    calls: list[int] = []
+    waited = Task(uid="waited", data=TaskData(summary="Back again", wait=datetime(2000, 1, 1)), task_index=7)
+
+    async def activate_waited_tasks(self) -> list[Task]:
+        calls.append(1)
+        return [waited]
+
+    monkeypatch.setattr(DummyClient, "activate_waited_tasks", activate_waited_tasks, raising=False)
+    exit_code, stdout = run_cli(["list"])
+    assert exit_code == 0
+    assert calls == []
+    assert "Activated" not in stdout
+
+    exit_code, stdout = run_cli(["list", "--activate-waited"])
+    assert exit_code == 0
+    assert calls == [1]
+    assert "Activated (1):" in stdout
+    assert "[7] Back again" in stdout
+
+    exit_code, stdout = run_cli(["list", "--activate-waited", "--all-envs"])
+    assert exit_code == 1
+    assert "--activate-waited cannot be combined with --all-envs" in stdout
+    assert calls == [1]
+
+
+@pytest.mark.parametrize(
+    ("selector", "expected"),
+    [
+        ("-1", ["3"]),
+        ("$", ["3"]),
+        ("^", ["1"]),
+        ("-2", ["2"]),
+        ("^,$,2", ["1", "3", "2"]),
+        ("1-3", ["1", "2", "3"]),
+        ("3,1-2", ["3", "1", "2"]),
+    ],
+)
+def test_relative_index_selectors_resolve_against_active_indices(selector: str, expected: list[str]) -> None:
+    DummyClient.list_entries = _show_entries()
+    exit_code, stdout = run_cli([selector, "show"])
+    assert exit_code == 0
+    assert _shown_ids(stdout) == expected
+
+
+@pytest.mark.parametrize("selector", ["-0", "1,-", "$$", "^2", "0", "1-", "0-2", "1-2-3"])
+def test_malformed_index_selectors_are_rejected(selector: str) -> None:
+    DummyClient.list_entries = _show_entries()
+    exit_code, stdout = run_cli([selector, "show"])
+    assert exit_code == 1
+    assert "invalid index selector" in stdout
+
+
+def test_reversed_index_range_is_rejected() -> None:
+    DummyClient.list_entries = _show_entries()
+    exit_code, stdout = run_cli(["3-1", "del"])
+    assert exit_code == 1
+    assert "invalid index range: 3-1 (start is after end)" in stdout
+    assert DummyClient.deleted == []
+
+
+def test_index_ranges_are_capped() -> None:
+    DummyClient.list_entries = _show_entries()
+    exit_code, stdout = run_cli(["1-100000000", "del"])
+    assert exit_code == 1
+    assert "index range 1-100000000 covers 100000000 indices; at most 500 can be selected" in stdout
+
+    # Ranges under the cap still add up past it
+    exit_code, stdout = run_cli(["1-400,401-800", "del"])
+    assert exit_code == 1
+    assert "800 indices selected; at most 500 can be selected" in stdout
+    assert DummyClient.deleted == []
+
+
+def test_relative_index_selector_out_of_range() -> None:
+    DummyClient.list_entries = _show_entries()
+    exit_code, stdout = run_cli(["-4", "do"])
+    assert exit_code == 1
+    assert "out of range" in stdout
+    assert DummyClient.completed == []
+
+
+def test_modify_reports_tasks_that_are_already_up_to_date() -> None:
+    DummyClient.list_entries = [
+        Task(uid="same", data=TaskData(summary="Same", priority=2, categories=[]), task_index=1)
+    ]
+    exit_code, stdout = run_cli(["1", "modify", "pri:2"])
+    assert exit_code == 0
+    assert "1 task(s) already up to date" in stdout
+    assert "Updated" not in stdout
+
+
+def test_validate_reports_no_problems() -> None:
+    exit_code, stdout = run_cli(["validate"])
+    assert exit_code == 0
+    assert stdout.strip() == "no problems found"
+
+
+def test_validate_lists_problems_and_fails() -> None:
+    DummyClient.integrity_problems = [
+        IntegrityProblem("completed_in_active", "tasks", "u1", "COMPLETED task is in the active table"),
+        IntegrityProblem("duplicate_index", "tasks", None, "index 3 is shared by a, b"),
+    ]
+    exit_code, stdout = run_cli(["validate"])
+    assert exit_code == 1
+    assert stdout.splitlines() == [
+        "Problems (2):",
+        "  completed_in_active  tasks u1: COMPLETED task is in the active table",
+        "  duplicate_index      tasks: index 3 is shared by a, b",
+    ]
+    exit_code, stdout = run_cli(["validate", "--json"])
+    assert exit_code == 1
+    assert json.loads(stdout)[1] == {
+        "check": "duplicate_index",
+        "table": "tasks",
+        "uid": None,
+        "detail": "index 3 is shared by a, b",
+    }
+
+
+def test_pretty_flag_indents_json_output() -> None:
+    DummyClient.integrity_problems = [IntegrityProblem("missing_index", "tasks", "u1", "active task has no index")]
+    exit_code, compact = run_cli(["validate", "--json"])
+    assert len(compact.splitlines()) == 1
+    exit_code, pretty = run_cli(["--pretty", "validate", "--json"])
+    assert exit_code == 1
+    assert pretty.startswith('[\n  {\n    "check": "missing_index",')
+    assert json.loads(pretty) == json.loads(compact)
+
+
+def test_repair_dry_run_then_apply() -> None:
+    DummyClient.integrity_problems = [IntegrityProblem("missing_index", "tasks", "u1", "active task has no index")]
+    exit_code, stdout = run_cli(["repair", "--dry-run"])
+    assert exit_code == 0
+    assert stdout.splitlines() == ["Would repair (1):", "  missing_index        tasks u1: fixed"]
+    assert DummyClient.integrity_problems
+
+    exit_code, stdout = run_cli(["repair"])
+    assert exit_code == 0
+    assert stdout.splitlines()[0] == "Repaired (1):"
+    exit_code, stdout = run_cli(["repair"])
+    assert stdout.strip() == "nothing to repair"
@@ -4,7 +4,17 @@ from __future__ import annotations
 import pytest
 from pathlib import Path
 
-from tdo.config import CaldavConfig, config_file_path, load_config, write_config_file
+from tdo.config import (
+    CaldavConfig,
+    RoutingConfig,
+    config_file_path,
+    list_environments,
+    load_config,
+    resolve_env,
+    uid_template_problem,
+    write_config_file,
+    write_default_env,
+)
 
 
 CONFIG = CaldavConfig(
@@ -59,3 +69,242 @@ def test_load_config_supports_legacy_format(tmp_path: Path) -> None:
     loaded = load_config(env="legacy", config_home=home)
     assert loaded.username == "alice"
     assert loaded.calendar_url == "https://example.com"
+
+
+def test_load_config_reads_agenda_section(tmp_path: Path) -> None:
+    target = config_file_path("agenda", config_home=tmp_path)
+    target.write_text(
+        "[caldav]\ncalendar_url = \"https://example.com\"\nusername = \"alice\"\n"
+        "\n[agenda]\nsoon_days = 2\nweek_days = 10\n"
+    )
+    loaded = load_config(env="agenda", config_home=tmp_path)
+    assert loaded.agenda.soon_days == 2
+    assert loaded.agenda.week_days == 10
+
+
+def test_agenda_config_defaults_and_round_trip(tmp_path: Path) -> None:
+    target = config_file_path("app", config_home=tmp_path)
+    write_config_file(target, CaldavConfig(calendar_url="https://example.com", username="alice"))
+    assert "[agenda]" not in target.read_text()
+    loaded = load_config(env="app", config_home=tmp_path)
+    assert loaded.agenda.soon_days == 3
+    assert loaded.agenda.week_days == 7
+
+    custom = CaldavConfig(calendar_url="https://example.com", username="alice")
+    custom.agenda.soon_days = 1
+    write_config_file(target, custom, force=True)
+    assert load_config(env="app", config_home=tmp_path).agenda.soon_days == 1
+
+
+def test_list_environments_reads_config_file_names(tmp_path: Path) -> None:
+    for env in ("work", "home"):
+        write_config_file(config_file_path(env, config_home=tmp_path), CONFIG)
+    (tmp_path / "notes.txt").write_text("ignored")
+    assert list_environments(tmp_path) == ["home", "work"]
+    assert list_environments(tmp_path / "missing") == []
+
+
+def test_delete_children_round_trips_and_rejects_unknown_modes(tmp_path: Path) -> None:
+    target = config_file_path("app", config_home=tmp_path)
+    write_config_file(target, CaldavConfig(calendar_url="https://example.com", username="alice"))
+    assert "delete_children" not in target.read_text()
+    assert load_config(env="app", config_home=tmp_path).delete_children == "orphan"
+
+    write_config_file(
+        target,
+        CaldavConfig(calendar_url="https://example.com", username="alice", delete_children="cascade"),
+        force=True,
+    )
+    assert load_config(env="app", config_home=tmp_path).delete_children == "cascade"
+
+    target.write_text("[caldav]\ncalendar_url = \"https://example.com\"\nusername = \"alice\"\ndelete_children = \"explode\"\n")
+    assert load_config(env="app", config_home=tmp_path).delete_children == "orphan"
+
+
+def test_complete_mode_round_trips_and_rejects_unknown_modes(tmp_path: Path) -> None:
+    target = config_file_path("app", config_home=tmp_path)
+    write_config_file(target, CaldavConfig(calendar_url="https://example.com", username="alice"))
+    assert "complete_mode" not in target.read_text()
+    assert load_config(env="app", config_home=tmp_path).complete_mode == "archive"
+
+    write_config_file(
+        target,
+        CaldavConfig(calendar_url="https://example.com", username="alice", complete_mode="delete"),
+        force=True,
+    )
+    assert load_config(env="app", config_home=tmp_path).complete_mode == "delete"
+
+    target.write_text("[caldav]\ncalendar_url = \"https://example.com\"\nusername = \"alice\"\ncomplete_mode = \"shred\"\n")
+    assert load_config(env="app", config_home=tmp_path).complete_mode == "archive"
+
+def test_open_children_round_trips_and_rejects_unknown_modes(tmp_path: Path) -> None:
+    target = config_file_path("app", config_home=tmp_path)
+    write_config_file(target, CaldavConfig(calendar_url="https://example.com", username="alice"))
+    assert "open_children" not in target.read_text()
+    assert load_config(env="app", config_home=tmp_path).open_children == "warn"
+
+    write_config_file(
+        target,
+        CaldavConfig(calendar_url="https://example.com", username="alice", open_children="error"),
+        force=True,
+    )
+    assert load_config(env="app", config_home=tmp_path).open_children == "error"
+
+    target.write_text("[caldav]\ncalendar_url = \"https://example.com\"\nusername = \"alice\"\nopen_children = \"panic\"\n")
+    assert load_config(env="app", config_home=tmp_path).open_children == "warn"
+
+def test_single_active_round_trips(tmp_path: Path, monkeypatch: pytest.MonkeyPatch) -> None:
+    target = config_file_path("app", config_home=tmp_path)
+    write_config_file(target, CaldavConfig(calendar_url="https://example.com", username="alice"))
+    assert "single_active" not in target.read_text()
+    assert load_config(env="app", config_home=tmp_path).single_active is False
+
+    monkeypatch.setenv("TDO_SINGLE_ACTIVE", "yes")
+    assert load_config(env="app", config_home=tmp_path).single_active is True
+    monkeypatch.delenv("TDO_SINGLE_ACTIVE")
+
+    write_config_file(
+        target,
+        CaldavConfig(calendar_url="https://example.com", username="alice", single_active=True),
+        force=True,
+    )
+    assert "single_active = true" in target.read_text()
+    assert load_config(env="app", config_home=tmp_path).single_active is True
+
+def test_uid_template_round_trips_and_rejects_unsafe_templates(tmp_path: Path) -> None:
+    target = config_file_path("app", config_home=tmp_path)
+    write_config_file(target, CaldavConfig(calendar_url="https://example.com", username="alice"))
+    assert "uid_template" not in target.read_text()
+    assert load_config(env="app", config_home=tmp_path).uid_template == "{summary}-{uuid}"
+
+    write_config_file(
+        target,
+        CaldavConfig(calendar_url="https://example.com", username="alice", uid_template="{uuid}@myhost"),
+        force=True,
+    )
+    assert load_config(env="app", config_home=tmp_path).uid_template == "{uuid}@myhost"
+
+    target.write_text("[caldav]\ncalendar_url = \"https://example.com\"\nusername = \"alice\"\nuid_template = \"task\"\n")
+    with pytest.raises(RuntimeError, match="invalid uid_template 'task': must contain"):
+        load_config(env="app", config_home=tmp_path)
+
+def test_modify_words_round_trips_and_rejects_unknown_modes(tmp_path: Path) -> None:
+    target = config_file_path("app", config_home=tmp_path)
+    write_config_file(
+        target,
+        CaldavConfig(calendar_url="https://example.com", username="alice", modify_words="append"),
+    )
+    assert load_config(env="app", config_home=tmp_path).modify_words == "append"
+
+    target.write_text("[caldav]\ncalendar_url = \"https://example.com\"\nusername = \"alice\"\nmodify_words = \"prepend\"\n")
+    assert load_config(env="app", config_home=tmp_path).modify_words == "replace"
+
+
+@pytest.mark.parametrize(
+    "template,problem",
+    [
+        ("{summary}@myhost", "must contain {uuid}"),
+        ("{uuid}@my host", "unsafe text"),
+        ("{uuid};x", "unsafe text"),
+        ("{uuid}/{summary}", "unsafe text"),
+        ("{id}@myhost", "unknown placeholder {id}"),
+        ("{uuid!r}", "no format spec"),
+        ("{uuid", "expected '}'"),
+    ],
+)
+def test_uid_template_problem_names_the_issue(template: str, problem: str) -> None:
+    assert problem in (uid_template_problem(template) or "")
+    assert uid_template_problem("{uuid}@mail.example.com") is None
+
+
+def test_resolve_env_precedence(tmp_path: Path, monkeypatch: pytest.MonkeyPatch) -> None:
+    monkeypatch.delenv("TDO_ENV", raising=False)
+    assert resolve_env(None, tmp_path) == "default"
+    write_default_env("work", tmp_path)
+    assert resolve_env(None, tmp_path) == "work"
+    assert config_file_path(config_home=tmp_path) == tmp_path / "config.work.toml"
+    monkeypatch.setenv("TDO_ENV", "home")
+    assert resolve_env(None, tmp_path) == "home"
+    assert resolve_env("explicit", tmp_path) == "explicit"
+    # The defaults file is not mistaken for an environment
+    assert list_environments(tmp_path) == []
+
+
+def test_cache_retention_settings_round_trip(tmp_path: Path, monkeypatch: pytest.MonkeyPatch) -> None:
+    monkeypatch.delenv("TDO_TRANSACTION_LOG_MAX_AGE_DAYS", raising=False)
+    monkeypatch.delenv("TDO_IDEMPOTENCY_WINDOW_HOURS", raising=False)
+    monkeypatch.delenv("TDO_LOCK_TIMEOUT_SECONDS", raising=False)
+    target = config_file_path("app", config_home=tmp_path)
+    write_config_file(target, CaldavConfig(calendar_url="https://example.com", username="alice"))
+    assert "[cache]" not in target.read_text()
+    assert load_config(env="app", config_home=tmp_path).cache.transaction_log_max_age_days == 0
+
+    custom = CaldavConfig(calendar_url="https://example.com", username="alice")
+    custom.cache.transaction_log_max_age_days = 30
+    custom.cache.idempotency_window_hours = 6
+    custom.cache.lock_timeout_seconds = 30
+    write_config_file(target, custom, force=True)
+    loaded = load_config(env="app", config_home=tmp_path)
+    assert loaded.cache.transaction_log_max_age_days == 30
+    assert loaded.cache.idempotency_window_hours == 6
+    assert loaded.cache.lock_timeout_seconds == 30
+    assert loaded.cache.transaction_log_size == 32
+
+
+def test_validation_settings_round_trip(tmp_path: Path, monkeypatch: pytest.MonkeyPatch) -> None:
+    monkeypatch.delenv("TDO_MAX_PAST_DUE_DAYS", raising=False)
+    monkeypatch.delenv("TDO_ALLOWED_TAGS", raising=False)
+    target = config_file_path("app", config_home=tmp_path)
+    write_config_file(target, CaldavConfig(calendar_url="https://example.com", username="alice"))
+    assert "[validation]" not in target.read_text()
+    assert load_config(env="app", config_home=tmp_path).validation.max_past_due_days == 3650
+
+    custom = CaldavConfig(calendar_url="https://example.com", username="alice")
+    custom.validation.max_past_due_days = 0
+    custom.validation.allowed_tags = ["home", "work"]
+    write_config_file(target, custom, force=True)
+    loaded = load_config(env="app", config_home=tmp_path)
+    assert loaded.validation.max_past_due_days == 0
+    assert loaded.validation.allowed_tags == ["home", "work"]
+    assert loaded.validation.allowed_projects == []
+
+    monkeypatch.setenv("TDO_ALLOWED_PROJECTS", "ops, infra")
+    assert load_config(env="app", config_home=tmp_path).validation.allowed_projects == ["ops", "infra"]
+
+
+def test_next_order_round_trips_and_drops_unknown_keys(tmp_path: Path, monkeypatch: pytest.MonkeyPatch) -> None:
+    monkeypatch.delenv("TDO_NEXT_ORDER", raising=False)
+    target = config_file_path("app", config_home=tmp_path)
+    write_config_file(target, CaldavConfig(calendar_url="https://example.com", username="alice"))
+    assert "[next]" not in target.read_text()
+    assert load_config(env="app", config_home=tmp_path).next.order == ["overdue", "priority", "due", "index"]
+
+    custom = CaldavConfig(calendar_url="https://example.com", username="alice")
+    custom.next.order = ["due", "priority"]
+    write_config_file(target, custom, force=True)
+    assert load_config(env="app", config_home=tmp_path).next.order == ["due", "priority"]
+
+    write_config_file(target, CaldavConfig(calendar_url="https://example.com", username="alice"), force=True)
+    monkeypatch.setenv("TDO_NEXT_ORDER", "Priority, bogus")
+    assert load_config(env="app", config_home=tmp_path).next.order == ["priority"]
+    monkeypatch.setenv("TDO_NEXT_ORDER", "bogus")
+    assert load_config(env="app", config_home=tmp_path).next.order == ["overdue", "priority", "due", "index"]
+
+
+def test_confirm_batch_size_reads_env_override(tmp_path: Path, monkeypatch: pytest.MonkeyPatch) -> None:
+    target = config_file_path("app", config_home=tmp_path)
+    write_config_file(target, CaldavConfig(calendar_url="https://example.com", username="alice"))
+    monkeypatch.delenv("TDO_CONFIRM_BATCH_SIZE", raising=False)
+    assert load_config(env="app", config_home=tmp_path).validation.confirm_batch_size == 20
+
+    monkeypatch.setenv("TDO_CONFIRM_BATCH_SIZE", "5")
+    assert load_config(env="app", config_home=tmp_path).validation.confirm_batch_size == 5
+
+
+def test_routing_rules_round_trip(tmp_path: Path) -> None:
+    target = config_file_path("work", config_home=tmp_path)
+    config = CaldavConfig(calendar_url="https://example.com", username="alice")
+    config.routing = RoutingConfig(tags={"Personal": "home"}, projects={"garden/veg": "home"})
+    write_config_file(target, config)
+    loaded = load_config(env="work", config_home=tmp_path)
+    assert loaded.routing == RoutingConfig(tags={"Personal": "home"}, projects={"garden/veg": "home"})
@@ -4,7 +4,7 @@ from datetime import datetime
 
 import pytest
 
-from tdo.diff import DiffMismatchError, TaskDiff, TaskSetDiff
+from tdo.diff import DiffMismatchError, FieldChange, TaskDiff, TaskSetDiff
 from tdo.models import Task, TaskData
 
 
@@ -341,3 +341,37 @@ class TestTaskSetDiff:
 
         with pytest.raises(DiffMismatchError):
             diff([task], key_fn=lambda t: t.uid)
+
+
+def test_task_diff_lists_tag_changes_with_additions_and_removals() -> None:
+    before = Task(uid="t", data=TaskData(summary="Plan", categories=["work", "later"]))
+    after = Task(uid="t", data=TaskData(summary="Plan", categories=["work", "urgent"]))
+    changes = before.diff(after).changes()
+    assert list(changes) == ["categories"]
+    assert changes["categories"].added == ["urgent"]
+    assert changes["categories"].removed == ["later"]
+    # No categories and an empty list are the same task
+    assert not Task(uid="t", data=TaskData(categories=None)).diff(Task(uid="t", data=TaskData(categories=[]))).changes()
+
+
+def test_task_diff_reports_project_changes_by_property_name() -> None:
+    before = Task(uid="t", data=TaskData(summary="Plan", x_properties={"X-PROJECT": "home"}))
+    after = Task(uid="t", data=TaskData(summary="Plan", priority=2, x_properties={"X-PROJECT": "work"}))
+    changes = before.diff(after).changes()
+    assert changes == {
+        "priority": FieldChange(None, 2),
+        "X-PROJECT": FieldChange("home", "work"),
+    }
+    dropped = after.diff(Task(uid="t", data=TaskData(summary="Plan", priority=2))).changes()
+    assert dropped == {"X-PROJECT": FieldChange("work", None)}
+
+
+def test_task_diff_serializes_like_the_transaction_log() -> None:
+    before = Task(uid="t", data=TaskData(summary="Plan", due=datetime(2025, 1, 2, 9, 0)))
+    after = Task(uid="t", data=TaskData(summary="Plan", due=datetime(2025, 1, 3, 9, 0)))
+    diff = before.diff(after)
+    restored = TaskSetDiff.from_json(TaskSetDiff(diffs={"t": diff}).to_json()).diffs["t"]
+    assert restored == diff
+    assert restored.changes()["due"].to_dict() == {"before": "2025-01-02T09:00:00", "after": "2025-01-03T09:00:00"}
+    # A create lists every field it sets
+    assert list(TaskDiff(pre=None, post=after.data).changes()) == ["summary", "due"]
@@ -0,0 +1,107 @@
+from __future__ import annotations
+
+from datetime import datetime
+
+import pytest
+
+from tdo.codec import (
+    decode_attachments,
+    decode_categories,
+    decode_x_properties,
+    encode_attachments,
+    encode_categories,
+    encode_x_properties,
+)
+from tdo.models import Attachment, Task, TaskData, TaskFilter
+
+
+def _task(uid: str, *, priority: int | None = None, parent: str | None = None) -> Task:
+    return Task(uid=uid, data=TaskData(summary=uid, priority=priority, parent=parent))
+
+
+def test_effective_priority_prefers_own_priority() -> None:
+    parent = _task("parent", priority=1)
+    child = _task("child", priority=5, parent="parent")
+    assert child.effective_priority({"parent": parent, "child": child}) == 5
+
+
+def test_effective_priority_inherits_through_ancestors() -> None:
+    root = _task("root", priority=2)
+    middle = _task("middle", parent="root")
+    leaf = _task("leaf", parent="middle")
+    tasks = {task.uid: task for task in (root, middle, leaf)}
+    assert leaf.effective_priority(tasks) == 2
+    assert leaf.data.priority is None
+
+
+def test_effective_priority_handles_missing_parent_and_cycles() -> None:
+    orphan = _task("orphan", parent="gone")
+    assert orphan.effective_priority({"orphan": orphan}) is None
+    a = _task("a", parent="b")
+    b = _task("b", parent="a")
+    assert a.effective_priority({"a": a, "b": b}) is None
+
+
+def test_estimate_reads_whole_minutes_from_x_property() -> None:
+    assert TaskData(x_properties={"X-ESTIMATE": "30"}).estimate == 30
+    assert TaskData().estimate is None
+    assert TaskData(x_properties={"X-ESTIMATE": "soon"}).estimate is None
+
+
+def test_time_spent_counts_open_interval_only_when_asked() -> None:
+    now = datetime(2026, 1, 1, 12, 0)
+    task = Task(
+        uid="t",
+        data=TaskData(x_properties={"X-TIMESPENT": "120", "X-STARTED-AT": str(int(now.timestamp()) - 30)}),
+    )
+    assert task.time_spent() == 120
+    assert task.time_spent(now) == 150
+    assert Task(uid="u", data=TaskData()).time_spent(now) == 0
+
+
+def test_due_window_end_counts_days_and_aligns_to_local_day() -> None:
+    now = datetime(2026, 1, 1, 9, 30)
+    assert TaskFilter().due_window_end(now) is None
+    assert TaskFilter(due_within_days=3).due_window_end(now) == datetime(2026, 1, 4, 9, 30)
+    aligned = TaskFilter(due_within_days=3, align_to_day=True).due_window_end(now)
+    assert aligned == datetime(2026, 1, 4, 23, 59, 59, 999999)
+    assert TaskFilter(due_within_days=0).has_metadata
+
+
+def test_x_property_helpers_round_trip() -> None:
+    data = TaskData(summary="t", x_properties={"X-PROJECT": "work", "X-EMPTY": ""})
+    assert data.project == "work"
+    assert data.x_property("X-EMPTY") is None
+    assert data.x_property("X-MISSING") is None
+
+    updated = data.with_x_property("X-PROJECT", "home").with_x_property("X-EMPTY", None)
+    assert updated.x_properties == {"X-PROJECT": "home"}
+    # The original is left alone
+    assert data.x_properties == {"X-PROJECT": "work", "X-EMPTY": ""}
+    assert updated.with_x_property("X-PROJECT", None).project is None
+
+
+def test_tag_helpers_treat_unset_as_empty() -> None:
+    assert TaskData().tags == []
+    data = TaskData(categories=["a"]).with_tags(["b", "a", "b"])
+    assert data.categories == ["b", "a"]
+    data.tags.append("c")
+    assert data.categories == ["b", "a"]
+
+
+def test_codec_round_trips_json_columns() -> None:
+    attachments = [Attachment(uri="https://example.com/a"), Attachment(uri="file.pdf", fmttype="application/pdf")]
+    assert decode_categories(encode_categories(["a", "b"])) == ["a", "b"]
+    assert decode_x_properties(encode_x_properties({"X-PROJECT": "work"})) == {"X-PROJECT": "work"}
+    assert decode_attachments(encode_attachments(attachments)) == attachments
+    assert decode_categories(encode_categories(None)) == []
+    assert decode_x_properties("not json") == {}
+    assert decode_attachments('{"uri": "x"}') == []
+
+
+@pytest.mark.parametrize(
+    "priority,letter",
+    [(None, None), (0, None), (1, "H"), (3, "H"), (4, "M"), (6, "M"), (7, "L"), (9, "L"), (10, None)],
+)
+def test_priority_letter_thresholds(priority: int | None, letter: str | None) -> None:
+    assert TaskData(priority=priority).priority_letter == letter
@@ -1,11 +1,28 @@
 from __future__ import annotations
 
+import json
+import os
+import time
+from dataclasses import replace
+from datetime import datetime, timedelta
 from pathlib import Path
 
 import pytest
 
-from tdo.models import Task, TaskData
-from tdo.sqlite_cache import SqliteTaskCache
+from tdo.models import Attachment, Task, TaskData, TaskFilter
+from tdo.diff import TaskDiff, TaskSetDiff
+from tdo.sqlite_cache import (
+    SCHEMA_VERSION,
+    CacheUnavailableError,
+    EnvironmentBusyError,
+    IntegrityProblem,
+    ReadOnlyCacheError,
+    SqliteTaskCache,
+    TaskRef,
+    TransactionLogRetention,
+    environment_lock,
+)
+from tdo.task_query import TaskQuery
 
 
 @pytest.mark.asyncio
@@ -22,3 +39,1286 @@ async def test_sqlite_cache_preserves_pending(tmp_path: Path) -> None:
         assert len(dirty) == 1 and dirty[0].task.uid == "pending"
     finally:
         await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_archive_moves_synced_completed_tasks(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        done = Task(uid="done", data=TaskData(summary="Done", status="COMPLETED"))
+        await cache.replace_remote_tasks([done])
+        local = Task(uid="local", data=TaskData(summary="Local"))
+        await cache.upsert_task(local, pending_action="create")
+        await cache.assign_index("local")
+        await cache.complete_task("local")
+
+        archived = await cache.archive_completed_tasks(completed_before=time.time() + 1)
+        assert [task.uid for task in archived] == ["done"]
+        assert [task.uid for task in await cache.list_completed_tasks()] == ["local"]
+        assert [task.uid for task in await cache.list_archived_tasks()] == ["done"]
+
+        # A later pull must not resurrect the archived task in completed_tasks
+        await cache.replace_remote_tasks([done])
+        assert [task.uid for task in await cache.list_completed_tasks()] == ["local"]
+        assert [task.uid for task in await cache.list_archived_tasks()] == ["done"]
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_completion_note_travels_with_completed_task(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        task = Task(uid="t", data=TaskData(summary="T"))
+        await cache.upsert_task(task, pending_action="create")
+        await cache.assign_index("t")
+        await cache.complete_task("t", note="shipped in 1.2")
+        [completed] = await cache.list_completed_tasks()
+        assert completed.completion_note == "shipped in 1.2"
+
+        # The note is local, so a pull of the synced task keeps it
+        assert cache._conn is not None
+        await cache._conn.execute("UPDATE completed_tasks SET pending_action = NULL")
+        await cache._conn.commit()
+        await cache.replace_remote_tasks([Task(uid="t", data=TaskData(summary="T", status="COMPLETED"))])
+        [completed] = await cache.list_completed_tasks()
+        assert completed.completion_note == "shipped in 1.2"
+
+        await cache.archive_completed_tasks(uids=["t"])
+        [archived] = await cache.list_archived_tasks()
+        assert archived.completion_note == "shipped in 1.2"
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_count_tags_and_projects_excludes_completed_by_default(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        await cache.replace_remote_tasks(
+            [
+                Task(uid="a", data=TaskData(summary="A", categories=["work", "easy"], x_properties={"X-PROJECT": "tdo"})),
+                Task(uid="b", data=TaskData(summary="B", categories=["work"])),
+                Task(
+                    uid="c",
+                    data=TaskData(summary="C", status="COMPLETED", categories=["work"], x_properties={"X-PROJECT": "tdo"}),
+                ),
+            ]
+        )
+        counts = await cache.count_tags_and_projects()
+        assert counts == {"tags": {"work": 2, "easy": 1}, "projects": {"tdo": 1}}
+        counts = await cache.count_tags_and_projects(include_completed=True)
+        assert counts == {"tags": {"work": 3, "easy": 1}, "projects": {"tdo": 2}}
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_sum_time_spent_tallies_tracked_seconds(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        now = datetime(2026, 5, 1, 12, 0)
+        started = int(now.timestamp()) - 600
+        await cache.replace_remote_tasks(
+            [
+                Task(
+                    uid="a",
+                    data=TaskData(
+                        summary="A",
+                        categories=["work"],
+                        x_properties={"X-PROJECT": "tdo", "X-TIMESPENT": "1200"},
+                    ),
+                ),
+                Task(
+                    uid="b",
+                    data=TaskData(
+                        summary="B",
+                        status="IN-PROCESS",
+                        categories=["work", "deep"],
+                        x_properties={"X-TIMESPENT": "60", "X-STARTED-AT": str(started)},
+                    ),
+                ),
+                Task(uid="c", data=TaskData(summary="C", categories=["work"])),
+                Task(
+                    uid="d",
+                    data=TaskData(
+                        summary="D",
+                        status="COMPLETED",
+                        x_properties={"X-PROJECT": "tdo", "X-TIMESPENT": "3000"},
+                    ),
+                ),
+            ]
+        )
+        spent = await cache.sum_time_spent(now=now)
+        assert spent.tags == {"work": 1860, "deep": 660}
+        assert spent.projects == {"tdo": 1200}
+        assert spent.total == 1860
+        spent = await cache.sum_time_spent(include_completed=True, now=now)
+        assert spent.projects == {"tdo": 4200}
+        assert spent.total == 4860
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_tag_and_project_recency_keeps_latest_update(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        await cache.replace_remote_tasks(
+            [
+                Task(uid="a", data=TaskData(summary="A", categories=["work"], x_properties={"X-PROJECT": "tdo"})),
+                Task(uid="b", data=TaskData(summary="B", categories=["work", "home"])),
+            ]
+        )
+        assert cache._conn is not None
+        for uid, updated_at in (("a", 100.0), ("b", 200.0)):
+            await cache._conn.execute("UPDATE tasks SET updated_at = ? WHERE uid = ?", (updated_at, uid))
+        await cache._conn.commit()
+        assert await cache.tag_and_project_recency() == {
+            "tags": {"work": 200.0, "home": 200.0},
+            "projects": {"tdo": 100.0},
+        }
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_resolve_refs_spans_active_and_completed_tasks(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        await cache.upsert_task(Task(uid="open", data=TaskData(summary="Open")), task_index=1)
+        await cache.upsert_task(Task(uid="milk", data=TaskData(summary="Buy milk")), task_index=2)
+        await cache.complete_task("milk")
+        refs = await cache.resolve_refs(["open", "milk", "missing", "open"])
+        assert refs == {
+            "open": TaskRef(uid="open", index=1, summary="Open", done=False),
+            "milk": TaskRef(uid="milk", index=2, summary="Buy milk", done=True),
+        }
+        assert await cache.resolve_refs([]) == {}
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_list_timeline_interleaves_active_and_completed(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        for uid in ("early", "done", "late", "other"):
+            await cache.upsert_task(Task(uid=uid, data=TaskData(summary=uid, categories=["work"] if uid != "other" else [])))
+        await cache.complete_task("done")
+        assert cache._conn is not None
+        await cache._conn.execute("UPDATE tasks SET updated_at = 100 WHERE uid = 'early'")
+        await cache._conn.execute("UPDATE tasks SET updated_at = 300 WHERE uid = 'late'")
+        await cache._conn.execute("UPDATE tasks SET updated_at = 400 WHERE uid = 'other'")
+        await cache._conn.execute("UPDATE completed_tasks SET completed_at = 200 WHERE uid = 'done'")
+        await cache._conn.commit()
+        timeline = await cache.list_timeline(task_filter=TaskFilter(tags=["work"]))
+        assert [(entry.task.uid, entry.state, entry.at) for entry in timeline] == [
+            ("early", "active", 100),
+            ("done", "completed", 200),
+            ("late", "active", 300),
+        ]
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_list_timeline_order_survives_a_pull(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        remote = [Task(uid=uid, data=TaskData(summary=uid), etag='"1"') for uid in ("first", "second", "open")]
+        await cache.replace_remote_tasks(remote)
+        await cache.complete_task("first", completed_at=100)
+        await cache.complete_task("second", completed_at=300)
+        assert cache._conn is not None
+        await cache._conn.execute("UPDATE tasks SET updated_at = 200 WHERE uid = 'open'")
+        await cache._conn.execute("UPDATE completed_tasks SET pending_action = NULL")
+        await cache._conn.commit()
+
+        await cache.replace_remote_tasks([
+            replace(remote[0], data=TaskData(summary="first", status="COMPLETED")),
+            replace(remote[1], data=TaskData(summary="second", status="COMPLETED")),
+            remote[2],
+        ])
+        timeline = await cache.list_timeline()
+        assert [(entry.task.uid, entry.at) for entry in timeline] == [("first", 100), ("open", 200), ("second", 300)]
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_rows_map_to_tasks_by_column_name(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        task = Task(
+            uid="named",
+            data=TaskData(
+                summary="Named",
+                due=datetime(2030, 1, 2, 3, 4),
+                priority=2,
+                categories=["work"],
+                x_properties={"X-PROJECT": "tdo"},
+                url="https://example.com",
+                parent="root",
+            ),
+        )
+        await cache.upsert_task(task, task_index=4)
+        await cache.upsert_task(replace(task, uid="finished"), task_index=5)
+        await cache.complete_task("finished", note="shipped")
+        assert cache._conn is not None
+        for table, build, uid in (
+            ("tasks", cache._build_task, "named"),
+            ("completed_tasks", cache._build_completed_task, "finished"),
+        ):
+            async with cache._conn.execute(f"PRAGMA table_info({table})") as cursor:
+                columns = [row["name"] for row in await cursor.fetchall()]
+            async with cache._conn.execute(f"SELECT * FROM {table} WHERE uid = ?", (uid,)) as cursor:
+                expected = build(await cursor.fetchone())
+            async with cache._conn.execute(
+                f"SELECT {', '.join(reversed(columns))} FROM {table} WHERE uid = ?", (uid,)
+            ) as cursor:
+                assert build(await cursor.fetchone()) == expected
+        assert expected.completion_note == "shipped"
+        assert expected.data.summary == "Named" and expected.task_index == 5
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_complete_and_delete_survive_added_columns(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        assert cache._conn is not None
+        for table in ("tasks", "completed_tasks", "deleted_tasks"):
+            await cache._conn.execute(f"ALTER TABLE {table} ADD COLUMN added_later TEXT DEFAULT 'x'")
+        await cache._conn.commit()
+        attachment = Attachment(uri="https://example.com/spec.pdf", fmttype="application/pdf")
+        for index, uid in enumerate(("done", "gone"), 1):
+            data = TaskData(summary=uid, priority=3, url=f"https://example.com/{uid}", attachments=[attachment])
+            await cache.upsert_task(Task(uid=uid, data=data), task_index=index)
+
+        await cache.complete_task("done")
+        completed = await cache.get_completed_task("done")
+        assert completed is not None
+        assert (completed.data.summary, completed.data.status, completed.task_index) == ("done", "COMPLETED", 1)
+        assert completed.data.url == "https://example.com/done"
+        assert completed.data.attachments == [attachment]
+
+        await cache.mark_for_deletion("gone")
+        deleted = await cache.get_deleted_task("gone")
+        assert deleted is not None
+        assert (deleted.data.summary, deleted.data.priority, deleted.task_index) == ("gone", 3, 2)
+        assert deleted.data.url == "https://example.com/gone"
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_search_deleted_tasks_ranks_and_keeps_deleted_at(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        for uid, summary in (("a", "Buy milk"), ("b", "Milk"), ("c", "Walk dog"), ("d", "Milk run")):
+            await cache.upsert_task(Task(uid=uid, data=TaskData(summary=summary)))
+        for uid in ("a", "b", "c"):
+            assert await cache.mark_for_deletion(uid)
+        matches = await cache.search_deleted_tasks("milk")
+        assert [match.task.uid for match in matches] == ["b", "a"]
+        assert all(match.deleted_at > 0 for match in matches)
+        assert [match.task.uid for match in await cache.search_deleted_tasks("milk", limit=1)] == ["b"]
+        assert await cache.search_tasks("milk") == [await cache.get_task("d")]
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_search_archived_tasks_finds_archived_matches(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        await cache.replace_remote_tasks(
+            [
+                Task(uid=uid, data=TaskData(summary=summary, status="COMPLETED"))
+                for uid, summary in (("a", "Buy milk"), ("b", "Milk"), ("c", "Milk run"))
+            ]
+        )
+        assert {task.uid for task in await cache.archive_completed_tasks(uids=["a", "b"])} == {"a", "b"}
+        matches = await cache.search_archived_tasks("milk")
+        assert [match.task.uid for match in matches] == ["b", "a"]
+        assert all(match.archived_at > 0 for match in matches)
+        assert matches[0].task.data.status == "COMPLETED"
+        assert [match.task.uid for match in await cache.search_archived_tasks("milk", limit=1)] == ["b"]
+        assert await cache.search_archived_tasks("run") == []
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_complete_and_delete_accept_backdated_times(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        for uid in ("done", "gone"):
+            await cache.upsert_task(Task(uid=uid, data=TaskData(summary=uid)))
+        await cache.complete_task("done", completed_at=1_700_000_000.0)
+        await cache.mark_for_deletion("gone", deleted_at=1_600_000_000.0)
+        assert await cache.count_completed_by_day(0) == {
+            datetime.fromtimestamp(1_700_000_000).date().isoformat(): 1
+        }
+        assert [match.deleted_at for match in await cache.search_deleted_tasks("gone")] == [1_600_000_000.0]
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_count_completed_by_day_groups_by_local_day(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        for uid in ("today", "also-today", "earlier", "too-old"):
+            await cache.upsert_task(Task(uid=uid, data=TaskData(summary=uid)))
+            await cache.complete_task(uid)
+        now = datetime.now()
+        assert cache._conn is not None
+        for uid, days_ago in (("earlier", 2), ("too-old", 10)):
+            await cache._conn.execute(
+                "UPDATE completed_tasks SET completed_at = ? WHERE uid = ?",
+                ((now - timedelta(days=days_ago)).timestamp(), uid),
+            )
+        await cache._conn.commit()
+        since = (now - timedelta(days=5)).timestamp()
+        assert await cache.count_completed_by_day(since) == {
+            now.date().isoformat(): 2,
+            (now - timedelta(days=2)).date().isoformat(): 1,
+        }
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_unusable_cache_directory_raises_actionable_error(
+    tmp_path: Path, monkeypatch: pytest.MonkeyPatch
+) -> None:
+    blocker = tmp_path / "blocker"
+    blocker.write_text("not a directory")
+    with pytest.raises(CacheUnavailableError, match="TDO_TASK_CACHE_FILE") as excinfo:
+        await SqliteTaskCache.create(blocker / "tdo" / "tasks.db")
+    assert excinfo.value.path == blocker / "tdo" / "tasks.db"
+
+    monkeypatch.setattr(os, "access", lambda path, mode: False)
+    with pytest.raises(CacheUnavailableError, match="is not writable"):
+        await SqliteTaskCache.create(tmp_path / "nested" / "tasks.db")
+    assert (tmp_path / "nested").is_dir()
+
+
+@pytest.mark.asyncio
+async def test_iter_tasks_for_export_yields_active_then_completed(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        await cache.replace_remote_tasks(
+            [
+                Task(uid="open", data=TaskData(summary="Open", categories=["work"])),
+                Task(uid="other", data=TaskData(summary="Other")),
+                Task(uid="done", data=TaskData(summary="Done", categories=["work"])),
+            ]
+        )
+        await cache.complete_task("done")
+        rows = [(state, task.uid) async for state, task in cache.iter_tasks_for_export()]
+        assert sorted(rows[:2]) == [("active", "open"), ("active", "other")]
+        assert rows[2:] == [("completed", "done")]
+        work = TaskFilter(tags=["work"])
+        assert [task.uid async for _, task in cache.iter_tasks_for_export(task_filter=work)] == ["open", "done"]
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_recompute_utc_dates_rewrites_only_stale_rows(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        due = datetime(2026, 3, 1, 9, 0)
+        await cache.replace_remote_tasks(
+            [
+                Task(uid="shifted", data=TaskData(summary="Shifted", due=due, wait=due - timedelta(days=1))),
+                Task(uid="fine", data=TaskData(summary="Fine", due=due)),
+                Task(uid="undated", data=TaskData(summary="Undated")),
+            ]
+        )
+        # As if written under another timezone (or by a parser that assumed UTC)
+        await cache._conn.execute("UPDATE tasks SET due_utc = due_utc + 3600 WHERE uid = 'shifted'")
+        await cache._conn.commit()
+
+        assert await cache.recompute_utc_dates(dry_run=True) == 1
+        assert await cache.recompute_utc_dates() == 1
+        async with cache._conn.execute("SELECT due_utc, wait_utc FROM tasks WHERE uid = 'shifted'") as cursor:
+            assert tuple(await cursor.fetchone()) == (due.timestamp(), (due - timedelta(days=1)).timestamp())
+        assert await cache.recompute_utc_dates() == 0
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_recompute_utc_dates_covers_archived_and_keeps_unparseable(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        due = datetime(2026, 3, 1, 9, 0)
+        await cache.replace_remote_tasks(
+            [
+                Task(uid="kept", data=TaskData(summary="Kept", status="COMPLETED", due=due)),
+                Task(uid="garbled", data=TaskData(summary="Garbled", due=due)),
+            ]
+        )
+        await cache.archive_completed_tasks(uids=["kept"])
+        assert cache._conn is not None
+        await cache._conn.execute("UPDATE archived_tasks SET due_utc = due_utc + 3600")
+        await cache._conn.execute("UPDATE tasks SET due = 'sometime' WHERE uid = 'garbled'")
+        await cache._conn.commit()
+
+        assert await cache.recompute_utc_dates() == 1
+        async with cache._conn.execute("SELECT due_utc FROM archived_tasks") as cursor:
+            assert (await cursor.fetchone())[0] == due.timestamp()
+        async with cache._conn.execute("SELECT due_utc FROM tasks WHERE uid = 'garbled'") as cursor:
+            assert (await cursor.fetchone())[0] == due.timestamp()
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_forget_tasks_erases_rows_and_scrubs_the_log(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        await cache.replace_remote_tasks(
+            [
+                Task(uid="a", data=TaskData(summary="Secret a"), href="/cal/a.ics"),
+                Task(uid="b", data=TaskData(summary="Secret a, the sequel"), href="/cal/b.ics"),
+            ]
+        )
+        await cache.mark_for_deletion("a")
+        both = TaskSetDiff(diffs={uid: TaskDiff(pre=TaskData(summary=uid), post=None) for uid in ("a", "b")})
+        only_a = TaskSetDiff(diffs={"a": TaskDiff(pre=None, post=TaskData(summary="a"))})
+        only_b = TaskSetDiff(diffs={"b": TaskDiff(pre=TaskData(summary="b"), post=TaskData(summary="b2"))})
+        assert await cache.claim_idempotency_key("k1", "[]") is None
+        await cache.log_transaction(both, operation="delete")
+        await cache.log_transaction(only_a, operation="add")
+        await cache.record_idempotent_result("k1", "[]", "Deleted (1):\n  - [1] Secret a\n")
+        # Its output mentions "Secret a", but the run only touched b
+        assert await cache.claim_idempotency_key("k2", "[]") is None
+        await cache.log_transaction(only_b, operation="modify")
+        await cache.record_idempotent_result("k2", "[]", "Modified (1):\n  - [2] Secret a, the sequel\n")
+
+        [report] = await cache.forget_tasks(["a"])
+        assert report.tables == ["deleted_tasks"]
+        assert (report.summary, report.log_entries, report.idempotency_keys) == ("Secret a", 2, 1)
+        assert await cache.get_deleted_task("a") is None
+        entry = await cache.pop_transaction()
+        assert entry is not None and entry.operation == "modify"
+        entry = await cache.pop_transaction()
+        assert entry is not None and entry.operation == "delete"
+        assert list(json.loads(entry.diff_json)) == ["b"]
+        assert await cache.pop_transaction() is None
+        assert await cache.get_idempotent_result("k1") is None
+        assert await cache.get_idempotent_result("k2") is not None
+        assert await cache.get_task("b") is not None
+
+        assert (await cache.forget_tasks(["missing"]))[0].tables == []
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_environment_lock_excludes_a_second_holder(tmp_path: Path) -> None:
+    path = SqliteTaskCache.lock_path(tmp_path / "tasks.db")
+    assert path == tmp_path / "tasks.db.lock"
+    async with environment_lock(path, 1):
+        with pytest.raises(EnvironmentBusyError, match="environment busy"):
+            async with environment_lock(path, 0.1):
+                pass
+    # Released on exit, so the next writer gets in
+    async with environment_lock(path, 0):
+        pass
+
+
+@pytest.mark.asyncio
+async def test_info_reports_path_schema_and_counts(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        await cache.replace_remote_tasks(
+            [Task(uid=uid, data=TaskData(summary=uid)) for uid in ("a", "b", "c")]
+        )
+        await cache.complete_task("a")
+        await cache.mark_for_deletion("b")
+        info = await cache.info()
+        assert info.path == (tmp_path / "cache.db").resolve()
+        assert info.schema_version == SCHEMA_VERSION
+        assert info.size_bytes > 0
+        assert (info.active, info.completed, info.deleted, info.archived) == (1, 1, 1, 0)
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_check_integrity_reports_inconsistent_rows(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        for uid in ("ok", "bad"):
+            await cache.upsert_task(Task(uid=uid, data=TaskData(summary=uid)), pending_action="create")
+            await cache.assign_index(uid)
+        assert await cache.check_integrity() == []
+
+        await cache.upsert_task(
+            Task(uid="child", data=TaskData(summary="child", parent="gone")), pending_action="create"
+        )
+        assert cache._conn is not None
+        await cache._conn.execute(
+            "UPDATE tasks SET status = 'COMPLETED', categories = 'not json', x_properties = '[]' WHERE uid = 'bad'"
+        )
+        await cache._conn.commit()
+        problems = await cache.check_integrity()
+        assert {(p.check, p.table, p.uid) for p in problems} == {
+            ("missing_index", "tasks", "child"),
+            ("completed_in_active", "tasks", "bad"),
+            ("malformed_json", "tasks", "bad"),
+            ("orphaned_parent", "tasks", "child"),
+        }
+        assert IntegrityProblem(
+            "malformed_json", "tasks", "bad", "x_properties is not a JSON object: '[]'"
+        ) in problems
+        # Read-only: nothing was fixed
+        assert len(await cache.check_integrity()) == len(problems)
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_repair_fixes_problems_and_dry_run_changes_nothing(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        for uid in ("ok", "bad"):
+            await cache.upsert_task(Task(uid=uid, data=TaskData(summary=uid)), pending_action="create")
+            await cache.assign_index(uid)
+        await cache.upsert_task(
+            Task(uid="child", data=TaskData(summary="child", parent="gone")), pending_action="create"
+        )
+        assert cache._conn is not None
+        await cache._conn.execute(
+            "UPDATE tasks SET categories = 'home, errands', x_properties = '[1]' WHERE uid = 'ok'"
+        )
+        await cache._conn.execute("UPDATE tasks SET status = 'COMPLETED' WHERE uid = 'bad'")
+        await cache._conn.commit()
+        before = await cache.check_integrity()
+
+        planned = await cache.repair(dry_run=True)
+        assert await cache.check_integrity() == before
+
+        fixes = await cache.repair()
+        assert fixes == planned
+        assert {(fix.check, fix.uid, fix.detail) for fix in fixes} == {
+            ("malformed_json", "ok", 'categories rewritten as ["home", "errands"]'),
+            ("malformed_json", "ok", "x_properties rewritten as {}"),
+            ("completed_in_active", "bad", "moved to completed_tasks"),
+            ("orphaned_parent", "child", "parent gone cleared"),
+            ("missing_index", "child", "assigned index 2"),
+        }
+        assert await cache.check_integrity() == []
+        assert [task.uid for task in await cache.list_completed_tasks()] == ["bad"]
+        ok = await cache.get_task("ok")
+        assert ok is not None and ok.data.categories == ["home", "errands"]
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_list_stale_tasks_reports_synced_tasks_older_than_threshold(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        await cache.replace_remote_tasks(
+            [
+                Task(uid="active", data=TaskData(summary="Active"), href="/cal/active.ics"),
+                Task(uid="done", data=TaskData(summary="Done", status="COMPLETED"), href="/cal/done.ics"),
+            ]
+        )
+        await cache.upsert_task(Task(uid="local", data=TaskData(summary="Local")), pending_action="create")
+
+        assert await cache.list_stale_tasks(synced_before=0) == []
+        stale = await cache.list_stale_tasks(synced_before=time.time() + 1)
+        assert {(entry.task.uid, entry.completed) for entry in stale} == {("active", False), ("done", True)}
+        assert all(entry.last_synced is not None for entry in stale)
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_tag_filter_matches_all_or_any_tags(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        await cache.replace_remote_tasks(
+            [
+                Task(uid="both", data=TaskData(summary="Both", categories=["work", "urgent"])),
+                Task(uid="work", data=TaskData(summary="Work", categories=["work"])),
+                Task(uid="home", data=TaskData(summary="Home", categories=["home"])),
+            ]
+        )
+        every = await cache.list_tasks_filtered(TaskFilter(tags=["work", "urgent"]))
+        assert [task.uid for task in every] == ["both"]
+        some = await cache.list_tasks_filtered(TaskFilter(tags=["work", "urgent"], tags_mode="any"))
+        assert {task.uid for task in some} == {"both", "work"}
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_tag_filters_match_nested_tags_by_prefix(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        await cache.replace_remote_tasks(
+            [
+                Task(uid="gym", data=TaskData(summary="Gym", categories=["area::health::gym"])),
+                Task(uid="health", data=TaskData(summary="Health", categories=["area::health"])),
+                Task(uid="care", data=TaskData(summary="Care", categories=["area::healthcare"])),
+                Task(uid="other", data=TaskData(summary="Other", categories=["other"])),
+            ]
+        )
+        nested = await cache.list_tasks_filtered(TaskFilter(tags=["area::health"]))
+        assert {task.uid for task in nested} == {"gym", "health"}
+        exact = await cache.list_tasks_filtered(TaskFilter(tags=["area::health"], tags_match="exact"))
+        assert [task.uid for task in exact] == ["health"]
+        area = await cache.list_tasks_filtered(TaskFilter(tags=["other", "area"], tags_mode="any"))
+        assert {task.uid for task in area} == {"gym", "health", "care", "other"}
+        rest = await cache.list_tasks_filtered(TaskFilter(exclude_tags=["area::health"]))
+        assert {task.uid for task in rest} == {"care", "other"}
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_exclusion_filters_win_over_matches(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        await cache.replace_remote_tasks(
+            [
+                Task(uid="ready", data=TaskData(summary="Ready", categories=["work"])),
+                Task(uid="blocked", data=TaskData(summary="Blocked", categories=["work", "waiting"])),
+                Task(uid="garden", data=TaskData(summary="Garden", categories=["work"], x_properties={"X-PROJECT": "home"})),
+            ]
+        )
+        matched = await cache.list_tasks_filtered(TaskFilter(tags=["work"], exclude_tags=["waiting"]))
+        assert {task.uid for task in matched} == {"ready", "garden"}
+        matched = await cache.list_tasks_filtered(
+            TaskFilter(tags=["work", "waiting"], tags_mode="any", exclude_tags=["waiting"], exclude_projects=["home"])
+        )
+        assert [task.uid for task in matched] == ["ready"]
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_has_filters_check_url_and_attachments(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        await cache.replace_remote_tasks(
+            [
+                Task(uid="link", data=TaskData(summary="Link", url="https://example.com")),
+                Task(uid="file", data=TaskData(summary="File", attachments=[Attachment(uri="https://example.com/a.pdf")])),
+                Task(uid="bare", data=TaskData(summary="Bare")),
+            ]
+        )
+        assert cache._conn is not None
+        await cache._conn.execute("UPDATE tasks SET attachments = 'not json' WHERE uid = 'bare'")
+        await cache._conn.commit()
+        with_url = await cache.list_tasks_filtered(TaskFilter(has_url=True))
+        assert [task.uid for task in with_url] == ["link"]
+        with_files = await cache.list_tasks_filtered(TaskFilter(has_attachments=True))
+        assert [task.uid for task in with_files] == ["file"]
+        neither = await cache.list_tasks_filtered(TaskFilter(has_url=False, has_attachments=False))
+        assert [task.uid for task in neither] == ["bare"]
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_due_within_days_keeps_overdue_and_drops_undated(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        now = datetime.now().astimezone()
+        await cache.replace_remote_tasks(
+            [
+                Task(uid="overdue", data=TaskData(summary="Overdue", due=now - timedelta(days=5))),
+                Task(uid="soon", data=TaskData(summary="Soon", due=now + timedelta(days=2))),
+                Task(uid="later", data=TaskData(summary="Later", due=now + timedelta(days=10))),
+                Task(uid="undated", data=TaskData(summary="Undated")),
+            ]
+        )
+        matched = await cache.list_tasks_filtered(TaskFilter(due_within_days=3))
+        assert sorted(task.uid for task in matched) == ["overdue", "soon"]
+        nothing_ahead = await cache.list_tasks_filtered(TaskFilter(due_within_days=0))
+        assert [task.uid for task in nothing_ahead] == ["overdue"]
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_custom_status_filter_is_independent_of_status(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        waiting = {"X-CUSTOM-STATUS": "waiting-on-reply"}
+        await cache.replace_remote_tasks(
+            [
+                Task(uid="open", data=TaskData(summary="Open", status="NEEDS-ACTION", x_properties=waiting)),
+                Task(uid="started", data=TaskData(summary="Started", status="IN-PROCESS", x_properties=waiting)),
+                Task(uid="plain", data=TaskData(summary="Plain", status="NEEDS-ACTION")),
+            ]
+        )
+        matched = await cache.list_tasks_filtered(TaskFilter(custom_status="waiting-on-reply"))
+        assert sorted(task.uid for task in matched) == ["open", "started"]
+        assert all(task.custom_status == "waiting-on-reply" for task in matched)
+        both = await cache.list_tasks_filtered(TaskFilter(status="IN-PROCESS", custom_status="waiting-on-reply"))
+        assert [task.uid for task in both] == ["started"]
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_filter_values_are_bound_not_interpolated(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        await cache.replace_remote_tasks(
+            [
+                Task(uid="a", data=TaskData(summary="A", categories=["work"], x_properties={"X-PROJECT": "o'brien"})),
+                Task(uid="b", data=TaskData(summary="B", categories=["home"])),
+            ]
+        )
+        for tag in ['work" OR 1=1 --', "%", "wor_", "'); DROP TABLE tasks; --"]:
+            assert await cache.list_tasks_filtered(TaskFilter(tags=[tag])) == []
+        matched = await cache.list_active_tasks(task_filter=TaskFilter(project="o'brien", tags=["work"]))
+        assert [task.uid for task in matched] == ["a"]
+        assert {task.uid for task in await cache.list_tasks()} == {"a", "b"}
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_activate_waited_tasks_clears_passed_waits(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        await cache.replace_remote_tasks(
+            [
+                Task(uid="synced", data=TaskData(summary="Synced", wait=datetime(2000, 1, 1))),
+                Task(uid="future", data=TaskData(summary="Future", wait=datetime(2999, 1, 1))),
+            ]
+        )
+        await cache.upsert_task(
+            Task(uid="local", data=TaskData(summary="Local", wait=datetime(2000, 1, 2))),
+            pending_action="create",
+        )
+
+        activated = await cache.activate_waited_tasks()
+        assert [task.uid for task in activated] == ["synced", "local"]
+        assert activated[0].data.wait == datetime(2000, 1, 1)
+        assert await cache.get_pending_action("synced") == "update"
+        assert await cache.get_pending_action("local") == "create"
+        assert await cache.get_pending_action("future") is None
+        synced = await cache.get_task("synced")
+        assert synced is not None and synced.data.wait is None
+        assert await cache.activate_waited_tasks() == []
+    finally:
+        await cache.close()
+
+
+def _log_diff(uid: str) -> TaskSetDiff[str]:
+    return TaskSetDiff(diffs={uid: TaskDiff(pre=None, post=TaskData(summary=uid))})
+
+
+@pytest.mark.asyncio
+async def test_log_transaction_enforces_retention_policy(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(
+        tmp_path / "cache.db",
+        retention=TransactionLogRetention(max_entries=3, max_age_days=7),
+    )
+    try:
+        for uid in ("a", "b", "c"):
+            assert await cache.log_transaction(_log_diff(uid), operation="add") == 0
+        # Count limit comes from the policy
+        assert await cache.log_transaction(_log_diff("d"), operation="add") == 1
+        assert [entry.operation for entry in await cache.get_transaction_log()] == ["add"] * 3
+
+        # Entries past max_age_days are trimmed on the next write
+        assert cache._conn is not None
+        await cache._conn.execute(
+            "UPDATE transaction_log SET created_at = ? WHERE id = (SELECT MIN(id) FROM transaction_log)",
+            (time.time() - 8 * 86400,),
+        )
+        await cache._conn.commit()
+        assert await cache.log_transaction(_log_diff("e"), operation="modify") == 1
+        assert len(await cache.get_transaction_log()) == 3
+
+        # An explicit max_entries overrides the policy
+        assert await cache.log_transaction(_log_diff("f"), max_entries=1) == 3
+        assert len(await cache.get_transaction_log()) == 1
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_idempotency_key_is_claimed_once(tmp_path: Path) -> None:
+    path = tmp_path / "cache.db"
+    first = await SqliteTaskCache.create(path)
+    second = await SqliteTaskCache.create(path)
+    try:
+        assert await first.claim_idempotency_key("k1", '["add", "a"]') is None
+        # Another process racing on the key sees the claim instead of running
+        claim = await second.claim_idempotency_key("k1", '["add", "a"]')
+        assert claim is not None and claim.pending
+
+        await first.record_idempotent_result("k1", '["add", "a"]', "Created\n")
+        done = await second.claim_idempotency_key("k1", '["add", "a"]')
+        assert done is not None and not done.pending
+        assert done.result == "Created\n"
+
+        # A released claim can be taken again; a settled key cannot be released
+        assert await first.claim_idempotency_key("k2", '["do", "1"]') is None
+        await first.release_idempotency_key("k2")
+        assert await second.claim_idempotency_key("k2", '["do", "1"]') is None
+        await first.release_idempotency_key("k1")
+        assert await first.get_idempotent_result("k1") is not None
+    finally:
+        await first.close()
+        await second.close()
+
+
+@pytest.mark.asyncio
+async def test_idempotency_keys_expire_after_window(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(
+        tmp_path / "cache.db",
+        retention=TransactionLogRetention(idempotency_window_hours=1),
+    )
+    try:
+        assert await cache.get_idempotent_result("k1") is None
+        assert await cache.record_idempotent_result("k1", '["add", "a"]', "Created\n") == 0
+        stored = await cache.get_idempotent_result("k1")
+        assert stored is not None
+        assert (stored.command, stored.result) == ('["add", "a"]', "Created\n")
+
+        assert cache._conn is not None
+        await cache._conn.execute("UPDATE idempotency_keys SET created_at = ?", (time.time() - 2 * 3600,))
+        await cache._conn.commit()
+        assert await cache.get_idempotent_result("k1") is None
+        # Expired keys are pruned on the next write
+        assert await cache.record_idempotent_result("k2", '["do", "1"]', "") == 1
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_delete_task_only_removes_rows_without_pending_changes(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        await cache.replace_remote_tasks([Task(uid="synced", data=TaskData(summary="Synced"))])
+        await cache.upsert_task(Task(uid="new", data=TaskData(summary="New")), pending_action="create")
+        await cache.upsert_task(Task(uid="edited", data=TaskData(summary="Edited")), pending_action="update")
+
+        assert await cache.delete_task("synced")
+        assert await cache.delete_task("new")
+        assert not await cache.delete_task("edited")
+        assert {task.uid for task in await cache.list_tasks()} == {"edited"}
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_mark_for_deletion_keeps_tombstones_until_flushed(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        await cache.replace_remote_tasks([
+            Task(uid="a", data=TaskData(summary="A")),
+            Task(uid="b", data=TaskData(summary="B")),
+        ])
+        await cache.upsert_task(Task(uid="new", data=TaskData(summary="New")), pending_action="create")
+        tombstones = {uid: await cache.mark_for_deletion(uid) for uid in ("a", "b", "new")}
+
+        # A never-pushed create leaves no tombstone
+        assert tombstones == {"a": True, "b": True, "new": False}
+        assert {task.uid for task in await cache.list_deleted_tasks()} == {"a", "b"}
+        await cache.flush_deleted_tasks(["a"])
+        dirty = await cache.dirty_tasks()
+        assert [(entry.task.uid, entry.action) for entry in dirty] == [("b", "delete")]
+
+        # A pull does not clear a tombstone still waiting to be pushed
+        await cache.replace_remote_tasks([Task(uid="c", data=TaskData(summary="C"))])
+        assert [task.uid for task in await cache.list_deleted_tasks()] == ["b"]
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_pull_keeps_local_edit_only_while_server_etag_is_unchanged(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        await cache.replace_remote_tasks([Task(uid="t", data=TaskData(summary="Server"), etag='"1"')])
+        synced = await cache.get_task("t")
+        assert synced is not None and synced.etag == '"1"'
+
+        edited = replace(synced, data=replace(synced.data, summary="Local"))
+        await cache.upsert_task(edited, pending_action="update")
+        assert (await cache.get_task("t")).etag == '"1"'
+
+        await cache.replace_remote_tasks([Task(uid="t", data=TaskData(summary="Server"), etag='"1"')])
+        kept = await cache.get_task("t")
+        assert kept.data.summary == "Local"
+        assert await cache.get_pending_action("t") == "update"
+
+        await cache.replace_remote_tasks([Task(uid="t", data=TaskData(summary="Server v2"), etag='"2"')])
+        replaced = await cache.get_task("t")
+        assert (replaced.data.summary, replaced.etag) == ("Server v2", '"2"')
+        assert await cache.get_pending_action("t") is None
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_etag_follows_task_through_complete_and_delete(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        await cache.replace_remote_tasks([
+            Task(uid="done", data=TaskData(summary="Done"), etag='"a"'),
+            Task(uid="gone", data=TaskData(summary="Gone"), etag='"b"'),
+        ])
+        await cache.complete_task("done")
+        await cache.mark_for_deletion("gone")
+        assert (await cache.get_completed_task("done")).etag == '"a"'
+        assert (await cache.get_deleted_task("gone")).etag == '"b"'
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_search_ranks_exact_then_prefix_then_substring(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        for uid, summary, priority in [
+            ("inside", "Write report draft", None),
+            ("prefix-old", "Report bugs", None),
+            ("exact", "report", None),
+            ("prefix-urgent", "Report taxes", 1),
+            ("other", "Unrelated", 1),
+        ]:
+            await cache.upsert_task(Task(uid=uid, data=TaskData(summary=summary, priority=priority)))
+        # The most recently updated task wins a tie
+        await cache.upsert_task(Task(uid="prefix-new", data=TaskData(summary="report cards")))
+
+        ranked = [task.uid for task in await cache.search_tasks("REPORT")]
+        assert ranked == ["exact", "prefix-urgent", "prefix-new", "prefix-old", "inside"]
+        assert [task.uid for task in await cache.search_tasks("report", limit=2)] == ["exact", "prefix-urgent"]
+        assert await cache.search_tasks("missing") == []
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_search_weights_rank_matches_by_field(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        await cache.replace_remote_tasks(
+            [
+                Task(uid="summary", data=TaskData(summary="Book gym class")),
+                Task(uid="project", data=TaskData(summary="Renew card", x_properties={"X-PROJECT": "gym"})),
+                Task(uid="tag", data=TaskData(summary="Buy shoes", categories=["area::gym"])),
+                Task(uid="none", data=TaskData(summary="Unrelated")),
+            ]
+        )
+        # The default searches summaries only
+        assert [task.uid for task in await cache.search_tasks("gym")] == ["summary"]
+        weights = {"summary": 1, "project": 3, "tags": 2}
+        ranked = [task.uid for task in await cache.search_tasks("gym", weights=weights)]
+        # Exact project (3 * 3) beats substring tag (2 * 1) and substring summary (1 * 1)
+        assert ranked == ["project", "tag", "summary"]
+        limited = await cache.search_tasks("gym", weights={"tags": 1, "summary": 5}, limit=1)
+        assert [task.uid for task in limited] == ["summary"]
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_age_filters_use_entry_and_updated_at(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        old = Task(uid="old", data=TaskData(summary="Old"), etag='"1"')
+        fresh = Task(uid="fresh", data=TaskData(summary="Fresh"), etag='"1"')
+        legacy = Task(uid="legacy", data=TaskData(summary="Legacy"), etag='"1"')
+        await cache.replace_remote_tasks([old, fresh, legacy])
+        long_ago = time.time() - 200 * 86400
+        assert cache._conn is not None
+        await cache._conn.execute("UPDATE tasks SET entry = ?, updated_at = ? WHERE uid = 'old'", (long_ago, long_ago))
+        # Rows that predate the entry column have no creation time
+        await cache._conn.execute("UPDATE tasks SET entry = NULL WHERE uid = 'legacy'")
+        await cache._conn.commit()
+
+        # An unchanged etag keeps both stamps across a pull
+        await cache.replace_remote_tasks([old, fresh, legacy])
+        cutoff = datetime.fromtimestamp(time.time() - 90 * 86400).astimezone()
+        untouched = await cache.list_tasks_filtered(TaskFilter(updated_before=cutoff))
+        assert [task.uid for task in untouched] == ["old"]
+        recent = await cache.list_tasks_filtered(TaskFilter(created_after=cutoff))
+        assert [task.uid for task in recent] == ["fresh"]
+
+        # The creation time follows the task between tables
+        await cache.complete_task("old")
+        done = TaskFilter(created_before=cutoff)
+        sql, params = TaskQuery("completed_tasks").apply_filter(done).build()
+        async with cache._conn.execute(sql, params) as cursor:
+            assert [row["uid"] for row in await cursor.fetchall()] == ["old"]
+        restored = await cache.restore_from_completed("old")
+        assert [task.uid for task in await cache.list_tasks_filtered(done)] == [restored.uid]
+
+        # A new etag means the server copy changed
+        await cache.replace_remote_tasks([replace(old, etag='"2"'), fresh, legacy])
+        assert await cache.list_tasks_filtered(TaskFilter(updated_before=cutoff)) == []
+        assert [task.uid for task in await cache.list_tasks_filtered(done)] == ["old"]
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_transaction_history_travels_to_another_cache(tmp_path: Path) -> None:
+    source = await SqliteTaskCache.create(tmp_path / "source.db")
+    dest = await SqliteTaskCache.create(tmp_path / "dest.db", retention=TransactionLogRetention(max_entries=3))
+    try:
+        added = TaskDiff(pre=None, post=TaskData(summary="Moved"))
+        other = TaskDiff(pre=None, post=TaskData(summary="Other"))
+        await source.log_transaction(TaskSetDiff(diffs={"moved": added, "other": other}), operation="add")
+        await source.log_transaction(TaskSetDiff(diffs={"other": other}), operation="modify")
+        await source.log_transaction(TaskSetDiff(diffs={"moved": added}), operation="modify")
+
+        history = await source.transaction_history(["moved"])
+        assert [entry.operation for entry in history] == ["add", "modify"]
+        assert all(set(json.loads(entry.diff_json)) == {"moved"} for entry in history)
+
+        await dest.log_transaction(TaskSetDiff(diffs={"local": other}), operation="add")
+        await dest.log_transaction(TaskSetDiff(diffs={"local": other}), operation="modify")
+        assert await dest.import_transactions(history, {"moved": "renamed"}) == 2
+        log = await dest.get_transaction_log()
+        # Capped at max_entries, so the oldest local entry went
+        assert [entry.operation for entry in log] == ["modify", "add", "modify"]
+        assert set(json.loads(log[0].diff_json)) == {"renamed"}
+        assert log[1].created_at == history[0].created_at
+    finally:
+        await source.close()
+        await dest.close()
+
+
+@pytest.mark.asyncio
+async def test_boundary_events_report_each_crossing_once(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        now = datetime.now().replace(microsecond=0)
+        tasks = [
+            Task(uid="overdue", data=TaskData(summary="Overdue", due=now - timedelta(minutes=5))),
+            Task(uid="old", data=TaskData(summary="Old", due=now - timedelta(days=2))),
+            Task(uid="both", data=TaskData(summary="Both", due=now - timedelta(minutes=1), wait=now - timedelta(minutes=3))),
+            Task(uid="later", data=TaskData(summary="Later", wait=now + timedelta(hours=1))),
+        ]
+        for task in tasks:
+            await cache.upsert_task(task, pending_action="create")
+        since = (now - timedelta(minutes=10)).timestamp()
+        events = await cache.list_boundary_events(since=since, until=now.timestamp())
+        assert [(event.kind, event.task.uid) for event in events] == [
+            ("overdue", "overdue"),
+            ("unwaited", "both"),
+            ("overdue", "both"),
+        ]
+        # The next poll starts where this one ended
+        assert await cache.list_boundary_events(since=now.timestamp(), until=now.timestamp() + 60) == []
+        await cache.complete_task("overdue")
+        events = await cache.list_boundary_events(since=since, until=now.timestamp())
+        assert [event.task.uid for event in events] == ["both", "both"]
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_count_tasks_by_is_scoped_like_list(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        tasks = [
+            Task(uid="a", data=TaskData(summary="A", categories=["x", "y"], x_properties={"X-PROJECT": "home"})),
+            Task(uid="b", data=TaskData(summary="B", status="IN-PROCESS", categories=["x"])),
+            Task(uid="c", data=TaskData(summary="C", x_properties={"X-PROJECT": "home"})),
+            Task(uid="waiting", data=TaskData(summary="W", wait=datetime.now() + timedelta(days=1))),
+        ]
+        for task in tasks:
+            await cache.upsert_task(task, pending_action="create")
+        await cache.complete_task("c")
+
+        assert await cache.count_tasks_by("project") == {"home": 1, "": 1}
+        assert await cache.count_tasks_by("project", include_completed=True) == {"home": 2, "": 1}
+        assert await cache.count_tasks_by("tag") == {"x": 2, "y": 1}
+        assert await cache.count_tasks_by("status") == {"NEEDS-ACTION": 1, "IN-PROCESS": 1}
+        assert await cache.count_tasks_by("tag", task_filter=TaskFilter(status="IN-PROCESS")) == {"x": 1}
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_swap_indices_exchanges_unique_indices(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        for uid in ("a", "b", "unindexed"):
+            await cache.upsert_task(Task(uid=uid, data=TaskData(summary=uid)), pending_action="create")
+        await cache.assign_index("a", 1)
+        await cache.assign_index("b", 7)
+
+        assert await cache.swap_indices("a", "b") == (7, 1)
+        assert (await cache.get_task_by_index(7)).uid == "a"
+        assert (await cache.get_task_by_index(1)).uid == "b"
+
+        with pytest.raises(KeyError):
+            await cache.swap_indices("a", "unindexed")
+        # A failed swap leaves both indices alone
+        assert (await cache.get_task_by_index(7)).uid == "a"
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_upgrade_adds_indexes_on_migrated_columns(tmp_path: Path) -> None:
+    import sqlite3
+
+    path = tmp_path / "cache.db"
+    columns = (
+        "uid TEXT PRIMARY KEY, summary TEXT NOT NULL, status TEXT NOT NULL, due TEXT, wait TEXT,"
+        " priority INTEGER, x_properties TEXT, categories TEXT, href TEXT, pending_action TEXT,"
+        " last_synced REAL, updated_at REAL NOT NULL"
+    )
+    # A cache from before due_utc and wait_utc existed
+    with sqlite3.connect(path) as conn:
+        conn.execute(f"CREATE TABLE tasks ({columns}, task_index INTEGER UNIQUE)")
+        conn.execute(f"CREATE TABLE completed_tasks ({columns}, completed_at REAL, task_index INTEGER)")
+        conn.execute(f"CREATE TABLE deleted_tasks ({columns}, deleted_at REAL, task_index INTEGER)")
+        conn.execute(
+            "INSERT INTO tasks (uid, summary, status, due, updated_at, task_index)"
+            " VALUES ('old', 'Old', 'NEEDS-ACTION', '2026-01-01T00:00:00+00:00', 1, 1)"
+        )
+    cache = await SqliteTaskCache.create(path)
+    try:
+        assert [task.uid for task in await cache.list_tasks()] == ["old"]
+        assert cache._conn is not None
+        async with cache._conn.execute("SELECT name FROM sqlite_master WHERE type = 'index'") as cursor:
+            names = {row[0] for row in await cursor.fetchall()}
+        assert {"idx_tasks_status", "idx_tasks_index", "idx_tasks_due_utc", "idx_tasks_wait_utc"} <= names
+    finally:
+        await cache.close()
+
+
+async def _downgrade_to_v1(path: Path) -> None:
+    """Rewrite a fresh cache into the shape a schema v1 build left behind."""
+    import sqlite3
+
+    cache = await SqliteTaskCache.create(path)
+    await cache.upsert_task(Task(uid="old", data=TaskData(summary="Old")), pending_action="create")
+    await cache.assign_index("old")
+    await cache.close()
+    with sqlite3.connect(path) as conn:
+        for index in ("idx_tasks_index", "idx_tasks_due_utc", "idx_tasks_wait_utc"):
+            conn.execute(f"DROP INDEX {index}")
+        for table in ("tasks", "completed_tasks", "deleted_tasks", "archived_tasks"):
+            conn.execute(f"ALTER TABLE {table} DROP COLUMN entry")
+        for column in ("pending", "log_id", "uids"):
+            conn.execute(f"ALTER TABLE idempotency_keys DROP COLUMN {column}")
+        conn.execute("PRAGMA user_version = 1")
+
+
+@pytest.mark.asyncio
+async def test_v1_cache_is_migrated_to_current_schema(tmp_path: Path) -> None:
+    path = tmp_path / "cache.db"
+    await _downgrade_to_v1(path)
+    cache = await SqliteTaskCache.create(path)
+    try:
+        assert (await cache.info()).schema_version == SCHEMA_VERSION == 4
+        assert cache._conn is not None
+        async with cache._conn.execute("PRAGMA table_info(tasks)") as cursor:
+            assert "entry" in {row[1] for row in await cursor.fetchall()}
+        async with cache._conn.execute("PRAGMA table_info(idempotency_keys)") as cursor:
+            assert {"pending", "log_id", "uids"} <= {row[1] for row in await cursor.fetchall()}
+        async with cache._conn.execute("SELECT name FROM sqlite_master WHERE type = 'index'") as cursor:
+            assert {"idx_tasks_index", "idx_tasks_due_utc"} <= {row[0] for row in await cursor.fetchall()}
+        assert [task.uid for task in await cache.list_tasks()] == ["old"]
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_common_list_queries_use_indexes(tmp_path: Path) -> None:
+    cache = await SqliteTaskCache.create(tmp_path / "cache.db")
+    try:
+        now = time.time()
+        # task_index lookups may use the UNIQUE constraint's own index
+        queries = {
+            "USING INDEX idx_tasks_status": TaskQuery().apply_filter(TaskFilter(status="IN-PROCESS")),
+            "(task_index=?)": TaskQuery().apply_filter(TaskFilter(indices=[3, 5])),
+            "USING INDEX idx_tasks_due_utc": TaskQuery().apply_filter(
+                TaskFilter(due_before=datetime.now().astimezone())
+            ),
+            "USING INDEX idx_tasks_wait_utc": TaskQuery().where("wait_utc IS NOT NULL").where("wait_utc > ?", now),
+        }
+        assert cache._conn is not None
+        for expected, query in queries.items():
+            sql, params = query.build()
+            async with cache._conn.execute(f"EXPLAIN QUERY PLAN {sql}", params) as cursor:
+                plan = " ".join(row[3] for row in await cursor.fetchall())
+            assert expected in plan and "SCAN tasks" not in plan, (expected, plan)
+    finally:
+        await cache.close()
+
+
+@pytest.mark.asyncio
+async def test_read_only_cache_reads_but_refuses_writes(tmp_path: Path) -> None:
+    import sqlite3
+
+    path = tmp_path / "cache.db"
+    # Nothing to read yet, so the first open creates the cache read-write
+    cache = await SqliteTaskCache.create(path, read_only=True)
+    try:
+        assert not cache.read_only
+        await cache.upsert_task(Task(uid="one", data=TaskData(summary="One")))
+    finally:
+        await cache.close()
+    reader = await SqliteTaskCache.create(path, read_only=True)
+    try:
+        assert reader.read_only
+        assert [task.uid for task in await reader.list_tasks()] == ["one"]
+        with pytest.raises(ReadOnlyCacheError, match="opened read-only"):
+            reader.require_writable("add a task")
+        with pytest.raises(sqlite3.OperationalError, match="readonly"):
+            await reader.upsert_task(Task(uid="two", data=TaskData(summary="Two")))
+    finally:
+        await reader.close()
+
+
+@pytest.mark.asyncio
+async def test_read_only_open_without_upgrade_never_writes(tmp_path: Path) -> None:
+    path = tmp_path / "cache.db"
+    cache = await SqliteTaskCache.create(path, read_only=True, upgrade=False)
+    try:
+        assert cache.read_only
+        assert await cache.list_tasks() == []
+    finally:
+        await cache.close()
+    assert not path.exists()
+
+    await _downgrade_to_v1(path)
+    with pytest.raises(CacheUnavailableError, match="must be migrated to version 4"):
+        await SqliteTaskCache.create(path, read_only=True, upgrade=False)
+
+
+@pytest.mark.asyncio
+async def test_read_only_open_migrates_an_older_schema(tmp_path: Path) -> None:
+    path = tmp_path / "cache.db"
+    await _downgrade_to_v1(path)
+    cache = await SqliteTaskCache.create(path, read_only=True)
+    try:
+        # Reading a v1 cache as-is would query the missing entry column
+        assert not cache.read_only
+        assert [task.uid for task in await cache.list_tasks()] == ["old"]
+    finally:
+        await cache.close()
+    reader = await SqliteTaskCache.create(path, read_only=True)
+    try:
+        assert reader.read_only
+        assert (await reader.info()).schema_version == SCHEMA_VERSION
+    finally:
+        await reader.close()
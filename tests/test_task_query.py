@@ -0,0 +1,71 @@
+from __future__ import annotations
+
+from datetime import datetime, timezone
+
+import pytest
+
+from tdo.models import TaskFilter
+from tdo.task_query import TaskQuery
+
+
+def test_build_binds_all_filter_values() -> None:
+    task_filter = TaskFilter(
+        project="work'; DROP TABLE tasks; --",
+        tags=['x" OR 1=1 --'],
+        indices=[3, 1],
+        status="NEEDS-ACTION",
+        due_before=datetime(2025, 1, 1),
+    )
+    sql, params = TaskQuery().apply_filter(task_filter).order_by("due_utc", nulls_last=True).build()
+    assert "DROP" not in sql and "OR 1=1" not in sql
+    assert sql.count("?") == len(params) == 8
+    # The nested-tag prefix is bound as well
+    assert params[:4] == ["work'; DROP TABLE tasks; --", 'x" OR 1=1 --', 14, 'x" OR 1=1 --::']
+    assert sql.endswith("ORDER BY due_utc IS NULL, due_utc ASC")
+
+
+@pytest.mark.parametrize(
+    "column",
+    ["due; DROP TABLE tasks", "due_utc DESC, (SELECT 1)", "1", "DUE", ""],
+)
+def test_order_by_rejects_columns_outside_allow_list(column: str) -> None:
+    with pytest.raises(ValueError):
+        TaskQuery().order_by(column)
+
+
+@pytest.mark.parametrize("direction", ["ASC; DELETE FROM tasks", "sideways", ""])
+def test_order_by_rejects_unknown_directions(direction: str) -> None:
+    with pytest.raises(ValueError):
+        TaskQuery().order_by("due", direction)
+
+
+def test_table_and_placeholder_counts_are_checked() -> None:
+    with pytest.raises(ValueError):
+        TaskQuery("tasks; DROP TABLE tasks")
+    with pytest.raises(ValueError):
+        TaskQuery().where("status = ?")
+    with pytest.raises(ValueError):
+        TaskQuery().where("status = 'x'", "extra")
+    sql, params = TaskQuery("completed_tasks").order_by("completed_at", "DESC").build()
+    assert sql == "SELECT * FROM completed_tasks ORDER BY completed_at DESC"
+    assert params == []
+
+
+def test_age_filters_bind_timestamps_and_skip_missing_columns() -> None:
+    bound = datetime(2025, 1, 1, tzinfo=timezone.utc)
+    task_filter = TaskFilter(created_after=bound, updated_before=bound)
+    sql, params = TaskQuery().apply_filter(task_filter).build()
+    assert "entry IS NOT NULL AND entry > ?" in sql
+    assert "updated_at IS NOT NULL AND updated_at < ?" in sql
+    assert params == [bound.timestamp(), bound.timestamp()]
+    # deleted_tasks has no updated_at, so nothing there matches an updated.* bound
+    sql, _ = TaskQuery("deleted_tasks").apply_filter(task_filter).build()
+    assert "NULL IS NOT NULL" in sql
+
+
+def test_build_counts_groups_by_allow_listed_keys_only() -> None:
+    sql, params = TaskQuery().where("status = ?", "NEEDS-ACTION").build_counts("status")
+    assert sql == "SELECT status AS key, COUNT(*) AS count FROM tasks WHERE status = ? GROUP BY key"
+    assert params == ["NEEDS-ACTION"]
+    with pytest.raises(ValueError):
+        TaskQuery().build_counts("status; DROP TABLE tasks")
@@ -3,7 +3,7 @@ from __future__ import annotations
 import arrow
 import pytest
 
-from tdo.time_parser import parse_due_value
+from tdo.time_parser import parse_age_value, parse_due_value
 
 
 REFERENCE = arrow.get("2025-05-15T10:30:00")
@@ -90,6 +90,29 @@ def test_parse_due_weekday_names(token: str, expected: arrow.Arrow) -> None:
     assert parse_due_value(token, REFERENCE) == expected
 
 
+@pytest.mark.parametrize(
+    "token,expected",
+    [
+        ("@1700000000", arrow.get(1700000000)),
+        ("@1700000000.5", arrow.get(1700000000.5)),
+        ("@0", arrow.get(0)),
+        ("@86400", arrow.get(86400)),
+        ("100000000", arrow.get(100000000)),
+        ("1700000000.25", arrow.get(1700000000.25)),
+        ("@", None),
+        ("@-5", None),
+        ("@1e9", None),
+    ],
+)
+def test_parse_due_epoch_seconds(token: str, expected: arrow.Arrow | None) -> None:
+    assert parse_due_value(token, REFERENCE) == expected
+
+
+def test_parse_due_short_numbers_are_not_epoch_seconds() -> None:
+    assert parse_due_value("2025", REFERENCE) != arrow.get(2025)
+    assert parse_due_value("99999999", REFERENCE) != arrow.get(99999999)
+
+
 @pytest.mark.parametrize(
     "token,expected",
     [
@@ -132,9 +155,37 @@ def test_parse_due_time_of_day(value: str, expected_hour: int) -> None:
     assert result.hour == expected_hour
 
 
+# The accepted partial-time grammar: a bare time lands on the reference
+# date, and a space-separated date and time on that date.
+@pytest.mark.parametrize(
+    "value,expected",
+    [
+        ("15:00", arrow.get("2025-05-15T15:00:00")),
+        ("15:00:30", arrow.get("2025-05-15T15:00:30")),
+        ("3pm", arrow.get("2025-05-15T15:00:00")),
+        ("3:30 pm", arrow.get("2025-05-15T15:30:00")),
+        ("9AM", arrow.get("2025-05-15T09:00:00")),
+        ("2025-06-01 08:15", arrow.get("2025-06-01T08:15:00")),
+        ("2025-06-01 8pm", arrow.get("2025-06-01T20:00:00")),
+        ("2025-06-01  08:15", arrow.get("2025-06-01T08:15:00")),
+        ("24:00", None),
+        ("12:60", None),
+        ("13pm", None),
+        ("0am", None),
+        ("2025-02-30 10:00", None),
+        ("2025-06-01 soon", None),
+    ],
+)
+def test_parse_due_partial_times(value: str, expected: arrow.Arrow | None) -> None:
+    assert parse_due_value(value, REFERENCE) == expected
+
+
 def test_parse_due_iso_and_invalid_formats() -> None:
     assert parse_due_value("2025-12-31T23:59:59Z", REFERENCE) == arrow.get("2025-12-31T23:59:59+00:00")
     assert parse_due_value("2025-12-31 23:59", REFERENCE) == arrow.get("2025-12-31T23:59:00")
+    # Space-separated ISO forms that the time-of-day grammar doesn't cover still parse
+    assert parse_due_value("2025-12-31 23:59:59+02:00", REFERENCE) == arrow.get("2025-12-31T23:59:59+02:00")
+    assert parse_due_value("2025-12-31 23:59:59.500", REFERENCE) == arrow.get("2025-12-31T23:59:59.500")
     assert parse_due_value("not-a-date", REFERENCE) is None
 
 
@@ -356,3 +407,12 @@ def test_empty_and_none_inputs() -> None:
     assert parse_due_value("", REFERENCE) is None
     assert parse_due_value("   ", REFERENCE) is None
     assert parse_due_value(None, REFERENCE) is None  # type: ignore[arg-type]
+
+
+def test_age_values_count_durations_back_from_now() -> None:
+    assert parse_age_value("90d", REFERENCE) == REFERENCE.shift(days=-90)
+    assert parse_age_value("2w", REFERENCE) == REFERENCE.shift(weeks=-2)
+    # Everything else reads like a due value
+    assert parse_age_value("2025-01-01", REFERENCE) == parse_due_value("2025-01-01", REFERENCE)
+    assert parse_age_value("today", REFERENCE) == parse_due_value("today", REFERENCE)
+    assert parse_age_value("soon-ish", REFERENCE) is None
@@ -51,13 +51,19 @@ _TOKEN_OPTIONS = [
     "project:work",
     "project:home",
     "project:",
+    "pro:errands",
     "due:eod",
     "due:tomorrow",
     "due:",
     "wait:2d",
+    "estimate:30",
+    "estimate:",
+    "custom_status:waiting-on-reply",
+    "custom_status:",
     "wait:",
     "pri:5",
     "pri:",
+    "priority:3",
     "status:done",
     "summary:check",
     "word",
@@ -89,16 +95,22 @@ def _simulate_descriptor(raw: str) -> UpdateDescriptor:
             key_lower = key.strip().lower()
             value = rest.strip()
 
-            if key_lower == "project":
+            if key_lower in ("project", "pro"):
                 x_properties["X-PROJECT"] = value  # Keep empty string to signal "unset"
                 continue
+            if key_lower == "estimate":
+                x_properties["X-ESTIMATE"] = value
+                continue
+            if key_lower == "custom_status":
+                x_properties["X-CUSTOM-STATUS"] = value
+                continue
             if key_lower == "due":
                 due = value  # Keep empty string to signal "unset"
                 continue
             if key_lower == "wait":
                 wait = value  # Keep empty string to signal "unset"
                 continue
-            if key_lower == "pri":
+            if key_lower in ("pri", "priority"):
                 if not value:
                     priority = 0  # Use 0 to signal "unset"
                 else:
@@ -248,6 +260,16 @@ _EXAMPLE_INPUTS: list[tuple[str, UpdateDescriptor]] = [
             remove_data=TaskData(),
         ),
     ),
+    (
+        "estimate:45 write report",
+        UpdateDescriptor(
+            add_data=TaskData(
+                summary="write report",
+                x_properties={"X-ESTIMATE": "45"},
+            ),
+            remove_data=TaskData(),
+        ),
+    ),
     (
         "wait:2d +alpha -beta",
         UpdateDescriptor(
@@ -267,3 +289,28 @@ def test_parse_update_examples() -> None:
         actual = _normalize_descriptor(parse_update(raw))
         expected_normalized = _normalize_descriptor(expected)
         assert actual == expected_normalized, f"Mismatch for input: {raw!r}"
+
+
+def test_inline_add_accepts_aliases_and_quoted_values() -> None:
+    descriptor = parse_update('buy milk +grocery pro:"Home Stuff" due:tomorrow priority:H "at noon"')
+    assert descriptor.add_data.summary == 'buy milk "at noon"'
+    assert descriptor.add_data.categories == ["grocery"]
+    assert descriptor.add_data.x_properties == {"X-PROJECT": "Home Stuff"}
+    assert descriptor.add_data.due == "tomorrow"
+    assert descriptor.add_data.priority == 1
+    assert descriptor.description == 'buy milk "at noon"'
+    assert parse_update("words summary:Other").description is None
+
+
+def test_quoted_summary_words_are_kept_as_typed() -> None:
+    descriptor = parse_update('Read "Dune" project:"Book Club"')
+    assert descriptor.add_data.summary == 'Read "Dune"'
+    assert descriptor.add_data.x_properties == {"X-PROJECT": "Book Club"}
+    assert parse_update('summary:"Read Dune" x:X-NOTE:"a b"').add_data.summary == "Read Dune"
+    assert parse_update('x:X-NOTE:"a b"').add_data.x_properties == {"X-NOTE": "a b"}
+
+
+def test_unbalanced_quotes_stay_literal() -> None:
+    descriptor = parse_update('say "hi there project:x')
+    assert descriptor.add_data.summary == 'say "hi there'
+    assert descriptor.add_data.x_properties == {"X-PROJECT": "x"}